@@ -0,0 +1,73 @@
+//! Latency of reading a session's memory, through the whole server.
+//!
+//! Runs against Redis when `REDIS_URL` is set, and the in-memory store otherwise:
+//!
+//!     REDIS_URL=redis://localhost:6379 cargo bench --bench get_memory
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use motorhead_client::models::{MemoryMessage, Role};
+use motorhead_client::Client;
+use std::env;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+const PORT: &str = "8099";
+const SESSION_ID: &str = "bench";
+
+fn start_server() {
+    let storage = if env::var("REDIS_URL").is_ok() {
+        "redis"
+    } else {
+        "memory"
+    };
+    env::set_var("MOTORHEAD_STORAGE", storage);
+    env::set_var("MOTORHEAD_LLM_PROVIDER", "mock");
+    env::set_var("MOTORHEAD_HOST", "127.0.0.1");
+    env::set_var("MOTORHEAD_PORT", PORT);
+    // Compactions would change the window between iterations.
+    env::set_var("MOTORHEAD_MAX_WINDOW_SIZE", "1000");
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "warn");
+    }
+
+    thread::spawn(|| {
+        actix_web::rt::System::new()
+            .block_on(motorhead::run(Vec::new()))
+            .expect("The server failed")
+    });
+}
+
+fn get_memory(c: &mut Criterion) {
+    start_server();
+    let runtime = Runtime::new().unwrap();
+    let client = Client::new(format!("http://127.0.0.1:{}", PORT));
+
+    runtime.block_on(async {
+        let mut attempts = 0;
+        while client.delete_session(SESSION_ID).await.is_err() {
+            attempts += 1;
+            assert!(attempts < 50, "The server did not start");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let messages = (0..100)
+            .map(|i| {
+                let role = if i % 2 == 0 {
+                    Role::User
+                } else {
+                    Role::Assistant
+                };
+                MemoryMessage::new(role, format!("Message number {} of the session", i))
+            })
+            .collect();
+        client.add_messages(SESSION_ID, messages).await.unwrap();
+    });
+
+    c.bench_function("get_memory", |b| {
+        b.to_async(&runtime)
+            .iter(|| async { client.get_memory(SESSION_ID).await.unwrap() })
+    });
+}
+
+criterion_group!(benches, get_memory);
+criterion_main!(benches);
@@ -0,0 +1,206 @@
+//! A typed async client for the Motörhead memory server.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), motorhead_client::Error> {
+//! use motorhead_client::models::{MemoryMessage, Role};
+//! use motorhead_client::Client;
+//!
+//! let client = Client::new("http://localhost:8080").api_key("...");
+//! client
+//!     .add_messages("session", vec![MemoryMessage::new(Role::User, "Hi!")])
+//!     .await?;
+//! let memory = client.get_memory("session").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::{Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fmt;
+
+pub mod models;
+
+use models::{MemoryMessage, MemoryMessages, MemoryResponse, SummarizeResponse};
+
+/// The header naming the tenant a request acts on, for keys that aren't bound to one.
+const TENANT_HEADER: &str = "X-Tenant-Id";
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request couldn't be sent, or its response read.
+    Http(reqwest::Error),
+    /// The server answered with an error, e.g. `NOT_FOUND` or `COMPACTION_IN_PROGRESS`.
+    Api {
+        status: u16,
+        code: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "Request failed: {}", e),
+            Error::Api {
+                status,
+                code,
+                message,
+            } => write!(f, "{} {}: {}", status, code, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetails {
+    code: String,
+    message: String,
+}
+
+/// A read, in the `{ data, meta }` envelope of `MOTORHEAD_RESPONSE_ENVELOPE` or not.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Read<T> {
+    Enveloped { data: T },
+    Bare(T),
+}
+
+/// Talks to one server, with an API key and tenant if set. Cheap to clone, sharing its
+/// connections.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    tenant: Option<String>,
+}
+
+impl Client {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(reqwest::Client::new(), base_url)
+    }
+
+    /// With a `reqwest` client of your own, e.g. with timeouts or a proxy.
+    pub fn with_http_client(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Client {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            tenant: None,
+        }
+    }
+
+    /// Sent as `Authorization: Bearer`, for servers with `MOTORHEAD_API_KEYS`.
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Sent as `X-Tenant-Id`.
+    pub fn tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    fn request(&self, method: Method, session_id: &str, path: &str) -> RequestBuilder {
+        let mut url = reqwest::Url::parse(&self.base_url).expect("Invalid base URL");
+        url.path_segments_mut()
+            .expect("The base URL can't be a base")
+            .pop_if_empty()
+            .extend(["sessions", session_id])
+            .extend(path.split('/'));
+
+        let mut request = self.http.request(method, url);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        if let Some(tenant) = &self.tenant {
+            request = request.header(TENANT_HEADER, tenant);
+        }
+        request
+    }
+
+    async fn send(request: RequestBuilder) -> Result<Response, Error> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let bytes = response.bytes().await?;
+        let (code, message) = match serde_json::from_slice::<ErrorBody>(&bytes) {
+            Ok(ErrorBody { error }) => (error.code, error.message),
+            Err(_) => (
+                status.as_str().to_string(),
+                String::from_utf8_lossy(&bytes).into_owned(),
+            ),
+        };
+        Err(Error::Api {
+            status: status.as_u16(),
+            code,
+            message,
+        })
+    }
+
+    async fn read<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Error> {
+        match Self::send(request).await?.json::<Read<T>>().await? {
+            Read::Enveloped { data } | Read::Bare(data) => Ok(data),
+        }
+    }
+
+    /// The session's window, newest first, and its summaries.
+    pub async fn get_memory(&self, session_id: &str) -> Result<MemoryResponse, Error> {
+        Self::read(self.request(Method::GET, session_id, "memory")).await
+    }
+
+    /// Appends the messages, oldest first, which may start a compaction in the background.
+    pub async fn add_messages(
+        &self,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<(), Error> {
+        self.add_messages_with_ttl(session_id, messages, None).await
+    }
+
+    /// Like `add_messages`, expiring the session `ttl_seconds` after this append.
+    pub async fn add_messages_with_ttl(
+        &self,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+        ttl_seconds: Option<u64>,
+    ) -> Result<(), Error> {
+        let body = MemoryMessages {
+            messages,
+            ttl_seconds,
+        };
+        Self::send(self.request(Method::POST, session_id, "memory").json(&body)).await?;
+        Ok(())
+    }
+
+    /// Deletes the session and everything kept for it.
+    pub async fn delete_session(&self, session_id: &str) -> Result<(), Error> {
+        Self::send(self.request(Method::DELETE, session_id, "memory")).await?;
+        Ok(())
+    }
+
+    /// Compacts the session right away and returns its new context. Fails with
+    /// `COMPACTION_IN_PROGRESS` while another compaction is running.
+    pub async fn summarize(&self, session_id: &str) -> Result<String, Error> {
+        let response: SummarizeResponse =
+            Self::read(self.request(Method::POST, session_id, "summarize")).await?;
+        Ok(response.context)
+    }
+}
@@ -0,0 +1,242 @@
+//! The types of the API, shared with the server so both agree on the wire format.
+
+use serde::{Deserialize, Serialize};
+
+/// Who a message is from. Roles other than the standard ones are kept as sent.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum Role {
+    User,
+    Assistant,
+    System,
+    Tool,
+    Custom(String),
+}
+
+impl Role {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::System => "system",
+            Role::Tool => "tool",
+            Role::Custom(role) => role,
+        }
+    }
+}
+
+impl From<String> for Role {
+    fn from(role: String) -> Self {
+        match role.as_str() {
+            "user" => Role::User,
+            "assistant" => Role::Assistant,
+            "system" => Role::System,
+            "tool" => Role::Tool,
+            _ => Role::Custom(role),
+        }
+    }
+}
+
+impl From<Role> for String {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::Custom(role) => role,
+            role => role.as_str().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A function call requested by the assistant, in the OpenAI chat format.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON-encoded, as sent by the model.
+    pub arguments: String,
+}
+
+fn null_as_empty<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Option::<String>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+/// A file a message refers to, stored by reference.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub url: String,
+    pub mime_type: String,
+    /// In bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The text extracted from the file, e.g. a PDF's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MemoryMessage {
+    pub role: Role,
+    /// Empty for assistant messages that only call tools.
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// The call a `tool` message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// The participant or tool the message is from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Who said it, in group chats where several people share the `user` role, e.g. a display
+    /// name. Summaries attribute what was said to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<Vec<Attachment>>,
+    /// Assigned by the server when the message is stored, unless the client sends one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Groups the messages of a turn, e.g. a tool call, its result and the reply, which
+    /// deletions and undos then take out whole.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub turn_id: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Map<String, serde_json::Value>>,
+    /// From 0 to 1, on the messages a compaction pinned for their importance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub importance: Option<f32>,
+}
+
+impl MemoryMessage {
+    /// A message with no tool calls, id or metadata.
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        MemoryMessage {
+            role,
+            content: content.into(),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            speaker: None,
+            attachments: None,
+            id: None,
+            turn_id: None,
+            created_at: None,
+            metadata: None,
+            importance: None,
+        }
+    }
+
+    /// Whether both say the same thing, whatever their ids, timestamps and metadata.
+    pub fn repeats(&self, other: &MemoryMessage) -> bool {
+        self.role == other.role
+            && self.content == other.content
+            && self.name == other.name
+            && self.speaker == other.speaker
+            && self.tool_calls == other.tool_calls
+            && self.tool_call_id == other.tool_call_id
+            && self.attachments == other.attachments
+    }
+
+    /// The message as a line of conversation for the summarizer, tool calls included, naming its
+    /// speaker or else its `name`. With `attachment_text`, the text extracted from its
+    /// attachments too.
+    pub fn transcript_line(&self, attachment_text: bool) -> String {
+        let mut line = match self.speaker.as_ref().or(self.name.as_ref()) {
+            Some(name) => format!("{} ({}):", self.role, name),
+            None => format!("{}:", self.role),
+        };
+        if !self.content.is_empty() {
+            line.push(' ');
+            line.push_str(&self.content);
+        }
+        for call in self.tool_calls.iter().flatten() {
+            line.push_str(&format!(
+                " [calls {}({})]",
+                call.function.name, call.function.arguments
+            ));
+        }
+        if attachment_text {
+            for attachment in self.attachments.iter().flatten() {
+                if let Some(text) = &attachment.text {
+                    line.push_str(&format!(" [attached {}: {}]", attachment.mime_type, text));
+                }
+            }
+        }
+        line
+    }
+}
+
+/// The summary of a stretch of the conversation about one topic.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ContextSegment {
+    pub topic: String,
+    pub summary: String,
+    /// Ids of the first and last messages summarized into the segment.
+    pub first_message_id: Option<String>,
+    pub last_message_id: Option<String>,
+    #[serde(default)]
+    pub message_count: usize,
+}
+
+/// The body of an append.
+#[derive(Serialize, Deserialize)]
+pub struct MemoryMessages {
+    pub messages: Vec<MemoryMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+}
+
+/// A session's memory, as `GET /sessions/:id/memory` returns it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MemoryResponse {
+    pub messages: Vec<MemoryMessage>,
+    pub context: Option<String>,
+    /// What older contexts were summarized into once they grew too long.
+    pub long_term_context: Option<String>,
+    /// With `MOTORHEAD_SEGMENTED_SUMMARIES_ENABLED`, a summary per topic, oldest first.
+    #[serde(default)]
+    pub context_segments: Vec<ContextSegment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compaction_error: Option<String>,
+    /// The session's system prompt, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// Tokens taken by `messages`.
+    pub tokens_in_window: usize,
+    pub messages_since_last_summary: u64,
+    pub compaction_in_progress: bool,
+    /// When the session's lease runs out, while one is held.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_expires_at: Option<u64>,
+    /// When paging, the offset of the next page, if there is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<usize>,
+    /// With an overlap, how many of the oldest `messages` were already summarized into the
+    /// context, taken back from the history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overlap: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SummarizeResponse {
+    pub context: String,
+}
@@ -0,0 +1,321 @@
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::models::AppState;
+use crate::telemetry;
+use crate::tenant::Tenant;
+use crate::webhooks::{notify, WebhookEvent};
+
+/// Sessions tracked at most before the quiet ones are forgotten.
+const MAX_TRACKED_SESSIONS: usize = 10_000;
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// How long a Slack notification can take.
+const SLACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anomaly {
+    /// More messages appended within a minute than `MOTORHEAD_ALERT_MESSAGES_PER_MINUTE`.
+    MessageRate,
+    /// More appends leaving the window over its size within an hour than
+    /// `MOTORHEAD_ALERT_WINDOW_OVERFLOWS_PER_HOUR`.
+    WindowOverflows,
+    /// As many compactions failed in a row as `MOTORHEAD_ALERT_COMPACTION_FAILURES`.
+    CompactionFailures,
+}
+
+impl Anomaly {
+    fn describe(&self, observed: u64) -> String {
+        match self {
+            Anomaly::MessageRate => format!("{} messages appended within a minute", observed),
+            Anomaly::WindowOverflows => {
+                format!("the window overflowed {} times within an hour", observed)
+            }
+            Anomaly::CompactionFailures => format!("{} compactions failed in a row", observed),
+        }
+    }
+}
+
+/// What counts as anomalous. Unset thresholds aren't checked.
+pub struct AlertThresholds {
+    pub messages_per_minute: Option<u64>,
+    pub window_overflows_per_hour: Option<u64>,
+    pub compaction_failures: Option<u64>,
+}
+
+/// A count over a fixed window of time.
+#[derive(Default)]
+struct Counter {
+    started_at: Option<Instant>,
+    count: u64,
+}
+
+impl Counter {
+    fn add(&mut self, now: Instant, period: Duration, count: u64) -> u64 {
+        match self.started_at {
+            Some(started_at) if now.duration_since(started_at) < period => self.count += count,
+            _ => {
+                self.started_at = Some(now);
+                self.count = count;
+            }
+        }
+        self.count
+    }
+}
+
+#[derive(Default)]
+struct SessionActivity {
+    messages: Counter,
+    window_overflows: Counter,
+    compaction_failures: u64,
+    last_seen: Option<Instant>,
+    alerted_at: HashMap<Anomaly, Instant>,
+}
+
+/// Notifies of sessions behaving like runaway loops, with a `session_anomaly` webhook and a
+/// Slack message, at most once per `cooldown` for each session and anomaly. Counted by each
+/// instance on its own.
+pub struct Alerts {
+    thresholds: AlertThresholds,
+    slack_webhook_url: Option<String>,
+    cooldown: Duration,
+    http: reqwest::Client,
+    sessions: Mutex<HashMap<String, SessionActivity>>,
+}
+
+impl Alerts {
+    pub fn new(
+        thresholds: AlertThresholds,
+        slack_webhook_url: Option<String>,
+        cooldown: Duration,
+    ) -> Self {
+        Alerts {
+            thresholds,
+            slack_webhook_url,
+            cooldown,
+            http: reqwest::Client::builder()
+                .timeout(SLACK_TIMEOUT)
+                .build()
+                .expect("Could not build the Slack client"),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Updates the session's activity with `update`, returning its observed value if that
+    /// crosses `threshold` and the session wasn't alerted of `anomaly` within the cooldown.
+    fn observe(
+        &self,
+        scoped_session_id: &str,
+        anomaly: Anomaly,
+        threshold: u64,
+        update: impl FnOnce(&mut SessionActivity, Instant) -> u64,
+    ) -> Option<u64> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.len() > MAX_TRACKED_SESSIONS {
+            sessions.retain(|_, activity| {
+                activity.compaction_failures > 0
+                    || activity
+                        .last_seen
+                        .is_some_and(|last_seen| now.duration_since(last_seen) < HOUR)
+            });
+        }
+
+        let activity = sessions.entry(scoped_session_id.to_string()).or_default();
+        activity.last_seen = Some(now);
+        let observed = update(activity, now);
+        if observed < threshold {
+            return None;
+        }
+        if let Some(alerted_at) = activity.alerted_at.get(&anomaly) {
+            if now.duration_since(*alerted_at) < self.cooldown {
+                return None;
+            }
+        }
+        activity.alerted_at.insert(anomaly, now);
+        Some(observed)
+    }
+
+    async fn post_to_slack(&self, url: &str, text: String) {
+        let result = self
+            .http
+            .post(url)
+            .json(&json!({ "text": text }))
+            .send()
+            .await;
+        let error = match result {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => format!("Slack answered {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+        tracing::warn!(error, "Could not post an alert to Slack");
+    }
+}
+
+/// Sends the alert in the background, with what there is to know about the session.
+fn alert(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    anomaly: Anomaly,
+    observed: u64,
+    threshold: u64,
+) {
+    let state = Arc::clone(state);
+    let tenant = tenant.clone();
+    let session_id = session_id.to_string();
+
+    tokio::spawn(async move {
+        let Some(alerts) = &state.alerts else {
+            return;
+        };
+        tracing::warn!(
+            tenant = tenant.id(),
+            session_id,
+            anomaly = ?anomaly,
+            observed,
+            threshold,
+            "Anomalous session"
+        );
+
+        let store = tenant.store(&state);
+        let metadata = store.get_metadata(&session_id).await.unwrap_or_else(|e| {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem reading the metadata of an anomalous session"
+            );
+            None
+        });
+        let usage = store.get_session_usage(&session_id).await.ok();
+
+        notify(
+            &state,
+            tenant.id(),
+            &session_id,
+            WebhookEvent::SessionAnomaly,
+            json!({
+                "anomaly": anomaly,
+                "observed": observed,
+                "threshold": threshold,
+                "metadata": metadata,
+                "usage": usage,
+            }),
+        );
+
+        if let Some(url) = &alerts.slack_webhook_url {
+            let mut text = format!(
+                "Session `{}` looks anomalous: {} (threshold {}).",
+                session_id,
+                anomaly.describe(observed),
+                threshold
+            );
+            if let Some(tenant) = tenant.id() {
+                text.push_str(&format!(" Tenant: `{}`.", tenant));
+            }
+            if let Some(usage) = usage {
+                text.push_str(&format!(" LLM tokens used: {}.", usage.total()));
+            }
+            alerts.post_to_slack(url, text).await;
+        }
+    });
+}
+
+/// Counts the messages appended to the session, and whether the append left its window over
+/// its size.
+pub fn record_append(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    messages: usize,
+    window_overflowed: bool,
+) {
+    let Some(alerts) = &state.alerts else {
+        return;
+    };
+    let scoped_session_id = tenant.scope(session_id);
+
+    if let Some(threshold) = alerts.thresholds.messages_per_minute {
+        let observed = alerts.observe(
+            &scoped_session_id,
+            Anomaly::MessageRate,
+            threshold,
+            |activity, now| activity.messages.add(now, MINUTE, messages as u64),
+        );
+        if let Some(observed) = observed {
+            alert(
+                state,
+                tenant,
+                session_id,
+                Anomaly::MessageRate,
+                observed,
+                threshold,
+            );
+        }
+    }
+
+    if let Some(threshold) = alerts
+        .thresholds
+        .window_overflows_per_hour
+        .filter(|_| window_overflowed)
+    {
+        let observed = alerts.observe(
+            &scoped_session_id,
+            Anomaly::WindowOverflows,
+            threshold,
+            |activity, now| activity.window_overflows.add(now, HOUR, 1),
+        );
+        if let Some(observed) = observed {
+            alert(
+                state,
+                tenant,
+                session_id,
+                Anomaly::WindowOverflows,
+                observed,
+                threshold,
+            );
+        }
+    }
+}
+
+/// Counts the session's failed compactions in a row, until one succeeds.
+pub fn record_compaction(state: &Arc<AppState>, tenant: &Tenant, session_id: &str, failed: bool) {
+    let Some(alerts) = &state.alerts else {
+        return;
+    };
+    let Some(threshold) = alerts.thresholds.compaction_failures else {
+        return;
+    };
+    let scoped_session_id = tenant.scope(session_id);
+
+    if !failed {
+        if let Some(activity) = alerts.sessions.lock().unwrap().get_mut(&scoped_session_id) {
+            activity.compaction_failures = 0;
+        }
+        return;
+    }
+    let observed = alerts.observe(
+        &scoped_session_id,
+        Anomaly::CompactionFailures,
+        threshold,
+        |activity, _| {
+            activity.compaction_failures += 1;
+            activity.compaction_failures
+        },
+    );
+    if let Some(observed) = observed {
+        alert(
+            state,
+            tenant,
+            session_id,
+            Anomaly::CompactionFailures,
+            observed,
+            threshold,
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use actix_web::{get, web, Responder};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::llm::CompletionRequest;
+use crate::models::{
+    AnalysisResponse, AppState, MotorheadError, SentimentAnalysis, SummaryOptions, TokenUsage,
+};
+use crate::response::read_response;
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+pub const SENTIMENT_PROMPT: &str = r#"
+        Rate how satisfied the user sounds in the lines of conversation provided, from -1 (angry or frustrated) through 0 (neutral) to 1 (happy). Only the user's lines count. Reply with the number only. If the user says nothing just return NONE
+
+        Lines of conversation:
+        {messages}
+        Sentiment:
+        "#;
+
+/// How many of the latest windows the rolling score averages.
+const ROLLING_WINDOWS: usize = 10;
+
+/// The metadata field the analysis is kept in.
+const METADATA_FIELD: &str = "sentiment";
+
+/// Parses the score out of a completion, tolerating text around the number. `None` for NONE.
+fn parse_sentiment(completion: &str) -> Result<Option<f32>, MotorheadError> {
+    if completion.trim() == "NONE" {
+        return Ok(None);
+    }
+    let score = completion
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .find_map(|word| {
+            word.trim_end_matches(|c: char| !c.is_ascii_digit())
+                .parse::<f32>()
+                .ok()
+        })
+        .filter(|score| score.is_finite())
+        .ok_or_else(|| {
+            MotorheadError::LlmError("No sentiment score in the completion".to_string())
+        })?;
+    Ok(Some(score.clamp(-1.0, 1.0)))
+}
+
+/// Asks the LLM how satisfied the user is in the compacted `messages`, and folds the score
+/// into the rolling one kept in the session's `metadata.sentiment`.
+#[tracing::instrument(skip_all)]
+pub async fn score_sentiment(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    messages: &[String],
+    options: &SummaryOptions,
+    usage: &mut TokenUsage,
+) -> Result<(), MotorheadError> {
+    let prompt = SENTIMENT_PROMPT.replace("{messages}", &messages.join("\n"));
+    let completion = state
+        .llm
+        .complete(CompletionRequest {
+            system: "You are a helpful AI assistant.",
+            prompt: &prompt,
+            max_tokens: 8,
+            model: options.model.as_deref(),
+            temperature: options.temperature,
+        })
+        .await?;
+    *usage += completion.usage;
+    let Some(last) = parse_sentiment(&completion.content)? else {
+        return Ok(());
+    };
+
+    let mut metadata = match store.get_metadata(session_id).await? {
+        Some(serde_json::Value::Object(metadata)) => metadata,
+        _ => serde_json::Map::new(),
+    };
+    let mut recent = metadata
+        .get(METADATA_FIELD)
+        .and_then(|analysis| serde_json::from_value::<SentimentAnalysis>(analysis.clone()).ok())
+        .map(|analysis| analysis.recent)
+        .unwrap_or_default();
+    recent.push(last);
+    if recent.len() > ROLLING_WINDOWS {
+        recent.remove(0);
+    }
+    let analysis = SentimentAnalysis {
+        score: recent.iter().sum::<f32>() / recent.len() as f32,
+        last,
+        recent,
+        updated_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64,
+    };
+    metadata.insert(
+        METADATA_FIELD.to_string(),
+        serde_json::to_value(analysis)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?,
+    );
+    store
+        .set_metadata(session_id, &serde_json::Value::Object(metadata))
+        .await
+}
+
+/// The session's rolling sentiment score, for flagging unhappy conversations. Scored by the
+/// compactions with `MOTORHEAD_SENTIMENT_ANALYSIS_ENABLED`.
+#[get("/sessions/{session_id}/analysis")]
+pub async fn get_analysis(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if !data.sentiment_analysis_enabled {
+        return Err(ApiError::new(
+            ErrorCode::FeatureDisabled,
+            "Sentiment analysis is not enabled",
+        )
+        .into());
+    }
+
+    let sentiment = tenant
+        .store(&data)
+        .get_metadata(&session_id)
+        .await?
+        .and_then(|metadata| metadata.get(METADATA_FIELD).cloned())
+        .and_then(|analysis| serde_json::from_value(analysis).ok())
+        .ok_or_else(|| ApiError::not_found("Session has not been analyzed yet"))?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        AnalysisResponse { sentiment },
+    ))
+}
@@ -0,0 +1,110 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use time::{Date, Month, OffsetDateTime};
+
+use crate::config::check_admin;
+use crate::errors::ApiError;
+use crate::models::{
+    AnalyticsCounts, AnalyticsQuery, AnalyticsResponse, AppState, DailyAnalytics, TenantAnalytics,
+};
+use crate::store::MemoryStore;
+use crate::telemetry;
+
+/// The longest range `GET /admin/analytics` reads, a hash per day.
+const MAX_DAYS: i64 = 366;
+
+/// Adds to the store's tenant's counters for today in UTC, with
+/// `MOTORHEAD_ANALYTICS_ENABLED`. Failing to doesn't fail what's counted.
+pub async fn record(state: &AppState, store: &dyn MemoryStore, counts: AnalyticsCounts) {
+    if !state.analytics_enabled || counts == AnalyticsCounts::default() {
+        return;
+    }
+    let today = OffsetDateTime::now_utc().date().to_string();
+    if let Err(e) = store.record_analytics(&today, &counts).await {
+        tracing::error!(
+            error = telemetry::error_message(&e),
+            "Problem recording the analytics"
+        );
+    }
+}
+
+fn parse_date(date: &str) -> Option<Date> {
+    let mut parts = date.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let month = Month::try_from(month.parse::<u8>().ok()?).ok()?;
+    Date::from_calendar_date(year.parse().ok()?, month, day.parse().ok()?).ok()
+}
+
+/// What each tenant did per day from `from` to `to`, the last 30 days by default, with totals
+/// for billing. Counted with `MOTORHEAD_ANALYTICS_ENABLED`.
+#[get("/admin/analytics")]
+pub async fn get_admin_analytics(
+    req: HttpRequest,
+    web::Query(query): web::Query<AnalyticsQuery>,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+
+    let invalid = || ApiError::invalid_request("from and to must be formatted as YYYY-MM-DD");
+    let to = match &query.to {
+        Some(to) => parse_date(to).ok_or_else(invalid)?,
+        None => OffsetDateTime::now_utc().date(),
+    };
+    let from = match &query.from {
+        Some(from) => parse_date(from).ok_or_else(invalid)?,
+        None => to - time::Duration::days(29),
+    };
+    if from > to {
+        return Err(ApiError::invalid_request("from must not be after to").into());
+    }
+    if (to - from).whole_days() >= MAX_DAYS {
+        return Err(ApiError::invalid_request(format!(
+            "The range can't be longer than {} days",
+            MAX_DAYS
+        ))
+        .into());
+    }
+    // The default namespace is asked for with an empty tenant.
+    let tenant = query
+        .tenant
+        .map(|tenant| (!tenant.is_empty()).then_some(tenant));
+
+    let mut tenants: BTreeMap<Option<String>, TenantAnalytics> = BTreeMap::new();
+    let mut day = from;
+    loop {
+        let date = day.to_string();
+        for (day_tenant, counts) in data.store.analytics_by_tenant(&date).await? {
+            if tenant.as_ref().is_some_and(|tenant| *tenant != day_tenant) {
+                continue;
+            }
+            let analytics = tenants
+                .entry(day_tenant.clone())
+                .or_insert_with(|| TenantAnalytics {
+                    tenant: day_tenant,
+                    total: AnalyticsCounts::default(),
+                    days: Vec::new(),
+                });
+            analytics.total += counts;
+            analytics.days.push(DailyAnalytics {
+                date: date.clone(),
+                counts,
+            });
+        }
+        match day.next_day() {
+            Some(next) if next <= to => day = next,
+            _ => break,
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(AnalyticsResponse {
+            from: from.to_string(),
+            to: to.to_string(),
+            tenants: tenants.into_values().collect(),
+        }))
+}
@@ -0,0 +1,428 @@
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use futures_util::stream::{self, StreamExt};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::auth::check_session_prefix;
+use crate::cold_storage;
+use crate::errors::{ApiError, ErrorCode};
+use crate::memory::{after_append, check_messages, check_roles, stamp_messages, summary_options};
+use crate::models::{
+    AckResponse, AppState, ExportFormat, ExportQuery, ForkRequest, ForkResponse, MemoryMessage,
+    MotorheadError, OpenAIContent, OpenAIMessage, SessionImport, SessionSnapshot, SummaryOptions,
+};
+use crate::quota;
+use crate::redaction::{redact, redact_messages};
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, actix_web::Error> {
+    serde_json::to_string(value).map_err(|e| ApiError::internal(e).into())
+}
+
+/// What's exported of the session, and its stored messages, oldest first.
+pub async fn snapshot(
+    store: &dyn MemoryStore,
+    session_id: &str,
+) -> Result<(SessionSnapshot, Vec<MemoryMessage>), MotorheadError> {
+    let (mut messages, context) = store.get_memory(session_id, 0, -1).await?;
+    messages.reverse();
+    let long_term_context = store.get_long_term_context(session_id).await?;
+    let context_segments = store.get_context_segments(session_id).await?;
+    let metadata = store.get_metadata(session_id).await?;
+    let entities = store.get_entities(session_id).await?;
+
+    let snapshot = SessionSnapshot {
+        session_id: session_id.to_string(),
+        exported_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64,
+        context,
+        long_term_context,
+        context_segments,
+        metadata,
+        entities,
+    };
+    Ok((snapshot, messages))
+}
+
+/// Exports every stored message of the session, oldest first, with its summaries, metadata
+/// and entities. `json` gives one document with a `messages` array; `ndjson` gives the
+/// session on the first line and one message per line after it. The body is streamed a
+/// message at a time.
+#[get("/sessions/{session_id}/export")]
+pub async fn export_session(
+    session_id: web::Path<String>,
+    web::Query(query): web::Query<ExportQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let (snapshot, messages) = snapshot(tenant.store(&data).as_ref(), &session_id).await?;
+    let snapshot = to_json(&snapshot)?;
+
+    let format = query.format;
+    let first = match format {
+        // Reopens the session object to append the messages array to it.
+        ExportFormat::Json => format!(
+            "{},\"messages\":[",
+            snapshot.strip_suffix('}').unwrap_or(&snapshot)
+        ),
+        ExportFormat::Ndjson => format!("{}\n", snapshot),
+    };
+    let lines = messages.into_iter().enumerate().map(move |(i, message)| {
+        let message = to_json(&message)?;
+        Ok(Bytes::from(match format {
+            ExportFormat::Json if i > 0 => format!(",{}", message),
+            ExportFormat::Json => message,
+            ExportFormat::Ndjson => format!("{}\n", message),
+        }))
+    });
+    let last = match format {
+        ExportFormat::Json => Some(Ok(Bytes::from_static(b"]}"))),
+        ExportFormat::Ndjson => None,
+    };
+
+    let body = stream::iter(
+        std::iter::once(Ok(Bytes::from(first)))
+            .chain(lines)
+            .chain(last),
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type(match format {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        })
+        .streaming::<_, actix_web::Error>(body))
+}
+
+/// Reads the whole body, refusing it once it grows past `max_bytes`.
+async fn read_body(payload: &mut web::Payload, max_bytes: usize) -> actix_web::Result<Bytes> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > max_bytes {
+            return Err(ApiError::new(
+                ErrorCode::PayloadTooLarge,
+                format!("Imports are limited to {} bytes", max_bytes),
+            )
+            .into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+/// Converts the `index`th message of a request from the OpenAI chat format. Content parts are
+/// joined, keeping only their text.
+pub fn from_openai(index: usize, message: OpenAIMessage) -> actix_web::Result<MemoryMessage> {
+    let content = match message.content {
+        Some(OpenAIContent::Text(text)) => text,
+        Some(OpenAIContent::Parts(parts)) => parts
+            .into_iter()
+            .filter_map(|part| part.text)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => String::new(),
+    };
+    let calls_tools = message
+        .tool_calls
+        .as_ref()
+        .is_some_and(|calls| !calls.is_empty());
+    if content.is_empty() && !calls_tools {
+        return Err(ApiError::new(
+            ErrorCode::InvalidMessage,
+            format!("Message {} has no text content", index),
+        )
+        .with("index", index)
+        .into());
+    }
+
+    Ok(MemoryMessage {
+        role: message.role.into(),
+        content,
+        tool_calls: message.tool_calls,
+        tool_call_id: message.tool_call_id,
+        name: message.name,
+        speaker: None,
+        attachments: None,
+        id: None,
+        turn_id: None,
+        created_at: None,
+        metadata: None,
+        importance: None,
+    })
+}
+
+/// Parses an export in either format, or a bare array of OpenAI-format messages.
+fn parse_import(body: &[u8], ndjson: bool) -> actix_web::Result<SessionImport> {
+    let invalid =
+        |e: serde_json::Error| ApiError::invalid_request(format!("Invalid import: {}", e));
+
+    if ndjson {
+        let mut lines = body
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.trim_ascii().is_empty());
+        let first = lines
+            .next()
+            .ok_or_else(|| ApiError::invalid_request("Empty import"))?;
+        let mut import: SessionImport = serde_json::from_slice(first).map_err(invalid)?;
+        for line in lines {
+            import
+                .messages
+                .push(serde_json::from_slice(line).map_err(invalid)?);
+        }
+        return Ok(import);
+    }
+
+    match serde_json::from_slice(body).map_err(invalid)? {
+        serde_json::Value::Array(messages) => {
+            let messages = messages
+                .into_iter()
+                .enumerate()
+                .map(|(index, message)| {
+                    from_openai(index, serde_json::from_value(message).map_err(invalid)?)
+                })
+                .collect::<actix_web::Result<_>>()?;
+            Ok(SessionImport {
+                context: None,
+                long_term_context: None,
+                context_segments: Vec::new(),
+                metadata: None,
+                entities: Default::default(),
+                config: None,
+                pinned: Vec::new(),
+                messages,
+            })
+        }
+        document @ serde_json::Value::Object(_) => {
+            Ok(serde_json::from_value(document).map_err(invalid)?)
+        }
+        _ => Err(
+            ApiError::invalid_request("An import is a session object or a messages array").into(),
+        ),
+    }
+}
+
+/// Replaces the session with an exported one (in the format given by the `Content-Type`,
+/// `application/x-ndjson` or JSON), or with a bare array of OpenAI-format messages.
+#[post("/sessions/{session_id}/import")]
+pub async fn import_session(
+    session_id: web::Path<String>,
+    mut payload: web::Payload,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    let summary = summary_options(&req, &data)?;
+    let body = read_body(&mut payload, data.import_max_bytes).await?;
+    let import = parse_import(&body, req.content_type() == "application/x-ndjson")?;
+
+    replace_session(&data, &tenant, &session_id, import, summary).await?;
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+/// Deletes the session and stores `import` in its place, after checking and redacting it.
+pub async fn replace_session(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    mut import: SessionImport,
+    summary: SummaryOptions,
+) -> actix_web::Result<()> {
+    if import
+        .metadata
+        .as_ref()
+        .is_some_and(|metadata| !metadata.is_object())
+    {
+        return Err(ApiError::invalid_request("Metadata must be a JSON object").into());
+    }
+    if let Some(index) = import
+        .messages
+        .iter()
+        .position(|message| message.role.as_str().is_empty())
+    {
+        return Err(ApiError::new(
+            ErrorCode::InvalidMessage,
+            format!("Message {} has no role", index),
+        )
+        .with("index", index)
+        .into());
+    }
+    check_messages(state, &import.messages)?;
+    check_roles(state, &import.messages).map_err(|e| ApiError::new(ErrorCode::UnknownRole, e))?;
+    if import
+        .config
+        .as_ref()
+        .and_then(|config| config.window_size)
+        .is_some_and(|window_size| window_size < 1)
+    {
+        return Err(ApiError::invalid_request("window_size must be at least 1").into());
+    }
+
+    // Scrubbed up front, so a failed redaction leaves the session as it was.
+    if let Some(context) = &import.context {
+        import.context = Some(redact(state, context).await?);
+    }
+    if let Some(long_term_context) = &import.long_term_context {
+        import.long_term_context = Some(redact(state, long_term_context).await?);
+    }
+    for value in import.entities.values_mut() {
+        *value = redact(state, value).await?;
+    }
+    let messages = redact_messages(state, stamp_messages(import.messages)).await?;
+    let pinned = redact_messages(state, import.pinned).await?;
+    quota::check_new(state, &messages)?;
+
+    let store = tenant.store(state);
+    let cold_segments = cold_storage::cold_segments(state, store.as_ref(), session_id).await?;
+    store.delete_session(session_id).await?;
+    cold_storage::delete_segments(state, session_id, &cold_segments).await;
+    state
+        .compaction_errors
+        .lock()
+        .unwrap()
+        .remove(&tenant.scope(session_id));
+
+    // The context goes first, as setting it resets the count of unsummarized messages.
+    if let Some(context) = &import.context {
+        store.set_context(session_id, context).await?;
+    }
+    if let Some(long_term_context) = &import.long_term_context {
+        store
+            .set_long_term_context(session_id, long_term_context)
+            .await?;
+    }
+    if !import.context_segments.is_empty() {
+        store
+            .set_context_segments(session_id, &import.context_segments)
+            .await?;
+    }
+    if let Some(metadata) = &import.metadata {
+        store.set_metadata(session_id, metadata).await?;
+    }
+    if !import.entities.is_empty() {
+        store.merge_entities(session_id, &import.entities).await?;
+    }
+    if let Some(config) = &import.config {
+        store.set_session_config(session_id, config).await?;
+    }
+    let (auto_pinned, pinned): (Vec<MemoryMessage>, Vec<MemoryMessage>) = pinned
+        .into_iter()
+        .partition(|message| message.importance.is_some());
+    for message in &pinned {
+        store.pin_message(session_id, message).await?;
+    }
+    if !auto_pinned.is_empty() {
+        store.set_auto_pinned(session_id, &auto_pinned).await?;
+    }
+
+    if !messages.is_empty() {
+        let len = store.append_messages(session_id, messages.clone()).await?;
+        after_append(state, tenant, session_id, messages, len, None, summary).await?;
+    }
+
+    Ok(())
+}
+
+/// Copies the session's window, pinned messages, summaries, metadata, entities and config
+/// into a new session, for branching the conversation off. With `until_message_id` the
+/// messages after that one are left out. Archived history isn't copied.
+#[post("/sessions/{session_id}/fork")]
+pub async fn fork_session(
+    session_id: web::Path<String>,
+    web::Json(fork): web::Json<ForkRequest>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    let summary = summary_options(&req, &data)?;
+    let store = tenant.store(&data);
+    let fork_id = fork
+        .session_id
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    check_session_prefix(&req, &fork_id)?;
+    if fork_id == *session_id {
+        return Err(ApiError::invalid_request("A session can't be forked into itself").into());
+    }
+
+    let (mut messages, context) = store.get_memory(&session_id, 0, -1).await?;
+    if let Some(until) = &fork.until_message_id {
+        let index = messages
+            .iter()
+            .position(|message| message.id.as_deref() == Some(until.as_str()))
+            .ok_or_else(|| ApiError::not_found("Message not found"))?;
+        messages.drain(..index);
+    }
+    messages.reverse();
+    let long_term_context = store.get_long_term_context(&session_id).await?;
+    let context_segments = store.get_context_segments(&session_id).await?;
+    let metadata = store.get_metadata(&session_id).await?;
+    let entities = store.get_entities(&session_id).await?;
+    let config = store.get_session_config(&session_id).await?;
+    let mut pinned = store.get_pinned(&session_id).await?;
+    if let Some(until) = messages.last().filter(|_| fork.until_message_id.is_some()) {
+        // Messages pinned after the fork point aren't part of the branch.
+        pinned.retain(|message| message.created_at <= until.created_at);
+    }
+    let (auto_pinned, pinned): (Vec<MemoryMessage>, Vec<MemoryMessage>) = pinned
+        .into_iter()
+        .partition(|message| message.importance.is_some());
+
+    let (existing, existing_context) = store.get_memory(&fork_id, 0, 0).await?;
+    if !existing.is_empty() || existing_context.is_some() {
+        return Err(ApiError::new(
+            ErrorCode::SessionExists,
+            format!("Session {} already exists", fork_id),
+        )
+        .into());
+    }
+    quota::check_new(&data, &messages)?;
+
+    // The context goes first, as setting it resets the count of unsummarized messages.
+    if let Some(context) = &context {
+        store.set_context(&fork_id, context).await?;
+    }
+    if let Some(long_term_context) = &long_term_context {
+        store
+            .set_long_term_context(&fork_id, long_term_context)
+            .await?;
+    }
+    if !context_segments.is_empty() {
+        store
+            .set_context_segments(&fork_id, &context_segments)
+            .await?;
+    }
+    if let Some(metadata) = &metadata {
+        store.set_metadata(&fork_id, metadata).await?;
+    }
+    if !entities.is_empty() {
+        store.merge_entities(&fork_id, &entities).await?;
+    }
+    if let Some(config) = &config {
+        store.set_session_config(&fork_id, config).await?;
+    }
+    for message in &pinned {
+        store.pin_message(&fork_id, message).await?;
+    }
+    if !auto_pinned.is_empty() {
+        store.set_auto_pinned(&fork_id, &auto_pinned).await?;
+    }
+
+    if !messages.is_empty() {
+        let len = store.append_messages(&fork_id, messages.clone()).await?;
+        after_append(&data, &tenant, &fork_id, messages, len, None, summary).await?;
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(ForkResponse {
+            session_id: fork_id,
+        }))
+}
@@ -0,0 +1,198 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{get, web, FromRequest, HttpMessage, HttpRequest, Responder};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+use crate::auth::{request_session, Actor};
+use crate::config::check_admin;
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{AppState, AuditEntry, AuditQuery, AuditResponse, MotorheadError};
+use crate::response::read_response;
+use crate::tenant::Tenant;
+
+/// The actor of the changes motorhead makes on its own, like compactions and idle reaping.
+pub const BACKGROUND_ACTOR: &str = "motorhead";
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Where changes are recorded, picked with `MOTORHEAD_AUDIT_LOG`.
+pub enum AuditLog {
+    /// A stream in Redis, `motorhead_audit`.
+    Redis,
+    /// A file of JSON lines, `MOTORHEAD_AUDIT_LOG_PATH`.
+    File { path: PathBuf, file: Mutex<File> },
+}
+
+impl AuditLog {
+    pub fn open_file(path: PathBuf) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        Ok(AuditLog::File {
+            path,
+            file: Mutex::new(File::from_std(file)),
+        })
+    }
+
+    async fn append(&self, state: &AppState, entry: &AuditEntry) -> Result<(), MotorheadError> {
+        match self {
+            AuditLog::Redis => state.store.append_audit(entry).await,
+            AuditLog::File { file, .. } => {
+                let mut line = serde_json::to_vec(entry)
+                    .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+                line.push(b'\n');
+                let mut file = file.lock().await;
+                let written = match file.write_all(&line).await {
+                    Ok(()) => file.flush().await,
+                    Err(e) => Err(e),
+                };
+                written.map_err(|e| MotorheadError::AuditUnavailable(e.to_string()))
+            }
+        }
+    }
+
+    async fn entries(
+        &self,
+        state: &AppState,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>, MotorheadError> {
+        match self {
+            AuditLog::Redis => state.store.audit_entries(since, limit).await,
+            // Scanned from the start: files are for setups small enough not to mind.
+            AuditLog::File { path, .. } => {
+                let file = File::open(path)
+                    .await
+                    .map_err(|e| MotorheadError::AuditUnavailable(e.to_string()))?;
+                let mut lines = BufReader::new(file).lines();
+                let mut entries = Vec::new();
+                while entries.len() < limit {
+                    let Some(line) = lines
+                        .next_line()
+                        .await
+                        .map_err(|e| MotorheadError::AuditUnavailable(e.to_string()))?
+                    else {
+                        break;
+                    };
+                    // A line cut short by a crash mid-write is skipped.
+                    let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) else {
+                        continue;
+                    };
+                    if entry.at >= since {
+                        entries.push(entry);
+                    }
+                }
+                Ok(entries)
+            }
+        }
+    }
+}
+
+/// Records a change about to be made, when the audit log is on. The change must not be made
+/// if this fails, so none go unrecorded.
+pub async fn record(
+    state: &AppState,
+    actor: &str,
+    tenant: &Tenant,
+    action: &str,
+    session_id: Option<&str>,
+    target: Option<String>,
+) -> Result<(), MotorheadError> {
+    let Some(audit) = &state.audit else {
+        return Ok(());
+    };
+    let entry = AuditEntry {
+        at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64,
+        actor: actor.to_string(),
+        tenant: tenant.id().map(str::to_string),
+        action: action.to_string(),
+        session_id: session_id.map(str::to_string),
+        target,
+    };
+    audit.append(state, &entry).await.map_err(|e| match e {
+        MotorheadError::AuditUnavailable(_) => e,
+        e => MotorheadError::AuditUnavailable(e.to_string()),
+    })
+}
+
+/// Records the requests that change something before they're handled: every method but
+/// `GET` and `HEAD`, except retrievals, plus WebSocket connections, whose messages aren't
+/// recorded one by one. Requests whose entry can't be written are refused. Runs after
+/// authentication and rate limiting, so only requests that get to the handlers are recorded.
+pub async fn record_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let state = req
+        .app_data::<web::Data<Arc<AppState>>>()
+        .expect("AppState is registered")
+        .clone();
+
+    let path = req.path();
+    let is_read = matches!(*req.method(), Method::GET | Method::HEAD)
+        || (req.method() == Method::POST && path.ends_with("/retrieval"));
+    if state.audit.is_some() && (!is_read || path.starts_with("/ws/")) {
+        // An invalid tenant is rejected by the handler before anything changes.
+        if let Ok(tenant) = Tenant::extract(req.request()).await {
+            let Actor(actor) = req
+                .extensions()
+                .get::<Actor>()
+                .cloned()
+                .unwrap_or_else(|| Actor::of(None));
+            let route = req.match_pattern().unwrap_or_else(|| path.to_string());
+            let action = format!("{} {}", req.method(), route);
+            let target = req
+                .uri()
+                .path_and_query()
+                .map_or_else(|| path.to_string(), |target| target.to_string());
+            let recorded = record(
+                &state,
+                &actor,
+                &tenant,
+                &action,
+                request_session(&req),
+                Some(target),
+            )
+            .await;
+            if let Err(e) = recorded {
+                tracing::error!(error = %e, action, "Error recording the request in the audit log");
+                return Err(e.into());
+            }
+        }
+    }
+
+    next.call(req).await
+}
+
+/// The audit log from `since` (milliseconds since the Unix epoch), oldest first, for every
+/// tenant. Page through it with the `at` of the last entry: entries of the same millisecond
+/// come again.
+#[get("/admin/audit")]
+pub async fn get_audit(
+    req: HttpRequest,
+    query: web::Query<AuditQuery>,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+    let Some(audit) = &data.audit else {
+        return Err(
+            ApiError::new(ErrorCode::FeatureDisabled, "The audit log is not enabled").into(),
+        );
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let entries = audit.entries(&data, query.since, limit).await?;
+    Ok(read_response(&data, None, AuditResponse { entries }))
+}
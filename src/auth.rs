@@ -0,0 +1,303 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{error, web, HttpMessage, HttpRequest, ResponseError};
+use sha1::{Digest, Sha1};
+use std::sync::Arc;
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::AppState;
+use crate::proxy::SESSION_HEADER;
+use crate::tenant::{is_valid_tenant, KeyTenant};
+
+/// What a key can do, each scope allowing what the ones before it do.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Scope {
+    /// Reading sessions.
+    Read,
+    /// Changing them too.
+    Write,
+    /// Everything, the server's config and metrics included.
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+/// An accepted API key, optionally issued to a single tenant, limited to a scope or to the
+/// sessions whose id starts with a prefix.
+#[derive(Clone)]
+pub struct ApiKey {
+    pub key: String,
+    pub tenant: Option<String>,
+    pub scope: Scope,
+    pub session_prefix: Option<String>,
+}
+
+impl ApiKey {
+    /// Parses a `MOTORHEAD_API_KEYS` entry: `key` or `tenant:key`, optionally followed by
+    /// `;scope=read|write|admin` and `;prefix=<session id prefix>`.
+    pub fn parse(entry: &str) -> Option<ApiKey> {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            return None;
+        }
+
+        let mut parts = entry.split(';');
+        let key = parts.next().unwrap_or_default();
+        let (tenant, key) = match key.split_once(':') {
+            Some((tenant, key)) => {
+                if !is_valid_tenant(tenant) {
+                    panic!("Invalid tenant in $MOTORHEAD_API_KEYS: {}", tenant);
+                }
+                (Some(tenant.to_string()), key)
+            }
+            None => (None, key),
+        };
+
+        let mut api_key = ApiKey {
+            key: key.to_string(),
+            tenant,
+            scope: Scope::Admin,
+            session_prefix: None,
+        };
+        for option in parts {
+            match option.split_once('=') {
+                Some(("scope", "read")) => api_key.scope = Scope::Read,
+                Some(("scope", "write")) => api_key.scope = Scope::Write,
+                Some(("scope", "admin")) => api_key.scope = Scope::Admin,
+                Some(("prefix", prefix)) if !prefix.is_empty() => {
+                    api_key.session_prefix = Some(prefix.to_string())
+                }
+                _ => panic!("Invalid option in $MOTORHEAD_API_KEYS: {}", option),
+            }
+        }
+        Some(api_key)
+    }
+
+    /// Whether the key can act on the session.
+    pub fn allows_session(&self, session_id: &str) -> bool {
+        self.session_prefix
+            .as_deref()
+            .is_none_or(|prefix| session_id.starts_with(prefix))
+    }
+}
+
+/// The session prefix of the key a request authenticated with, if restricted to one. Set by
+/// the auth middleware, for handlers naming sessions elsewhere than in the path.
+#[derive(Clone)]
+pub struct KeySessionPrefix(pub String);
+
+/// Rejects acting on `session_id` with a key restricted to other sessions.
+pub fn check_session_prefix(req: &HttpRequest, session_id: &str) -> actix_web::Result<()> {
+    match req.extensions().get::<KeySessionPrefix>() {
+        Some(KeySessionPrefix(prefix)) if !session_id.starts_with(prefix.as_str()) => {
+            Err(out_of_prefix(prefix))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn out_of_prefix(prefix: &str) -> actix_web::Error {
+    ApiError::new(
+        ErrorCode::Forbidden,
+        format!(
+            "This API key can only access sessions starting with {}",
+            prefix
+        ),
+    )
+    .into()
+}
+
+/// Who a request is made by, for the audit log: `key:{AuthenticatedKey}` for API keys and
+/// `jwt:{subject}` for tokens. Set by the auth middleware.
+#[derive(Clone)]
+pub struct Actor(pub String);
+
+impl Actor {
+    pub fn of(api_key: Option<&ApiKey>) -> Self {
+        match api_key {
+            Some(api_key) if api_key.key.starts_with("jwt:") => Actor(api_key.key.clone()),
+            Some(api_key) => Actor(format!("key:{}", AuthenticatedKey::new(&api_key.key).0)),
+            None => Actor("anonymous".to_string()),
+        }
+    }
+}
+
+/// Identifies the API key a request authenticated with, by a truncated sha1 of the key so the
+/// key itself isn't kept around (e.g. in rate limit bucket names). Set by the auth middleware.
+#[derive(Clone)]
+pub struct AuthenticatedKey(pub String);
+
+impl AuthenticatedKey {
+    pub fn new(key: &str) -> Self {
+        let digest = Sha1::digest(key.as_bytes());
+        AuthenticatedKey(
+            digest
+                .iter()
+                .take(8)
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        )
+    }
+}
+
+/// Paths that stay reachable without credentials, so probes keep working and clients can be
+/// generated from the API's description.
+const PUBLIC_PATHS: &[&str] = &["/", "/healthz", "/readyz", "/openapi.json", "/docs"];
+
+fn unauthorized(message: &str) -> actix_web::Error {
+    let mut response = ApiError::new(ErrorCode::Unauthorized, message).error_response();
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_static("Bearer"),
+    );
+    error::InternalError::from_response("", response).into()
+}
+
+/// Compares in constant time so response timing doesn't leak how much of a key matched.
+fn keys_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Whether requests need credentials, API keys or JWTs being configured.
+pub fn auth_enabled(state: &AppState) -> bool {
+    !state.api_keys.is_empty() || state.jwt.is_some()
+}
+
+/// The configured key matching a request's bearer `token` or, with `MOTORHEAD_JWT_JWKS_URL`,
+/// the key a valid JWT stands for. Errors with why the request isn't authenticated.
+pub async fn authenticate(state: &AppState, token: Option<&str>) -> Result<ApiKey, &'static str> {
+    let token = token.ok_or("Missing bearer token")?;
+    if let Some(api_key) = state
+        .api_keys
+        .iter()
+        .find(|api_key| keys_match(&api_key.key, token))
+    {
+        return Ok(api_key.clone());
+    }
+
+    let Some(jwt) = &state.jwt else {
+        return Err("Invalid API key");
+    };
+    let claims = jwt.validate(token).await?;
+    // Rate limited per subject, the way keys are per key. Tokens can't reach the admin
+    // routes, which stay with API keys.
+    Ok(ApiKey {
+        key: format!("jwt:{}", claims.subject),
+        tenant: claims.tenant,
+        scope: Scope::Write,
+        session_prefix: None,
+    })
+}
+
+/// The scope a request needs: the server's config, metrics, admin and dev routes and bulk
+/// deletes are for admin keys, reads (including retrieval, a POST) for any key, and the rest for
+/// write keys. The WebSocket takes writes, so it's a write route too.
+fn required_scope(req: &ServiceRequest) -> Scope {
+    let path = req.path();
+    if path.starts_with("/admin/")
+        || path.starts_with("/config/")
+        || path.starts_with("/dev/")
+        || path == "/metrics"
+    {
+        return Scope::Admin;
+    }
+    if path.starts_with("/ws/") {
+        return Scope::Write;
+    }
+    // Deleting sessions in bulk.
+    if path == "/sessions" && req.method() == Method::DELETE {
+        return Scope::Admin;
+    }
+    let reads = matches!(*req.method(), Method::GET | Method::HEAD)
+        || (req.method() == Method::POST && path.ends_with("/retrieval"));
+    if reads {
+        Scope::Read
+    } else {
+        Scope::Write
+    }
+}
+
+/// The session a request acts on, for the routes of a single session: the one in its path,
+/// or in the header of the chat completions proxy. Ids are matched as sent, encoded.
+pub fn request_session(req: &ServiceRequest) -> Option<&str> {
+    let path = req.path();
+    if path == "/v1/chat/completions" {
+        return req.headers().get(SESSION_HEADER)?.to_str().ok();
+    }
+    let rest = path
+        .strip_prefix("/sessions/")
+        .or_else(|| path.strip_prefix("/ws/sessions/"))?;
+    let session_id = rest.split('/').next()?;
+    // `/sessions/batch` names its sessions in the body.
+    (!session_id.is_empty() && path != "/sessions/batch").then_some(session_id)
+}
+
+/// Rejects requests without a valid `Authorization: Bearer` key when `MOTORHEAD_API_KEYS` is
+/// configured. With no keys configured every request is let through.
+pub async fn require_api_key(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let state = req
+        .app_data::<web::Data<Arc<AppState>>>()
+        .expect("AppState is registered")
+        .clone();
+
+    if auth_enabled(&state) && !PUBLIC_PATHS.contains(&req.path()) {
+        let api_key = authenticate(&state, bearer_token(&req))
+            .await
+            .map_err(unauthorized)?;
+
+        let scope = required_scope(&req);
+        if api_key.scope < scope {
+            return Err(ApiError::new(
+                ErrorCode::Forbidden,
+                format!("This API key can't access {} routes", scope.as_str()),
+            )
+            .into());
+        }
+        if let Some(prefix) = &api_key.session_prefix {
+            // Routes across sessions, like the session list, are off limits.
+            if !request_session(&req)
+                .is_some_and(|session_id| session_id.starts_with(prefix.as_str()))
+            {
+                return Err(out_of_prefix(prefix));
+            }
+            req.extensions_mut()
+                .insert(KeySessionPrefix(prefix.clone()));
+        }
+
+        req.extensions_mut()
+            .insert(AuthenticatedKey::new(&api_key.key));
+        req.extensions_mut().insert(Actor::of(Some(&api_key)));
+        if let Some(tenant) = &api_key.tenant {
+            req.extensions_mut().insert(KeyTenant(tenant.clone()));
+        }
+    }
+
+    next.call(req).await
+}
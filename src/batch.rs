@@ -0,0 +1,225 @@
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::audit;
+use crate::auth::Actor;
+use crate::delete_intent::confirm_delete;
+use crate::errors::ApiError;
+use crate::memory::{
+    after_append, check_roles, check_ttl, forget_session, prepare_messages, record_append,
+    summary_options,
+};
+use crate::models::{AppState, BatchOperation, BatchRequest, BatchResponse, BatchResult};
+use crate::quota;
+use crate::ratelimit::take_session_write;
+use crate::store::BatchOp;
+use crate::tenant::Tenant;
+use crate::undo;
+
+/// Most operations accepted in one batch.
+const MAX_BATCH_OPERATIONS: usize = 1000;
+
+fn succeeded() -> BatchResult {
+    BatchResult {
+        status: "Ok",
+        error: None,
+    }
+}
+
+fn failed(error: impl ToString) -> BatchResult {
+    BatchResult {
+        status: "Error",
+        error: Some(error.to_string()),
+    }
+}
+
+fn session_id(operation: &BatchOperation) -> &str {
+    match operation {
+        BatchOperation::Append { session_id, .. } | BatchOperation::Delete { session_id, .. } => {
+            session_id
+        }
+    }
+}
+
+/// Applies appends and deletes across sessions in one go. Operations are checked and
+/// answered one by one, appends going through the same checks, moderation, redaction and
+/// hooks as single ones, and recorded in the audit log one by one; the store writes all the
+/// valid ones together.
+#[post("/sessions/batch")]
+pub async fn post_batch(
+    web::Json(batch): web::Json<BatchRequest>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    let summary = summary_options(&req, &data)?;
+    let store = tenant.store(&data);
+    let Actor(actor) = req
+        .extensions()
+        .get::<Actor>()
+        .cloned()
+        .unwrap_or_else(|| Actor::of(None));
+    if batch.operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(ApiError::invalid_request(format!(
+            "A batch takes at most {} operations",
+            MAX_BATCH_OPERATIONS
+        ))
+        .into());
+    }
+
+    // Rejected operations get their result right away, the others once applied.
+    let mut results: Vec<Option<BatchResult>> = Vec::with_capacity(batch.operations.len());
+    let mut operations = Vec::new();
+    for operation in batch.operations {
+        let session_id = session_id(&operation);
+        if session_id.is_empty() {
+            results.push(Some(failed("Missing session_id")));
+            continue;
+        }
+        if let BatchOperation::Append { messages, .. } = &operation {
+            if let Err(e) = check_roles(&data, messages) {
+                results.push(Some(failed(e)));
+                continue;
+            }
+        }
+        if take_session_write(&data, &tenant, session_id)
+            .await
+            .is_some()
+        {
+            results.push(Some(failed("Rate limit exceeded")));
+            continue;
+        }
+
+        let operation = match operation {
+            BatchOperation::Append {
+                session_id,
+                messages,
+                ttl_seconds,
+            } => match async {
+                check_ttl(store.as_ref(), ttl_seconds)?;
+                let messages = prepare_messages(&data, &session_id, messages).await?;
+                quota::enforce(&data, &tenant, store.as_ref(), &session_id, &messages).await?;
+                Ok::<_, actix_web::Error>(messages)
+            }
+            .await
+            {
+                // The hooks dropped them all.
+                Ok(messages) if messages.is_empty() => {
+                    results.push(Some(succeeded()));
+                    continue;
+                }
+                Ok(messages) => BatchOperation::Append {
+                    session_id,
+                    messages,
+                    ttl_seconds,
+                },
+                Err(e) => {
+                    results.push(Some(failed(e)));
+                    continue;
+                }
+            },
+            BatchOperation::Delete {
+                session_id,
+                confirm,
+            } => match confirm_delete(&data, store.as_ref(), &session_id, confirm.as_deref()).await
+            {
+                Ok(()) => BatchOperation::Delete {
+                    session_id,
+                    confirm,
+                },
+                Err(e) => {
+                    results.push(Some(failed(e)));
+                    continue;
+                }
+            },
+        };
+        let action = match &operation {
+            BatchOperation::Append { .. } => "batch append",
+            BatchOperation::Delete { .. } => "batch delete",
+        };
+        let recorded = audit::record(
+            &data,
+            &actor,
+            &tenant,
+            action,
+            Some(self::session_id(&operation)),
+            None,
+        )
+        .await;
+        if let Err(e) = recorded {
+            results.push(Some(failed(e)));
+            continue;
+        }
+        results.push(None);
+        operations.push(operation);
+    }
+
+    let ops: Vec<BatchOp> = operations
+        .iter()
+        .map(|operation| match operation {
+            BatchOperation::Append {
+                session_id,
+                messages,
+                ..
+            } => BatchOp::Append {
+                session_id,
+                messages,
+            },
+            BatchOperation::Delete { session_id, .. } => BatchOp::Delete { session_id },
+        })
+        .collect();
+    // Each session as it was before the batch, for it to be undone at once.
+    let mut before = HashMap::new();
+    for operation in &operations {
+        if let BatchOperation::Append { session_id, .. } = operation {
+            if !before.contains_key(session_id.as_str()) {
+                let snapshot = undo::snapshot(&data, &tenant, session_id).await;
+                before.insert(session_id.as_str(), snapshot);
+            }
+        }
+    }
+    let lengths = store.apply_batch(&ops).await?;
+    for (session_id, snapshot) in before {
+        record_append(&data, &tenant, session_id, snapshot);
+    }
+
+    let mut applied = operations.into_iter().zip(lengths);
+    for result in results.iter_mut().filter(|result| result.is_none()) {
+        let (operation, len) = applied.next().expect("one length per operation");
+        let outcome = match operation {
+            BatchOperation::Append {
+                session_id,
+                messages,
+                ttl_seconds,
+            } => {
+                after_append(
+                    &data,
+                    &tenant,
+                    &session_id,
+                    messages,
+                    len.unwrap_or_default(),
+                    ttl_seconds,
+                    summary.clone(),
+                )
+                .await
+            }
+            BatchOperation::Delete { session_id, .. } => {
+                forget_session(&data, &tenant, &session_id);
+                Ok(())
+            }
+        };
+
+        *result = Some(match outcome {
+            Ok(()) => succeeded(),
+            Err(e) => failed(e),
+        });
+    }
+
+    let response = BatchResponse {
+        results: results.into_iter().flatten().collect(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
@@ -0,0 +1,244 @@
+use actix_web::{get, web, HttpRequest, Responder};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::check_admin;
+use crate::errors::{ApiError, ErrorCode};
+use crate::llm::{Completion, CompletionRequest, LlmClient};
+use crate::metrics;
+use crate::models::{AppState, MotorheadError};
+use crate::response::read_response;
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls go through.
+    Closed,
+    /// Calls are refused until the cooldown is over.
+    Open,
+    /// The cooldown is over: one call goes through to see whether the LLM is back.
+    HalfOpen,
+}
+
+#[derive(Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    /// Milliseconds since the Unix epoch, while open.
+    opened_at: Option<u64>,
+    trial_in_flight: bool,
+}
+
+/// Refuses LLM calls for `cooldown` once `failure_threshold` failed in a row, so compactions
+/// fail fast during an outage instead of each waiting on its own timeouts and retries. Set
+/// with `MOTORHEAD_LLM_CIRCUIT_FAILURES`. Each instance keeps its own.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    breaker: Mutex<Breaker>,
+}
+
+#[derive(Serialize)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    /// When the circuit opened, in milliseconds since the Unix epoch.
+    pub opened_at: Option<u64>,
+    /// When a call will be let through again.
+    pub retry_at: Option<u64>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// A call let through by the breaker, whose outcome is reported with `record`. A trial
+/// dropped before then, e.g. with its compaction, lets the next call be the trial.
+struct Permit<'a> {
+    circuit: &'a CircuitBreaker,
+    trial: bool,
+}
+
+impl Permit<'_> {
+    fn record(mut self, success: bool) {
+        let mut breaker = self.circuit.breaker.lock().unwrap();
+        if success {
+            *breaker = Breaker::default();
+            metrics::LLM_CIRCUIT_OPEN.set(0);
+        } else {
+            breaker.consecutive_failures += 1;
+            let reopen = self.trial
+                || (breaker.opened_at.is_none()
+                    && breaker.consecutive_failures >= self.circuit.failure_threshold);
+            if reopen {
+                if breaker.opened_at.is_none() {
+                    tracing::warn!(
+                        consecutive_failures = breaker.consecutive_failures,
+                        cooldown_seconds = self.circuit.cooldown.as_secs(),
+                        "Opening the LLM circuit"
+                    );
+                }
+                breaker.opened_at = Some(now_ms());
+                metrics::LLM_CIRCUIT_OPEN.set(1);
+            }
+            breaker.trial_in_flight = false;
+        }
+        self.trial = false;
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.trial {
+            self.circuit.breaker.lock().unwrap().trial_in_flight = false;
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            breaker: Mutex::new(Breaker::default()),
+        }
+    }
+
+    fn state_of(&self, breaker: &Breaker, now: u64) -> CircuitState {
+        match breaker.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if now < opened_at + self.cooldown.as_millis() as u64 => {
+                CircuitState::Open
+            }
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        let breaker = self.breaker.lock().unwrap();
+        self.state_of(&breaker, now_ms()) == CircuitState::Open
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let breaker = self.breaker.lock().unwrap();
+        CircuitStatus {
+            state: self.state_of(&breaker, now_ms()),
+            consecutive_failures: breaker.consecutive_failures,
+            opened_at: breaker.opened_at,
+            retry_at: breaker
+                .opened_at
+                .map(|opened_at| opened_at + self.cooldown.as_millis() as u64),
+        }
+    }
+
+    fn acquire(&self) -> Result<Permit<'_>, MotorheadError> {
+        let mut breaker = self.breaker.lock().unwrap();
+        let trial = match self.state_of(&breaker, now_ms()) {
+            CircuitState::Closed => false,
+            CircuitState::HalfOpen if !breaker.trial_in_flight => {
+                breaker.trial_in_flight = true;
+                true
+            }
+            CircuitState::Open | CircuitState::HalfOpen => {
+                metrics::LLM_CIRCUIT_REJECTED.inc();
+                return Err(MotorheadError::LlmCircuitOpen);
+            }
+        };
+        Ok(Permit {
+            circuit: self,
+            trial,
+        })
+    }
+}
+
+/// Wraps the configured LLM client with `MOTORHEAD_LLM_TIMEOUT_SECONDS` and the circuit
+/// breaker, whichever are set. Only completions count towards the breaker, as failures worth
+/// retrying; rejected requests show the provider is up.
+pub struct GuardedLlm {
+    inner: Arc<dyn LlmClient>,
+    timeout: Option<Duration>,
+    circuit: Option<Arc<CircuitBreaker>>,
+}
+
+impl GuardedLlm {
+    pub fn new(
+        inner: Arc<dyn LlmClient>,
+        timeout: Option<Duration>,
+        circuit: Option<Arc<CircuitBreaker>>,
+    ) -> Self {
+        GuardedLlm {
+            inner,
+            timeout,
+            circuit,
+        }
+    }
+
+    async fn with_timeout<T>(
+        &self,
+        call: impl std::future::Future<Output = Result<T, MotorheadError>>,
+    ) -> Result<T, MotorheadError> {
+        let Some(timeout) = self.timeout else {
+            return call.await;
+        };
+        match tokio::time::timeout(timeout, call).await {
+            Ok(result) => result,
+            Err(_) => {
+                metrics::LLM_TIMEOUTS.inc();
+                Err(MotorheadError::LlmUnavailable(format!(
+                    "No response within {}s",
+                    timeout.as_secs()
+                )))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl LlmClient for GuardedLlm {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, MotorheadError> {
+        let permit = self
+            .circuit
+            .as_deref()
+            .map(CircuitBreaker::acquire)
+            .transpose()?;
+        let result = self.with_timeout(self.inner.complete(request)).await;
+        if let Some(permit) = permit {
+            permit.record(!matches!(result, Err(MotorheadError::LlmUnavailable(_))));
+        }
+        result
+    }
+
+    async fn ping(&self) -> Result<(), MotorheadError> {
+        self.with_timeout(self.inner.ping()).await
+    }
+
+    fn model<'a>(&'a self, requested: Option<&'a str>) -> &'a str {
+        self.inner.model(requested)
+    }
+
+    fn chat_completions_request(&self) -> Option<reqwest::RequestBuilder> {
+        self.inner.chat_completions_request()
+    }
+}
+
+/// The state of the LLM circuit breaker of this instance.
+#[get("/admin/llm/circuit")]
+pub async fn get_llm_circuit(
+    req: HttpRequest,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+    let Some(circuit) = &data.llm_circuit else {
+        return Err(ApiError::new(
+            ErrorCode::FeatureDisabled,
+            "The LLM circuit breaker is not enabled",
+        )
+        .into());
+    };
+
+    Ok(read_response(&data, None, circuit.status()))
+}
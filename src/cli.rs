@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use crate::archive::snapshot;
+use crate::audit;
+use crate::memory::delete_session;
+use crate::models::{AppState, MotorheadError};
+use crate::reducer::run_compaction;
+use crate::tenant::Tenant;
+
+pub const USAGE: &str = "Usage: motorhead [--config <file>] [--tenant <id>] [command]
+
+Commands:
+  serve                   Run the server (the default)
+  sessions list           List the sessions, most recently active first
+  sessions dump <id>      Print the session like GET /sessions/{id}/export does
+  sessions delete <id>    Delete the session
+  compact <id>            Compact the session and print its new context
+  migrate-keys            Move the sessions stored before MOTORHEAD_KEY_PREFIX was set under it
+
+Options:
+  --config <file>         Read the env vars that aren't set from a TOML file
+  --tenant <id>           Act on the tenant's sessions, for the commands other than serve
+
+The commands other than serve use the storage configured for the server.";
+
+/// Sessions listed at a time by `sessions list`.
+const LIST_PAGE_SIZE: usize = 1000;
+
+pub enum Command {
+    Serve,
+    ListSessions,
+    DumpSession(String),
+    DeleteSession(String),
+    Compact(String),
+    MigrateKeys,
+    Help,
+}
+
+pub struct Cli {
+    pub config: Option<String>,
+    pub tenant: Option<String>,
+    pub command: Command,
+}
+
+/// Parses the arguments, without the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+    let mut config = None;
+    let mut tenant = None;
+    let mut words = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                config = Some(args.next().ok_or("--config needs a value")?);
+            }
+            "--tenant" => {
+                tenant = Some(args.next().ok_or("--tenant needs a value")?);
+            }
+            "-h" | "--help" => words.insert(0, "help".to_string()),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option: {}", flag)),
+            _ => words.push(arg),
+        }
+    }
+
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let command = match words.as_slice() {
+        [] | ["serve"] => Command::Serve,
+        ["help", ..] => Command::Help,
+        ["sessions", "list"] => Command::ListSessions,
+        ["sessions", "dump", id] => Command::DumpSession(id.to_string()),
+        ["sessions", "delete", id] => Command::DeleteSession(id.to_string()),
+        ["compact", id] => Command::Compact(id.to_string()),
+        ["migrate-keys"] => Command::MigrateKeys,
+        _ => return Err(format!("Unknown command: {}", words.join(" "))),
+    };
+
+    Ok(Cli {
+        config,
+        tenant,
+        command,
+    })
+}
+
+/// Runs a command other than `serve` against the store, printing its output to stdout.
+pub async fn run(state: &Arc<AppState>, cli: Cli) -> Result<(), MotorheadError> {
+    let tenant = Tenant::new(cli.tenant);
+    let store = tenant.store(state);
+
+    match cli.command {
+        Command::Serve | Command::Help => {}
+        Command::ListSessions => {
+            let mut offset = 0;
+            loop {
+                let page = store.list_sessions(offset, LIST_PAGE_SIZE).await?;
+                let page_len = page.len();
+                for (session_id, last_activity) in page {
+                    println!("{}\t{}", session_id, last_activity);
+                }
+                if page_len < LIST_PAGE_SIZE {
+                    break;
+                }
+                offset += page_len;
+            }
+        }
+        Command::DumpSession(session_id) => {
+            let (snapshot, messages) = snapshot(store.as_ref(), &session_id).await?;
+            let serialization =
+                |e: serde_json::Error| MotorheadError::SerializationError(e.to_string());
+            let mut dump = serde_json::to_value(snapshot).map_err(serialization)?;
+            dump["messages"] = serde_json::to_value(messages).map_err(serialization)?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&dump).map_err(serialization)?
+            );
+        }
+        Command::DeleteSession(session_id) => {
+            audit::record(
+                state,
+                "cli",
+                &tenant,
+                "sessions delete",
+                Some(&session_id),
+                None,
+            )
+            .await?;
+            delete_session(state, &tenant, &session_id).await?;
+        }
+        Command::Compact(session_id) => {
+            let options = state.runtime().summary_options;
+            let context = run_compaction(state, &tenant, &session_id, &options)
+                .await
+                .expect("Nothing else runs compactions in this process")?;
+            println!("{}", context);
+        }
+        Command::MigrateKeys => {
+            let moved = store.migrate_legacy_sessions().await?;
+            println!("Moved {} sessions", moved);
+        }
+    }
+
+    Ok(())
+}
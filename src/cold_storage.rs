@@ -0,0 +1,355 @@
+use flate2::write::{GzDecoder, GzEncoder};
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::metrics;
+use crate::models::{AppState, ColdSegment, MemoryMessage, MotorheadError};
+use crate::sigv4::{self, hex, Credentials};
+use crate::store::MemoryStore;
+use crate::telemetry;
+use crate::tenant::{listed_tenants, Tenant};
+
+/// Sessions listed at a time while looking for archived messages to move.
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// Messages stored per object, so a page of the history reads one or two of them.
+const SEGMENT_MESSAGES: usize = 1000;
+
+/// An S3-compatible bucket, addressed path-style (`{endpoint}/{bucket}/{key}`), which AWS,
+/// MinIO and the XML API of Google Cloud Storage all take.
+pub struct Bucket {
+    pub endpoint: String,
+    pub name: String,
+    pub region: String,
+    pub credentials: Credentials,
+}
+
+/// Moves the archived messages older than `after_days` from the history in Redis to gzipped
+/// NDJSON objects in `bucket`, checking every `interval`. Set with
+/// `MOTORHEAD_COLD_STORAGE_BUCKET`.
+pub struct ColdStorage {
+    http: reqwest::Client,
+    bucket: Bucket,
+    prefix: String,
+    pub after_days: u64,
+    pub interval: Duration,
+}
+
+/// Percent-encodes an object key for its URL, leaving the `/` between its parts.
+fn encode_key(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn unavailable(e: impl std::fmt::Display) -> MotorheadError {
+    MotorheadError::ColdStorageUnavailable(e.to_string())
+}
+
+impl ColdStorage {
+    pub fn new(bucket: Bucket, prefix: String, after_days: u64, interval: Duration) -> Self {
+        ColdStorage {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()
+                .expect("The HTTP client builds"),
+            bucket,
+            prefix,
+            after_days,
+            interval,
+        }
+    }
+
+    /// Sends a request for the object, signed with AWS Signature Version 4.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, MotorheadError> {
+        let url = reqwest::Url::parse(&format!(
+            "{}/{}/{}",
+            self.bucket.endpoint.trim_end_matches('/'),
+            self.bucket.name,
+            encode_key(key)
+        ))
+        .map_err(unavailable)?;
+        let headers = sigv4::sign(
+            &self.bucket.credentials,
+            &self.bucket.region,
+            "s3",
+            method.as_str(),
+            &url,
+            &body,
+        )
+        .map_err(unavailable)?;
+
+        let mut request = self.http.request(method, url);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.body(body).send().await.map_err(unavailable)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(unavailable(format!("{} {}", status, body.trim())));
+        }
+        Ok(response)
+    }
+
+    /// The key of a new object for the session's messages, under the tenant and a sha1 of the
+    /// session id, so a session's objects share a prefix whatever its id.
+    fn object_key(&self, tenant: &Tenant, session_id: &str, first_created_at: u64) -> String {
+        format!(
+            "{}{}/{}/{}-{}.ndjson.gz",
+            self.prefix,
+            tenant.id().unwrap_or("_default"),
+            hex(&Sha1::digest(session_id.as_bytes())),
+            first_created_at,
+            uuid::Uuid::new_v4().simple()
+        )
+    }
+
+    async fn put_messages(
+        &self,
+        key: &str,
+        messages: &[MemoryMessage],
+    ) -> Result<(), MotorheadError> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for message in messages {
+            let line = serde_json::to_vec(message)
+                .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+            encoder
+                .write_all(&line)
+                .and_then(|()| encoder.write_all(b"\n"))
+                .map_err(unavailable)?;
+        }
+        let body = encoder.finish().map_err(unavailable)?;
+
+        self.send(reqwest::Method::PUT, key, body).await?;
+        Ok(())
+    }
+
+    async fn get_messages(&self, key: &str) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let response = self.send(reqwest::Method::GET, key, Vec::new()).await?;
+        let body = response.bytes().await.map_err(unavailable)?;
+
+        let mut decoder = GzDecoder::new(Vec::new());
+        decoder.write_all(&body).map_err(unavailable)?;
+        let body = decoder.finish().map_err(unavailable)?;
+
+        body.split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                serde_json::from_slice(line)
+                    .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+            })
+            .collect()
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<(), MotorheadError> {
+        self.send(reqwest::Method::DELETE, key, Vec::new()).await?;
+        Ok(())
+    }
+}
+
+/// Like `MemoryStore::get_history`, reading the messages moved to cold storage before those
+/// still in the history.
+pub async fn get_history(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    offset: usize,
+    limit: usize,
+) -> Result<Vec<MemoryMessage>, MotorheadError> {
+    let Some(cold_storage) = &state.cold_storage else {
+        return store.get_history(session_id, offset, limit).await;
+    };
+
+    let mut messages = Vec::new();
+    let mut skip = offset;
+    for segment in store.get_cold_segments(session_id).await? {
+        if messages.len() == limit {
+            return Ok(messages);
+        }
+        if skip >= segment.messages {
+            skip -= segment.messages;
+            continue;
+        }
+        let stored = cold_storage.get_messages(&segment.key).await?;
+        messages.extend(stored.into_iter().skip(skip).take(limit - messages.len()));
+        skip = 0;
+    }
+    if messages.len() < limit {
+        let rest = store
+            .get_history(session_id, skip, limit - messages.len())
+            .await?;
+        messages.extend(rest);
+    }
+
+    Ok(messages)
+}
+
+/// The session's objects in cold storage, to delete along with it with `delete_segments`.
+pub async fn cold_segments(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+) -> Result<Vec<ColdSegment>, MotorheadError> {
+    if state.cold_storage.is_none() {
+        return Ok(Vec::new());
+    }
+    store.get_cold_segments(session_id).await
+}
+
+/// Deletes the objects of a deleted session. Objects that fail to be are left behind, for a
+/// lifecycle rule of the bucket to expire.
+pub async fn delete_segments(state: &AppState, session_id: &str, segments: &[ColdSegment]) {
+    let Some(cold_storage) = &state.cold_storage else {
+        return;
+    };
+    for segment in segments {
+        if let Err(e) = cold_storage.delete_object(&segment.key).await {
+            tracing::warn!(
+                session_id,
+                key = segment.key,
+                error = telemetry::error_message(&e),
+                "Error deleting a cold storage object"
+            );
+        }
+    }
+}
+
+/// Moves up to `max` of the session's archived messages created before `cutoff_ms` to cold
+/// storage, oldest first and a segment at a time. Messages without a timestamp are moved
+/// along with the ones around them. Returns how many were moved.
+pub async fn move_session(
+    cold_storage: &ColdStorage,
+    store: &dyn MemoryStore,
+    tenant: &Tenant,
+    session_id: &str,
+    cutoff_ms: u64,
+    max: usize,
+) -> Result<usize, MotorheadError> {
+    let is_old = |message: &MemoryMessage| message.created_at.is_none_or(|at| at < cutoff_ms);
+    let mut moved = 0;
+    loop {
+        // Most sessions have nothing old enough, which the oldest message tells.
+        let oldest = store.get_history(session_id, 0, 1).await?;
+        if moved == max || !oldest.first().is_some_and(is_old) {
+            return Ok(moved);
+        }
+
+        let limit = SEGMENT_MESSAGES.min(max - moved);
+        let mut messages = store.get_history(session_id, 0, limit).await?;
+        let old = messages
+            .iter()
+            .position(|message| !is_old(message))
+            .unwrap_or(messages.len());
+        messages.truncate(old);
+        let first_created_at = messages.iter().find_map(|message| message.created_at);
+        let last_created_at = messages.iter().rev().find_map(|message| message.created_at);
+        let segment = ColdSegment {
+            key: cold_storage.object_key(tenant, session_id, first_created_at.unwrap_or(0)),
+            messages: messages.len(),
+            first_created_at,
+            last_created_at,
+        };
+
+        cold_storage.put_messages(&segment.key, &messages).await?;
+        if !store.move_to_cold(session_id, &segment).await? {
+            // The history was deleted meanwhile.
+            cold_storage.delete_object(&segment.key).await?;
+            return Ok(moved);
+        }
+        moved += segment.messages;
+        metrics::COLD_STORAGE_MESSAGES.inc_by(segment.messages as u64);
+        if segment.messages < limit {
+            return Ok(moved);
+        }
+    }
+}
+
+async fn move_tenant(
+    state: &AppState,
+    cold_storage: &ColdStorage,
+    tenant: &Tenant,
+    cutoff_ms: u64,
+) {
+    let store = tenant.store(state);
+    let mut offset = 0;
+    loop {
+        let page = match store.list_sessions(offset, LIST_PAGE_SIZE).await {
+            Ok(page) => page,
+            Err(e) => {
+                tracing::error!(
+                    tenant = tenant.id(),
+                    error = telemetry::error_message(&e),
+                    "Error listing sessions for cold storage"
+                );
+                return;
+            }
+        };
+        let page_len = page.len();
+        for (session_id, _) in page {
+            let moved = move_session(
+                cold_storage,
+                store.as_ref(),
+                tenant,
+                &session_id,
+                cutoff_ms,
+                usize::MAX,
+            )
+            .await;
+            match moved {
+                Ok(0) => {}
+                Ok(messages) => tracing::info!(
+                    tenant = tenant.id(),
+                    session_id,
+                    messages,
+                    "Moved archived messages to cold storage"
+                ),
+                Err(e) => tracing::error!(
+                    tenant = tenant.id(),
+                    session_id,
+                    error = telemetry::error_message(&e),
+                    "Error moving archived messages to cold storage"
+                ),
+            }
+        }
+        if page_len < LIST_PAGE_SIZE {
+            return;
+        }
+        offset += page_len;
+    }
+}
+
+pub async fn run_cold_storage(state: Arc<AppState>) {
+    let Some(cold_storage) = &state.cold_storage else {
+        return;
+    };
+    let mut interval = tokio::time::interval(cold_storage.interval);
+
+    loop {
+        interval.tick().await;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let cutoff_ms = now.saturating_sub(cold_storage.after_days * 24 * 60 * 60 * 1000);
+
+        for tenant in listed_tenants(&state) {
+            move_tenant(&state, cold_storage, &tenant, cutoff_ms).await;
+        }
+    }
+}
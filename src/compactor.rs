@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::models::{AppState, MemoryMessage, MotorheadError, SummaryOptions, TokenUsage};
+use crate::reducer::summarize_in_chunks;
+use crate::retrieval::index_messages;
+use crate::store::MemoryStore;
+
+/// What a compaction does with the messages it takes out of the window, picked with
+/// `MOTORHEAD_COMPACTION_STRATEGY` or a session's `compaction_strategy`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionStrategy {
+    /// Folds them into the context with the LLM.
+    Summarize,
+    /// Drops them, leaving the context as is.
+    Drop,
+    /// Embeds them into the vector store, for retrieval to find, leaving the context as is.
+    Archive,
+}
+
+impl CompactionStrategy {
+    pub fn parse(strategy: &str) -> Option<Self> {
+        match strategy {
+            "summarize" => Some(CompactionStrategy::Summarize),
+            "drop" => Some(CompactionStrategy::Drop),
+            "archive" => Some(CompactionStrategy::Archive),
+            _ => None,
+        }
+    }
+
+    pub fn compactor(&self) -> &'static dyn Compactor {
+        match self {
+            CompactionStrategy::Summarize => &SummarizeCompactor,
+            CompactionStrategy::Drop => &DropCompactor,
+            CompactionStrategy::Archive => &ArchiveCompactor,
+        }
+    }
+}
+
+/// What a compactor gets to work with.
+pub struct CompactorInput<'a> {
+    pub state: &'a Arc<AppState>,
+    pub store: &'a Arc<dyn MemoryStore>,
+    pub session_id: &'a str,
+    pub context: Option<String>,
+    /// The messages taken out of the window, newest first, without the pinned ones.
+    pub messages: Vec<MemoryMessage>,
+    /// The transcript lines of `messages`, newest first.
+    pub lines: Vec<String>,
+    pub options: &'a SummaryOptions,
+}
+
+/// Turns the messages a compaction takes out of the window into the session's new context.
+#[async_trait]
+pub trait Compactor: Send + Sync {
+    /// The new context. The LLM tokens used are added to `usage`.
+    async fn compact(
+        &self,
+        input: CompactorInput<'_>,
+        usage: &mut TokenUsage,
+    ) -> Result<String, MotorheadError>;
+
+    /// Whether it calls the LLM, and so counts towards the token budget. Only those compactions
+    /// update the long-term context, entities and segments.
+    fn uses_llm(&self) -> bool {
+        false
+    }
+}
+
+pub struct SummarizeCompactor;
+
+#[async_trait]
+impl Compactor for SummarizeCompactor {
+    async fn compact(
+        &self,
+        input: CompactorInput<'_>,
+        usage: &mut TokenUsage,
+    ) -> Result<String, MotorheadError> {
+        let prompt_template = input.state.runtime().summary_prompt;
+        let completion = summarize_in_chunks(
+            input.state,
+            input.store.as_ref(),
+            input.session_id,
+            &prompt_template,
+            input.context,
+            input.lines,
+            input.options,
+        )
+        .await?;
+        *usage += completion.usage;
+        Ok(completion.content)
+    }
+
+    fn uses_llm(&self) -> bool {
+        true
+    }
+}
+
+pub struct DropCompactor;
+
+#[async_trait]
+impl Compactor for DropCompactor {
+    async fn compact(
+        &self,
+        input: CompactorInput<'_>,
+        _usage: &mut TokenUsage,
+    ) -> Result<String, MotorheadError> {
+        Ok(input.context.unwrap_or_default())
+    }
+}
+
+pub struct ArchiveCompactor;
+
+#[async_trait]
+impl Compactor for ArchiveCompactor {
+    async fn compact(
+        &self,
+        input: CompactorInput<'_>,
+        _usage: &mut TokenUsage,
+    ) -> Result<String, MotorheadError> {
+        // Messages indexed as they were appended are in the vector store already.
+        if !indexes_appends(input.state) {
+            index_messages(
+                input.session_id.to_string(),
+                Arc::clone(input.state),
+                Arc::clone(input.store),
+                input.messages,
+            )
+            .await?;
+        }
+        Ok(input.context.unwrap_or_default())
+    }
+}
+
+/// Whether appended messages are embedded right away. With `archive` as the default
+/// strategy, they're embedded when compacted instead.
+pub fn indexes_appends(state: &AppState) -> bool {
+    state.retrieval_enabled && state.compaction_strategy != CompactionStrategy::Archive
+}
@@ -0,0 +1,46 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::models::MotorheadError;
+
+/// Starts compressed contents, followed by the base64 of a zstd frame. Contents without it
+/// were stored as they are.
+const MARKER: char = '\u{1}';
+
+/// Compresses message contents longer than `threshold` bytes with zstd before they're
+/// written to Redis. Set with `MOTORHEAD_COMPRESSION_THRESHOLD_BYTES`.
+pub struct Compression {
+    pub threshold: usize,
+    pub level: i32,
+}
+
+impl Compression {
+    /// The content as stored: compressed if it's long enough and compressing makes it
+    /// shorter.
+    pub fn compress(&self, content: &str) -> Result<Option<String>, MotorheadError> {
+        if content.len() <= self.threshold {
+            return Ok(None);
+        }
+        let compressed = zstd::encode_all(content.as_bytes(), self.level)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+
+        let stored = format!("{}{}", MARKER, STANDARD.encode(compressed));
+        Ok((stored.len() < content.len()).then_some(stored))
+    }
+}
+
+/// A content read back, decompressed if it was compressed. Works with compression off, so
+/// turning it off leaves what was compressed readable. Contents that only happen to start
+/// with the marker are returned as they are.
+pub fn decompress(content: String) -> String {
+    let Some(encoded) = content.strip_prefix(MARKER) else {
+        return content;
+    };
+
+    STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|compressed| zstd::decode_all(compressed.as_slice()).ok())
+        .and_then(|decompressed| String::from_utf8(decompressed).ok())
+        .unwrap_or(content)
+}
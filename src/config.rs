@@ -0,0 +1,150 @@
+use actix_web::{get, patch, put, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use std::sync::Arc;
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{
+    AckResponse, AdminConfig, AdminConfigPatch, AppState, MotorheadError, SummaryPrompt,
+};
+use crate::ratelimit::RateLimit;
+use crate::shared_config::{self, summary_fields};
+use crate::tenant::KeyTenant;
+
+pub fn check_summary_prompt(prompt: &str) -> actix_web::Result<()> {
+    if !prompt.contains("{messages}") {
+        return Err(ApiError::invalid_request(
+            "The prompt must contain the {messages} placeholder",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[get("/config/summary-prompt")]
+pub async fn get_summary_prompt(
+    req: HttpRequest,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+    let prompt = data.runtime().summary_prompt;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(SummaryPrompt { prompt }))
+}
+
+#[put("/config/summary-prompt")]
+pub async fn put_summary_prompt(
+    req: HttpRequest,
+    web::Json(summary_prompt): web::Json<SummaryPrompt>,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+    check_summary_prompt(&summary_prompt.prompt)?;
+
+    let fields = [("summary_prompt", Some(summary_prompt.prompt.clone()))];
+    if !shared_config::update(&data, &fields).await? {
+        data.runtime.write().unwrap().summary_prompt = summary_prompt.prompt;
+    }
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+/// The runtime settings are shared by every tenant, so keys issued to one can't touch them.
+pub fn check_admin(req: &HttpRequest) -> actix_web::Result<()> {
+    if req.extensions().get::<KeyTenant>().is_some() {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "Tenant API keys can't access the server config",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[get("/admin/config")]
+pub async fn get_admin_config(
+    req: HttpRequest,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(AdminConfig::from(data.runtime())))
+}
+
+/// Applies the changes all at once, or none of them if one is invalid, and returns the
+/// resulting settings. They last until restart.
+#[patch("/admin/config")]
+pub async fn patch_admin_config(
+    req: HttpRequest,
+    web::Json(patch): web::Json<AdminConfigPatch>,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+
+    if patch.window_size.is_some_and(|window_size| window_size < 1) {
+        return Err(ApiError::invalid_request("window_size must be at least 1").into());
+    }
+    if let Some(prompt) = &patch.summary_prompt {
+        check_summary_prompt(prompt)?;
+    }
+    if matches!(patch.session_ttl_seconds, Some(Some(_))) && !data.store.expires_sessions() {
+        return Err(MotorheadError::Unsupported("session TTL").into());
+    }
+    if patch.idempotency_ttl_seconds == Some(0) {
+        return Err(ApiError::invalid_request("idempotency_ttl_seconds must be at least 1").into());
+    }
+
+    // Like the env vars, a limit of 0 turns the rate limiting off.
+    let rate_limit = |per_minute: Option<u32>| {
+        per_minute
+            .filter(|per_minute| *per_minute > 0)
+            .map(|per_minute| RateLimit { per_minute })
+    };
+
+    // The summarization settings go through the shared config when it's enabled.
+    let mut fields = patch
+        .summary
+        .as_ref()
+        .map(summary_fields)
+        .unwrap_or_default();
+    if let Some(prompt) = &patch.summary_prompt {
+        fields.push(("summary_prompt", Some(prompt.clone())));
+    }
+    let shared = !fields.is_empty() && shared_config::update(&data, &fields).await?;
+
+    let config = {
+        let mut runtime = data.runtime.write().unwrap();
+        if let Some(window_size) = patch.window_size {
+            runtime.window_size = window_size;
+        }
+        if let (Some(summary_options), false) = (patch.summary, shared) {
+            runtime.summary_options = summary_options;
+        }
+        if let (Some(prompt), false) = (patch.summary_prompt, shared) {
+            runtime.summary_prompt = prompt;
+        }
+        if let Some(ttl_seconds) = patch.session_ttl_seconds {
+            runtime.session_ttl_seconds = ttl_seconds;
+        }
+        if let Some(ttl_seconds) = patch.idempotency_ttl_seconds {
+            runtime.idempotency_ttl_seconds = ttl_seconds;
+        }
+        if let Some(per_minute) = patch.session_writes_per_minute {
+            runtime.session_write_rate_limit = rate_limit(per_minute);
+        }
+        if let Some(per_minute) = patch.api_key_requests_per_minute {
+            runtime.api_key_rate_limit = rate_limit(per_minute);
+        }
+        runtime.clone()
+    };
+    tracing::info!("Runtime config updated");
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(AdminConfig::from(config)))
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+
+/// Sets the env vars of the config file passed with `--config` that aren't set already, so
+/// the environment overrides the file and the settings are read the same way either way.
+pub fn load(path: &str) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    for (name, value) in parse(&source)? {
+        if env::var_os(&name).is_none() {
+            env::set_var(name, value);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a flat TOML file of env vars, named in any case:
+///
+/// ```toml
+/// redis_url = "redis://localhost:6379"
+/// motorhead_max_window_size = 12
+/// motorhead_api_keys = ["key-1", "key-2"]
+/// ```
+///
+/// Values are strings, numbers, booleans or one-line arrays of them, which become
+/// comma-separated lists. Tables and multi-line values aren't supported.
+fn parse(source: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut vars = BTreeMap::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |message: &str| format!("line {}: {}", line_number, message);
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(error("tables aren't supported"));
+        }
+
+        let (key, rest) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected key = value"))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(error("keys must be env var names"));
+        }
+
+        let mut cursor = Cursor::new(rest);
+        let value = cursor.value().map_err(|e| error(&e))?;
+        cursor.skip_whitespace();
+        if !matches!(cursor.peek(), None | Some('#')) {
+            return Err(error("unexpected characters after the value"));
+        }
+
+        if vars.insert(key.to_ascii_uppercase(), value).is_some() {
+            return Err(error("duplicate key"));
+        }
+    }
+
+    Ok(vars)
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(rest: &'a str) -> Self {
+        Cursor { rest }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn value(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('[') => {
+                self.next();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some(']') {
+                        self.next();
+                        return Ok(items.join(","));
+                    }
+                    items.push(self.scalar()?);
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(items.join(",")),
+                        _ => return Err("expected , or ] in the array".to_string()),
+                    }
+                }
+            }
+            _ => self.scalar(),
+        }
+    }
+
+    fn scalar(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('"') => {
+                self.next();
+                self.basic_string()
+            }
+            Some('\'') => {
+                self.next();
+                let (value, rest) = self.rest.split_once('\'').ok_or("unterminated string")?;
+                self.rest = rest;
+                Ok(value.to_string())
+            }
+            _ => {
+                let end = self
+                    .rest
+                    .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+                    .unwrap_or(self.rest.len());
+                let (token, rest) = self.rest.split_at(end);
+                self.rest = rest;
+                match token {
+                    "true" | "false" => Ok(token.to_string()),
+                    _ if token
+                        .starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+')
+                        && token.replace('_', "").parse::<f64>().is_ok() =>
+                    {
+                        Ok(token.replace('_', ""))
+                    }
+                    "" => Err("expected a value".to_string()),
+                    _ => Err(format!("invalid value {}, strings must be quoted", token)),
+                }
+            }
+        }
+    }
+
+    /// The rest of a `"` string, with its escapes resolved.
+    fn basic_string(&mut self) -> Result<String, String> {
+        let mut value = String::new();
+        loop {
+            match self.next().ok_or("unterminated string")? {
+                '"' => return Ok(value),
+                '\\' => {
+                    let c = match self.next().ok_or("unterminated string")? {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        '"' => '"',
+                        '\\' => '\\',
+                        'u' => {
+                            let hex = self.rest.get(..4).ok_or("invalid \\u escape")?;
+                            self.rest = &self.rest[4..];
+                            u32::from_str_radix(hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or("invalid \\u escape")?
+                        }
+                        other => return Err(format!("invalid escape \\{}", other)),
+                    };
+                    value.push(c);
+                }
+                c => value.push(c),
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use actix_cors::Cors;
+use actix_web::http::Uri;
+
+/// Cross-origin settings, from the `MOTORHEAD_CORS_*` variables.
+#[derive(Clone)]
+pub struct CorsConfig {
+    /// Empty to allow any origin.
+    pub origins: Vec<String>,
+    /// `None` to allow any.
+    pub methods: Option<Vec<String>>,
+    /// `None` to allow any.
+    pub headers: Option<Vec<String>>,
+    pub max_age_seconds: usize,
+    pub credentials: bool,
+}
+
+/// Response headers that browsers only show to scripts when exposed.
+const EXPOSED_HEADERS: [&str; 3] = ["Retry-After", "Idempotent-Replayed", "ETag"];
+
+/// Splits a comma-separated list, `None` standing for `*`.
+pub fn parse_list(value: &str) -> Option<Vec<String>> {
+    let items: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect();
+    (!items.iter().any(|item| item == "*")).then_some(items)
+}
+
+impl CorsConfig {
+    /// Checks the origins are `scheme://host[:port]`, as browsers send them.
+    pub fn validate(&self) -> Result<(), String> {
+        for origin in &self.origins {
+            let uri = origin
+                .parse::<Uri>()
+                .map_err(|e| format!("{}: {}", origin, e))?;
+            if uri.scheme().is_none() || uri.host().is_none() || origin.ends_with('/') {
+                return Err(format!("{}: expected scheme://host[:port]", origin));
+            }
+        }
+        if self.credentials && self.origins.is_empty() {
+            return Err("credentials can't be allowed for any origin".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn build(&self) -> Cors {
+        let mut cors = Cors::default()
+            .expose_headers(EXPOSED_HEADERS)
+            .max_age(self.max_age_seconds);
+
+        cors = if self.origins.is_empty() {
+            cors.allow_any_origin().send_wildcard()
+        } else {
+            self.origins
+                .iter()
+                .fold(cors, |cors, origin| cors.allowed_origin(origin))
+        };
+        cors = match &self.methods {
+            Some(methods) => cors.allowed_methods(methods.iter().map(String::as_str)),
+            None => cors.allow_any_method(),
+        };
+        cors = match &self.headers {
+            Some(headers) => cors.allowed_headers(headers.iter().map(String::as_str)),
+            None => cors.allow_any_header(),
+        };
+        if self.credentials {
+            cors = cors.supports_credentials();
+        }
+
+        cors
+    }
+}
@@ -0,0 +1,87 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{AppState, DeleteIntentResponse, MotorheadError};
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+/// How long a delete intent's token can confirm the delete.
+const INTENT_TTL_MS: u64 = 5 * 60 * 1000;
+
+/// How many messages the session holds if deleting it takes a confirmation, with
+/// `MOTORHEAD_DELETE_CONFIRMATION_MESSAGES` messages or more.
+pub async fn confirmation_needed(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+) -> Result<Option<u64>, MotorheadError> {
+    let Some(threshold) = state.delete_confirmation_messages else {
+        return Ok(None);
+    };
+    let size = store.stored_size(session_id).await?;
+    let stored = size.window + size.history;
+    Ok((stored >= threshold).then_some(stored))
+}
+
+/// Checks that deleting the session was confirmed with the token of a delete intent, if it
+/// holds `MOTORHEAD_DELETE_CONFIRMATION_MESSAGES` messages or more. The token is used up.
+pub async fn confirm_delete(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    token: Option<&str>,
+) -> actix_web::Result<()> {
+    let Some(stored) = confirmation_needed(state, store, session_id).await? else {
+        return Ok(());
+    };
+
+    let confirmed = match token {
+        Some(token) => store.take_delete_intent(session_id, token).await?,
+        None => false,
+    };
+    if !confirmed {
+        return Err(ApiError::new(
+            ErrorCode::ConfirmationRequired,
+            "Deleting a session this large takes the token of a delete intent as `confirm`",
+        )
+        .with("messages", stored)
+        .into());
+    }
+    Ok(())
+}
+
+/// Starts deleting the session, returning the token that `DELETE /sessions/:id/memory` then
+/// takes as `confirm`, within a few minutes. A new intent replaces the last one.
+#[post("/sessions/{session_id}/memory/delete-intent")]
+pub async fn post_delete_intent(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if data.delete_confirmation_messages.is_none() {
+        return Err(ApiError::new(
+            ErrorCode::FeatureDisabled,
+            "Delete confirmations are not enabled",
+        )
+        .into());
+    }
+
+    let token = uuid::Uuid::new_v4().to_string();
+    tenant
+        .store(&data)
+        .set_delete_intent(&session_id, &token, INTENT_TTL_MS)
+        .await?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(DeleteIntentResponse {
+            token,
+            expires_at: now + INTENT_TTL_MS,
+        }))
+}
@@ -0,0 +1,54 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use std::sync::Arc;
+
+use crate::config::check_summary_prompt;
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{AppState, DevSummarizeRequest, DevSummarizeResponse};
+use crate::reducer::summarize_messages;
+
+/// Runs the summarization of a compaction on the conversation given, without reading or
+/// writing any session, to evaluate prompts and models against fixture conversations.
+/// Requires `MOTORHEAD_DEV_ENDPOINTS_ENABLED`.
+#[post("/dev/summarize")]
+pub async fn dev_summarize(
+    web::Json(request): web::Json<DevSummarizeRequest>,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if !data.dev_endpoints_enabled {
+        return Err(
+            ApiError::new(ErrorCode::FeatureDisabled, "Dev endpoints are not enabled").into(),
+        );
+    }
+    if request.messages.is_empty() {
+        return Err(ApiError::invalid_request("messages must not be empty").into());
+    }
+    let runtime = data.runtime();
+    let prompt_template = match request.summary_prompt {
+        Some(prompt) => {
+            check_summary_prompt(&prompt)?;
+            prompt
+        }
+        None => runtime.summary_prompt,
+    };
+    let options = request.options.or(&runtime.summary_options);
+
+    // Newest first, like the window compactions read.
+    let mut messages = request.messages;
+    messages.reverse();
+    let (completion, summarized_messages) = summarize_messages(
+        &data,
+        &prompt_template,
+        request.previous_summary,
+        &messages,
+        &options,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(DevSummarizeResponse {
+            context: completion.content,
+            summarized_messages,
+            usage: completion.usage,
+        }))
+}
@@ -0,0 +1,116 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{check_count, embedding_error, Embedder, EmbeddingKind};
+use crate::models::MotorheadError;
+
+pub const DEFAULT_API_BASE: &str = "https://api.cohere.com";
+
+/// Most texts `/v2/embed` takes in one call.
+const MAX_TEXTS: usize = 96;
+
+/// Embeddings from Cohere's `/v2/embed` endpoint.
+pub struct CohereEmbedder {
+    http: reqwest::Client,
+    url: String,
+    api_key: String,
+    model: String,
+    dimensions: usize,
+}
+
+impl CohereEmbedder {
+    pub fn new(api_base: &str, api_key: String, model: String, dimensions: usize) -> Self {
+        CohereEmbedder {
+            http: reqwest::Client::new(),
+            url: format!("{}/v2/embed", api_base.trim_end_matches('/')),
+            api_key,
+            model,
+            dimensions,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    texts: Vec<String>,
+    input_type: &'static str,
+    embedding_types: [&'static str; 1],
+}
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    embeddings: Embeddings,
+}
+
+#[derive(Deserialize)]
+struct Embeddings {
+    float: Vec<Vec<f32>>,
+}
+
+impl CohereEmbedder {
+    /// Embeds up to `MAX_TEXTS` inputs with one call.
+    async fn embed_chunk(
+        &self,
+        inputs: Vec<String>,
+        kind: EmbeddingKind,
+    ) -> Result<Vec<Vec<f32>>, MotorheadError> {
+        let count = inputs.len();
+        let body = EmbedRequest {
+            model: &self.model,
+            texts: inputs,
+            input_type: match kind {
+                EmbeddingKind::Document => "search_document",
+                EmbeddingKind::Query => "search_query",
+            },
+            embedding_types: ["float"],
+        };
+
+        let response = self
+            .http
+            .post(&self.url)
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(embedding_error)?;
+
+        let status = response.status();
+        let bytes = response.bytes().await.map_err(embedding_error)?;
+        if !status.is_success() {
+            return Err(embedding_error(format!(
+                "Cohere returned {}: {}",
+                status,
+                String::from_utf8_lossy(&bytes)
+            )));
+        }
+
+        let response: EmbedResponse = serde_json::from_slice(&bytes).map_err(embedding_error)?;
+        check_count(response.embeddings.float, count)
+    }
+}
+
+#[async_trait]
+impl Embedder for CohereEmbedder {
+    async fn embed(
+        &self,
+        inputs: Vec<String>,
+        kind: EmbeddingKind,
+    ) -> Result<Vec<Vec<f32>>, MotorheadError> {
+        let mut vectors = Vec::with_capacity(inputs.len());
+        let mut inputs = inputs.into_iter().peekable();
+        while inputs.peek().is_some() {
+            let chunk = inputs.by_ref().take(MAX_TEXTS).collect();
+            vectors.extend(self.embed_chunk(chunk, kind).await?);
+        }
+        Ok(vectors)
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn max_inputs(&self) -> usize {
+        MAX_TEXTS
+    }
+}
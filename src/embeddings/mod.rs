@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+
+use crate::models::MotorheadError;
+
+mod cohere;
+mod ollama;
+mod openai;
+pub use self::cohere::{CohereEmbedder, DEFAULT_API_BASE as COHERE_DEFAULT_API_BASE};
+pub use self::ollama::OllamaEmbedder;
+pub use self::openai::OpenAIEmbedder;
+
+/// What the texts being embedded are for. Some models embed search queries differently from
+/// the documents searched.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EmbeddingKind {
+    Document,
+    Query,
+}
+
+/// A model turning texts into vectors for retrieval, picked with
+/// `MOTORHEAD_EMBEDDING_PROVIDER`.
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    /// One vector per input, in order.
+    async fn embed(
+        &self,
+        inputs: Vec<String>,
+        kind: EmbeddingKind,
+    ) -> Result<Vec<Vec<f32>>, MotorheadError>;
+
+    /// The length of the vectors, which the vector index is created with.
+    fn dimensions(&self) -> usize;
+
+    /// Most inputs the provider takes in one call, for batches to be sized within it.
+    fn max_inputs(&self) -> usize {
+        usize::MAX
+    }
+}
+
+pub(crate) fn embedding_error(err: impl ToString) -> MotorheadError {
+    MotorheadError::EmbeddingError(err.to_string())
+}
+
+/// Fails unless there's a vector per input, so callers can zip them.
+pub(crate) fn check_count(
+    vectors: Vec<Vec<f32>>,
+    inputs: usize,
+) -> Result<Vec<Vec<f32>>, MotorheadError> {
+    if vectors.len() != inputs {
+        return Err(embedding_error(format!(
+            "Expected {} embeddings, got {}",
+            inputs,
+            vectors.len()
+        )));
+    }
+    Ok(vectors)
+}
@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{check_count, embedding_error, Embedder, EmbeddingKind};
+use crate::models::MotorheadError;
+
+/// Embeddings from a model served by Ollama's `/api/embed` endpoint, e.g. `all-minilm`, for
+/// deployments without access to a hosted provider.
+pub struct OllamaEmbedder {
+    http: reqwest::Client,
+    url: String,
+    model: String,
+    dimensions: usize,
+}
+
+impl OllamaEmbedder {
+    pub fn new(host: &str, model: String, dimensions: usize) -> Self {
+        OllamaEmbedder {
+            http: reqwest::Client::new(),
+            url: format!("{}/api/embed", host.trim_end_matches('/')),
+            model,
+            dimensions,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[async_trait]
+impl Embedder for OllamaEmbedder {
+    async fn embed(
+        &self,
+        inputs: Vec<String>,
+        _kind: EmbeddingKind,
+    ) -> Result<Vec<Vec<f32>>, MotorheadError> {
+        let count = inputs.len();
+        let body = EmbedRequest {
+            model: &self.model,
+            input: inputs,
+        };
+
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(embedding_error)?;
+
+        let status = response.status();
+        let bytes = response.bytes().await.map_err(embedding_error)?;
+        if !status.is_success() {
+            return Err(embedding_error(format!(
+                "Ollama returned {}: {}",
+                status,
+                String::from_utf8_lossy(&bytes)
+            )));
+        }
+
+        let response: EmbedResponse = serde_json::from_slice(&bytes).map_err(embedding_error)?;
+        check_count(response.embeddings, count)
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+}
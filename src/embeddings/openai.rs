@@ -0,0 +1,65 @@
+use async_openai::types::{CreateEmbeddingRequest, EmbeddingInput};
+use async_trait::async_trait;
+
+use super::{check_count, embedding_error, Embedder, EmbeddingKind};
+use crate::models::MotorheadError;
+
+/// Most inputs the embeddings endpoint takes in one call.
+const MAX_INPUTS: usize = 2048;
+
+pub struct OpenAIEmbedder {
+    client: async_openai::Client,
+    model: String,
+    dimensions: usize,
+}
+
+impl OpenAIEmbedder {
+    pub fn new(client: async_openai::Client, model: String, dimensions: usize) -> Self {
+        OpenAIEmbedder {
+            client,
+            model,
+            dimensions,
+        }
+    }
+}
+
+#[async_trait]
+impl Embedder for OpenAIEmbedder {
+    async fn embed(
+        &self,
+        inputs: Vec<String>,
+        _kind: EmbeddingKind,
+    ) -> Result<Vec<Vec<f32>>, MotorheadError> {
+        let count = inputs.len();
+        let request = CreateEmbeddingRequest {
+            model: self.model.clone(),
+            input: EmbeddingInput::StringArray(inputs),
+            user: None,
+        };
+
+        let response = self
+            .client
+            .embeddings()
+            .create(request)
+            .await
+            .map_err(embedding_error)?;
+
+        let mut data = response.data;
+        data.sort_by_key(|embedding| embedding.index);
+
+        check_count(
+            data.into_iter()
+                .map(|embedding| embedding.embedding)
+                .collect(),
+            count,
+        )
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn max_inputs(&self) -> usize {
+        MAX_INPUTS
+    }
+}
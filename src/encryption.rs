@@ -0,0 +1,185 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::models::MotorheadError;
+use crate::sigv4::{self, Credentials};
+
+/// Marks encrypted values, followed by the base64 of the nonce and the ciphertext with its
+/// tag. Values without it were written before encryption was enabled and are read as is.
+const PREFIX: &str = "mhenc1:";
+
+/// Marks values encrypted with a tenant's own key, see `TenantKeys`.
+const TENANT_PREFIX: &str = "mhenc1t:";
+
+/// Encrypts message contents and summaries with AES-256-GCM before they're written to Redis,
+/// with the key from `MOTORHEAD_ENCRYPTION_KEY` or the data key of
+/// `MOTORHEAD_ENCRYPTION_KMS_DATA_KEY`.
+pub struct Cipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+    prefix: &'static str,
+}
+
+impl Cipher {
+    pub fn new(key: &[u8]) -> Result<Self, String> {
+        let key = UnboundKey::new(&AES_256_GCM, key)
+            .map_err(|_| format!("The key must be 32 bytes, not {}", key.len()))?;
+        Ok(Cipher {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+            prefix: PREFIX,
+        })
+    }
+
+    /// The same key as a tenant's own, whose values are told apart from the server key's.
+    pub fn for_tenant(self) -> Self {
+        Cipher {
+            prefix: TENANT_PREFIX,
+            ..self
+        }
+    }
+
+    /// Whether the value was encrypted with a tenant's own key.
+    pub fn is_tenant_sealed(value: &str) -> bool {
+        value.starts_with(TENANT_PREFIX)
+    }
+
+    /// From the base64 of a 32 byte key.
+    pub fn from_base64(key: &str) -> Result<Self, String> {
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|e| format!("The key isn't base64: {}", e))?;
+        Self::new(&key)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, MotorheadError> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng.fill(&mut nonce).map_err(|_| {
+            MotorheadError::EncryptionError("No randomness for a nonce".to_string())
+        })?;
+
+        let mut in_out = plaintext.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut in_out,
+            )
+            .map_err(|_| MotorheadError::EncryptionError("Sealing failed".to_string()))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(in_out);
+
+        Ok(format!("{}{}", self.prefix, STANDARD.encode(sealed)))
+    }
+
+    /// Decrypts a value from `encrypt`, or returns it as is if it wasn't encrypted.
+    pub fn decrypt(&self, value: String) -> Result<String, MotorheadError> {
+        let Some(encoded) = value.strip_prefix(self.prefix) else {
+            return Ok(value);
+        };
+        let invalid = || {
+            MotorheadError::EncryptionError(
+                "A value couldn't be decrypted, is it the same key?".to_string(),
+            )
+        };
+
+        let mut sealed = STANDARD.decode(encoded).map_err(|_| invalid())?;
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+        let mut in_out = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed).map_err(|_| invalid())?;
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut in_out)
+            .map_err(|_| invalid())?;
+
+        String::from_utf8(plaintext.to_vec()).map_err(|_| invalid())
+    }
+}
+
+/// The keys tenants registered with `PUT /tenant/encryption-key`, with
+/// `MOTORHEAD_TENANT_ENCRYPTION_KEYS_ENABLED`. Their messages and summaries are encrypted
+/// with them rather than the server key, so deleting a key leaves what it encrypted unreadable.
+/// Kept in memory for the store to encrypt with, and refreshed from it by
+/// `run_tenant_key_refresh`.
+#[derive(Clone, Default)]
+pub struct TenantKeys(Arc<RwLock<HashMap<String, Arc<Cipher>>>>);
+
+impl TenantKeys {
+    pub fn get(&self, tenant: &str) -> Option<Arc<Cipher>> {
+        self.0.read().unwrap().get(tenant).cloned()
+    }
+
+    pub fn insert(&self, tenant: &str, cipher: Cipher) {
+        self.0
+            .write()
+            .unwrap()
+            .insert(tenant.to_string(), Arc::new(cipher));
+    }
+
+    pub fn remove(&self, tenant: &str) {
+        self.0.write().unwrap().remove(tenant);
+    }
+
+    /// Replaces the keys with the stored ones, the base64 of each tenant's key.
+    pub fn replace(&self, keys: BTreeMap<String, String>) {
+        let keys = keys
+            .into_iter()
+            .filter_map(|(tenant, key)| match Cipher::from_base64(&key) {
+                Ok(cipher) => Some((tenant, Arc::new(cipher.for_tenant()))),
+                Err(e) => {
+                    tracing::warn!(tenant, "Ignoring an invalid tenant encryption key: {}", e);
+                    None
+                }
+            })
+            .collect();
+        *self.0.write().unwrap() = keys;
+    }
+}
+
+#[derive(Deserialize)]
+struct KmsDecryptResponse {
+    #[serde(rename = "Plaintext")]
+    plaintext: String,
+}
+
+/// Decrypts a data key encrypted with AWS KMS (the `CiphertextBlob` of `GenerateDataKey`, in
+/// base64), so only the encrypted data key is kept in the configuration.
+pub async fn decrypt_data_key(
+    region: &str,
+    credentials: &Credentials,
+    ciphertext: &str,
+) -> Result<Vec<u8>, String> {
+    let url = reqwest::Url::parse(&format!("https://kms.{}.amazonaws.com/", region))
+        .map_err(|e| e.to_string())?;
+    let body = serde_json::to_vec(&serde_json::json!({ "CiphertextBlob": ciphertext.trim() }))
+        .map_err(|e| e.to_string())?;
+    let headers = sigv4::sign(credentials, region, "kms", "POST", &url, &body)?;
+
+    let mut request = reqwest::Client::new()
+        .post(url)
+        .timeout(Duration::from_secs(30))
+        .header("content-type", "application/x-amz-json-1.1")
+        .header("x-amz-target", "TrentService.Decrypt");
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let response = request.body(body).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("{} {}", status, body.trim()));
+    }
+
+    let response: KmsDecryptResponse = response.json().await.map_err(|e| e.to_string())?;
+    STANDARD
+        .decode(response.plaintext)
+        .map_err(|e| format!("Invalid plaintext from KMS: {}", e))
+}
@@ -0,0 +1,21 @@
+use actix_web::{get, web, Responder};
+use std::sync::Arc;
+
+use crate::models::{AppState, EntitiesResponse};
+use crate::response::read_response;
+use crate::tenant::Tenant;
+
+#[get("/sessions/{session_id}/entities")]
+pub async fn get_entities(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let entities = tenant.store(&data).get_entities(&session_id).await?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        EntitiesResponse { entities },
+    ))
+}
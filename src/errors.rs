@@ -0,0 +1,241 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde_json::{json, Map, Value};
+
+use crate::models::MotorheadError;
+
+/// What went wrong, for clients to act on without parsing messages. Serialized in
+/// SCREAMING_SNAKE_CASE, and each has the HTTP status it's answered with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    InvalidRequest,
+    PayloadTooLarge,
+    InvalidMessage,
+    UnknownRole,
+    MessageFlagged,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    FeatureDisabled,
+    CompactionInProgress,
+    CompactionConflict,
+    VersionMismatch,
+    SessionLocked,
+    SessionExists,
+    KeyExists,
+    KeyDeleted,
+    ConfirmationRequired,
+    RateLimited,
+    TokenBudgetExhausted,
+    SessionQuotaExceeded,
+    Unsupported,
+    Timeout,
+    RedisUnavailable,
+    WriteBufferFull,
+    LoadShed,
+    AuditUnavailable,
+    ColdStorageUnavailable,
+    RedisError,
+    PostgresUnavailable,
+    PostgresError,
+    LlmUnavailable,
+    LlmError,
+    SummarizationFailed,
+    EmbeddingFailed,
+    ModerationUnavailable,
+    UpstreamError,
+    HookFailed,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ErrorCode::InvalidMessage => "INVALID_MESSAGE",
+            ErrorCode::UnknownRole => "UNKNOWN_ROLE",
+            ErrorCode::MessageFlagged => "MESSAGE_FLAGGED",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::FeatureDisabled => "FEATURE_DISABLED",
+            ErrorCode::CompactionInProgress => "COMPACTION_IN_PROGRESS",
+            ErrorCode::CompactionConflict => "COMPACTION_CONFLICT",
+            ErrorCode::VersionMismatch => "VERSION_MISMATCH",
+            ErrorCode::SessionLocked => "SESSION_LOCKED",
+            ErrorCode::SessionExists => "SESSION_EXISTS",
+            ErrorCode::KeyExists => "KEY_EXISTS",
+            ErrorCode::KeyDeleted => "KEY_DELETED",
+            ErrorCode::ConfirmationRequired => "CONFIRMATION_REQUIRED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::TokenBudgetExhausted => "TOKEN_BUDGET_EXHAUSTED",
+            ErrorCode::SessionQuotaExceeded => "SESSION_QUOTA_EXCEEDED",
+            ErrorCode::Unsupported => "UNSUPPORTED",
+            ErrorCode::Timeout => "TIMEOUT",
+            ErrorCode::RedisUnavailable => "REDIS_UNAVAILABLE",
+            ErrorCode::WriteBufferFull => "WRITE_BUFFER_FULL",
+            ErrorCode::LoadShed => "LOAD_SHED",
+            ErrorCode::AuditUnavailable => "AUDIT_UNAVAILABLE",
+            ErrorCode::ColdStorageUnavailable => "COLD_STORAGE_UNAVAILABLE",
+            ErrorCode::RedisError => "REDIS_ERROR",
+            ErrorCode::PostgresUnavailable => "POSTGRES_UNAVAILABLE",
+            ErrorCode::PostgresError => "POSTGRES_ERROR",
+            ErrorCode::LlmUnavailable => "LLM_UNAVAILABLE",
+            ErrorCode::LlmError => "LLM_ERROR",
+            ErrorCode::SummarizationFailed => "SUMMARIZATION_FAILED",
+            ErrorCode::EmbeddingFailed => "EMBEDDING_FAILED",
+            ErrorCode::ModerationUnavailable => "MODERATION_UNAVAILABLE",
+            ErrorCode::UpstreamError => "UPSTREAM_ERROR",
+            ErrorCode::HookFailed => "HOOK_FAILED",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest | ErrorCode::InvalidMessage => StatusCode::BAD_REQUEST,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnknownRole | ErrorCode::MessageFlagged => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound | ErrorCode::FeatureDisabled => StatusCode::NOT_FOUND,
+            ErrorCode::CompactionInProgress
+            | ErrorCode::CompactionConflict
+            | ErrorCode::VersionMismatch
+            | ErrorCode::SessionLocked
+            | ErrorCode::SessionExists
+            | ErrorCode::KeyExists => StatusCode::CONFLICT,
+            ErrorCode::KeyDeleted => StatusCode::GONE,
+            ErrorCode::ConfirmationRequired => StatusCode::PRECONDITION_REQUIRED,
+            ErrorCode::RateLimited
+            | ErrorCode::TokenBudgetExhausted
+            | ErrorCode::SessionQuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::Unsupported => StatusCode::NOT_IMPLEMENTED,
+            ErrorCode::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::RedisUnavailable
+            | ErrorCode::WriteBufferFull
+            | ErrorCode::LoadShed
+            | ErrorCode::AuditUnavailable
+            | ErrorCode::ColdStorageUnavailable
+            | ErrorCode::PostgresUnavailable
+            | ErrorCode::LlmUnavailable
+            | ErrorCode::ModerationUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::LlmError
+            | ErrorCode::SummarizationFailed
+            | ErrorCode::EmbeddingFailed
+            | ErrorCode::UpstreamError
+            | ErrorCode::HookFailed => StatusCode::BAD_GATEWAY,
+            ErrorCode::RedisError | ErrorCode::PostgresError | ErrorCode::InternalError => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+/// An error response: `{ "error": { "code": "...", "message": "..." } }`, with the code's
+/// status. Some errors add fields next to `code` and `message`, like the `index` of the
+/// message at fault.
+#[derive(Debug)]
+pub struct ApiError {
+    code: ErrorCode,
+    message: String,
+    details: Map<String, Value>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl ToString) -> Self {
+        ApiError {
+            code,
+            message: message.to_string(),
+            details: Map::new(),
+        }
+    }
+
+    pub fn invalid_request(message: impl ToString) -> Self {
+        ApiError::new(ErrorCode::InvalidRequest, message)
+    }
+
+    pub fn not_found(message: impl ToString) -> Self {
+        ApiError::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn internal(message: impl ToString) -> Self {
+        ApiError::new(ErrorCode::InternalError, message)
+    }
+
+    pub fn with(mut self, field: &str, value: impl Into<Value>) -> Self {
+        self.details.insert(field.to_string(), value.into());
+        self
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut error = Map::new();
+        error.insert("code".to_string(), json!(self.code.as_str()));
+        error.insert("message".to_string(), json!(self.message));
+        error.extend(self.details.clone());
+
+        HttpResponse::build(self.status_code())
+            .content_type("application/json")
+            .json(json!({ "error": error }))
+    }
+}
+
+impl MotorheadError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            MotorheadError::RedisError(e)
+                if e.is_connection_refusal()
+                    || e.is_connection_dropped()
+                    || e.is_timeout()
+                    || e.is_io_error() =>
+            {
+                ErrorCode::RedisUnavailable
+            }
+            MotorheadError::RedisError(_) => ErrorCode::RedisError,
+            MotorheadError::PostgresUnavailable(_) => ErrorCode::PostgresUnavailable,
+            MotorheadError::PostgresError(_) => ErrorCode::PostgresError,
+            MotorheadError::IncrementalSummarizationError(_) => ErrorCode::SummarizationFailed,
+            MotorheadError::SerializationError(_) => ErrorCode::InternalError,
+            MotorheadError::Unsupported(_) => ErrorCode::Unsupported,
+            MotorheadError::EmbeddingError(_) => ErrorCode::EmbeddingFailed,
+            MotorheadError::LlmError(_) => ErrorCode::LlmError,
+            MotorheadError::LlmUnavailable(_) | MotorheadError::LlmCircuitOpen => {
+                ErrorCode::LlmUnavailable
+            }
+            MotorheadError::ModerationError(_) => ErrorCode::ModerationUnavailable,
+            MotorheadError::HookError(_) => ErrorCode::HookFailed,
+            MotorheadError::TokenBudgetExhausted(_) => ErrorCode::TokenBudgetExhausted,
+            MotorheadError::SessionQuotaExceeded => ErrorCode::SessionQuotaExceeded,
+            MotorheadError::CompactionConflict => ErrorCode::CompactionConflict,
+            MotorheadError::WriteBufferFull => ErrorCode::WriteBufferFull,
+            MotorheadError::AuditUnavailable(_) => ErrorCode::AuditUnavailable,
+            MotorheadError::ColdStorageUnavailable(_) => ErrorCode::ColdStorageUnavailable,
+            MotorheadError::EncryptionError(_) => ErrorCode::InternalError,
+            MotorheadError::TenantKeyDeleted => ErrorCode::KeyDeleted,
+        }
+    }
+}
+
+/// Lets handlers return storage and LLM errors with `?`.
+impl ResponseError for MotorheadError {
+    fn status_code(&self) -> StatusCode {
+        self.code().status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ApiError::new(self.code(), self).error_response()
+    }
+}
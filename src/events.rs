@@ -0,0 +1,37 @@
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpResponse, Responder};
+use futures_util::stream::{self, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::models::AppState;
+use crate::tenant::Tenant;
+
+/// Idle proxies tend to drop quiet connections, so a comment is sent this often.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[get("/sessions/{session_id}/memory/stream")]
+pub async fn stream_memory(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let events = tenant
+        .store(&data)
+        .subscribe(&session_id)
+        .await?
+        .map(|event| format!("data: {}\n\n", event));
+
+    let keepalive = stream::unfold((), |_| async {
+        tokio::time::sleep(KEEPALIVE_INTERVAL).await;
+        Some((": keepalive\n\n".to_string(), ()))
+    });
+
+    let body = stream::select(events, keepalive)
+        .map(|chunk| Ok::<_, actix_web::Error>(Bytes::from(chunk)));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body))
+}
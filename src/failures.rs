@@ -0,0 +1,75 @@
+use actix_web::{get, web, Responder};
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::models::{AppState, CompactionFailuresResponse, MotorheadError};
+use crate::reducer::spawn_compaction_retry;
+use crate::response::read_response;
+use crate::telemetry;
+use crate::tenant::Tenant;
+
+/// Periodically retries the compactions in the failure queue that are due. Exits if the
+/// storage backend has no queue.
+pub async fn run_retry_worker(state: Arc<AppState>) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.compaction_retry_interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        // Retrying now would only use up the retries of the queued failures.
+        if state
+            .llm_circuit
+            .as_ref()
+            .is_some_and(|circuit| circuit.is_open())
+        {
+            continue;
+        }
+
+        let failures = match state.store.compaction_failures().await {
+            Ok(failures) => failures,
+            Err(MotorheadError::Unsupported(_)) => return,
+            Err(e) => {
+                tracing::error!(
+                    error = telemetry::error_message(&e),
+                    "Error reading the compaction retry queue"
+                );
+                continue;
+            }
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        for failure in failures {
+            if failure.next_retry_at.is_some_and(|next| next <= now) {
+                tracing::info!(session_id = %failure.session_id, "Retrying compaction");
+                let tenant = Tenant::new(failure.tenant);
+                spawn_compaction_retry(&state, &tenant, &failure.session_id, failure.retries + 1)
+                    .await;
+            }
+        }
+    }
+}
+
+/// Lists queued compaction failures. Tenant requests only see their own sessions.
+#[get("/admin/compaction/failures")]
+pub async fn get_compaction_failures(
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let mut failures = data.store.compaction_failures().await?;
+
+    if let Some(tenant) = tenant.id() {
+        failures.retain(|failure| failure.tenant.as_deref() == Some(tenant));
+    }
+    failures.sort_by_key(|failure| Reverse(failure.failed_at));
+
+    Ok(read_response(
+        &data,
+        None,
+        CompactionFailuresResponse { failures },
+    ))
+}
@@ -0,0 +1,750 @@
+use actix_web::http::StatusCode;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use h2::server::SendResponse;
+use h2::RecvStream;
+use prost::Message as _;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::TcpListener;
+
+use crate::audit;
+use crate::auth::{auth_enabled, authenticate, Actor, ApiKey, AuthenticatedKey, Scope};
+use crate::delete_intent::confirm_delete;
+use crate::memory::{append_memory, check_roles, delete_session, read_memory};
+use crate::metrics;
+use crate::models::{AppState, Attachment, FunctionCall, MemoryFields, MemoryMessage, ToolCall};
+use crate::ratelimit::{take_key_request, take_session_write};
+use crate::reducer::run_compaction;
+use crate::telemetry;
+use crate::tenant::{Tenant, TenantError, TENANT_HEADER};
+
+/// `proto/motorhead.proto`'s service.
+const SERVICE_PREFIX: &str = "/motorhead.v1.Memory/";
+
+const METHODS: [&str; 4] = ["GetMemory", "AppendMemory", "DeleteMemory", "Summarize"];
+
+/// Largest request message accepted, gRPC's usual default.
+const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+/// The messages of `proto/motorhead.proto`, kept in sync by hand.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FunctionCall {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub arguments: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ToolCall {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub r#type: String,
+        #[prost(message, optional, tag = "3")]
+        pub function: Option<FunctionCall>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Attachment {
+        #[prost(string, tag = "1")]
+        pub url: String,
+        #[prost(string, tag = "2")]
+        pub mime_type: String,
+        #[prost(uint64, optional, tag = "3")]
+        pub size: Option<u64>,
+        #[prost(string, optional, tag = "4")]
+        pub text: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Message {
+        #[prost(string, tag = "1")]
+        pub role: String,
+        #[prost(string, tag = "2")]
+        pub content: String,
+        #[prost(string, optional, tag = "3")]
+        pub id: Option<String>,
+        #[prost(uint64, optional, tag = "4")]
+        pub created_at: Option<u64>,
+        #[prost(message, repeated, tag = "5")]
+        pub tool_calls: Vec<ToolCall>,
+        #[prost(string, optional, tag = "6")]
+        pub tool_call_id: Option<String>,
+        #[prost(string, optional, tag = "7")]
+        pub name: Option<String>,
+        #[prost(string, optional, tag = "8")]
+        pub metadata: Option<String>,
+        #[prost(string, optional, tag = "9")]
+        pub speaker: Option<String>,
+        #[prost(message, repeated, tag = "10")]
+        pub attachments: Vec<Attachment>,
+        #[prost(string, optional, tag = "11")]
+        pub turn_id: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ContextSegment {
+        #[prost(string, tag = "1")]
+        pub topic: String,
+        #[prost(string, tag = "2")]
+        pub summary: String,
+        #[prost(string, optional, tag = "3")]
+        pub first_message_id: Option<String>,
+        #[prost(string, optional, tag = "4")]
+        pub last_message_id: Option<String>,
+        #[prost(uint64, tag = "5")]
+        pub message_count: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SessionRequest {
+        #[prost(string, tag = "1")]
+        pub session_id: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DeleteMemoryRequest {
+        #[prost(string, tag = "1")]
+        pub session_id: String,
+        #[prost(string, optional, tag = "2")]
+        pub confirm: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetMemoryResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub messages: Vec<Message>,
+        #[prost(string, optional, tag = "2")]
+        pub context: Option<String>,
+        #[prost(string, optional, tag = "3")]
+        pub compaction_error: Option<String>,
+        #[prost(uint64, tag = "4")]
+        pub tokens_in_window: u64,
+        #[prost(uint64, tag = "5")]
+        pub messages_since_last_summary: u64,
+        #[prost(bool, tag = "6")]
+        pub compaction_in_progress: bool,
+        #[prost(string, optional, tag = "7")]
+        pub long_term_context: Option<String>,
+        #[prost(message, repeated, tag = "8")]
+        pub context_segments: Vec<ContextSegment>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AppendMemoryRequest {
+        #[prost(string, tag = "1")]
+        pub session_id: String,
+        #[prost(message, repeated, tag = "2")]
+        pub messages: Vec<Message>,
+        #[prost(uint64, optional, tag = "3")]
+        pub ttl_seconds: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Empty {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SummarizeResponse {
+        #[prost(string, tag = "1")]
+        pub context: String,
+    }
+}
+
+/// The gRPC status codes the service answers with.
+#[derive(Clone, Copy)]
+enum Code {
+    Ok = 0,
+    InvalidArgument = 3,
+    NotFound = 5,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    Unauthenticated = 16,
+}
+
+impl Code {
+    fn as_str(self) -> &'static str {
+        match self {
+            Code::Ok => "OK",
+            Code::InvalidArgument => "INVALID_ARGUMENT",
+            Code::NotFound => "NOT_FOUND",
+            Code::PermissionDenied => "PERMISSION_DENIED",
+            Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+            Code::FailedPrecondition => "FAILED_PRECONDITION",
+            Code::Aborted => "ABORTED",
+            Code::Unimplemented => "UNIMPLEMENTED",
+            Code::Internal => "INTERNAL",
+            Code::Unavailable => "UNAVAILABLE",
+            Code::Unauthenticated => "UNAUTHENTICATED",
+        }
+    }
+}
+
+struct Status {
+    code: Code,
+    message: String,
+}
+
+impl Status {
+    fn new(code: Code, message: impl std::fmt::Display) -> Self {
+        Status {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn internal(message: impl std::fmt::Display) -> Self {
+        Status::new(Code::Internal, message)
+    }
+
+    /// The gRPC equivalent of an error from the shared HTTP code paths.
+    fn from_http(err: actix_web::Error) -> Self {
+        let code = match err.as_response_error().status_code() {
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => Code::InvalidArgument,
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::CONFLICT => Code::Aborted,
+            StatusCode::PRECONDITION_REQUIRED => Code::FailedPrecondition,
+            StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::NOT_IMPLEMENTED => Code::Unimplemented,
+            StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            _ => Code::Internal,
+        };
+        Status::new(code, err)
+    }
+
+    fn rate_limited(retry_after_ms: u64) -> Self {
+        Status::new(
+            Code::ResourceExhausted,
+            format!(
+                "Rate limit exceeded, retry in {}s",
+                retry_after_ms.div_ceil(1000)
+            ),
+        )
+    }
+}
+
+/// `grpc-message` is percent-encoded, leaving printable ASCII other than `%` as is.
+fn encode_message(message: &str) -> String {
+    message
+        .bytes()
+        .map(|b| match b {
+            b' '..=b'~' if b != b'%' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn from_proto(index: usize, message: proto::Message) -> Result<MemoryMessage, Status> {
+    let metadata = message
+        .metadata
+        .map(|metadata| serde_json::from_str(&metadata))
+        .transpose()
+        .map_err(|e| {
+            Status::new(
+                Code::InvalidArgument,
+                format!("Message {} has invalid metadata: {}", index, e),
+            )
+        })?;
+    let tool_calls: Vec<ToolCall> = message
+        .tool_calls
+        .into_iter()
+        .map(|call| {
+            let function = call.function.unwrap_or_default();
+            ToolCall {
+                id: call.id,
+                kind: if call.r#type.is_empty() {
+                    "function".to_string()
+                } else {
+                    call.r#type
+                },
+                function: FunctionCall {
+                    name: function.name,
+                    arguments: function.arguments,
+                },
+            }
+        })
+        .collect();
+    let attachments: Vec<Attachment> = message
+        .attachments
+        .into_iter()
+        .map(|attachment| Attachment {
+            url: attachment.url,
+            mime_type: attachment.mime_type,
+            size: attachment.size,
+            text: attachment.text,
+        })
+        .collect();
+
+    Ok(MemoryMessage {
+        role: message.role.into(),
+        content: message.content,
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        tool_call_id: message.tool_call_id,
+        name: message.name,
+        speaker: message.speaker,
+        attachments: (!attachments.is_empty()).then_some(attachments),
+        id: message.id,
+        turn_id: message.turn_id,
+        created_at: message.created_at,
+        metadata,
+        importance: None,
+    })
+}
+
+fn to_proto(message: MemoryMessage) -> proto::Message {
+    proto::Message {
+        role: message.role.into(),
+        content: message.content,
+        id: message.id,
+        turn_id: message.turn_id,
+        created_at: message.created_at,
+        tool_calls: message
+            .tool_calls
+            .into_iter()
+            .flatten()
+            .map(|call| proto::ToolCall {
+                id: call.id,
+                r#type: call.kind,
+                function: Some(proto::FunctionCall {
+                    name: call.function.name,
+                    arguments: call.function.arguments,
+                }),
+            })
+            .collect(),
+        tool_call_id: message.tool_call_id,
+        name: message.name,
+        speaker: message.speaker,
+        attachments: message
+            .attachments
+            .into_iter()
+            .flatten()
+            .map(|attachment| proto::Attachment {
+                url: attachment.url,
+                mime_type: attachment.mime_type,
+                size: attachment.size,
+                text: attachment.text,
+            })
+            .collect(),
+        metadata: message
+            .metadata
+            .map(|metadata| serde_json::Value::Object(metadata).to_string()),
+    }
+}
+
+fn decode<M: prost::Message + Default>(body: Bytes) -> Result<M, Status> {
+    M::decode(body).map_err(|e| Status::new(Code::InvalidArgument, e))
+}
+
+fn session_id(api_key: Option<&ApiKey>, session_id: String) -> Result<String, Status> {
+    if session_id.is_empty() {
+        return Err(Status::new(Code::InvalidArgument, "session_id is required"));
+    }
+    if !api_key.is_none_or(|api_key| api_key.allows_session(&session_id)) {
+        return Err(Status::new(
+            Code::PermissionDenied,
+            "This API key can't access this session",
+        ));
+    }
+    Ok(session_id)
+}
+
+/// The key and tenant of a call, the way the HTTP middleware finds them.
+async fn authenticate_call(
+    state: &AppState,
+    headers: &http::HeaderMap,
+) -> Result<(Option<ApiKey>, Tenant), Status> {
+    let mut key = None;
+    let mut key_tenant = None;
+    if auth_enabled(state) {
+        let token = headers
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        let api_key = authenticate(state, token)
+            .await
+            .map_err(|e| Status::new(Code::Unauthenticated, e))?;
+        key_tenant = api_key.tenant.clone();
+        key = Some(api_key);
+    }
+
+    let header = headers.get(TENANT_HEADER).map(|value| value.as_bytes());
+    let tenant = Tenant::resolve(key_tenant, header).map_err(|e| match e {
+        TenantError::InvalidHeader => Status::new(Code::InvalidArgument, e),
+        TenantError::KeyMismatch => Status::new(Code::PermissionDenied, e),
+    })?;
+
+    Ok((key, tenant))
+}
+
+async fn limit_session_writes(
+    state: &AppState,
+    tenant: &Tenant,
+    session_id: &str,
+) -> Result<(), Status> {
+    match take_session_write(state, tenant, session_id).await {
+        Some(wait) => Err(Status::rate_limited(wait)),
+        None => Ok(()),
+    }
+}
+
+/// Records the call in the audit log, the way the HTTP middleware records requests.
+async fn audit_call(
+    state: &AppState,
+    api_key: Option<&ApiKey>,
+    tenant: &Tenant,
+    method: &str,
+    session_id: &str,
+) -> Result<(), Status> {
+    let Actor(actor) = Actor::of(api_key);
+    audit::record(
+        state,
+        &actor,
+        tenant,
+        &format!("grpc {}", method),
+        Some(session_id),
+        None,
+    )
+    .await
+    .map_err(|e| Status::new(Code::Unavailable, e))
+}
+
+async fn get_memory(
+    state: &Arc<AppState>,
+    api_key: Option<&ApiKey>,
+    tenant: Tenant,
+    body: Bytes,
+) -> Result<Bytes, Status> {
+    let request: proto::SessionRequest = decode(body)?;
+    let session_id = session_id(api_key, request.session_id)?;
+
+    let store = tenant.read_store(state);
+    let memory = read_memory(
+        state,
+        &tenant,
+        store.as_ref(),
+        &session_id,
+        None,
+        MemoryFields::ALL,
+    )
+    .await
+    .map_err(Status::internal)?;
+
+    Ok(proto::GetMemoryResponse {
+        messages: memory.messages.into_iter().map(to_proto).collect(),
+        context: memory.context,
+        compaction_error: memory.compaction_error,
+        tokens_in_window: memory.tokens_in_window as u64,
+        messages_since_last_summary: memory.messages_since_last_summary,
+        compaction_in_progress: memory.compaction_in_progress,
+        long_term_context: memory.long_term_context,
+        context_segments: memory
+            .context_segments
+            .into_iter()
+            .map(|segment| proto::ContextSegment {
+                topic: segment.topic,
+                summary: segment.summary,
+                first_message_id: segment.first_message_id,
+                last_message_id: segment.last_message_id,
+                message_count: segment.message_count as u64,
+            })
+            .collect(),
+    }
+    .encode_to_vec()
+    .into())
+}
+
+async fn append(
+    state: &Arc<AppState>,
+    api_key: Option<&ApiKey>,
+    tenant: Tenant,
+    body: Bytes,
+) -> Result<Bytes, Status> {
+    let request: proto::AppendMemoryRequest = decode(body)?;
+    let session_id = session_id(api_key, request.session_id)?;
+    let messages = request
+        .messages
+        .into_iter()
+        .enumerate()
+        .map(|(index, message)| from_proto(index, message))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_roles(state, &messages).map_err(|e| Status::new(Code::InvalidArgument, e))?;
+    limit_session_writes(state, &tenant, &session_id).await?;
+
+    audit_call(state, api_key, &tenant, "AppendMemory", &session_id).await?;
+
+    append_memory(
+        state,
+        &tenant,
+        &session_id,
+        messages,
+        request.ttl_seconds,
+        state.runtime().summary_options,
+    )
+    .await
+    .map_err(Status::from_http)?;
+
+    Ok(proto::Empty {}.encode_to_vec().into())
+}
+
+async fn delete(
+    state: &Arc<AppState>,
+    api_key: Option<&ApiKey>,
+    tenant: Tenant,
+    body: Bytes,
+) -> Result<Bytes, Status> {
+    let request: proto::DeleteMemoryRequest = decode(body)?;
+    let session_id = session_id(api_key, request.session_id)?;
+    limit_session_writes(state, &tenant, &session_id).await?;
+
+    confirm_delete(
+        state,
+        tenant.store(state).as_ref(),
+        &session_id,
+        request.confirm.as_deref(),
+    )
+    .await
+    .map_err(Status::from_http)?;
+    audit_call(state, api_key, &tenant, "DeleteMemory", &session_id).await?;
+
+    delete_session(state, &tenant, &session_id)
+        .await
+        .map_err(Status::internal)?;
+
+    Ok(proto::Empty {}.encode_to_vec().into())
+}
+
+async fn summarize(
+    state: &Arc<AppState>,
+    api_key: Option<&ApiKey>,
+    tenant: Tenant,
+    body: Bytes,
+) -> Result<Bytes, Status> {
+    let request: proto::SessionRequest = decode(body)?;
+    let session_id = session_id(api_key, request.session_id)?;
+    limit_session_writes(state, &tenant, &session_id).await?;
+
+    audit_call(state, api_key, &tenant, "Summarize", &session_id).await?;
+
+    let context = run_compaction(
+        state,
+        &tenant,
+        &session_id,
+        &state.runtime().summary_options,
+    )
+    .await
+    .ok_or_else(|| {
+        Status::new(
+            Code::Aborted,
+            "A compaction is already running for this session",
+        )
+    })?
+    .map_err(Status::internal)?;
+
+    Ok(proto::SummarizeResponse { context }.encode_to_vec().into())
+}
+
+/// Reads the single length-prefixed message of a unary call.
+async fn read_message(body: &mut RecvStream) -> Result<Bytes, Status> {
+    let mut buffer = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(Status::internal)?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        if buffer.len() + chunk.len() > MAX_MESSAGE_BYTES + 5 {
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                format!("Messages are limited to {} bytes", MAX_MESSAGE_BYTES),
+            ));
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+
+    if buffer.len() < 5 {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            "Missing request message",
+        ));
+    }
+    let compressed = buffer.get_u8();
+    let len = buffer.get_u32() as usize;
+    if compressed != 0 {
+        return Err(Status::new(
+            Code::Unimplemented,
+            "Compressed messages are not supported",
+        ));
+    }
+    if len != buffer.len() {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            "Expected a single request message",
+        ));
+    }
+
+    Ok(buffer.freeze())
+}
+
+async fn call(
+    state: &Arc<AppState>,
+    method: &str,
+    request: http::Request<RecvStream>,
+) -> Result<Bytes, Status> {
+    let is_grpc = request
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/grpc"));
+    if request.method() != http::Method::POST || !is_grpc {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            "Expected a POST with an application/grpc body",
+        ));
+    }
+
+    let (api_key, tenant) = authenticate_call(state, request.headers()).await?;
+    let api_key = api_key.as_ref();
+    if let Some(api_key) = api_key {
+        let scope = match method {
+            "GetMemory" => Scope::Read,
+            _ => Scope::Write,
+        };
+        if api_key.scope < scope {
+            return Err(Status::new(
+                Code::PermissionDenied,
+                format!("This API key can't call {} methods", scope.as_str()),
+            ));
+        }
+        if let Some(wait) = take_key_request(state, &AuthenticatedKey::new(&api_key.key)).await {
+            return Err(Status::rate_limited(wait));
+        }
+    }
+
+    let body = read_message(&mut request.into_body()).await?;
+    match method {
+        "GetMemory" => get_memory(state, api_key, tenant, body).await,
+        "AppendMemory" => append(state, api_key, tenant, body).await,
+        "DeleteMemory" => delete(state, api_key, tenant, body).await,
+        "Summarize" => summarize(state, api_key, tenant, body).await,
+        _ => Err(Status::new(
+            Code::Unimplemented,
+            format!("Unknown method {}", method),
+        )),
+    }
+}
+
+fn grpc_response(status: Option<&Status>) -> http::Response<()> {
+    let mut response = http::Response::builder()
+        .status(200)
+        .header(http::header::CONTENT_TYPE, "application/grpc");
+    if let Some(status) = status {
+        response = response
+            .header("grpc-status", status.code as u16)
+            .header("grpc-message", encode_message(&status.message));
+    }
+    response.body(()).expect("Valid gRPC response headers")
+}
+
+async fn respond(
+    state: Arc<AppState>,
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+) {
+    let started = Instant::now();
+    let method = request
+        .uri()
+        .path()
+        .strip_prefix(SERVICE_PREFIX)
+        .unwrap_or_default()
+        .to_string();
+
+    let result = call(&state, &method, request).await;
+    let code = result
+        .as_ref()
+        .map_or_else(|status| status.code, |_| Code::Ok);
+    // Unknown methods share a label, so clients can't add label values at will.
+    let label = if METHODS.contains(&method.as_str()) {
+        method.as_str()
+    } else {
+        "unknown"
+    };
+    metrics::record_grpc_request(label, code.as_str(), started.elapsed());
+
+    let sent = match result {
+        Ok(message) => {
+            let mut frame = BytesMut::with_capacity(message.len() + 5);
+            frame.put_u8(0);
+            frame.put_u32(message.len() as u32);
+            frame.put(message);
+
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("grpc-status", http::HeaderValue::from(Code::Ok as u16));
+            respond
+                .send_response(grpc_response(None), false)
+                .and_then(|mut stream| {
+                    stream.send_data(frame.freeze(), false)?;
+                    stream.send_trailers(trailers)
+                })
+        }
+        // Trailers-only: the status goes in the headers and there's no body.
+        Err(status) => {
+            if matches!(status.code, Code::Internal | Code::Unavailable) {
+                tracing::error!(
+                    method,
+                    code = status.code as i32,
+                    error = telemetry::redact(&status.message),
+                    "gRPC call failed"
+                );
+            }
+            respond
+                .send_response(grpc_response(Some(&status)), true)
+                .map(|_| ())
+        }
+    };
+
+    if let Err(e) = sent {
+        tracing::warn!(error = %e, "Problem sending gRPC response");
+    }
+}
+
+/// Serves `proto/motorhead.proto` over HTTP/2 cleartext (h2c), for as long as the server runs.
+pub async fn serve(state: Arc<AppState>, listener: TcpListener) {
+    loop {
+        let (socket, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::warn!(error = %e, "Problem accepting gRPC connection");
+                continue;
+            }
+        };
+        let state = Arc::clone(&state);
+
+        tokio::spawn(async move {
+            let mut connection = match h2::server::handshake(socket).await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    tracing::warn!(error = %e, "Problem starting gRPC connection");
+                    return;
+                }
+            };
+
+            while let Some(request) = connection.accept().await {
+                match request {
+                    Ok((request, send)) => {
+                        tokio::spawn(respond(Arc::clone(&state), request, send));
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "gRPC connection closed");
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
@@ -1,6 +1,14 @@
-use crate::models::HealthCheckResponse;
-use actix_web::{get, web, Responder};
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::models::{
+    AppState, ComponentStatus, HealthCheckResponse, MotorheadError, ProbeComponents, ProbeResponse,
+};
+use actix_web::{get, web, HttpResponse, Responder};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Upper bound for each readiness check, so a hung dependency fails the probe instead of
+/// stalling it.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[get("/")]
 pub async fn get_health() -> actix_web::Result<impl Responder> {
@@ -13,3 +21,50 @@ pub async fn get_health() -> actix_web::Result<impl Responder> {
 
     Ok(web::Json(res))
 }
+
+/// Liveness: the process is up and serving requests.
+#[get("/healthz")]
+pub async fn get_healthz() -> actix_web::Result<impl Responder> {
+    Ok(web::Json(ProbeResponse {
+        status: "ok",
+        components: None,
+    }))
+}
+
+async fn check(probe: impl Future<Output = Result<(), MotorheadError>>) -> ComponentStatus {
+    let error = match tokio::time::timeout(CHECK_TIMEOUT, probe).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some("Timed out".to_string()),
+    };
+
+    ComponentStatus {
+        status: if error.is_none() { "ok" } else { "error" },
+        error,
+    }
+}
+
+/// Readiness: the storage backend (and, if `MOTORHEAD_READINESS_CHECK_LLM` is set, the LLM
+/// provider) can be reached. Responds with `503` otherwise.
+#[get("/readyz")]
+pub async fn get_readyz(data: web::Data<Arc<AppState>>) -> actix_web::Result<impl Responder> {
+    let store = check(data.store.ping()).await;
+    let llm = if data.readiness_check_llm {
+        Some(check(data.llm.ping()).await)
+    } else {
+        None
+    };
+
+    let ready = store.error.is_none() && llm.as_ref().is_none_or(|llm| llm.error.is_none());
+    let response = ProbeResponse {
+        status: if ready { "ok" } else { "unavailable" },
+        components: Some(ProbeComponents { store, llm }),
+    };
+
+    let mut builder = if ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    Ok(builder.content_type("application/json").json(response))
+}
@@ -0,0 +1,252 @@
+use actix_web::{get, web, Responder};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::cold_storage;
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{
+    AppState, ContextVersion, HistoryQuery, HistoryResponse, MemoryMessage, MotorheadError,
+    RangeQuery, RangeResponse, ReplayQuery, ReplayResponse,
+};
+use crate::response::read_response;
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Messages read from the history at a time.
+const HISTORY_PAGE_SIZE: usize = 1000;
+
+#[get("/sessions/{session_id}/history")]
+pub async fn get_history(
+    session_id: web::Path<String>,
+    query: web::Query<HistoryQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    // Fetch one extra message to know whether there are more.
+    let mut messages = cold_storage::get_history(
+        &data,
+        tenant.store(&data).as_ref(),
+        &session_id,
+        offset,
+        limit + 1,
+    )
+    .await?;
+
+    let next_offset = (messages.len() > limit).then_some(offset + limit);
+    messages.truncate(limit);
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        HistoryResponse {
+            messages,
+            next_offset,
+        },
+    ))
+}
+
+/// An RFC 3339 timestamp of the query, in milliseconds since the Unix epoch.
+fn parse_timestamp(name: &str, value: Option<&str>) -> actix_web::Result<Option<u64>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let millis = OffsetDateTime::parse(value, &Rfc3339)
+        .ok()
+        .and_then(|at| u64::try_from(at.unix_timestamp_nanos() / 1_000_000).ok())
+        .ok_or_else(|| {
+            ApiError::invalid_request(format!(
+                "{} must be an RFC 3339 timestamp, e.g. 2024-01-31T12:00:00Z",
+                name
+            ))
+        })?;
+    Ok(Some(millis))
+}
+
+/// Up to `limit` of the messages of the session created in `[from, to)`, oldest first, and
+/// whether there were more.
+async fn messages_in_range(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    from: u64,
+    to: u64,
+    limit: usize,
+) -> Result<(Vec<MemoryMessage>, bool), MotorheadError> {
+    let in_range = |message: &MemoryMessage| {
+        message
+            .created_at
+            .is_some_and(|created_at| from <= created_at && created_at < to)
+    };
+    let mut messages = Vec::new();
+
+    let mut offset = 0;
+    loop {
+        let page =
+            cold_storage::get_history(state, store, session_id, offset, HISTORY_PAGE_SIZE).await?;
+        let page_len = page.len();
+
+        for message in page.into_iter().filter(in_range) {
+            if messages.len() == limit {
+                return Ok((messages, true));
+            }
+            messages.push(message);
+        }
+
+        if page_len < HISTORY_PAGE_SIZE {
+            break;
+        }
+        offset += page_len;
+    }
+
+    let window = store.get_messages(session_id, 0, -1).await?;
+    for message in window.into_iter().rev().filter(in_range) {
+        if messages.len() == limit {
+            return Ok((messages, true));
+        }
+        messages.push(message);
+    }
+
+    Ok((messages, false))
+}
+
+/// The session's messages created between `from` and `to`, looking through the archived
+/// history as well as the window. Messages stored without a timestamp are left out.
+#[get("/sessions/{session_id}/memory/range")]
+pub async fn get_memory_range(
+    session_id: web::Path<String>,
+    query: web::Query<RangeQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let from = parse_timestamp("from", query.from.as_deref())?.unwrap_or(0);
+    let to = parse_timestamp("to", query.to.as_deref())?.unwrap_or(u64::MAX);
+    if from > to {
+        return Err(ApiError::invalid_request("from must not be after to").into());
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let (messages, truncated) = messages_in_range(
+        &data,
+        tenant.store(&data).as_ref(),
+        &session_id,
+        from,
+        to,
+        limit,
+    )
+    .await?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        RangeResponse {
+            messages,
+            truncated,
+        },
+    ))
+}
+
+/// The messages of the window at `at`, newest first: those created by then that came after
+/// the newest one `version` summarized. Only the newest `MAX_LIMIT` are kept, and whether
+/// there were more.
+async fn window_at(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    at: u64,
+    version: Option<&ContextVersion>,
+) -> Result<(Vec<MemoryMessage>, bool), MotorheadError> {
+    let cutoff_id = version.and_then(|version| version.last_message_id.as_deref());
+    let mut window = VecDeque::new();
+    let mut truncated = false;
+    let mut found_cutoff = false;
+    let mut push = |message: MemoryMessage| {
+        if cutoff_id.is_some() && message.id.as_deref() == cutoff_id {
+            window.clear();
+            truncated = false;
+            found_cutoff = true;
+        } else if message
+            .created_at
+            .is_some_and(|created_at| created_at <= at)
+        {
+            if window.len() == MAX_LIMIT {
+                window.pop_front();
+                truncated = true;
+            }
+            window.push_back(message);
+        }
+    };
+
+    let mut offset = 0;
+    loop {
+        let page =
+            cold_storage::get_history(state, store, session_id, offset, HISTORY_PAGE_SIZE).await?;
+        let page_len = page.len();
+        page.into_iter().for_each(&mut push);
+
+        if page_len < HISTORY_PAGE_SIZE {
+            break;
+        }
+        offset += page_len;
+    }
+    let current = store.get_messages(session_id, 0, -1).await?;
+    current.into_iter().rev().for_each(&mut push);
+
+    // The newest summarized message was deleted since, tell by its timestamp instead.
+    if let (false, Some(last_created_at)) = (
+        found_cutoff,
+        version.and_then(|version| version.last_created_at),
+    ) {
+        window.retain(|message| {
+            message
+                .created_at
+                .is_some_and(|created_at| created_at > last_created_at)
+        });
+    }
+
+    Ok((window.into_iter().rev().collect(), truncated))
+}
+
+/// What the window and context of the session were at `at`, e.g. to find out why the agent
+/// answered the way it did then. Rebuilt from the history and the contexts compactions stored.
+#[get("/sessions/{session_id}/replay")]
+pub async fn get_replay(
+    session_id: web::Path<String>,
+    query: web::Query<ReplayQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if !data.history_enabled {
+        return Err(ApiError::new(ErrorCode::FeatureDisabled, "History is not enabled").into());
+    }
+    let at = parse_timestamp("at", Some(&query.at))?.unwrap_or_default();
+
+    let store = tenant.store(&data);
+    let version = store
+        .get_context_versions(&session_id)
+        .await?
+        .into_iter()
+        .rev()
+        .find(|version| version.at <= at);
+    let (messages, truncated) =
+        window_at(&data, store.as_ref(), &session_id, at, version.as_ref()).await?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        ReplayResponse {
+            at,
+            messages,
+            context_at: version.as_ref().map(|version| version.at),
+            context: version.map(|version| version.context),
+            truncated,
+        },
+    ))
+}
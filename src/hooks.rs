@@ -0,0 +1,166 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{AppState, MemoryMessage, MotorheadError};
+
+/// Custom processing of messages on their way into and out of the store (enrichment,
+/// translation...), without changing the handlers. Both stages leave messages as they are
+/// unless implemented.
+#[async_trait]
+pub trait MessageHook: Send + Sync {
+    /// Runs on appended messages, oldest first, after moderation and redaction and before
+    /// they're stored. It may change, add or drop messages.
+    async fn pre_write(
+        &self,
+        _session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        Ok(messages)
+    }
+
+    /// Runs on the messages of a read, newest first, before they're returned. What it changes
+    /// isn't stored.
+    async fn post_read(
+        &self,
+        _session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        Ok(messages)
+    }
+}
+
+/// The hooks of `MOTORHEAD_MESSAGE_HOOKS`, run in the order they're listed.
+pub struct MessageHooks {
+    hooks: Vec<Box<dyn MessageHook>>,
+}
+
+impl MessageHooks {
+    pub fn new(hooks: Vec<Box<dyn MessageHook>>) -> Self {
+        MessageHooks { hooks }
+    }
+}
+
+/// Runs appended messages through the pre-write hooks, if any.
+pub async fn pre_write(
+    state: &AppState,
+    session_id: &str,
+    mut messages: Vec<MemoryMessage>,
+) -> Result<Vec<MemoryMessage>, MotorheadError> {
+    for hook in state.message_hooks.iter().flat_map(|hooks| &hooks.hooks) {
+        messages = hook.pre_write(session_id, messages).await?;
+    }
+    Ok(messages)
+}
+
+/// Runs read messages through the post-read hooks, if any.
+pub async fn post_read(
+    state: &AppState,
+    session_id: &str,
+    mut messages: Vec<MemoryMessage>,
+) -> Result<Vec<MemoryMessage>, MotorheadError> {
+    for hook in state.message_hooks.iter().flat_map(|hooks| &hooks.hooks) {
+        messages = hook.post_read(session_id, messages).await?;
+    }
+    Ok(messages)
+}
+
+/// Trims the whitespace around the content of appended messages.
+pub struct TrimHook;
+
+#[async_trait]
+impl MessageHook for TrimHook {
+    async fn pre_write(
+        &self,
+        _session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        Ok(messages
+            .into_iter()
+            .map(|mut message| {
+                let trimmed = message.content.trim();
+                if trimmed.len() < message.content.len() {
+                    message.content = trimmed.to_string();
+                }
+                message
+            })
+            .collect())
+    }
+}
+
+/// Posts `{"stage", "session_id", "messages"}` to a URL at both stages, which answers with the
+/// messages to carry on with as `{"messages": [...]}`.
+pub struct WebhookHook {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl WebhookHook {
+    pub fn new(url: String) -> Self {
+        WebhookHook {
+            http: reqwest::Client::new(),
+            url,
+        }
+    }
+
+    async fn call(
+        &self,
+        stage: &str,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let hook_error = |e: reqwest::Error| MotorheadError::HookError(e.to_string());
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&WebhookRequest {
+                stage,
+                session_id,
+                messages: &messages,
+            })
+            .send()
+            .await
+            .map_err(hook_error)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(MotorheadError::HookError(format!(
+                "Message hook webhook returned {}",
+                status
+            )));
+        }
+
+        let response: WebhookResponse = response.json().await.map_err(hook_error)?;
+        Ok(response.messages)
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookRequest<'a> {
+    stage: &'a str,
+    session_id: &'a str,
+    messages: &'a [MemoryMessage],
+}
+
+#[derive(Deserialize)]
+struct WebhookResponse {
+    messages: Vec<MemoryMessage>,
+}
+
+#[async_trait]
+impl MessageHook for WebhookHook {
+    async fn pre_write(
+        &self,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        self.call("pre_write", session_id, messages).await
+    }
+
+    async fn post_read(
+        &self,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        self.call("post_read", session_id, messages).await
+    }
+}
@@ -0,0 +1,241 @@
+use std::cmp::{Ordering, Reverse};
+
+use crate::llm::CompletionRequest;
+use crate::models::{AppState, MemoryMessage, MotorheadError, Role, SummaryOptions, TokenUsage};
+use crate::store::MemoryStore;
+
+/// How compactions score the messages they take out of the window, picked with
+/// `MOTORHEAD_IMPORTANCE_SCORING`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportanceScorer {
+    /// Looks for what tends to matter later: things to remember, preferences and details like
+    /// numbers, emails and links.
+    Heuristic,
+    /// Asks the LLM, which counts towards the token budget.
+    Llm,
+}
+
+/// Keeps the messages a compaction scores at least `threshold` verbatim, as pinned messages,
+/// instead of summarizing them. Up to `limit` per session, the least important are
+/// summarized by the next compaction beyond it.
+pub struct ImportanceRetention {
+    pub scorer: ImportanceScorer,
+    pub threshold: f32,
+    pub limit: usize,
+}
+
+pub const IMPORTANCE_PROMPT: &str = r#"
+        Rate how important each numbered line of conversation provided is to remember word for word later, from 0 for small talk to 1 for what a summary could lose and shouldn't: facts about the user, preferences, decisions, commitments, and details like names, numbers and dates. Return a JSON array with one number per line, in order.
+
+        Lines of conversation:
+        {messages}
+        Scores:
+        "#;
+
+/// Phrases asking for something to be kept in mind.
+const REMEMBER_CUES: [&str; 9] = [
+    "remember",
+    "don't forget",
+    "do not forget",
+    "important",
+    "note that",
+    "make sure",
+    "from now on",
+    "always",
+    "never",
+];
+
+/// Phrases stating something about the user or what was agreed.
+const FACT_CUES: [&str; 14] = [
+    "my name",
+    "call me",
+    "i prefer",
+    "i like",
+    "i don't like",
+    "i hate",
+    "allergic",
+    "i live",
+    "i work",
+    "my email",
+    "my phone",
+    "my address",
+    "deadline",
+    "we agreed",
+];
+
+fn heuristic_score(message: &MemoryMessage) -> f32 {
+    // Tool results are too long and too verbatim to be worth keeping.
+    if message.role == Role::Tool {
+        return 0.0;
+    }
+    let content = message.content.to_lowercase();
+    let mut score = 0.0;
+    if REMEMBER_CUES.iter().any(|cue| content.contains(cue)) {
+        score += 0.5;
+    }
+    if FACT_CUES.iter().any(|cue| content.contains(cue)) {
+        score += 0.5;
+    }
+    if content.chars().any(|c| c.is_ascii_digit()) {
+        score += 0.2;
+    }
+    if content.contains('@') || content.contains("http://") || content.contains("https://") {
+        score += 0.2;
+    }
+    f32::min(score, 1.0)
+}
+
+/// Parses the scores out of a completion, tolerating text around the JSON array.
+fn parse_scores(completion: &str, expected: usize) -> Result<Vec<f32>, MotorheadError> {
+    let array = match (completion.find('['), completion.rfind(']')) {
+        (Some(start), Some(end)) if start < end => &completion[start..=end],
+        _ => {
+            return Err(MotorheadError::LlmError(
+                "No importance scores in the completion".to_string(),
+            ))
+        }
+    };
+
+    let scores: Vec<f32> = serde_json::from_str(array)
+        .map_err(|e| MotorheadError::LlmError(format!("Invalid importance scores: {}", e)))?;
+    if scores.len() != expected {
+        return Err(MotorheadError::LlmError(format!(
+            "Expected {} importance scores, got {}",
+            expected,
+            scores.len()
+        )));
+    }
+    Ok(scores
+        .into_iter()
+        .map(|score| score.clamp(0.0, 1.0))
+        .collect())
+}
+
+/// The score of each of `messages`, in order.
+async fn score_messages(
+    state: &AppState,
+    scorer: ImportanceScorer,
+    messages: &[MemoryMessage],
+    options: &SummaryOptions,
+    usage: &mut TokenUsage,
+) -> Result<Vec<f32>, MotorheadError> {
+    if scorer == ImportanceScorer::Heuristic {
+        return Ok(messages.iter().map(heuristic_score).collect());
+    }
+
+    let lines: Vec<String> = messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| {
+            format!(
+                "{}. {}",
+                index + 1,
+                message.transcript_line(state.summarize_attachments)
+            )
+        })
+        .collect();
+    let prompt = IMPORTANCE_PROMPT.replace("{messages}", &lines.join("\n"));
+    let completion = state
+        .llm
+        .complete(CompletionRequest {
+            system: "You are a helpful AI assistant.",
+            prompt: &prompt,
+            max_tokens: (8 * messages.len() + 16).min(u16::MAX as usize) as u16,
+            model: options.model.as_deref(),
+            temperature: options.temperature,
+        })
+        .await?;
+    *usage += completion.usage;
+
+    parse_scores(&completion.content, messages.len())
+}
+
+/// What retention leaves for a compaction to do.
+pub struct Retained {
+    /// The session's messages pinned for their importance from now on.
+    pub auto_pinned: Vec<MemoryMessage>,
+    /// What's left to summarize, newest first: the messages taken out of the window that
+    /// aren't kept, then the ones pinned until now that no longer fit.
+    pub summarized: Vec<MemoryMessage>,
+    /// The transcript lines of `summarized`.
+    pub lines: Vec<String>,
+}
+
+/// Scores `summarized` (newest first, with their transcript `lines`) and picks the ones to
+/// keep verbatim among them and the session's messages already pinned for their importance.
+/// `None` if none of `summarized` make it, which leaves the pinned ones as they were, or if
+/// `MOTORHEAD_IMPORTANCE_SCORING` is off.
+pub async fn retain_important(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    summarized: &[MemoryMessage],
+    lines: &[String],
+    options: &SummaryOptions,
+    usage: &mut TokenUsage,
+) -> Result<Option<Retained>, MotorheadError> {
+    let Some(retention) = &state.importance_retention else {
+        return Ok(None);
+    };
+    let scores = score_messages(state, retention.scorer, summarized, options, usage).await?;
+    let candidates: Vec<MemoryMessage> = summarized
+        .iter()
+        .zip(scores)
+        .filter(|(message, score)| *score >= retention.threshold && message.id.is_some())
+        .map(|(message, score)| MemoryMessage {
+            importance: Some(score),
+            ..message.clone()
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    // The most important first, the newest of the same importance.
+    let mut ranked = store.get_auto_pinned(session_id).await?;
+    ranked.extend(candidates);
+    ranked.sort_by(|a, b| {
+        b.importance
+            .partial_cmp(&a.importance)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| b.created_at.cmp(&a.created_at))
+    });
+    let mut evicted = ranked.split_off(retention.limit.min(ranked.len()));
+    if !ranked.iter().any(|message| {
+        summarized
+            .iter()
+            .any(|summarized| summarized.id == message.id)
+    }) {
+        return Ok(None);
+    }
+
+    let (mut summarized, mut lines): (Vec<MemoryMessage>, Vec<String>) = summarized
+        .iter()
+        .zip(lines)
+        .filter(|(message, _)| !ranked.iter().any(|kept| kept.id == message.id))
+        .map(|(message, line)| (message.clone(), line.clone()))
+        .unzip();
+    // Pinned by earlier compactions, so older than what this one takes out of the window.
+    evicted.retain(|message| {
+        !summarized
+            .iter()
+            .any(|summarized| summarized.id == message.id)
+    });
+    evicted.sort_by_key(|message| Reverse(message.created_at));
+    lines.extend(
+        evicted
+            .iter()
+            .map(|message| message.transcript_line(state.summarize_attachments)),
+    );
+    summarized.extend(evicted.into_iter().map(|message| MemoryMessage {
+        importance: None,
+        ..message
+    }));
+
+    ranked.sort_by_key(|message| message.created_at);
+    Ok(Some(Retained {
+        auto_pinned: ranked,
+        summarized,
+        lines,
+    }))
+}
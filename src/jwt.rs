@@ -0,0 +1,283 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature::{self, RsaParameters, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+use crate::tenant::is_valid_tenant;
+
+/// Unknown key ids refetch the keys at most this often, so tokens signed with made up ids
+/// can't hammer the identity provider.
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How bearer tokens that aren't API keys are validated as JWTs, from the `MOTORHEAD_JWT_*`
+/// variables.
+pub struct JwtConfig {
+    pub jwks_url: String,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// The claim naming the tenant of the token's requests, if any.
+    pub tenant_claim: Option<String>,
+    pub leeway_seconds: u64,
+    /// How long the fetched keys are used before fetching them again.
+    pub refresh_interval: Duration,
+}
+
+#[derive(Clone, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+#[derive(Default)]
+struct KeyCache {
+    keys: Vec<Jwk>,
+    fetched_at: Option<Instant>,
+    /// The last fetch, successful or not.
+    attempted_at: Option<Instant>,
+}
+
+/// What a valid token tells about its bearer.
+pub struct Claims {
+    pub subject: String,
+    pub tenant: Option<String>,
+}
+
+pub struct JwtValidator {
+    config: JwtConfig,
+    http: reqwest::Client,
+    cache: RwLock<KeyCache>,
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, &'static str> {
+    URL_SAFE_NO_PAD.decode(part).map_err(|_| "Invalid token")
+}
+
+fn now_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Checks the signature of `message` with `jwk`, for the algorithms identity providers
+/// sign with. Symmetric ones (`HS*`) and `none` aren't accepted.
+fn verify(jwk: &Jwk, alg: &str, message: &[u8], signature: &[u8]) -> Result<(), &'static str> {
+    let component = |value: &Option<String>| -> Result<Vec<u8>, &'static str> {
+        decode(value.as_deref().ok_or("Invalid signing key")?)
+    };
+    let rsa = |parameters: &'static RsaParameters| -> Result<(), &'static str> {
+        if jwk.kty != "RSA" {
+            return Err("Invalid signing key");
+        }
+        RsaPublicKeyComponents {
+            n: component(&jwk.n)?,
+            e: component(&jwk.e)?,
+        }
+        .verify(parameters, message, signature)
+        .map_err(|_| "Invalid token signature")
+    };
+    let ec = |crv: &str,
+              algorithm: &'static signature::EcdsaVerificationAlgorithm|
+     -> Result<(), &'static str> {
+        if jwk.kty != "EC" || jwk.crv.as_deref() != Some(crv) {
+            return Err("Invalid signing key");
+        }
+        // An uncompressed point.
+        let mut point = vec![0x04];
+        point.extend(component(&jwk.x)?);
+        point.extend(component(&jwk.y)?);
+        UnparsedPublicKey::new(algorithm, point)
+            .verify(message, signature)
+            .map_err(|_| "Invalid token signature")
+    };
+
+    match alg {
+        "RS256" => rsa(&signature::RSA_PKCS1_2048_8192_SHA256),
+        "RS384" => rsa(&signature::RSA_PKCS1_2048_8192_SHA384),
+        "RS512" => rsa(&signature::RSA_PKCS1_2048_8192_SHA512),
+        "PS256" => rsa(&signature::RSA_PSS_2048_8192_SHA256),
+        "PS384" => rsa(&signature::RSA_PSS_2048_8192_SHA384),
+        "PS512" => rsa(&signature::RSA_PSS_2048_8192_SHA512),
+        "ES256" => ec("P-256", &signature::ECDSA_P256_SHA256_FIXED),
+        "ES384" => ec("P-384", &signature::ECDSA_P384_SHA384_FIXED),
+        "EdDSA" => {
+            if jwk.kty != "OKP" || jwk.crv.as_deref() != Some("Ed25519") {
+                return Err("Invalid signing key");
+            }
+            UnparsedPublicKey::new(&signature::ED25519, component(&jwk.x)?)
+                .verify(message, signature)
+                .map_err(|_| "Invalid token signature")
+        }
+        _ => Err("Unsupported token algorithm"),
+    }
+}
+
+/// Whether the `aud` claim, a string or an array of them, has `audience`.
+fn has_audience(claim: Option<&Value>, audience: &str) -> bool {
+    match claim {
+        Some(Value::String(aud)) => aud == audience,
+        Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+        _ => false,
+    }
+}
+
+impl JwtValidator {
+    pub fn new(config: JwtConfig) -> Self {
+        JwtValidator {
+            config,
+            http: reqwest::Client::new(),
+            cache: RwLock::new(KeyCache::default()),
+        }
+    }
+
+    async fn fetch_keys(&self) -> Result<Vec<Jwk>, reqwest::Error> {
+        let jwks: Jwks = self
+            .http
+            .get(&self.config.jwks_url)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(jwks.keys)
+    }
+
+    /// The key the token was signed with: the one with its `kid`, or the only one of the
+    /// JWKS. The keys are fetched again once stale, or when the `kid` is unknown.
+    async fn find_key(&self, kid: Option<&str>) -> Result<Jwk, &'static str> {
+        let find = |keys: &[Jwk]| match kid {
+            Some(kid) => keys
+                .iter()
+                .find(|key| key.kid.as_deref() == Some(kid))
+                .cloned(),
+            None if keys.len() == 1 => keys.first().cloned(),
+            None => None,
+        };
+
+        {
+            let cache = self.cache.read().await;
+            let fresh = cache
+                .fetched_at
+                .is_some_and(|at| at.elapsed() < self.config.refresh_interval);
+            if fresh {
+                if let Some(key) = find(&cache.keys) {
+                    return Ok(key);
+                }
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        // Another request may have refetched while this one waited for the lock.
+        let recent = cache
+            .attempted_at
+            .is_some_and(|at| at.elapsed() < MIN_REFETCH_INTERVAL);
+        if !recent {
+            cache.attempted_at = Some(Instant::now());
+            match self.fetch_keys().await {
+                Ok(keys) => {
+                    cache.keys = keys;
+                    cache.fetched_at = Some(Instant::now());
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "Error fetching the JWT signing keys");
+                    // The keys fetched before are still used meanwhile.
+                    if cache.keys.is_empty() {
+                        return Err("Signing keys unavailable");
+                    }
+                }
+            }
+        }
+
+        find(&cache.keys).ok_or("Unknown token signing key")
+    }
+
+    pub async fn validate(&self, token: &str) -> Result<Claims, &'static str> {
+        let mut parts = token.split('.');
+        let (Some(encoded_header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("Invalid token");
+        };
+
+        let header: Header =
+            serde_json::from_slice(&decode(encoded_header)?).map_err(|_| "Invalid token")?;
+        let key = self.find_key(header.kid.as_deref()).await?;
+        let signed = &token[..token.len() - signature.len() - 1];
+        verify(&key, &header.alg, signed.as_bytes(), &decode(signature)?)?;
+
+        let claims: Value =
+            serde_json::from_slice(&decode(payload)?).map_err(|_| "Invalid token")?;
+        let now = now_seconds();
+        let leeway = self.config.leeway_seconds;
+        let exp = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .ok_or("Token has no expiry")?;
+        if exp + leeway <= now {
+            return Err("Expired token");
+        }
+        if claims
+            .get("nbf")
+            .and_then(Value::as_u64)
+            .is_some_and(|nbf| nbf > now + leeway)
+        {
+            return Err("Token not valid yet");
+        }
+        if let Some(issuer) = &self.config.issuer {
+            if claims.get("iss").and_then(Value::as_str) != Some(issuer.as_str()) {
+                return Err("Invalid token issuer");
+            }
+        }
+        if let Some(audience) = &self.config.audience {
+            if !has_audience(claims.get("aud"), audience) {
+                return Err("Invalid token audience");
+            }
+        }
+
+        let tenant = match &self.config.tenant_claim {
+            Some(claim) => {
+                let tenant = claims
+                    .get(claim)
+                    .and_then(Value::as_str)
+                    .filter(|tenant| is_valid_tenant(tenant))
+                    .ok_or("Invalid token tenant claim")?;
+                Some(tenant.to_string())
+            }
+            None => None,
+        };
+        let subject = claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+
+        Ok(Claims { subject, tenant })
+    }
+}
@@ -0,0 +1,401 @@
+use sha1::{Digest, Sha1};
+
+/// Hash holding `hashed id -> original session id`, so hashed keys can be mapped back.
+const SESSION_IDS_KEY: &str = "motorhead_session_ids";
+
+/// Sorted set of session key ids scored by last activity (unix ms).
+const SESSIONS_KEY: &str = "motorhead_sessions";
+
+/// Hash of `{tenant}:{session id} -> CompactionFailure` JSON, shared by all tenants.
+const COMPACTION_FAILURES_KEY: &str = "motorhead_compaction_failures";
+
+/// Prefix of the `Recap` JSON of each user's days (`{prefix}{{user id}}:{YYYY-MM-DD}`), and of
+/// the sorted set of the days each user has one for (`{prefix}{{user id}}`). The user id is
+/// hash-tagged so both are on the same cluster slot.
+const RECAPS_PREFIX: &str = "motorhead_recaps:";
+
+/// Stream of `AuditEntry` JSON, shared by all tenants.
+const AUDIT_KEY: &str = "motorhead_audit";
+
+/// When an instance last wrote to the primary (unix ms), read from the replica to tell
+/// how far behind it is.
+const HEARTBEAT_KEY: &str = "motorhead_heartbeat";
+
+/// Hash of the summarization settings shared by all instances, see `shared_config`.
+const SHARED_CONFIG_KEY: &str = "motorhead_config";
+
+/// Hash of `tenant -> key` of the tenants' own encryption keys, see `TenantKeys`.
+const TENANT_KEYS_KEY: &str = "motorhead_tenant_keys";
+
+/// Prefix of the token bucket hashes of the rate limiter.
+const RATE_LIMIT_PREFIX: &str = "motorhead_rate_limit:";
+
+/// Prefix of the markers expiring along with sessions. Their names
+/// (`{prefix}{tenant}:{session id}`) tell which session expired.
+const EXPIRY_PREFIX: &str = "motorhead_expiry:";
+
+/// Prefix of the hashes of each month's token usage (`{prefix}{YYYY-MM}`), with fields
+/// `{tenant}:prompt_tokens` and `{tenant}:completion_tokens`.
+const USAGE_PREFIX: &str = "motorhead_usage:";
+
+/// Prefix of the hashes of each day's analytics (`{prefix}{YYYY-MM-DD}`), with fields
+/// `{tenant}:{counter}`, see `AnalyticsCounts`.
+const ANALYTICS_PREFIX: &str = "motorhead_analytics:";
+
+/// Namespace soft-deleted sessions are moved to, under the configured namespace if any.
+const TRASH_NAMESPACE: &str = "motorhead_trash";
+
+/// RediSearch index over the message vectors.
+pub const VECTOR_INDEX: &str = "motorhead_vectors";
+
+/// Prefix of the hashes indexed by `VECTOR_INDEX`.
+pub const VECTOR_PREFIX: &str = "motorhead_vector:";
+
+/// Builds the Redis keys used for a session. When hashing is enabled the session id is
+/// replaced by a truncated sha1 of it, which keeps key names short for long ids. Keys of a
+/// tenant are prefixed with `{tenant}:`.
+///
+/// On a Redis Cluster the session's part of the key names is hash-tagged (`{session id}`), so
+/// that all of a session's keys land on the same slot and can be pipelined together.
+///
+/// With a namespace every key lives under `{namespace}:` and the session's keys are joined
+/// with `:` (`{namespace}:{session id}:context`) instead of the bare `{session id}_context`.
+#[derive(Clone)]
+pub struct SessionKeys {
+    hash_length: Option<usize>,
+    tenant: Option<String>,
+    namespace: Option<String>,
+    hash_tag: bool,
+}
+
+impl SessionKeys {
+    pub fn new(hash_length: Option<usize>, namespace: Option<String>, hash_tag: bool) -> Self {
+        SessionKeys {
+            hash_length,
+            tenant: None,
+            namespace,
+            hash_tag,
+        }
+    }
+
+    pub fn for_tenant(&self, tenant: &str) -> Self {
+        SessionKeys {
+            tenant: Some(tenant.to_string()),
+            ..self.clone()
+        }
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// The keys sessions used before a namespace was configured, if one is. Clusters have
+    /// none: moving keys across slots isn't possible.
+    pub fn legacy(&self) -> Option<Self> {
+        if self.hash_tag {
+            return None;
+        }
+
+        self.namespace.as_ref().map(|_| SessionKeys {
+            namespace: None,
+            ..self.clone()
+        })
+    }
+
+    /// The names a session's keys are moved to when it's soft-deleted.
+    pub fn trashed(&self) -> Self {
+        let namespace = match &self.namespace {
+            Some(namespace) => format!("{}:trash", namespace),
+            None => TRASH_NAMESPACE.to_string(),
+        };
+
+        SessionKeys {
+            namespace: Some(namespace),
+            ..self.clone()
+        }
+    }
+
+    /// Prefixed to the names of the vector hashes of a soft-deleted session, which takes them
+    /// out of `VECTOR_INDEX`.
+    pub fn trashed_vector_prefix(&self) -> String {
+        self.trashed().namespaced("")
+    }
+
+    fn namespaced(&self, key: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}:{}", namespace, key),
+            None => key.to_string(),
+        }
+    }
+
+    fn scoped(&self, key: &str) -> String {
+        match &self.tenant {
+            Some(tenant) => self.namespaced(&format!("{}:{}", tenant, key)),
+            None => self.namespaced(key),
+        }
+    }
+
+    /// Names a global key, dropping the redundant `motorhead_` once namespaced.
+    fn global(&self, key: &str) -> String {
+        match &self.namespace {
+            Some(_) => key.trim_start_matches("motorhead_").to_string(),
+            None => key.to_string(),
+        }
+    }
+
+    /// Names one of the session's keys other than its message list.
+    fn suffixed(&self, session_id: &str, suffix: &str) -> String {
+        match &self.namespace {
+            Some(_) => format!("{}:{}", self.base(session_id), suffix),
+            None => format!("{}_{}", self.base(session_id), suffix),
+        }
+    }
+
+    pub fn session_ids(&self) -> String {
+        self.scoped(&self.global(SESSION_IDS_KEY))
+    }
+
+    pub fn sessions(&self) -> String {
+        self.scoped(&self.global(SESSIONS_KEY))
+    }
+
+    /// Not scoped by tenant: bucket names already are where needed.
+    pub fn rate_limit(&self, bucket: &str) -> String {
+        self.namespaced(&format!("{}{}", self.global(RATE_LIMIT_PREFIX), bucket))
+    }
+
+    /// Not scoped by tenant: the failures of every tenant are retried by the same worker.
+    pub fn compaction_failures(&self) -> String {
+        self.namespaced(&self.global(COMPACTION_FAILURES_KEY))
+    }
+
+    pub fn recaps(&self, user_id: &str) -> String {
+        self.scoped(&format!("{}{{{}}}", self.global(RECAPS_PREFIX), user_id))
+    }
+
+    pub fn recap(&self, user_id: &str, date: &str) -> String {
+        format!("{}:{}", self.recaps(user_id), date)
+    }
+
+    /// Not scoped by tenant: replication doesn't depend on it.
+    pub fn heartbeat(&self) -> String {
+        self.namespaced(&self.global(HEARTBEAT_KEY))
+    }
+
+    /// Not scoped by tenant, like the runtime settings it holds.
+    pub fn shared_config(&self) -> String {
+        self.namespaced(&self.global(SHARED_CONFIG_KEY))
+    }
+
+    /// Not scoped by tenant: the keys of every tenant are loaded together.
+    pub fn tenant_keys(&self) -> String {
+        self.namespaced(&self.global(TENANT_KEYS_KEY))
+    }
+
+    /// Not scoped by tenant: the audit log is read by admins, for every tenant.
+    pub fn audit(&self) -> String {
+        self.namespaced(&self.global(AUDIT_KEY))
+    }
+
+    /// Not scoped by tenant: the admin usage report lists every tenant's.
+    pub fn usage(&self, month: &str) -> String {
+        self.namespaced(&format!("{}{}", self.global(USAGE_PREFIX), month))
+    }
+
+    /// Not scoped by tenant, like `usage`.
+    pub fn analytics(&self, day: &str) -> String {
+        self.namespaced(&format!("{}{}", self.global(ANALYTICS_PREFIX), day))
+    }
+
+    pub fn expiry(&self, session_id: &str) -> String {
+        self.namespaced(&format!(
+            "{}{}:{}",
+            self.global(EXPIRY_PREFIX),
+            self.tenant.as_deref().unwrap_or_default(),
+            session_id
+        ))
+    }
+
+    /// The tenant and session id an expiry marker stands for.
+    pub fn parse_expiry(&self, key: &str) -> Option<(Option<String>, String)> {
+        let marker = key.strip_prefix(&self.namespaced(&self.global(EXPIRY_PREFIX)))?;
+        let (tenant, session_id) = marker.split_once(':')?;
+        Some((
+            (!tenant.is_empty()).then(|| tenant.to_string()),
+            session_id.to_string(),
+        ))
+    }
+
+    /// Whether the keys of different sessions may live on different cluster slots.
+    pub fn is_hash_tagged(&self) -> bool {
+        self.hash_tag
+    }
+
+    pub fn is_hashed(&self) -> bool {
+        self.hash_length.is_some()
+    }
+
+    pub fn id(&self, session_id: &str) -> String {
+        match self.hash_length {
+            Some(length) => {
+                let digest = Sha1::digest(session_id.as_bytes());
+                let mut hashed: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+                hashed.truncate(length);
+                hashed
+            }
+            None => session_id.to_string(),
+        }
+    }
+
+    /// The session's id as it appears in key names, including the tenant prefix.
+    pub fn base(&self, session_id: &str) -> String {
+        if self.hash_tag {
+            self.scoped(&format!("{{{}}}", self.id(session_id)))
+        } else {
+            self.scoped(&self.id(session_id))
+        }
+    }
+
+    pub fn messages(&self, session_id: &str) -> String {
+        self.base(session_id)
+    }
+
+    pub fn context(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "context")
+    }
+
+    /// The summary older contexts were folded into.
+    pub fn long_term_context(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "context_l2")
+    }
+
+    /// Hash of the session's `prompt_tokens` and `completion_tokens`.
+    pub fn session_usage(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "usage")
+    }
+
+    /// Hash of how many of the session's messages were tagged with each language.
+    pub fn languages(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "languages")
+    }
+
+    pub fn context_versions(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "context_versions")
+    }
+
+    /// Hash of when the session was last compacted (`at`, ms) and its compaction `count`.
+    pub fn compaction(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "compaction")
+    }
+
+    /// JSON of the chunks summarized so far by a split compaction, see `CompactionProgress`.
+    pub fn compaction_progress(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "compaction_progress")
+    }
+
+    /// JSON array of the session's `ContextSegment`s.
+    pub fn context_segments(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "context_segments")
+    }
+
+    pub fn metadata(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "metadata")
+    }
+
+    /// The session's settings, see `SessionConfig`.
+    pub fn config(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "config")
+    }
+
+    /// Hash of the facts extracted from the session's messages.
+    pub fn entities(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "entities")
+    }
+
+    /// Hash of `key -> value JSON` of the session's key-value memory.
+    pub fn kv(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "kv")
+    }
+
+    /// Hash of `message id -> message JSON` of the session's pinned messages.
+    pub fn pinned(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "pinned")
+    }
+
+    /// Hash of `message id -> message JSON` of the messages compactions pinned for their
+    /// importance.
+    pub fn pinned_auto(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "pinned_auto")
+    }
+
+    /// List of the session's `ColdSegment` JSONs, oldest first.
+    pub fn cold(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "cold")
+    }
+
+    /// List of the messages compactions moved out of the window, oldest first.
+    pub fn history(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "history")
+    }
+
+    /// Counter of the messages appended since the last compaction.
+    pub fn unsummarized(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "unsummarized")
+    }
+
+    /// Marker of an idempotency key already seen for the session.
+    pub fn idempotency(&self, session_id: &str, key: &str) -> String {
+        self.suffixed(session_id, &format!("idempotency:{}", key))
+    }
+
+    /// Marker of a soft-deleted session, expiring when it can no longer be restored. Only
+    /// used with `trashed` keys.
+    pub fn deleted(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "deleted")
+    }
+
+    /// Random token replaced on every change to the session, see `MemoryStore::session_version`.
+    pub fn version(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "version")
+    }
+
+    /// The session's lease, `{token}:{expiry}`. Not one of the session's own keys, so it's
+    /// kept when the session is deleted or moved.
+    pub fn lock(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "lock")
+    }
+
+    /// Token of the session's pending delete intent, expiring on its own. Not one of the
+    /// session's own keys, like `lock`.
+    pub fn delete_intent(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "delete_intent")
+    }
+
+    /// Token of the instance compacting the session, expiring unless it's renewed. Not one of
+    /// the session's own keys, like `lock`.
+    pub fn compaction_claim(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "compaction_claim")
+    }
+
+    /// Pub/sub channel the session's change events are published on.
+    pub fn events(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "events")
+    }
+
+    /// Matches the `events` channels of every session, across tenants.
+    pub fn events_pattern(&self) -> String {
+        match &self.namespace {
+            Some(_) => self.namespaced("*:events"),
+            None => "*_events".to_string(),
+        }
+    }
+
+    /// Set of the vector hash keys stored for the session.
+    pub fn vectors(&self, session_id: &str) -> String {
+        self.suffixed(session_id, "vectors")
+    }
+
+    pub fn vector(&self, session_id: &str, vector_id: &str) -> String {
+        format!("{}{}:{}", VECTOR_PREFIX, self.base(session_id), vector_id)
+    }
+}
@@ -0,0 +1,75 @@
+use actix_web::{delete, get, put, web, HttpResponse, Responder};
+use std::sync::Arc;
+
+use crate::errors::ApiError;
+use crate::models::{AckResponse, AppState, KvResponse};
+use crate::response::read_response;
+use crate::tenant::Tenant;
+
+/// Longest key accepted, in bytes.
+const MAX_KEY_LENGTH: usize = 256;
+
+#[get("/sessions/{session_id}/kv/{key}")]
+pub async fn get_kv(
+    path: web::Path<(String, String)>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let (session_id, key) = path.into_inner();
+    let value = tenant
+        .store(&data)
+        .get_kv(&session_id, &key)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Key not found"))?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        KvResponse { value },
+    ))
+}
+
+/// Sets `key` to the JSON body, whatever its type, in the session's key-value memory.
+#[put("/sessions/{session_id}/kv/{key}")]
+pub async fn put_kv(
+    path: web::Path<(String, String)>,
+    web::Json(value): web::Json<serde_json::Value>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let (session_id, key) = path.into_inner();
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(ApiError::invalid_request(format!(
+            "Keys are limited to {} bytes",
+            MAX_KEY_LENGTH
+        ))
+        .into());
+    }
+
+    tenant
+        .store(&data)
+        .set_kv(&session_id, &key, &value)
+        .await?;
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+#[delete("/sessions/{session_id}/kv/{key}")]
+pub async fn delete_kv(
+    path: web::Path<(String, String)>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let (session_id, key) = path.into_inner();
+    if !tenant.store(&data).delete_kv(&session_id, &key).await? {
+        return Err(ApiError::not_found("Key not found").into());
+    }
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
@@ -0,0 +1,202 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use crate::models::{AppState, MemoryMessage};
+
+/// Common short words of the languages written in the Latin script, which tell them apart
+/// better than letter frequencies on chat-length texts.
+const STOPWORDS: [(&str, &[&str]); 8] = [
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "you", "to", "of", "it", "that", "this", "what", "with",
+            "for", "have", "was", "can", "not", "my", "do", "how",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "la", "los", "las", "que", "y", "es", "de", "en", "un", "una", "por", "con",
+            "para", "pero", "como", "está", "muy", "qué", "yo",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "et", "est", "de", "des", "un", "une", "je", "vous", "pas", "que",
+            "pour", "avec", "dans", "ce", "qui", "sur", "mais",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "ich", "nicht", "sie", "es", "ein", "eine", "zu",
+            "mit", "den", "auf", "für", "wie", "was", "auch", "du",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "lo", "la", "gli", "e", "è", "di", "che", "un", "una", "non", "per", "con",
+            "sono", "come", "mi", "ma", "ho", "questo", "anche",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "a", "os", "as", "e", "é", "de", "que", "um", "uma", "não", "para", "com", "em",
+            "do", "da", "eu", "você", "mas", "está",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "is", "ik", "je", "niet", "van", "dat", "op", "te", "met",
+            "zijn", "voor", "maar", "wat", "hoe", "ook", "er",
+        ],
+    ),
+    (
+        "tr",
+        &[
+            "ve", "bir", "bu", "da", "de", "için", "ne", "ile", "mi", "ben", "sen", "çok", "var",
+            "yok", "ama", "gibi", "daha", "nasıl", "evet", "değil",
+        ],
+    ),
+];
+
+/// Letters only some of the Latin-script languages use, each worth a stopword.
+const LETTERS: [(&str, &str); 6] = [
+    ("es", "ñ¿¡"),
+    ("fr", "œùû"),
+    ("de", "ßäöü"),
+    ("pt", "ãõ"),
+    ("nl", "ĳ"),
+    ("tr", "ğış"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+    Thai,
+    Hangul,
+    Kana,
+    Han,
+}
+
+fn script(c: char) -> Option<Script> {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{c0}'..='\u{24f}' => Some(Script::Latin),
+        '\u{370}'..='\u{3ff}' => Some(Script::Greek),
+        '\u{400}'..='\u{4ff}' => Some(Script::Cyrillic),
+        '\u{590}'..='\u{5ff}' => Some(Script::Hebrew),
+        '\u{600}'..='\u{6ff}' => Some(Script::Arabic),
+        '\u{900}'..='\u{97f}' => Some(Script::Devanagari),
+        '\u{e00}'..='\u{e7f}' => Some(Script::Thai),
+        '\u{1100}'..='\u{11ff}' | '\u{ac00}'..='\u{d7af}' => Some(Script::Hangul),
+        '\u{3040}'..='\u{30ff}' => Some(Script::Kana),
+        '\u{4e00}'..='\u{9fff}' => Some(Script::Han),
+        _ => None,
+    }
+}
+
+fn detect_latin(text: &str) -> Option<&'static str> {
+    let text = text.to_lowercase();
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    let mut scores: Vec<(&'static str, usize)> = STOPWORDS
+        .iter()
+        .map(|(language, stopwords)| {
+            let hits = words.iter().filter(|word| stopwords.contains(word)).count();
+            let letters = LETTERS
+                .iter()
+                .filter(|(letters_language, _)| letters_language == language)
+                .map(|(_, letters)| text.chars().filter(|c| letters.contains(*c)).count())
+                .sum::<usize>();
+            (*language, hits + letters)
+        })
+        .collect();
+    scores.sort_by_key(|(_, score)| Reverse(*score));
+
+    // Too little to go on, or a tie between languages sharing words.
+    match scores.as_slice() {
+        [(language, best), (_, second), ..] if *best >= 2 && best > second => Some(language),
+        _ => None,
+    }
+}
+
+/// The ISO 639-1 code of the language the text is most likely in, from its script and, for the
+/// Latin script, its common words. `None` when it's too short or ambiguous to tell.
+pub fn detect(text: &str) -> Option<&'static str> {
+    let mut counts: BTreeMap<Script, usize> = BTreeMap::new();
+    for script in text.chars().filter_map(script) {
+        *counts.entry(script).or_default() += 1;
+    }
+    // Japanese mixes kana with kanji, which are counted as Han.
+    if counts.contains_key(&Script::Kana) {
+        return Some("ja");
+    }
+    let (script, _) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+
+    Some(match script {
+        Script::Latin => return detect_latin(text),
+        Script::Cyrillic if text.chars().any(|c| "іїєґ".contains(c)) => "uk",
+        Script::Cyrillic => "ru",
+        Script::Greek => "el",
+        Script::Arabic if text.chars().any(|c| "پچژگ".contains(c)) => "fa",
+        Script::Arabic => "ar",
+        Script::Hebrew => "he",
+        Script::Devanagari => "hi",
+        Script::Thai => "th",
+        Script::Hangul => "ko",
+        Script::Kana => "ja",
+        Script::Han => "zh",
+    })
+}
+
+/// Tags each message whose language can be told with `metadata.language`, with
+/// `MOTORHEAD_LANGUAGE_DETECTION_ENABLED`. Tags sent by the client are kept.
+pub fn tag_messages(state: &AppState, mut messages: Vec<MemoryMessage>) -> Vec<MemoryMessage> {
+    if !state.language_detection_enabled {
+        return messages;
+    }
+    for message in &mut messages {
+        if message
+            .metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.contains_key("language"))
+        {
+            continue;
+        }
+        if let Some(language) = detect(&message.content) {
+            message
+                .metadata
+                .get_or_insert_with(Default::default)
+                .insert("language".to_string(), language.into());
+        }
+    }
+    messages
+}
+
+/// How many of the messages are tagged with each language.
+pub fn count_languages(messages: &[MemoryMessage]) -> BTreeMap<String, u64> {
+    let mut counts = BTreeMap::new();
+    for message in messages {
+        let language = message
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("language"))
+            .and_then(|language| language.as_str());
+        if let Some(language) = language {
+            *counts.entry(language.to_string()).or_default() += 1;
+        }
+    }
+    counts
+}
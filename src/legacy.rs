@@ -0,0 +1,72 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use std::sync::Arc;
+
+use crate::models::{
+    AppState, LegacyMemoryResponse, LegacyMessage, MemoryMessage, MemoryResponse, RepairAction,
+    RepairRequest, RepairResponse, Role,
+};
+use crate::tenant::Tenant;
+
+/// The original API's roles, as LangChain's `MotorheadMemory` sends them.
+const HUMAN: &str = "Human";
+const AI: &str = "AI";
+
+/// Stores `Human` and `AI` messages as `user` and `assistant` ones, so summaries, prompts and
+/// the rest of the API see standard roles.
+pub fn normalize_roles(messages: &mut [MemoryMessage]) {
+    for message in messages {
+        let role = match &message.role {
+            Role::Custom(role) if role == HUMAN => Role::User,
+            Role::Custom(role) if role == AI => Role::Assistant,
+            _ => continue,
+        };
+        message.role = role;
+    }
+}
+
+fn legacy_role(role: Role) -> String {
+    match role {
+        Role::User => HUMAN.to_string(),
+        Role::Assistant => AI.to_string(),
+        role => role.into(),
+    }
+}
+
+impl From<MemoryResponse> for LegacyMemoryResponse {
+    fn from(response: MemoryResponse) -> Self {
+        LegacyMemoryResponse {
+            messages: response
+                .messages
+                .into_iter()
+                .map(|message| LegacyMessage {
+                    role: legacy_role(message.role),
+                    content: message.content,
+                })
+                .collect(),
+            context: response.context,
+            tokens: response.tokens_in_window,
+        }
+    }
+}
+
+/// Rewrites or purges the session's entries that don't decode as messages, which reads skip
+/// unless `MOTORHEAD_RECOVER_MALFORMED_ENTRIES` is set.
+#[post("/admin/sessions/{session_id}/repair")]
+pub async fn repair_session(
+    session_id: web::Path<String>,
+    web::Json(request): web::Json<RepairRequest>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let repaired = tenant
+        .store(&data)
+        .repair_entries(&session_id, request.action == RepairAction::Purge)
+        .await?;
+    if repaired > 0 {
+        tracing::info!(session_id = %session_id, repaired, "Repaired malformed message entries");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(RepairResponse { repaired }))
+}
@@ -0,0 +1,1492 @@
+use actix_web::{error, middleware, web, App, HttpServer};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+mod alerts;
+mod analytics;
+use alerts::{AlertThresholds, Alerts};
+use analytics::get_admin_analytics;
+mod archive;
+use archive::{export_session, fork_session, import_session};
+mod audit;
+use audit::{get_audit, AuditLog};
+mod auth;
+use auth::ApiKey;
+mod batch;
+use batch::post_batch;
+mod circuit;
+use circuit::{get_llm_circuit, CircuitBreaker, GuardedLlm};
+mod compactor;
+use compactor::CompactionStrategy;
+mod cli;
+use cli::Command;
+mod cold_storage;
+use cold_storage::{run_cold_storage, Bucket, ColdStorage};
+mod compression;
+use compression::Compression;
+mod config;
+mod config_file;
+mod cors;
+mod delete_intent;
+mod dev;
+use cors::CorsConfig;
+use delete_intent::post_delete_intent;
+use dev::dev_summarize;
+mod embeddings;
+mod encryption;
+use encryption::{decrypt_data_key, Cipher, TenantKeys};
+mod entities;
+mod events;
+mod failures;
+use config::{get_admin_config, get_summary_prompt, patch_admin_config, put_summary_prompt};
+use embeddings::{CohereEmbedder, Embedder, OllamaEmbedder, OpenAIEmbedder};
+use entities::get_entities;
+use events::stream_memory;
+use failures::{get_compaction_failures, run_retry_worker};
+use legacy::repair_session;
+mod keys;
+use keys::SessionKeys;
+mod kv;
+mod language;
+mod legacy;
+use kv::{delete_kv, get_kv, put_kv};
+mod jwt;
+use jwt::{JwtConfig, JwtValidator};
+mod llm;
+mod lock;
+use llm::{
+    AnthropicClient, AzureOpenAIClient, LlmClient, MockClient, ModelPrice, OllamaClient,
+    OpenAIClient,
+};
+use lock::{acquire_lock, release_lock};
+mod memory;
+mod metrics;
+mod reducer;
+use memory::{
+    delete_context, delete_last_messages, delete_memory, delete_message, flush_session,
+    get_context, get_memory, patch_message, pin_message, post_memory, regenerate_session_summary,
+    restore_memory, summarize_session, unpin_message,
+};
+use reducer::{CompactionTrigger, DEFAULT_SUMMARY_PROMPT};
+mod metadata;
+use metadata::{delete_metadata, get_metadata, put_metadata};
+mod analysis;
+use analysis::get_analysis;
+use metrics::get_metrics;
+mod models;
+mod moderation;
+mod openapi;
+use moderation::{Moderation, ModerationAction, Moderator, OpenAIModerator, WebhookModerator};
+use openapi::{get_docs, get_openapi};
+mod ratelimit;
+mod reaper;
+use reaper::{run_idle_reaper, IdleAction, IdleReaper};
+mod recaps;
+use recaps::{delete_recaps, get_recaps, run_recap_job, RecapJob};
+mod redaction;
+mod replica;
+use replica::{run_replica_monitor, ReadReplica};
+mod response;
+use models::{AppState, RuntimeConfig, SummaryOptions};
+use ratelimit::{LocalBuckets, RateLimit};
+use redaction::Redactor;
+mod errors;
+use errors::{ApiError, ErrorCode};
+mod grpc;
+mod healthcheck;
+mod hooks;
+use hooks::{MessageHook, MessageHooks, TrimHook, WebhookHook};
+mod history;
+use healthcheck::{get_health, get_healthz, get_readyz};
+use history::{get_history, get_memory_range, get_replay};
+mod importance;
+use importance::{ImportanceRetention, ImportanceScorer};
+mod prompt;
+mod proxy;
+mod quota;
+mod read_cache;
+use prompt::get_prompt;
+use proxy::chat_completions;
+use quota::{QuotaPolicy, SessionQuota};
+use read_cache::{run_read_cache_invalidator, ReadCache};
+mod retrieval;
+use retrieval::{run_embedding_batcher, run_retrieval, EmbeddingBatcher};
+mod search;
+use search::{search_memory, search_user};
+mod session_config;
+mod sessions;
+mod shared_config;
+use shared_config::{run_shared_config_listener, SharedConfig};
+mod shedding;
+use shedding::{run_load_monitor, LoadShedder};
+mod snapshot;
+use snapshot::{export_user, get_snapshot, post_restore};
+mod shutdown;
+mod sigv4;
+use sigv4::Credentials;
+mod store;
+use redis::{ClientTlsConfig, TlsCertificates};
+use session_config::{
+    delete_system_prompt, get_session_config, get_system_prompt, put_session_config,
+    put_system_prompt,
+};
+use sessions::{delete_sessions, list_sessions};
+use store::{
+    InMemoryStore, MemoryStore, MessageLog, PostgresStore, RedisAuth, RedisPool, RedisStore,
+    RedisTopology,
+};
+mod tasks;
+mod telemetry;
+mod tenant;
+mod tenant_keys;
+use tenant_keys::{delete_tenant_key, put_tenant_key, run_tenant_key_refresh};
+mod timeouts;
+mod titles;
+mod tls;
+mod undo;
+use timeouts::{RequestTimeouts, RouteTimeout};
+use undo::{redo_session, undo_session, UndoLog};
+mod tokens;
+mod usage;
+mod webhooks;
+mod write_buffer;
+mod ws;
+use tasks::TaskTracker;
+use usage::{get_admin_usage, get_session_usage};
+use webhooks::{run_expiry_listener, WebhookEvent, Webhooks};
+use write_buffer::{run_write_buffer_flusher, OverflowPolicy, WriteBuffer};
+use ws::memory_ws;
+
+/// Runs the server, or the command given in the arguments, without the program name.
+pub async fn run(args: impl IntoIterator<Item = String>) -> io::Result<()> {
+    let cli = cli::parse(args).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, cli::USAGE);
+        std::process::exit(2);
+    });
+    if let Command::Help = cli.command {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    if let Some(path) = &cli.config {
+        config_file::load(path).unwrap_or_else(|e| panic!("Invalid config file {}: {}", path, e));
+    }
+
+    let tracer_provider = telemetry::init();
+
+    tracing::info!("Starting Motörhead 🤘");
+    metrics::init();
+
+    let mut openai_client = async_openai::Client::new();
+    if let Ok(api_base) = env::var("OPENAI_API_BASE") {
+        openai_client = openai_client.with_api_base(api_base);
+    }
+
+    let llm_provider = env::var("MOTORHEAD_LLM_PROVIDER").unwrap_or_else(|_| "openai".to_string());
+    let llm: Arc<dyn LlmClient> = match llm_provider.as_str() {
+        "openai" => Arc::new(OpenAIClient::new(
+            openai_client.clone(),
+            "gpt-3.5-turbo".to_string(),
+        )),
+        "anthropic" => {
+            let api_key = env::var("ANTHROPIC_API_KEY").expect("$ANTHROPIC_API_KEY is not set");
+            let model = env::var("ANTHROPIC_MODEL")
+                .unwrap_or_else(|_| "claude-3-5-haiku-latest".to_string());
+            Arc::new(AnthropicClient::new(api_key, model))
+        }
+        "azure" => {
+            let endpoint =
+                env::var("AZURE_OPENAI_ENDPOINT").expect("$AZURE_OPENAI_ENDPOINT is not set");
+            let api_key =
+                env::var("AZURE_OPENAI_API_KEY").expect("$AZURE_OPENAI_API_KEY is not set");
+            let deployment =
+                env::var("AZURE_OPENAI_DEPLOYMENT").expect("$AZURE_OPENAI_DEPLOYMENT is not set");
+            let api_version = env::var("AZURE_OPENAI_API_VERSION")
+                .unwrap_or_else(|_| llm::AZURE_DEFAULT_API_VERSION.to_string());
+            Arc::new(AzureOpenAIClient::new(
+                &endpoint,
+                api_key,
+                &deployment,
+                &api_version,
+            ))
+        }
+        "ollama" => {
+            let host =
+                env::var("OLLAMA_HOST").unwrap_or_else(|_| llm::OLLAMA_DEFAULT_HOST.to_string());
+            let model = env::var("OLLAMA_MODEL").unwrap_or_else(|_| "llama3".to_string());
+            Arc::new(OllamaClient::new(&host, model))
+        }
+        "mock" => Arc::new(MockClient),
+        other => panic!("Unknown $MOTORHEAD_LLM_PROVIDER: {}", other),
+    };
+    let llm_timeout = env::var("MOTORHEAD_LLM_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|seconds| *seconds > 0)
+        .map(Duration::from_secs);
+    let llm_circuit = env::var("MOTORHEAD_LLM_CIRCUIT_FAILURES")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok())
+        .filter(|failures| *failures > 0)
+        .map(|failures| {
+            let cooldown = env::var("MOTORHEAD_LLM_CIRCUIT_COOLDOWN_SECONDS")
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+                .unwrap_or(30);
+            Arc::new(CircuitBreaker::new(failures, Duration::from_secs(cooldown)))
+        });
+    let llm_prices: HashMap<String, ModelPrice> = env::var("MOTORHEAD_LLM_PRICES")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            ModelPrice::parse(entry)
+                .unwrap_or_else(|e| panic!("Invalid $MOTORHEAD_LLM_PRICES: {}", e))
+        })
+        .collect();
+    let llm: Arc<dyn LlmClient> = if llm_timeout.is_some() || llm_circuit.is_some() {
+        Arc::new(GuardedLlm::new(llm, llm_timeout, llm_circuit.clone()))
+    } else {
+        llm
+    };
+    let host = env::var("MOTORHEAD_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
+    let port = env::var("MOTORHEAD_PORT")
+        .ok()
+        .and_then(|s| s.parse::<u16>().ok())
+        .unwrap_or(8000);
+    let uds_path = env::var("MOTORHEAD_UDS_PATH")
+        .ok()
+        .filter(|path| !path.is_empty());
+    let workers = env::var("MOTORHEAD_WORKERS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|workers| *workers > 0);
+
+    let tls = match (
+        env::var("MOTORHEAD_TLS_CERT").ok(),
+        env::var("MOTORHEAD_TLS_KEY").ok(),
+    ) {
+        (Some(cert), Some(key)) => Some(tls::server_config(&cert, &key).unwrap_or_else(|e| {
+            panic!("Invalid $MOTORHEAD_TLS_CERT or $MOTORHEAD_TLS_KEY: {}", e)
+        })),
+        (None, None) => None,
+        _ => panic!("$MOTORHEAD_TLS_CERT and $MOTORHEAD_TLS_KEY must be set together"),
+    };
+
+    let grpc_port = env::var("MOTORHEAD_GRPC_PORT")
+        .ok()
+        .map(|s| s.parse::<u16>().expect("Invalid $MOTORHEAD_GRPC_PORT"));
+
+    let window_size = env::var("MOTORHEAD_MAX_WINDOW_SIZE")
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or(12);
+
+    let window_tokens = env::var("MOTORHEAD_MAX_WINDOW_TOKENS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok());
+
+    let shutdown_grace_period = env::var("MOTORHEAD_SHUTDOWN_GRACE_PERIOD_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(30));
+
+    let flush_timeout_ms = env::var("MOTORHEAD_FLUSH_TIMEOUT_MS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(30_000);
+
+    let request_timeouts = RequestTimeouts {
+        default: env::var("MOTORHEAD_REQUEST_TIMEOUT_MS").ok().map(|s| {
+            s.parse::<u64>()
+                .ok()
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis)
+                .expect("$MOTORHEAD_REQUEST_TIMEOUT_MS must be a positive number")
+        }),
+        routes: env::var("MOTORHEAD_ROUTE_TIMEOUTS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                RouteTimeout::parse(entry)
+                    .unwrap_or_else(|e| panic!("Invalid $MOTORHEAD_ROUTE_TIMEOUTS: {}", e))
+            })
+            .collect(),
+    };
+    let request_timeouts = (request_timeouts.default.is_some()
+        || !request_timeouts.routes.is_empty())
+    .then_some(request_timeouts);
+
+    let reducer_input_budget_tokens = env::var("MOTORHEAD_REDUCER_INPUT_BUDGET_TOKENS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok());
+
+    let summary_chunk_tokens = env::var("MOTORHEAD_SUMMARY_CHUNK_TOKENS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|chunk_tokens| *chunk_tokens > 0);
+
+    let long_term_threshold_tokens = env::var("MOTORHEAD_LONG_TERM_THRESHOLD_TOKENS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok());
+
+    let hash_session_ids = env::var("MOTORHEAD_HASH_SESSION_IDS")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+    let session_id_hash_length = env::var("MOTORHEAD_SESSION_ID_HASH_LENGTH")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(16)
+        .clamp(8, 40);
+
+    let response_envelope = env::var("MOTORHEAD_RESPONSE_ENVELOPE")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let storage = env::var("MOTORHEAD_STORAGE").unwrap_or_else(|_| "redis".to_string());
+    let cipher = match (
+        env::var("MOTORHEAD_ENCRYPTION_KEY").ok(),
+        env::var("MOTORHEAD_ENCRYPTION_KMS_DATA_KEY").ok(),
+    ) {
+        (None, None) => None,
+        (Some(_), Some(_)) => panic!(
+            "Set either $MOTORHEAD_ENCRYPTION_KEY or $MOTORHEAD_ENCRYPTION_KMS_DATA_KEY, not both"
+        ),
+        (Some(key), None) => Some(
+            Cipher::from_base64(&key)
+                .unwrap_or_else(|e| panic!("Invalid $MOTORHEAD_ENCRYPTION_KEY: {}", e)),
+        ),
+        (None, Some(data_key)) => {
+            let region = env::var("MOTORHEAD_ENCRYPTION_KMS_REGION")
+                .or_else(|_| env::var("AWS_REGION"))
+                .unwrap_or_else(|_| "us-east-1".to_string());
+            let key = decrypt_data_key(&region, &Credentials::from_env("KMS"), &data_key)
+                .await
+                .unwrap_or_else(|e| panic!("Could not decrypt the data key with KMS: {}", e));
+            Some(Cipher::new(&key).unwrap_or_else(|e| panic!("Invalid data key: {}", e)))
+        }
+    };
+    if cipher.is_some() && storage != "redis" {
+        panic!("Encryption at rest needs the redis storage");
+    }
+    let tenant_keys = env::var("MOTORHEAD_TENANT_ENCRYPTION_KEYS_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false)
+        .then(TenantKeys::default);
+    if tenant_keys.is_some() && storage != "redis" {
+        panic!("$MOTORHEAD_TENANT_ENCRYPTION_KEYS_ENABLED needs the redis storage");
+    }
+    let compression = env::var("MOTORHEAD_COMPRESSION_THRESHOLD_BYTES")
+        .ok()
+        .map(|threshold| Compression {
+            threshold: threshold
+                .parse::<usize>()
+                .expect("Invalid $MOTORHEAD_COMPRESSION_THRESHOLD_BYTES"),
+            level: env::var("MOTORHEAD_COMPRESSION_LEVEL")
+                .ok()
+                .map(|level| {
+                    level
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|level| (1..=22).contains(level))
+                        .expect("$MOTORHEAD_COMPRESSION_LEVEL must be a zstd level from 1 to 22")
+                })
+                .unwrap_or(3),
+        });
+    if compression.is_some() && storage != "redis" {
+        panic!("$MOTORHEAD_COMPRESSION_THRESHOLD_BYTES needs the redis storage");
+    }
+    let mut replica = None;
+    let store: Arc<dyn MemoryStore> = match storage.as_str() {
+        "redis" => {
+            let redis_url = env::var("REDIS_URL").expect("$REDIS_URL is not set");
+            let urls: Vec<&str> = redis_url.split(',').map(str::trim).collect();
+            let redis_mode =
+                env::var("MOTORHEAD_REDIS_MODE").unwrap_or_else(|_| "standalone".to_string());
+
+            let read_pem = |var: &str| {
+                env::var(var).ok().map(|path| {
+                    fs::read(&path)
+                        .unwrap_or_else(|e| panic!("Could not read ${} ({}): {}", var, path, e))
+                })
+            };
+            let client_tls = match (
+                read_pem("MOTORHEAD_REDIS_TLS_CLIENT_CERT"),
+                read_pem("MOTORHEAD_REDIS_TLS_CLIENT_KEY"),
+            ) {
+                (Some(client_cert), Some(client_key)) => Some(ClientTlsConfig {
+                    client_cert,
+                    client_key,
+                }),
+                (None, None) => None,
+                _ => panic!(
+                    "$MOTORHEAD_REDIS_TLS_CLIENT_CERT and $MOTORHEAD_REDIS_TLS_CLIENT_KEY must be set together"
+                ),
+            };
+            let root_cert = read_pem("MOTORHEAD_REDIS_TLS_CA_CERT");
+            let auth = RedisAuth {
+                username: env::var("MOTORHEAD_REDIS_USERNAME").ok(),
+                password: env::var("MOTORHEAD_REDIS_PASSWORD").ok(),
+                certificates: (client_tls.is_some() || root_cert.is_some()).then_some(
+                    TlsCertificates {
+                        client_tls,
+                        root_cert,
+                    },
+                ),
+            };
+
+            let topology = match redis_mode.as_str() {
+                "standalone" => RedisTopology::standalone(urls[0], &auth),
+                "cluster" => RedisTopology::cluster(&urls, &auth),
+                "sentinel" => {
+                    let master = env::var("MOTORHEAD_REDIS_SENTINEL_MASTER")
+                        .unwrap_or_else(|_| "mymaster".to_string());
+                    RedisTopology::sentinel(&urls, &master, &auth)
+                }
+                other => panic!("Unknown $MOTORHEAD_REDIS_MODE: {}", other),
+            }
+            .unwrap_or_else(|e| panic!("Invalid Redis configuration: {}", e));
+            let message_log = match env::var("MOTORHEAD_REDIS_MESSAGE_LOG").as_deref() {
+                Err(_) | Ok("list") => MessageLog::List,
+                Ok("stream") => MessageLog::Stream,
+                Ok(other) => panic!("Unknown $MOTORHEAD_REDIS_MESSAGE_LOG: {}", other),
+            };
+            let pool_size = env::var("MOTORHEAD_REDIS_POOL_SIZE")
+                .ok()
+                .and_then(|s| s.parse::<usize>().ok())
+                .unwrap_or(16);
+            let pool_timeout_ms = env::var("MOTORHEAD_REDIS_POOL_TIMEOUT_MS")
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+                .unwrap_or(5000);
+            let pool_timeout = Duration::from_millis(pool_timeout_ms);
+            let pool = RedisPool::new(topology, pool_size, pool_timeout)
+                .unwrap_or_else(|e| panic!("Invalid Redis configuration: {}", e));
+            let store = RedisStore::new(
+                pool,
+                SessionKeys::new(
+                    hash_session_ids.then_some(session_id_hash_length),
+                    env::var("MOTORHEAD_KEY_PREFIX")
+                        .ok()
+                        .filter(|prefix| !prefix.is_empty()),
+                    redis_mode == "cluster",
+                ),
+                message_log,
+                cipher.map(Arc::new),
+                tenant_keys.clone(),
+                compression.map(Arc::new),
+                env::var("MOTORHEAD_RECOVER_MALFORMED_ENTRIES")
+                    .map(|s| s == "true")
+                    .unwrap_or(false),
+            );
+            store
+                .ping()
+                .await
+                .unwrap_or_else(|e| panic!("Could not connect to Redis: {}", e));
+
+            if let Ok(replica_url) = env::var("MOTORHEAD_REDIS_REPLICA_URL") {
+                if redis_mode == "cluster" {
+                    panic!("$MOTORHEAD_REDIS_REPLICA_URL isn't supported with the cluster mode");
+                }
+                let max_staleness = env::var("MOTORHEAD_REDIS_REPLICA_MAX_STALENESS_MS")
+                    .ok()
+                    .map(|s| {
+                        s.parse::<u64>().ok().filter(|ms| *ms >= 100).expect(
+                            "$MOTORHEAD_REDIS_REPLICA_MAX_STALENESS_MS must be at least 100",
+                        )
+                    })
+                    .unwrap_or(1000);
+                let topology = RedisTopology::standalone(&replica_url, &auth)
+                    .unwrap_or_else(|e| panic!("Invalid read replica configuration: {}", e));
+                let pool = RedisPool::new(topology, pool_size, pool_timeout)
+                    .unwrap_or_else(|e| panic!("Invalid read replica configuration: {}", e));
+                let replica_store = store.replica(pool);
+                replica_store
+                    .ping()
+                    .await
+                    .unwrap_or_else(|e| panic!("Could not connect to the read replica: {}", e));
+                replica = Some(ReadReplica::new(
+                    Arc::new(replica_store),
+                    Duration::from_millis(max_staleness),
+                ));
+            }
+            Arc::new(store)
+        }
+        "postgres" => {
+            let postgres_url = env::var("POSTGRES_URL").expect("$POSTGRES_URL is not set");
+            let pool_size = env::var("MOTORHEAD_POSTGRES_POOL_SIZE")
+                .ok()
+                .and_then(|s| s.parse::<usize>().ok())
+                .unwrap_or(16);
+            let store = PostgresStore::connect(&postgres_url, pool_size)
+                .await
+                .unwrap_or_else(|e| panic!("Could not connect to Postgres: {}", e));
+            Arc::new(store)
+        }
+        "memory" => Arc::new(InMemoryStore::new()),
+        other => panic!("Unknown $MOTORHEAD_STORAGE: {}", other),
+    };
+
+    let history_enabled = env::var("MOTORHEAD_HISTORY_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let proxy_enabled = env::var("MOTORHEAD_PROXY_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let dev_endpoints_enabled = env::var("MOTORHEAD_DEV_ENDPOINTS_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let analytics_enabled = env::var("MOTORHEAD_ANALYTICS_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+    if analytics_enabled && storage == "postgres" {
+        panic!("$MOTORHEAD_ANALYTICS_ENABLED needs the redis or memory storage");
+    }
+
+    let legacy_api_enabled = env::var("MOTORHEAD_LEGACY_API_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let swagger_ui_enabled = env::var("MOTORHEAD_SWAGGER_UI_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let retrieval_enabled = env::var("MOTORHEAD_RETRIEVAL_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+    let embedding_provider =
+        env::var("MOTORHEAD_EMBEDDING_PROVIDER").unwrap_or_else(|_| "openai".to_string());
+    let embedding_model = env::var("MOTORHEAD_EMBEDDING_MODEL").ok();
+    let embedding_dimensions = env::var("MOTORHEAD_EMBEDDING_DIMENSIONS").ok().map(|s| {
+        s.parse::<usize>()
+            .ok()
+            .filter(|dimensions| *dimensions > 0)
+            .expect("Invalid $MOTORHEAD_EMBEDDING_DIMENSIONS")
+    });
+    // The dimensions default to those of the provider's default model, so another model
+    // needs them set.
+    let embedder: Arc<dyn Embedder> = match embedding_provider.as_str() {
+        "openai" => Arc::new(OpenAIEmbedder::new(
+            openai_client.clone(),
+            embedding_model.unwrap_or_else(|| "text-embedding-ada-002".to_string()),
+            embedding_dimensions.unwrap_or(1536),
+        )),
+        "cohere" => {
+            let api_key = env::var("COHERE_API_KEY").expect("$COHERE_API_KEY is not set");
+            let api_base = env::var("COHERE_API_BASE")
+                .unwrap_or_else(|_| embeddings::COHERE_DEFAULT_API_BASE.to_string());
+            Arc::new(CohereEmbedder::new(
+                &api_base,
+                api_key,
+                embedding_model.unwrap_or_else(|| "embed-english-v3.0".to_string()),
+                embedding_dimensions.unwrap_or(1024),
+            ))
+        }
+        "ollama" => {
+            let host =
+                env::var("OLLAMA_HOST").unwrap_or_else(|_| llm::OLLAMA_DEFAULT_HOST.to_string());
+            Arc::new(OllamaEmbedder::new(
+                &host,
+                embedding_model.unwrap_or_else(|| "all-minilm".to_string()),
+                embedding_dimensions.unwrap_or(384),
+            ))
+        }
+        other => panic!("Unknown $MOTORHEAD_EMBEDDING_PROVIDER: {}", other),
+    };
+    if retrieval_enabled {
+        store
+            .init_vectors(embedder.dimensions())
+            .await
+            .unwrap_or_else(|e| panic!("Could not set up vector retrieval: {}", e));
+    }
+
+    let summary_prompt =
+        env::var("MOTORHEAD_SUMMARY_PROMPT").unwrap_or_else(|_| DEFAULT_SUMMARY_PROMPT.to_string());
+
+    let session_ttl_seconds = env::var("MOTORHEAD_SESSION_TTL_SECONDS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok());
+    if session_ttl_seconds.is_some() && storage == "postgres" {
+        panic!("$MOTORHEAD_SESSION_TTL_SECONDS needs the redis or memory storage");
+    }
+
+    let api_keys: Vec<ApiKey> = env::var("MOTORHEAD_API_KEYS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(ApiKey::parse)
+        .collect();
+
+    let jwt = env::var("MOTORHEAD_JWT_JWKS_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .map(|jwks_url| {
+            JwtValidator::new(JwtConfig {
+                jwks_url,
+                issuer: env::var("MOTORHEAD_JWT_ISSUER").ok(),
+                audience: env::var("MOTORHEAD_JWT_AUDIENCE").ok(),
+                tenant_claim: env::var("MOTORHEAD_JWT_TENANT_CLAIM").ok(),
+                leeway_seconds: env::var("MOTORHEAD_JWT_LEEWAY_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .unwrap_or(60),
+                refresh_interval: Duration::from_secs(
+                    env::var("MOTORHEAD_JWT_JWKS_REFRESH_SECONDS")
+                        .ok()
+                        .and_then(|s| s.parse::<u64>().ok())
+                        .unwrap_or(3600),
+                ),
+            })
+        });
+
+    let cors = env::var("MOTORHEAD_CORS_ALLOWED_ORIGINS")
+        .ok()
+        .filter(|origins| !origins.trim().is_empty())
+        .map(|origins| CorsConfig {
+            origins: cors::parse_list(&origins).unwrap_or_default(),
+            methods: env::var("MOTORHEAD_CORS_ALLOWED_METHODS")
+                .map(|methods| cors::parse_list(&methods))
+                .unwrap_or_else(|_| {
+                    Some(
+                        ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                            .map(String::from)
+                            .to_vec(),
+                    )
+                }),
+            headers: env::var("MOTORHEAD_CORS_ALLOWED_HEADERS")
+                .map(|headers| cors::parse_list(&headers))
+                .unwrap_or(None),
+            max_age_seconds: env::var("MOTORHEAD_CORS_MAX_AGE_SECONDS")
+                .ok()
+                .and_then(|s| s.parse::<usize>().ok())
+                .unwrap_or(3600),
+            credentials: env::var("MOTORHEAD_CORS_ALLOW_CREDENTIALS")
+                .map(|s| s == "true")
+                .unwrap_or(false),
+        });
+    if let Some(cors) = &cors {
+        cors.validate()
+            .unwrap_or_else(|e| panic!("Invalid CORS configuration: {}", e));
+    }
+
+    let readiness_check_llm = env::var("MOTORHEAD_READINESS_CHECK_LLM")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let llm_max_attempts = env::var("MOTORHEAD_LLM_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok())
+        .unwrap_or(3)
+        .max(1);
+
+    let llm_retry_base_delay_ms = env::var("MOTORHEAD_LLM_RETRY_BASE_DELAY_MS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(500);
+
+    let compaction_retry_interval_secs = env::var("MOTORHEAD_COMPACTION_RETRY_INTERVAL_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(60)
+        .max(1);
+
+    let compaction_max_retries = env::var("MOTORHEAD_COMPACTION_MAX_RETRIES")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok())
+        .unwrap_or(5);
+
+    let compaction_trigger = match env::var("MOTORHEAD_COMPACTION_TRIGGER").as_deref() {
+        Err(_) | Ok("messages") => CompactionTrigger::Messages,
+        Ok("tokens") => CompactionTrigger::Tokens(
+            env::var("MOTORHEAD_COMPACTION_TRIGGER_TOKENS")
+                .expect("$MOTORHEAD_COMPACTION_TRIGGER_TOKENS is not set")
+                .parse::<usize>()
+                .expect("Invalid $MOTORHEAD_COMPACTION_TRIGGER_TOKENS"),
+        ),
+        Ok("elapsed") => CompactionTrigger::Elapsed(Duration::from_secs(
+            env::var("MOTORHEAD_COMPACTION_TRIGGER_SECONDS")
+                .expect("$MOTORHEAD_COMPACTION_TRIGGER_SECONDS is not set")
+                .parse::<u64>()
+                .expect("Invalid $MOTORHEAD_COMPACTION_TRIGGER_SECONDS"),
+        )),
+        Ok("ratio") => {
+            let ratio = env::var("MOTORHEAD_COMPACTION_RATIO")
+                .ok()
+                .map(|s| {
+                    s.parse::<f64>()
+                        .expect("Invalid $MOTORHEAD_COMPACTION_RATIO")
+                })
+                .unwrap_or(0.5);
+            if !(ratio > 0.0 && ratio <= 1.0) {
+                panic!("$MOTORHEAD_COMPACTION_RATIO must be over 0 and at most 1");
+            }
+            CompactionTrigger::Ratio(ratio)
+        }
+        Ok(other) => panic!("Unknown $MOTORHEAD_COMPACTION_TRIGGER: {}", other),
+    };
+    let compaction_strategy = match env::var("MOTORHEAD_COMPACTION_STRATEGY") {
+        Err(_) => CompactionStrategy::Summarize,
+        Ok(strategy) => CompactionStrategy::parse(&strategy)
+            .unwrap_or_else(|| panic!("Unknown $MOTORHEAD_COMPACTION_STRATEGY: {}", strategy)),
+    };
+    if compaction_strategy == CompactionStrategy::Archive && !retrieval_enabled {
+        panic!("$MOTORHEAD_COMPACTION_STRATEGY=archive needs $MOTORHEAD_RETRIEVAL_ENABLED");
+    }
+
+    let monthly_token_budget = env::var("MOTORHEAD_MONTHLY_TOKEN_BUDGET")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|budget| *budget > 0);
+
+    let entity_extraction_enabled = env::var("MOTORHEAD_ENTITY_EXTRACTION_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let summarize_attachments = env::var("MOTORHEAD_SUMMARIZE_ATTACHMENTS")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let language_detection_enabled = env::var("MOTORHEAD_LANGUAGE_DETECTION_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let sentiment_analysis_enabled = env::var("MOTORHEAD_SENTIMENT_ANALYSIS_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let segmented_summaries_enabled = env::var("MOTORHEAD_SEGMENTED_SUMMARIES_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    let rate_limit = |var: &str| {
+        env::var(var)
+            .ok()
+            .and_then(|s| s.parse::<u32>().ok())
+            .filter(|per_minute| *per_minute > 0)
+            .map(|per_minute| RateLimit { per_minute })
+    };
+    let session_write_rate_limit = rate_limit("MOTORHEAD_SESSION_WRITES_PER_MINUTE");
+    let api_key_rate_limit = rate_limit("MOTORHEAD_API_KEY_REQUESTS_PER_MINUTE");
+
+    let idempotency_ttl_seconds = env::var("MOTORHEAD_IDEMPOTENCY_TTL_SECONDS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(600)
+        .max(1);
+
+    let role_validation = env::var("MOTORHEAD_ROLE_VALIDATION_ENABLED")
+        .ok()
+        .and_then(|s| s.parse::<bool>().ok())
+        .unwrap_or(false);
+    let allowed_roles = role_validation.then(|| {
+        env::var("MOTORHEAD_CUSTOM_ROLES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|role| !role.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    });
+
+    let import_max_bytes = env::var("MOTORHEAD_IMPORT_MAX_BYTES")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(10 * 1024 * 1024);
+    let max_body_bytes = env::var("MOTORHEAD_MAX_BODY_BYTES")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(2 * 1024 * 1024);
+    let max_messages_per_request = env::var("MOTORHEAD_MAX_MESSAGES_PER_REQUEST")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(1000);
+    let max_message_length = env::var("MOTORHEAD_MAX_MESSAGE_LENGTH")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(100_000);
+
+    let quota_limit = |name: &str| {
+        env::var(name).ok().map(|s| {
+            s.parse::<u64>()
+                .ok()
+                .filter(|max| *max > 0)
+                .unwrap_or_else(|| panic!("${} must be a positive integer", name))
+        })
+    };
+    let session_quota = match (
+        quota_limit("MOTORHEAD_SESSION_MAX_STORED_MESSAGES"),
+        quota_limit("MOTORHEAD_SESSION_MAX_STORED_BYTES"),
+    ) {
+        (None, None) => None,
+        (max_messages, max_bytes) => Some(SessionQuota {
+            max_messages,
+            max_bytes,
+            policy: match env::var("MOTORHEAD_SESSION_QUOTA_POLICY").as_deref() {
+                Err(_) | Ok("reject") => QuotaPolicy::Reject,
+                Ok("evict") => QuotaPolicy::Evict,
+                Ok(other) => panic!("Unknown $MOTORHEAD_SESSION_QUOTA_POLICY: {}", other),
+            },
+        }),
+    };
+
+    let moderation = env::var("MOTORHEAD_MODERATION").ok().map(|moderator| {
+        let moderator: Box<dyn Moderator> = match moderator.as_str() {
+            "openai" => {
+                let api_key = env::var("OPENAI_API_KEY").expect("$OPENAI_API_KEY is not set");
+                let api_base = env::var("OPENAI_API_BASE")
+                    .unwrap_or_else(|_| moderation::OPENAI_DEFAULT_API_BASE.to_string());
+                let model = env::var("MOTORHEAD_MODERATION_MODEL")
+                    .unwrap_or_else(|_| "omni-moderation-latest".to_string());
+                Box::new(OpenAIModerator::new(&api_base, api_key, model))
+            }
+            "webhook" => Box::new(WebhookModerator::new(
+                env::var("MOTORHEAD_MODERATION_WEBHOOK_URL")
+                    .expect("$MOTORHEAD_MODERATION_WEBHOOK_URL is not set"),
+            )),
+            other => panic!("Unknown $MOTORHEAD_MODERATION: {}", other),
+        };
+        let action = match env::var("MOTORHEAD_MODERATION_ACTION").as_deref() {
+            Err(_) | Ok("reject") => ModerationAction::Reject,
+            Ok("flag") => ModerationAction::Flag,
+            Ok("redact") => ModerationAction::Redact,
+            Ok(other) => panic!("Unknown $MOTORHEAD_MODERATION_ACTION: {}", other),
+        };
+        Moderation::new(moderator, action)
+    });
+
+    let message_hooks = env::var("MOTORHEAD_MESSAGE_HOOKS").ok().map(|hooks| {
+        let hooks = hooks
+            .split(',')
+            .map(str::trim)
+            .filter(|hook| !hook.is_empty())
+            .map(|hook| -> Box<dyn MessageHook> {
+                match hook {
+                    "trim" => Box::new(TrimHook),
+                    "webhook" => Box::new(WebhookHook::new(
+                        env::var("MOTORHEAD_MESSAGE_HOOK_WEBHOOK_URL")
+                            .expect("$MOTORHEAD_MESSAGE_HOOK_WEBHOOK_URL is not set"),
+                    )),
+                    other => panic!("Unknown hook in $MOTORHEAD_MESSAGE_HOOKS: {}", other),
+                }
+            })
+            .collect();
+        MessageHooks::new(hooks)
+    });
+
+    let pii_rules = env::var("MOTORHEAD_PII_REDACTION").unwrap_or_default();
+    let pii_rules: Vec<&str> = pii_rules
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .collect();
+    let pii_patterns: BTreeMap<String, String> = env::var("MOTORHEAD_PII_REDACTION_PATTERNS")
+        .map(|patterns| {
+            serde_json::from_str(&patterns)
+                .unwrap_or_else(|e| panic!("Invalid $MOTORHEAD_PII_REDACTION_PATTERNS: {}", e))
+        })
+        .unwrap_or_default();
+    let pii_llm = env::var("MOTORHEAD_PII_REDACTION_LLM")
+        .ok()
+        .and_then(|s| s.parse::<bool>().ok())
+        .unwrap_or(false);
+    let redactor = (!pii_rules.is_empty() || !pii_patterns.is_empty() || pii_llm).then(|| {
+        Redactor::new(&pii_rules, pii_patterns, pii_llm)
+            .unwrap_or_else(|e| panic!("Invalid PII redaction configuration: {}", e))
+    });
+
+    let webhook_urls: Vec<String> = env::var("MOTORHEAD_WEBHOOK_URLS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect();
+    let webhook_events = match env::var("MOTORHEAD_WEBHOOK_EVENTS") {
+        Ok(events) => events
+            .split(',')
+            .map(str::trim)
+            .filter(|event| !event.is_empty())
+            .map(|event| {
+                WebhookEvent::parse(event)
+                    .unwrap_or_else(|| panic!("Unknown webhook event: {}", event))
+            })
+            .collect(),
+        Err(_) => WebhookEvent::ALL.to_vec(),
+    };
+    let webhook_max_attempts = env::var("MOTORHEAD_WEBHOOK_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok())
+        .unwrap_or(5);
+    let webhook_retry_base_delay_ms = env::var("MOTORHEAD_WEBHOOK_RETRY_BASE_DELAY_MS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(1000);
+    // Built without URLs too, for the compaction callbacks of the sessions.
+    let webhooks = Arc::new(Webhooks::new(
+        webhook_urls,
+        env::var("MOTORHEAD_WEBHOOK_SECRET").ok(),
+        webhook_events,
+        webhook_max_attempts,
+        webhook_retry_base_delay_ms,
+    ));
+
+    let summary_options = SummaryOptions {
+        model: env::var("MOTORHEAD_SUMMARY_MODEL").ok(),
+        temperature: env::var("MOTORHEAD_SUMMARY_TEMPERATURE")
+            .ok()
+            .and_then(|s| s.parse::<f32>().ok()),
+        max_tokens: env::var("MOTORHEAD_SUMMARY_MAX_TOKENS")
+            .ok()
+            .and_then(|s| s.parse::<u16>().ok()),
+        max_messages: env::var("MOTORHEAD_SUMMARY_MAX_MESSAGES")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok()),
+        language: env::var("MOTORHEAD_SUMMARY_LANGUAGE").ok(),
+        system_prompt: None,
+    };
+
+    let shared_config = env::var("MOTORHEAD_SHARED_CONFIG_ENABLED")
+        .map(|s| s == "true")
+        .unwrap_or(false)
+        .then(|| SharedConfig {
+            summary_prompt: summary_prompt.clone(),
+            summary_options: summary_options.clone(),
+        });
+    if shared_config.is_some() && storage != "redis" {
+        panic!("$MOTORHEAD_SHARED_CONFIG_ENABLED needs the redis storage");
+    }
+
+    let trash_ttl_seconds = env::var("MOTORHEAD_TRASH_TTL_SECONDS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(7 * 24 * 3600)
+        .max(1);
+
+    let delete_confirmation_messages =
+        env::var("MOTORHEAD_DELETE_CONFIRMATION_MESSAGES")
+            .ok()
+            .map(|s| {
+                s.parse::<u64>()
+                    .ok()
+                    .filter(|min| *min > 0)
+                    .unwrap_or_else(|| {
+                        panic!("$MOTORHEAD_DELETE_CONFIRMATION_MESSAGES must be a positive integer")
+                    })
+            });
+    if delete_confirmation_messages.is_some() && storage == "postgres" {
+        panic!("$MOTORHEAD_DELETE_CONFIRMATION_MESSAGES needs the redis or memory storage");
+    }
+
+    let window_overlap = env::var("MOTORHEAD_WINDOW_OVERLAP")
+        .ok()
+        .map(|s| {
+            s.parse::<usize>()
+                .ok()
+                .filter(|overlap| *overlap <= 1000)
+                .expect("$MOTORHEAD_WINDOW_OVERLAP must be a number of messages up to 1000")
+        })
+        .unwrap_or(0);
+    if window_overlap > 0 && !history_enabled {
+        panic!("$MOTORHEAD_WINDOW_OVERLAP needs $MOTORHEAD_HISTORY_ENABLED");
+    }
+
+    let undo = env::var("MOTORHEAD_UNDO_DEPTH")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|depth| *depth > 0)
+        .map(|depth| {
+            let ttl_seconds = env::var("MOTORHEAD_UNDO_TTL_SECONDS")
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+                .unwrap_or(600)
+                .max(1);
+            UndoLog::new(depth, Duration::from_secs(ttl_seconds))
+        });
+
+    let write_buffer = env::var("MOTORHEAD_WRITE_BUFFER_CAPACITY")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|capacity| *capacity > 0)
+        .map(|capacity| {
+            let overflow = match env::var("MOTORHEAD_WRITE_BUFFER_OVERFLOW").as_deref() {
+                Err(_) | Ok("reject") => OverflowPolicy::Reject,
+                Ok("drop_oldest") => OverflowPolicy::DropOldest,
+                Ok(other) => panic!("Unknown $MOTORHEAD_WRITE_BUFFER_OVERFLOW: {}", other),
+            };
+            let flush_interval_ms = env::var("MOTORHEAD_WRITE_BUFFER_FLUSH_INTERVAL_MS")
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+                .unwrap_or(1000)
+                .max(1);
+            WriteBuffer::new(capacity, overflow, Duration::from_millis(flush_interval_ms))
+        });
+
+    let embedding_batcher = env::var("MOTORHEAD_EMBEDDING_BATCH_WINDOW_MS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|window_ms| *window_ms > 0 && retrieval_enabled)
+        .map(|window_ms| {
+            let max_inputs = env::var("MOTORHEAD_EMBEDDING_BATCH_SIZE")
+                .ok()
+                .and_then(|s| s.parse::<usize>().ok())
+                .unwrap_or(100)
+                .clamp(1, embedder.max_inputs());
+            EmbeddingBatcher::new(max_inputs, Duration::from_millis(window_ms))
+        });
+
+    let read_cache = env::var("MOTORHEAD_READ_CACHE_SESSIONS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|capacity| *capacity > 0)
+        .map(ReadCache::new);
+    if read_cache.is_some() && storage == "postgres" {
+        panic!("$MOTORHEAD_READ_CACHE_SESSIONS needs the redis or memory storage");
+    }
+
+    let shed_latency = env::var("MOTORHEAD_SHED_LATENCY_MS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis);
+    let shed_error_rate = env::var("MOTORHEAD_SHED_ERROR_RATE").ok().map(|s| {
+        s.parse::<f64>()
+            .ok()
+            .filter(|rate| *rate >= 0.0 && *rate < 1.0)
+            .unwrap_or_else(|| panic!("$MOTORHEAD_SHED_ERROR_RATE must be at least 0 and under 1"))
+    });
+    let load_shedder = (shed_latency.is_some() || shed_error_rate.is_some()).then(|| {
+        let cache_ttl_seconds = env::var("MOTORHEAD_SHED_CACHE_TTL_SECONDS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(10);
+        LoadShedder::new(
+            shed_latency,
+            shed_error_rate,
+            Duration::from_secs(cache_ttl_seconds),
+        )
+    });
+
+    let idle_reaper = env::var("MOTORHEAD_IDLE_SESSION_SECONDS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|idle_seconds| *idle_seconds > 0)
+        .map(|idle_seconds| {
+            let action = match env::var("MOTORHEAD_IDLE_SESSION_ACTION").as_deref() {
+                Err(_) | Ok("delete") => IdleAction::Delete,
+                Ok("trash") => IdleAction::Trash,
+                Ok(other) => panic!("Unknown $MOTORHEAD_IDLE_SESSION_ACTION: {}", other),
+            };
+            let interval_secs = env::var("MOTORHEAD_IDLE_SCAN_INTERVAL_SECONDS")
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+                .unwrap_or(300)
+                .max(1);
+            IdleReaper {
+                idle_seconds,
+                action,
+                interval: Duration::from_secs(interval_secs),
+            }
+        });
+
+    let importance_retention = match env::var("MOTORHEAD_IMPORTANCE_SCORING").as_deref() {
+        Err(_) | Ok("off") => None,
+        Ok(scoring) => {
+            let scorer = match scoring {
+                "heuristic" => ImportanceScorer::Heuristic,
+                "llm" => ImportanceScorer::Llm,
+                other => panic!("Unknown $MOTORHEAD_IMPORTANCE_SCORING: {}", other),
+            };
+            let threshold = env::var("MOTORHEAD_IMPORTANCE_THRESHOLD")
+                .ok()
+                .map(|s| {
+                    s.parse::<f32>()
+                        .expect("Invalid $MOTORHEAD_IMPORTANCE_THRESHOLD")
+                })
+                .unwrap_or(0.5);
+            if !(threshold > 0.0 && threshold <= 1.0) {
+                panic!("$MOTORHEAD_IMPORTANCE_THRESHOLD must be over 0 and at most 1");
+            }
+            Some(ImportanceRetention {
+                scorer,
+                threshold,
+                limit: env::var("MOTORHEAD_AUTO_PIN_LIMIT")
+                    .ok()
+                    .and_then(|s| s.parse::<usize>().ok())
+                    .unwrap_or(20),
+            })
+        }
+    };
+
+    let session_title_after_messages =
+        env::var("MOTORHEAD_SESSION_TITLE_AFTER_MESSAGES")
+            .ok()
+            .map(|count| {
+                count
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .expect("$MOTORHEAD_SESSION_TITLE_AFTER_MESSAGES must be a positive integer")
+            });
+
+    let recap_job = env::var("MOTORHEAD_RECAP_HOUR")
+        .ok()
+        .map(|hour| {
+            hour.parse::<u8>()
+                .ok()
+                .filter(|hour| *hour < 24)
+                .expect("$MOTORHEAD_RECAP_HOUR must be an hour from 0 to 23")
+        })
+        .map(|hour| RecapJob {
+            hour,
+            retention_days: env::var("MOTORHEAD_RECAP_RETENTION_DAYS")
+                .ok()
+                .and_then(|s| s.parse::<u16>().ok())
+                .unwrap_or(30)
+                .max(1),
+        });
+
+    let cold_storage = env::var("MOTORHEAD_COLD_STORAGE_BUCKET").ok().map(|name| {
+        if storage != "redis" || !history_enabled {
+            panic!("$MOTORHEAD_COLD_STORAGE_BUCKET needs the redis storage and $MOTORHEAD_HISTORY_ENABLED");
+        }
+        let region =
+            env::var("MOTORHEAD_COLD_STORAGE_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let bucket = Bucket {
+            endpoint: env::var("MOTORHEAD_COLD_STORAGE_ENDPOINT")
+                .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region)),
+            name,
+            region,
+            credentials: Credentials::from_env("cold storage"),
+        };
+        ColdStorage::new(
+            bucket,
+            env::var("MOTORHEAD_COLD_STORAGE_PREFIX").unwrap_or_else(|_| "motorhead/".to_string()),
+            env::var("MOTORHEAD_COLD_STORAGE_AFTER_DAYS")
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+                .unwrap_or(30),
+            Duration::from_secs(
+                env::var("MOTORHEAD_COLD_STORAGE_INTERVAL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .unwrap_or(3600)
+                    .max(1),
+            ),
+        )
+    });
+
+    let parse_threshold = |var: &str| {
+        env::var(var).ok().map(|s| {
+            s.parse::<u64>()
+                .ok()
+                .filter(|threshold| *threshold > 0)
+                .unwrap_or_else(|| panic!("${} must be a positive number", var))
+        })
+    };
+    let alert_thresholds = AlertThresholds {
+        messages_per_minute: parse_threshold("MOTORHEAD_ALERT_MESSAGES_PER_MINUTE"),
+        window_overflows_per_hour: parse_threshold("MOTORHEAD_ALERT_WINDOW_OVERFLOWS_PER_HOUR"),
+        compaction_failures: parse_threshold("MOTORHEAD_ALERT_COMPACTION_FAILURES"),
+    };
+    let alerts = (alert_thresholds.messages_per_minute.is_some()
+        || alert_thresholds.window_overflows_per_hour.is_some()
+        || alert_thresholds.compaction_failures.is_some())
+    .then(|| {
+        Alerts::new(
+            alert_thresholds,
+            env::var("MOTORHEAD_ALERT_SLACK_WEBHOOK_URL").ok(),
+            Duration::from_secs(
+                env::var("MOTORHEAD_ALERT_COOLDOWN_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .unwrap_or(3600),
+            ),
+        )
+    });
+
+    let audit = match env::var("MOTORHEAD_AUDIT_LOG").as_deref() {
+        Err(_) => None,
+        Ok("redis") if storage == "redis" => Some(AuditLog::Redis),
+        Ok("redis") => panic!("$MOTORHEAD_AUDIT_LOG=redis needs the redis storage"),
+        Ok("file") => {
+            let path = env::var("MOTORHEAD_AUDIT_LOG_PATH")
+                .unwrap_or_else(|_| "motorhead-audit.jsonl".to_string());
+            let audit = AuditLog::open_file(path.clone().into())
+                .unwrap_or_else(|e| panic!("Could not open the audit log {}: {}", path, e));
+            Some(audit)
+        }
+        Ok(other) => panic!("Unknown $MOTORHEAD_AUDIT_LOG: {}", other),
+    };
+
+    let session_cleanup = Arc::new(Mutex::new(HashMap::new()));
+    let session_state = Arc::new(AppState {
+        runtime: RwLock::new(RuntimeConfig {
+            window_size,
+            summary_options,
+            summary_prompt,
+            session_ttl_seconds,
+            idempotency_ttl_seconds,
+            session_write_rate_limit,
+            api_key_rate_limit,
+        }),
+        window_tokens,
+        session_cleanup,
+        embedder,
+        llm,
+        llm_circuit,
+        llm_prices,
+        tasks: Arc::new(TaskTracker::default()),
+        flush_timeout_ms,
+        request_timeouts,
+        reducer_input_budget_tokens,
+        summary_chunk_tokens,
+        long_term_threshold_tokens,
+        store,
+        response_envelope,
+        retrieval_enabled,
+        history_enabled,
+        window_overlap,
+        proxy_enabled,
+        dev_endpoints_enabled,
+        legacy_api_enabled,
+        swagger_ui_enabled,
+        api_keys,
+        jwt,
+        readiness_check_llm,
+        llm_max_attempts,
+        llm_retry_base_delay_ms,
+        compaction_retry_interval_secs,
+        compaction_max_retries,
+        compaction_trigger,
+        compaction_strategy,
+        monthly_token_budget,
+        entity_extraction_enabled,
+        summarize_attachments,
+        language_detection_enabled,
+        sentiment_analysis_enabled,
+        session_quota,
+        message_hooks,
+        tenant_keys,
+        shared_config,
+        segmented_summaries_enabled,
+        rate_buckets: LocalBuckets::default(),
+        import_max_bytes,
+        trash_ttl_seconds,
+        delete_confirmation_messages,
+        max_messages_per_request,
+        max_message_length,
+        allowed_roles,
+        moderation,
+        redactor,
+        webhooks,
+        compaction_errors: std::sync::Mutex::new(HashMap::new()),
+        undo,
+        analytics_enabled,
+        write_buffer,
+        embedding_batcher,
+        load_shedder,
+        read_cache,
+        idle_reaper,
+        recap_job,
+        session_title_after_messages,
+        importance_retention,
+        cold_storage,
+        alerts,
+        replica,
+        audit,
+    });
+
+    if let Some(keys) = &session_state.tenant_keys {
+        tenant_keys::load(&session_state, keys)
+            .await
+            .unwrap_or_else(|e| panic!("Could not load the tenant encryption keys: {}", e));
+    }
+    shared_config::reload(&session_state)
+        .await
+        .unwrap_or_else(|e| panic!("Could not load the shared config: {}", e));
+
+    if !matches!(cli.command, Command::Serve) {
+        let result = cli::run(&session_state, cli).await;
+        telemetry::shutdown(tracer_provider);
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    tokio::spawn(run_retry_worker(session_state.clone()));
+    tokio::spawn(run_write_buffer_flusher(session_state.clone()));
+    tokio::spawn(run_embedding_batcher(session_state.clone()));
+    tokio::spawn(run_idle_reaper(session_state.clone()));
+    tokio::spawn(run_recap_job(session_state.clone()));
+    tokio::spawn(run_cold_storage(session_state.clone()));
+    tokio::spawn(run_replica_monitor(session_state.clone()));
+    tokio::spawn(run_shared_config_listener(session_state.clone()));
+    tokio::spawn(run_tenant_key_refresh(session_state.clone()));
+    tokio::spawn(run_load_monitor(session_state.clone()));
+    tokio::spawn(run_read_cache_invalidator(session_state.clone()));
+    if session_state.webhooks.wants(WebhookEvent::SessionExpired) {
+        tokio::spawn(run_expiry_listener(session_state.clone()));
+    }
+
+    if let Some(grpc_port) = grpc_port {
+        let listener = tokio::net::TcpListener::bind((host.as_str(), grpc_port)).await?;
+        tracing::info!(grpc_port, "Serving gRPC");
+        tokio::spawn(grpc::serve(session_state.clone(), listener));
+    }
+
+    let tasks = Arc::clone(&session_state.tasks);
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(session_state.clone()))
+            .wrap(middleware::from_fn(timeouts::limit_duration))
+            .wrap(middleware::from_fn(audit::record_requests))
+            .wrap(middleware::from_fn(ratelimit::limit_requests))
+            .wrap(middleware::from_fn(auth::require_api_key))
+            .wrap(middleware::from_fn(shedding::shed_requests))
+            .wrap(middleware::from_fn(metrics::track_requests))
+            // Outside of authentication, so preflight requests are answered without a key.
+            .wrap(middleware::Condition::new(
+                cors.is_some(),
+                cors.as_ref().map(CorsConfig::build).unwrap_or_default(),
+            ))
+            .wrap(middleware::from_fn(telemetry::trace_requests))
+            .service(get_health)
+            .service(get_healthz)
+            .service(get_readyz)
+            .service(get_metrics)
+            .service(get_openapi)
+            .service(get_docs)
+            .service(get_compaction_failures)
+            .service(repair_session)
+            .service(list_sessions)
+            .service(delete_sessions)
+            .service(post_batch)
+            .service(get_memory)
+            .service(search_memory)
+            .service(search_user)
+            .service(stream_memory)
+            .service(memory_ws)
+            .service(post_memory)
+            .service(delete_memory)
+            .service(restore_memory)
+            .service(patch_message)
+            .service(delete_message)
+            .service(delete_last_messages)
+            .service(undo_session)
+            .service(redo_session)
+            .service(pin_message)
+            .service(unpin_message)
+            .service(flush_session)
+            .service(summarize_session)
+            .service(get_context)
+            .service(delete_context)
+            .service(regenerate_session_summary)
+            .service(acquire_lock)
+            .service(release_lock)
+            .service(post_delete_intent)
+            .service(run_retrieval)
+            .service(get_summary_prompt)
+            .service(put_summary_prompt)
+            .service(put_tenant_key)
+            .service(delete_tenant_key)
+            .service(get_admin_config)
+            .service(patch_admin_config)
+            .service(get_snapshot)
+            .service(post_restore)
+            .service(export_user)
+            .service(get_recaps)
+            .service(delete_recaps)
+            .service(get_admin_usage)
+            .service(get_admin_analytics)
+            .service(get_llm_circuit)
+            .service(get_audit)
+            .service(get_session_usage)
+            .service(get_session_config)
+            .service(put_session_config)
+            .service(get_system_prompt)
+            .service(put_system_prompt)
+            .service(delete_system_prompt)
+            .service(get_metadata)
+            .service(get_analysis)
+            .service(put_metadata)
+            .service(delete_metadata)
+            .service(get_kv)
+            .service(put_kv)
+            .service(delete_kv)
+            .service(get_entities)
+            .service(get_history)
+            .service(get_memory_range)
+            .service(get_replay)
+            .service(get_prompt)
+            .service(chat_completions)
+            .service(dev_summarize)
+            .service(export_session)
+            .service(import_session)
+            .service(fork_session)
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(max_body_bytes)
+                    .error_handler(|err, _req| {
+                        let code = match err {
+                            error::JsonPayloadError::Overflow { .. }
+                            | error::JsonPayloadError::OverflowKnownLength { .. } => {
+                                ErrorCode::PayloadTooLarge
+                            }
+                            _ => ErrorCode::InvalidRequest,
+                        };
+                        ApiError::new(code, err).into()
+                    }),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _req| ApiError::invalid_request(err).into()),
+            )
+    });
+    let server = match tls {
+        Some(config) => {
+            tracing::info!("Serving over TLS");
+            server.bind_rustls_0_23((host.as_str(), port), config)?
+        }
+        None => server.bind((host.as_str(), port))?,
+    }
+    .disable_signals();
+    let server = match &uds_path {
+        Some(path) => {
+            // A socket left behind by a previous run would fail the bind.
+            let stale =
+                fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket());
+            if stale {
+                fs::remove_file(path)?;
+            }
+            tracing::info!(path, "Serving on a Unix socket");
+            server.bind_uds(path)?
+        }
+        None => server,
+    };
+    let server = match workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    }
+    .run();
+    tokio::spawn(shutdown::shutdown_on_signal(
+        server.handle(),
+        tasks,
+        shutdown_grace_period,
+    ));
+    let result = server.await;
+
+    telemetry::shutdown(tracer_provider);
+
+    result
+}
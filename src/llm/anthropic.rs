@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{status_error, transport_error, Completion, CompletionRequest, LlmClient};
+use crate::metrics;
+use crate::models::{MotorheadError, TokenUsage};
+
+const API_URL: &str = "https://api.anthropic.com/v1/messages";
+const MODELS_URL: &str = "https://api.anthropic.com/v1/models";
+const API_VERSION: &str = "2023-06-01";
+
+pub struct AnthropicClient {
+    http: reqwest::Client,
+    api_key: String,
+    model: String,
+}
+
+impl AnthropicClient {
+    pub fn new(api_key: String, model: String) -> Self {
+        AnthropicClient {
+            http: reqwest::Client::new(),
+            api_key,
+            model,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    system: &'a str,
+    messages: [Message<'a>; 1],
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+#[async_trait]
+impl LlmClient for AnthropicClient {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, MotorheadError> {
+        let body = MessagesRequest {
+            model: request.model.unwrap_or(&self.model),
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            system: request.system,
+            messages: [Message {
+                role: "user",
+                content: request.prompt,
+            }],
+        };
+
+        let response = self
+            .http
+            .post(API_URL)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(&body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+
+        let status = response.status();
+        let bytes = response.bytes().await.map_err(transport_error)?;
+
+        if !status.is_success() {
+            let message = match serde_json::from_slice::<ErrorResponse>(&bytes) {
+                Ok(ErrorResponse { error }) => format!("{}: {}", error.kind, error.message),
+                Err(_) => String::from_utf8_lossy(&bytes).into_owned(),
+            };
+            return Err(status_error(
+                status,
+                format!("Anthropic returned {}: {}", status, message),
+            ));
+        }
+
+        let response: MessagesResponse =
+            serde_json::from_slice(&bytes).map_err(|e| MotorheadError::LlmError(e.to_string()))?;
+
+        let usage = response
+            .usage
+            .as_ref()
+            .map(|usage| TokenUsage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+            })
+            .unwrap_or_default();
+        metrics::record_llm_usage(usage.prompt_tokens, usage.completion_tokens);
+
+        let completion: String = response
+            .content
+            .into_iter()
+            .filter(|block| block.kind == "text")
+            .map(|block| block.text)
+            .collect();
+
+        if completion.is_empty() {
+            return Err(MotorheadError::LlmError("No completion found".to_string()));
+        }
+
+        Ok(Completion {
+            content: completion,
+            usage,
+        })
+    }
+
+    async fn ping(&self) -> Result<(), MotorheadError> {
+        let response = self
+            .http
+            .get(MODELS_URL)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .send()
+            .await
+            .map_err(transport_error)?;
+
+        if !response.status().is_success() {
+            return Err(MotorheadError::LlmError(format!(
+                "Anthropic returned {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn model<'a>(&'a self, requested: Option<&'a str>) -> &'a str {
+        requested.unwrap_or(&self.model)
+    }
+}
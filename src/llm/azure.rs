@@ -0,0 +1,181 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{status_error, transport_error, Completion, CompletionRequest, LlmClient};
+use crate::metrics;
+use crate::models::{MotorheadError, TokenUsage};
+
+pub const DEFAULT_API_VERSION: &str = "2024-02-01";
+
+/// Chat completions against an Azure OpenAI deployment. Azure addresses models by deployment
+/// name, takes the key in an `api-key` header and requires an `api-version` query parameter,
+/// none of which the OpenAI client supports.
+pub struct AzureOpenAIClient {
+    http: reqwest::Client,
+    url: String,
+    models_url: String,
+    api_key: String,
+    deployment: String,
+}
+
+impl AzureOpenAIClient {
+    pub fn new(endpoint: &str, api_key: String, deployment: &str, api_version: &str) -> Self {
+        let endpoint = endpoint.trim_end_matches('/');
+        let url = format!(
+            "{}/openai/deployments/{}/chat/completions?api-version={}",
+            endpoint, deployment, api_version
+        );
+        let models_url = format!("{}/openai/models?api-version={}", endpoint, api_version);
+
+        AzureOpenAIClient {
+            http: reqwest::Client::new(),
+            url,
+            models_url,
+            api_key,
+            deployment: deployment.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    max_tokens: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    messages: [ChatMessage<'a>; 2],
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    code: Option<String>,
+    message: String,
+}
+
+#[async_trait]
+impl LlmClient for AzureOpenAIClient {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, MotorheadError> {
+        let body = ChatRequest {
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            messages: [
+                ChatMessage {
+                    role: "system",
+                    content: request.system,
+                },
+                ChatMessage {
+                    role: "user",
+                    content: request.prompt,
+                },
+            ],
+        };
+
+        let response = self
+            .http
+            .post(&self.url)
+            .header("api-key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+
+        let status = response.status();
+        let bytes = response.bytes().await.map_err(transport_error)?;
+
+        if !status.is_success() {
+            let message = match serde_json::from_slice::<ErrorResponse>(&bytes) {
+                Ok(ErrorResponse { error }) => match error.code {
+                    Some(code) => format!("{}: {}", code, error.message),
+                    None => error.message,
+                },
+                Err(_) => String::from_utf8_lossy(&bytes).into_owned(),
+            };
+            return Err(status_error(
+                status,
+                format!("Azure OpenAI returned {}: {}", status, message),
+            ));
+        }
+
+        let response: ChatResponse =
+            serde_json::from_slice(&bytes).map_err(|e| MotorheadError::LlmError(e.to_string()))?;
+
+        let usage = response
+            .usage
+            .map(|usage| TokenUsage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            })
+            .unwrap_or_default();
+        metrics::record_llm_usage(usage.prompt_tokens, usage.completion_tokens);
+
+        let content = response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| MotorheadError::LlmError("No completion found".to_string()))?;
+
+        Ok(Completion { content, usage })
+    }
+
+    async fn ping(&self) -> Result<(), MotorheadError> {
+        let response = self
+            .http
+            .get(&self.models_url)
+            .header("api-key", &self.api_key)
+            .send()
+            .await
+            .map_err(transport_error)?;
+
+        if !response.status().is_success() {
+            return Err(MotorheadError::LlmError(format!(
+                "Azure OpenAI returned {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn model<'a>(&'a self, _requested: Option<&'a str>) -> &'a str {
+        &self.deployment
+    }
+
+    fn chat_completions_request(&self) -> Option<reqwest::RequestBuilder> {
+        Some(self.http.post(&self.url).header("api-key", &self.api_key))
+    }
+}
@@ -0,0 +1,93 @@
+use async_trait::async_trait;
+use sha1::{Digest, Sha1};
+
+use super::{Completion, CompletionRequest, LlmClient};
+use crate::metrics;
+use crate::models::{MotorheadError, TokenUsage};
+use crate::tokens::count_tokens;
+
+const MODEL: &str = "mock";
+
+/// Answers without any network, for CI and local development: the same prompt always gets
+/// the same completion, in the shape the reducer asks for. Summaries name how many lines they
+/// cover and a digest of the prompt, entities come back empty, importance scores are all 0 (so
+/// nothing gets pinned for it), sentiment is neutral, and segments are a single `mock` topic. Usage is counted with
+/// the tokenizer, so budgets apply as they would.
+pub struct MockClient;
+
+/// How many numbered lines (`1. ...`, `2. ...`) the prompt has, as the importance and
+/// segment prompts number them.
+fn numbered_lines(prompt: &str) -> usize {
+    let mut count = 0;
+    for line in prompt.lines() {
+        if line.trim_start().starts_with(&format!("{}. ", count + 1)) {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// How many lines the last conversation section of the prompt has (`New lines of
+/// conversation:` and the like), up to the label after it.
+fn conversation_lines(prompt: &str) -> usize {
+    let lines: Vec<&str> = prompt.lines().map(str::trim).collect();
+    let start = lines
+        .iter()
+        .rposition(|line| line.ends_with("conversation:") || line.ends_with("Conversations:"))
+        .map_or(0, |start| start + 1);
+    lines[start..]
+        .iter()
+        .take_while(|line| !line.ends_with(':'))
+        .filter(|line| !line.is_empty())
+        .count()
+}
+
+fn complete(prompt: &str) -> String {
+    let label = prompt.trim_end().lines().last().unwrap_or_default().trim();
+    match label {
+        "Facts:" => "{}".to_string(),
+        "Sentiment:" => "0".to_string(),
+        "Scores:" => format!("[{}]", vec!["0"; numbered_lines(prompt)].join(",")),
+        "Segments:" => serde_json::json!([{
+            "topic": "mock",
+            "summary": format!("Mock summary of {} lines", numbered_lines(prompt)),
+            "lines": numbered_lines(prompt),
+            "continues": true,
+        }])
+        .to_string(),
+        _ => {
+            let digest: String = Sha1::digest(prompt.as_bytes())[..4]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            format!(
+                "Mock summary of {} lines ({})",
+                conversation_lines(prompt),
+                digest
+            )
+        }
+    }
+}
+
+#[async_trait]
+impl LlmClient for MockClient {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, MotorheadError> {
+        let content = complete(request.prompt);
+
+        let usage = TokenUsage {
+            prompt_tokens: (count_tokens(request.system) + count_tokens(request.prompt)) as u64,
+            completion_tokens: count_tokens(&content) as u64,
+        };
+        metrics::record_llm_usage(usage.prompt_tokens, usage.completion_tokens);
+
+        Ok(Completion { content, usage })
+    }
+
+    async fn ping(&self) -> Result<(), MotorheadError> {
+        Ok(())
+    }
+
+    fn model<'a>(&'a self, requested: Option<&'a str>) -> &'a str {
+        requested.unwrap_or(MODEL)
+    }
+}
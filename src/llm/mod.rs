@@ -0,0 +1,104 @@
+use async_trait::async_trait;
+use reqwest::StatusCode;
+
+use crate::models::{MotorheadError, TokenUsage};
+
+mod anthropic;
+mod azure;
+mod mock;
+mod ollama;
+mod openai;
+pub use self::anthropic::AnthropicClient;
+pub use self::azure::{AzureOpenAIClient, DEFAULT_API_VERSION as AZURE_DEFAULT_API_VERSION};
+pub use self::mock::MockClient;
+pub use self::ollama::{OllamaClient, DEFAULT_HOST as OLLAMA_DEFAULT_HOST};
+pub use self::openai::OpenAIClient;
+
+/// Failures sending a request or reading its response (connection errors, timeouts) are
+/// transient.
+pub(crate) fn transport_error(err: reqwest::Error) -> MotorheadError {
+    MotorheadError::LlmUnavailable(err.to_string())
+}
+
+/// Rate limits and server errors are transient; any other status means the request itself
+/// was rejected.
+pub(crate) fn status_error(status: StatusCode, message: String) -> MotorheadError {
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        MotorheadError::LlmUnavailable(message)
+    } else {
+        MotorheadError::LlmError(message)
+    }
+}
+
+/// What a model costs, in USD per million tokens.
+#[derive(Clone, Copy)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl ModelPrice {
+    /// An entry of `MOTORHEAD_LLM_PRICES`, `model=prompt:completion`.
+    pub fn parse(entry: &str) -> Result<(String, ModelPrice), String> {
+        let invalid = || format!("expected model=prompt:completion, got {}", entry);
+        let (model, prices) = entry.trim().split_once('=').ok_or_else(invalid)?;
+        let (prompt, completion) = prices.split_once(':').ok_or_else(invalid)?;
+        let price = |price: &str| {
+            price
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|price| *price >= 0.0)
+                .ok_or_else(invalid)
+        };
+        Ok((
+            model.trim().to_string(),
+            ModelPrice {
+                prompt: price(prompt)?,
+                completion: price(completion)?,
+            },
+        ))
+    }
+
+    pub fn cost(&self, prompt_tokens: usize, completion_tokens: usize) -> f64 {
+        (prompt_tokens as f64 * self.prompt + completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+pub struct CompletionRequest<'a> {
+    pub system: &'a str,
+    pub prompt: &'a str,
+    pub max_tokens: u16,
+    /// Replaces the client's configured model. Ignored by Azure, which addresses models by
+    /// deployment.
+    pub model: Option<&'a str>,
+    /// The provider's default when unset.
+    pub temperature: Option<f32>,
+}
+
+pub struct Completion {
+    pub content: String,
+    /// As reported by the provider, zero when it doesn't.
+    pub usage: TokenUsage,
+}
+
+/// A chat model the reducer can ask for completions.
+#[async_trait]
+pub trait LlmClient: Send + Sync {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, MotorheadError>;
+
+    /// Checks that the provider is reachable and accepts our credentials, without spending
+    /// tokens.
+    async fn ping(&self) -> Result<(), MotorheadError>;
+
+    /// The model a request for `requested` (the configured one if None) is answered by.
+    fn model<'a>(&'a self, requested: Option<&'a str>) -> &'a str;
+
+    /// A request to the provider's OpenAI-compatible chat completions endpoint, credentials
+    /// included, for the proxy to send client bodies to as they are. None for providers
+    /// without one.
+    fn chat_completions_request(&self) -> Option<reqwest::RequestBuilder> {
+        None
+    }
+}
@@ -0,0 +1,162 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{status_error, transport_error, Completion, CompletionRequest, LlmClient};
+use crate::metrics;
+use crate::models::{MotorheadError, TokenUsage};
+
+pub const DEFAULT_HOST: &str = "http://localhost:11434";
+
+/// Chat completions against Ollama's native `/api/chat` endpoint. Its OpenAI-compatible one
+/// is only used by the proxy.
+pub struct OllamaClient {
+    http: reqwest::Client,
+    url: String,
+    openai_url: String,
+    tags_url: String,
+    model: String,
+}
+
+impl OllamaClient {
+    pub fn new(host: &str, model: String) -> Self {
+        let host = host.trim_end_matches('/');
+
+        OllamaClient {
+            http: reqwest::Client::new(),
+            url: format!("{}/api/chat", host),
+            openai_url: format!("{}/v1/chat/completions", host),
+            tags_url: format!("{}/api/tags", host),
+            model,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: [ChatMessage<'a>; 2],
+    stream: bool,
+    options: Options,
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct Options {
+    num_predict: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    message: ResponseMessage,
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[async_trait]
+impl LlmClient for OllamaClient {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, MotorheadError> {
+        let body = ChatRequest {
+            model: request.model.unwrap_or(&self.model),
+            messages: [
+                ChatMessage {
+                    role: "system",
+                    content: request.system,
+                },
+                ChatMessage {
+                    role: "user",
+                    content: request.prompt,
+                },
+            ],
+            stream: false,
+            options: Options {
+                num_predict: request.max_tokens,
+                temperature: request.temperature,
+            },
+        };
+
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+
+        let status = response.status();
+        let bytes = response.bytes().await.map_err(transport_error)?;
+
+        if !status.is_success() {
+            let message = match serde_json::from_slice::<ErrorResponse>(&bytes) {
+                Ok(ErrorResponse { error }) => error,
+                Err(_) => String::from_utf8_lossy(&bytes).into_owned(),
+            };
+            return Err(status_error(
+                status,
+                format!("Ollama returned {}: {}", status, message),
+            ));
+        }
+
+        let response: ChatResponse =
+            serde_json::from_slice(&bytes).map_err(|e| MotorheadError::LlmError(e.to_string()))?;
+
+        let usage = TokenUsage {
+            prompt_tokens: response.prompt_eval_count,
+            completion_tokens: response.eval_count,
+        };
+        metrics::record_llm_usage(usage.prompt_tokens, usage.completion_tokens);
+
+        if response.message.content.is_empty() {
+            return Err(MotorheadError::LlmError("No completion found".to_string()));
+        }
+
+        Ok(Completion {
+            content: response.message.content,
+            usage,
+        })
+    }
+
+    async fn ping(&self) -> Result<(), MotorheadError> {
+        let response = self
+            .http
+            .get(&self.tags_url)
+            .send()
+            .await
+            .map_err(transport_error)?;
+
+        if !response.status().is_success() {
+            return Err(MotorheadError::LlmError(format!(
+                "Ollama returned {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn model<'a>(&'a self, requested: Option<&'a str>) -> &'a str {
+        requested.unwrap_or(&self.model)
+    }
+
+    fn chat_completions_request(&self) -> Option<reqwest::RequestBuilder> {
+        Some(self.http.post(&self.openai_url))
+    }
+}
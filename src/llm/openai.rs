@@ -0,0 +1,110 @@
+use async_openai::error::OpenAIError;
+use async_openai::types::{
+    ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, Role,
+};
+use async_trait::async_trait;
+
+use super::{Completion, CompletionRequest, LlmClient};
+use crate::metrics;
+use crate::models::{MotorheadError, TokenUsage};
+
+pub struct OpenAIClient {
+    client: async_openai::Client,
+    http: reqwest::Client,
+    model: String,
+}
+
+impl OpenAIClient {
+    pub fn new(client: async_openai::Client, model: String) -> Self {
+        OpenAIClient {
+            client,
+            http: reqwest::Client::new(),
+            model,
+        }
+    }
+}
+
+fn llm_error(err: impl std::fmt::Display) -> MotorheadError {
+    MotorheadError::LlmError(err.to_string())
+}
+
+/// Rate limits are already retried by async-openai itself, so only connection failures and
+/// server errors are left to the caller.
+fn api_error(err: OpenAIError) -> MotorheadError {
+    match &err {
+        OpenAIError::Reqwest(_) => MotorheadError::LlmUnavailable(err.to_string()),
+        OpenAIError::ApiError(api_error) if api_error.r#type == "server_error" => {
+            MotorheadError::LlmUnavailable(err.to_string())
+        }
+        _ => llm_error(err),
+    }
+}
+
+#[async_trait]
+impl LlmClient for OpenAIClient {
+    async fn complete(&self, request: CompletionRequest<'_>) -> Result<Completion, MotorheadError> {
+        let mut args = CreateChatCompletionRequestArgs::default();
+        args.max_tokens(request.max_tokens)
+            .model(request.model.unwrap_or(&self.model))
+            .messages([
+                ChatCompletionRequestMessageArgs::default()
+                    .role(Role::System)
+                    .content(request.system)
+                    .build()
+                    .map_err(llm_error)?,
+                ChatCompletionRequestMessageArgs::default()
+                    .role(Role::User)
+                    .content(request.prompt)
+                    .build()
+                    .map_err(llm_error)?,
+            ]);
+        if let Some(temperature) = request.temperature {
+            args.temperature(temperature);
+        }
+        let request = args.build().map_err(llm_error)?;
+
+        let response = self
+            .client
+            .chat()
+            .create(request)
+            .await
+            .map_err(api_error)?;
+
+        let usage = response
+            .usage
+            .as_ref()
+            .map(|usage| TokenUsage {
+                prompt_tokens: usage.prompt_tokens.into(),
+                completion_tokens: usage.completion_tokens.into(),
+            })
+            .unwrap_or_default();
+        metrics::record_llm_usage(usage.prompt_tokens, usage.completion_tokens);
+
+        let content = response
+            .choices
+            .first()
+            .ok_or_else(|| llm_error("No completion found"))?
+            .message
+            .content
+            .clone();
+
+        Ok(Completion { content, usage })
+    }
+
+    async fn ping(&self) -> Result<(), MotorheadError> {
+        self.client.models().list().await.map_err(llm_error)?;
+        Ok(())
+    }
+
+    fn model<'a>(&'a self, requested: Option<&'a str>) -> &'a str {
+        requested.unwrap_or(&self.model)
+    }
+
+    fn chat_completions_request(&self) -> Option<reqwest::RequestBuilder> {
+        let url = format!(
+            "{}/chat/completions",
+            self.client.api_base().trim_end_matches('/')
+        );
+        Some(self.http.post(url).bearer_auth(self.client.api_key()))
+    }
+}
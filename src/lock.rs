@@ -0,0 +1,108 @@
+use actix_web::{delete, post, web, HttpRequest, HttpResponse, Responder};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{AckResponse, AppState, LockQuery, LockResponse, MotorheadError};
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+/// Sent to renew or release a lease, with the token it was taken with.
+const LOCK_TOKEN_HEADER: &str = "X-Lock-Token";
+
+const DEFAULT_TTL_MS: u64 = 30_000;
+const MAX_TTL_MS: u64 = 10 * 60 * 1000;
+
+/// When the session's lease runs out, if it's held and the store keeps leases.
+pub async fn lock_expiry(
+    store: &dyn MemoryStore,
+    session_id: &str,
+) -> Result<Option<u64>, MotorheadError> {
+    match store.lock_expiry(session_id).await {
+        Err(MotorheadError::Unsupported(_)) => Ok(None),
+        result => result,
+    }
+}
+
+fn lock_token(req: &HttpRequest) -> actix_web::Result<Option<String>> {
+    req.headers()
+        .get(LOCK_TOKEN_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .filter(|token| !token.is_empty() && !token.contains(':'))
+                .map(str::to_string)
+                .ok_or_else(|| ApiError::invalid_request("Invalid X-Lock-Token header").into())
+        })
+        .transpose()
+}
+
+/// Takes the session's lease, for `ttl_ms`, so that agents sharing the session can take turns
+/// writing to it. With the `X-Lock-Token` of a held lease, renews it instead. Leases are
+/// advisory: writes aren't refused without one.
+#[post("/sessions/{session_id}/lock")]
+pub async fn acquire_lock(
+    session_id: web::Path<String>,
+    query: web::Query<LockQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    let ttl_ms = query.ttl_ms.unwrap_or(DEFAULT_TTL_MS);
+    if !(1..=MAX_TTL_MS).contains(&ttl_ms) {
+        return Err(ApiError::invalid_request(format!(
+            "ttl_ms must be between 1 and {}",
+            MAX_TTL_MS
+        ))
+        .into());
+    }
+    let token = lock_token(&req)?.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let store = tenant.store(&data);
+    if !store.acquire_lock(&session_id, &token, ttl_ms, now).await? {
+        let mut error = ApiError::new(
+            ErrorCode::SessionLocked,
+            "The session is locked by another token",
+        );
+        if let Some(expires_at) = store.lock_expiry(&session_id).await? {
+            error = error.with("expires_at", expires_at);
+        }
+        return Err(error.into());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(LockResponse {
+            token,
+            expires_at: now + ttl_ms,
+        }))
+}
+
+#[delete("/sessions/{session_id}/lock")]
+pub async fn release_lock(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    let token = lock_token(&req)?
+        .ok_or_else(|| ApiError::invalid_request("The X-Lock-Token header is required"))?;
+
+    if !tenant
+        .store(&data)
+        .release_lock(&session_id, &token)
+        .await?
+    {
+        return Err(ApiError::not_found("The session isn't locked with this token").into());
+    }
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
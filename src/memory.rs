@@ -1,126 +1,1459 @@
-use actix_web::{delete, error, get, post, web, HttpResponse, Responder};
+use actix_web::http::header::{self, ETag, EntityTag, Header, HeaderValue, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde_json::json;
+use sha1::{Digest, Sha1};
 use std::sync::Arc;
-use tokio;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
 
-use crate::models::{AckResponse, AppState, MemoryMessage, MemoryMessages, MemoryResponse};
-use crate::reducer::handle_compaction;
+use crate::alerts;
+use crate::analytics;
+use crate::cold_storage;
+use crate::compactor::indexes_appends;
+use crate::delete_intent::confirm_delete;
+use crate::errors::{ApiError, ErrorCode};
+use crate::hooks;
+use crate::language;
+use crate::legacy::normalize_roles;
+use crate::lock::lock_expiry;
+use crate::models::{
+    AckResponse, AnalyticsCounts, AppState, AppendQuery, AppendResponse, AppendReturn,
+    ClearContextQuery, ContextResponse, DeleteLastQuery, DeleteLastResponse, DeleteMode,
+    DeleteQuery, FlushQuery, LegacyMemoryResponse, MemoryFields, MemoryMessage, MemoryMessages,
+    MemoryQuery, MemoryResponse, MessageOrder, MessagePage, MessagePatch, MotorheadError, Role,
+    SummarizeQuery, SummarizeResponse, SummaryOptions,
+};
+use crate::moderation::moderate;
+use crate::quota;
+use crate::read_cache;
+use crate::redaction::{redact, redact_messages};
+use crate::reducer::{
+    clear_context, compaction_in_progress, estimate_compaction, needs_compaction,
+    regenerate_summary, run_compaction, spawn_compaction,
+};
+use crate::response::{read_response, streamed_read_response};
+use crate::retrieval::index_messages;
+use crate::session_config::window_size;
+use crate::shedding;
+use crate::store::{MemoryStore, Restore, SessionWindow};
+use crate::tasks::TaskTracker;
+use crate::telemetry;
+use crate::tenant::Tenant;
+use crate::titles;
+use crate::tokens::{count_message_tokens, count_tokens, fit_within_tokens};
+use crate::undo;
+use crate::webhooks::{notify, WebhookEvent};
+use crate::write_buffer::is_outage;
 
+const SESSION_TTL_HEADER: &str = "X-Session-TTL";
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+const SUMMARY_OPTIONS_HEADER: &str = "X-Summary-Options";
+
+/// Most messages in a page of `GET /sessions/{session_id}/memory`.
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Reads returning more messages than this stream their body.
+const STREAMED_WINDOW_MESSAGES: usize = 256;
+
+/// The summary options for compactions a request triggers: the `X-Summary-Options` JSON
+/// header if sent, completed with the configured defaults.
+pub fn summary_options(req: &HttpRequest, state: &AppState) -> actix_web::Result<SummaryOptions> {
+    let defaults = state.runtime().summary_options;
+    let Some(value) = req.headers().get(SUMMARY_OPTIONS_HEADER) else {
+        return Ok(defaults);
+    };
+
+    let options: SummaryOptions = value
+        .to_str()
+        .ok()
+        .and_then(|value| serde_json::from_str(value).ok())
+        .ok_or_else(|| ApiError::invalid_request("Invalid X-Summary-Options header"))?;
+    Ok(options.or(&defaults))
+}
+
+/// The session's pinned messages that aren't in `window`, oldest first.
+pub async fn pinned_outside(
+    store: &dyn MemoryStore,
+    session_id: &str,
+    window: &[MemoryMessage],
+) -> Result<Vec<MemoryMessage>, MotorheadError> {
+    let mut pinned = store.get_pinned(session_id).await?;
+    pinned.retain(|pinned| !window.iter().any(|message| message.id == pinned.id));
+    Ok(pinned)
+}
+
+/// Reads the session's current window, trimmed to the token budget if one is set, followed
+/// by the pinned messages that have left it. With a `page`, reads that page of the stored
+/// messages instead, as is. Only reads the messages or contexts asked for in `fields`.
+pub async fn read_memory(
+    state: &AppState,
+    tenant: &Tenant,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    page: Option<MessagePage>,
+    fields: MemoryFields,
+) -> Result<MemoryResponse, MotorheadError> {
+    let config = store
+        .get_session_config(session_id)
+        .await?
+        .unwrap_or_default();
+    let mut next_offset = None;
+    let window = match page {
+        Some(MessagePage { offset, limit }) => {
+            // Fetch one extra message to know whether there are more.
+            let mut window = store
+                .read_window(session_id, offset as i64, (offset + limit) as i64, fields)
+                .await?;
+            next_offset = (window.messages.len() > limit).then_some(offset + limit);
+            window.messages.truncate(limit);
+            window
+        }
+        None => {
+            let window_size = config.window_size.unwrap_or(state.runtime().window_size);
+            let mut window = store
+                .read_window(session_id, 0, window_size, fields)
+                .await?;
+            let messages = &mut window.messages;
+            if let Some(window_tokens) = state.window_tokens {
+                messages.truncate(fit_within_tokens(messages, window_tokens));
+            }
+            // Pinned messages that left the window, like `pinned_outside`.
+            let pinned: Vec<MemoryMessage> = std::mem::take(&mut window.pinned)
+                .into_iter()
+                .filter(|pinned| !messages.iter().any(|message| message.id == pinned.id))
+                .collect();
+            messages.extend(pinned.into_iter().rev());
+            window
+        }
+    };
+    let SessionWindow {
+        messages,
+        context,
+        long_term_context,
+        context_segments,
+        messages_since_summary: messages_since_last_summary,
+        ..
+    } = window;
+    let messages = hooks::post_read(state, session_id, messages).await?;
+
+    let tokens_in_window = messages.iter().map(count_message_tokens).sum();
+    let lock_expires_at = lock_expiry(store, session_id).await?;
+
+    let scoped_session_id = tenant.scope(session_id);
+    let compaction_in_progress = compaction_in_progress(state, tenant, session_id).await?;
+
+    let compaction_error = state
+        .compaction_errors
+        .lock()
+        .unwrap()
+        .get(&scoped_session_id)
+        .cloned();
+
+    Ok(MemoryResponse {
+        messages,
+        context,
+        long_term_context,
+        context_segments,
+        compaction_error,
+        system_prompt: config.system_prompt,
+        tokens_in_window,
+        messages_since_last_summary,
+        compaction_in_progress,
+        lock_expires_at,
+        next_offset,
+        overlap: None,
+    })
+}
+
+/// Adds the newest `overlap` messages of the history after the window's, so a model reading
+/// both sees the last summarized turns again rather than jumping from the summary to the
+/// window. Those still returned, e.g. pinned, aren't repeated.
+async fn add_overlap(
+    store: &dyn MemoryStore,
+    session_id: &str,
+    response: &mut MemoryResponse,
+    overlap: usize,
+) -> Result<(), MotorheadError> {
+    let mut archived = store.get_history_tail(session_id, overlap).await?;
+    archived.retain(|archived| {
+        archived.id.is_none()
+            || !response
+                .messages
+                .iter()
+                .any(|message| message.id == archived.id)
+    });
+    response.overlap = Some(archived.len());
+    response.tokens_in_window += archived.iter().map(count_message_tokens).sum::<usize>();
+    response.messages.extend(archived.into_iter().rev());
+    Ok(())
+}
+
+/// With role validation on, fails on the first message whose role is neither a standard one
+/// nor one of the configured custom roles.
+pub fn check_roles(state: &AppState, messages: &[MemoryMessage]) -> Result<(), String> {
+    let Some(custom_roles) = &state.allowed_roles else {
+        return Ok(());
+    };
+
+    let unknown = messages.iter().position(
+        |message| matches!(&message.role, Role::Custom(role) if !custom_roles.contains(role)),
+    );
+    match unknown {
+        Some(index) => Err(format!(
+            "Message {} has an unknown role: {}",
+            index, messages[index].role
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Refuses appends of more than `MOTORHEAD_MAX_MESSAGES_PER_REQUEST` messages, or with one
+/// longer than `MOTORHEAD_MAX_MESSAGE_LENGTH`, with a `413`. Messages without content get a
+/// `400`, unless they're assistant messages calling tools or carry attachments, and so do
+/// attachments without a `url` or `mime_type`.
+pub fn check_messages(state: &AppState, messages: &[MemoryMessage]) -> actix_web::Result<()> {
+    if messages.len() > state.max_messages_per_request {
+        return Err(ApiError::new(
+            ErrorCode::PayloadTooLarge,
+            format!(
+                "Appends are limited to {} messages",
+                state.max_messages_per_request
+            ),
+        )
+        .into());
+    }
+
+    for (index, message) in messages.iter().enumerate() {
+        if message.content.chars().count() > state.max_message_length {
+            return Err(ApiError::new(
+                ErrorCode::PayloadTooLarge,
+                format!(
+                    "Message {} is longer than {} characters",
+                    index, state.max_message_length
+                ),
+            )
+            .with("index", index)
+            .into());
+        }
+        let attachments = message.attachments.as_deref().unwrap_or_default();
+        if attachments
+            .iter()
+            .any(|attachment| attachment.url.is_empty() || attachment.mime_type.is_empty())
+        {
+            return Err(ApiError::new(
+                ErrorCode::InvalidMessage,
+                format!(
+                    "Message {} has an attachment without a url or mime_type",
+                    index
+                ),
+            )
+            .with("index", index)
+            .into());
+        }
+        if message.content.trim().is_empty()
+            && message.tool_calls.is_none()
+            && attachments.is_empty()
+        {
+            return Err(ApiError::new(
+                ErrorCode::InvalidMessage,
+                format!("Message {} has no content", index),
+            )
+            .with("index", index)
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives the messages an id and creation time, unless the client sent them.
+pub fn stamp_messages(messages: Vec<MemoryMessage>) -> Vec<MemoryMessage> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+
+    messages
+        .into_iter()
+        .map(|mut message| {
+            message
+                .id
+                .get_or_insert_with(|| uuid::Uuid::new_v4().to_string());
+            message.created_at.get_or_insert(now);
+            message
+        })
+        .collect()
+}
+
+/// Readies appended messages for the store, the same way on every append path: checks,
+/// moderates and redacts them, gives them ids, runs the pre-write hooks and tags their
+/// languages. Empty if the hooks dropped them all.
+pub async fn prepare_messages(
+    state: &AppState,
+    session_id: &str,
+    messages: Vec<MemoryMessage>,
+) -> actix_web::Result<Vec<MemoryMessage>> {
+    check_messages(state, &messages)?;
+    let messages = moderate(state, session_id, messages).await?;
+    let messages = redact_messages(state, stamp_messages(messages)).await?;
+    // Stamped again for the messages hooks add.
+    let messages = stamp_messages(hooks::pre_write(state, session_id, messages).await?);
+    Ok(language::tag_messages(state, messages))
+}
+
+/// The bookkeeping for an append the store took, `before` being the session as it was:
+/// makes it undoable and drops the session's cached reads. Before the compaction the append
+/// may start, which is undone with it.
+pub fn record_append(
+    state: &AppState,
+    tenant: &Tenant,
+    session_id: &str,
+    before: Option<undo::Snapshot>,
+) {
+    undo::record(state, tenant, session_id, before);
+    shedding::forget(state, tenant, session_id);
+    read_cache::invalidate(state, tenant, session_id);
+}
+
+/// Refuses a session TTL with a `501` if the store can't expire sessions, before the write
+/// that would otherwise be committed without it.
+pub fn check_ttl(store: &dyn MemoryStore, ttl_seconds: Option<u64>) -> actix_web::Result<()> {
+    if ttl_seconds.is_some() && !store.expires_sessions() {
+        return Err(MotorheadError::Unsupported("session TTL").into());
+    }
+    Ok(())
+}
+
+/// Checks, moderates and stores new messages and kicks off the background work they trigger
+/// (indexing, compaction). `ttl_seconds` falls back to `MOTORHEAD_SESSION_TTL_SECONDS`.
+pub async fn append_memory(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    messages: Vec<MemoryMessage>,
+    ttl_seconds: Option<u64>,
+    summary: SummaryOptions,
+) -> actix_web::Result<()> {
+    let store = tenant.store(state);
+    check_ttl(store.as_ref(), ttl_seconds)?;
+    let messages = prepare_messages(state, session_id, messages).await?;
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    let buffer = state.write_buffer.as_ref();
+    // While the store is unreachable the append is buffered, unchecked.
+    match quota::enforce(state, tenant, store.as_ref(), session_id, &messages).await {
+        Err(e) if !(buffer.is_some() && is_outage(&e)) => return Err(e.into()),
+        _ => {}
+    }
+    // Appends to a session with some already buffered go after them, to keep their order.
+    if let Some(buffer) = buffer.filter(|buffer| buffer.has_pending(tenant, session_id)) {
+        return Ok(buffer.push(tenant, session_id, messages, ttl_seconds, summary)?);
+    }
+    let before = undo::snapshot(state, tenant, session_id).await;
+    let len = match store.append_messages(session_id, messages.clone()).await {
+        Ok(len) => len,
+        Err(e) => match buffer {
+            Some(buffer) if is_outage(&e) => {
+                tracing::warn!(
+                    session_id,
+                    error = telemetry::error_message(&e),
+                    "Store unreachable, buffering the append"
+                );
+                return Ok(buffer.push(tenant, session_id, messages, ttl_seconds, summary)?);
+            }
+            _ => return Err(e.into()),
+        },
+    };
+    record_append(state, tenant, session_id, before);
+
+    Ok(after_append(
+        state,
+        tenant,
+        session_id,
+        messages,
+        len,
+        ttl_seconds,
+        summary,
+    )
+    .await?)
+}
+
+/// `messages` without the ones repeating the latest message of their role, stored or earlier
+/// in `messages`, and how many were dropped. Retrying clients otherwise store a turn twice.
+async fn dedup_messages(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    messages: Vec<MemoryMessage>,
+) -> Result<(Vec<MemoryMessage>, usize), MotorheadError> {
+    let window_size = window_size(state, store, session_id).await?;
+    // Newest first.
+    let stored = store.get_messages(session_id, 0, window_size).await?;
+
+    let mut kept: Vec<MemoryMessage> = Vec::with_capacity(messages.len());
+    let mut skipped = 0;
+    for message in messages {
+        let latest = kept
+            .iter()
+            .rev()
+            .chain(stored.iter())
+            .find(|other| other.role == message.role);
+        if latest.is_some_and(|latest| message.repeats(latest)) {
+            skipped += 1;
+        } else {
+            kept.push(message);
+        }
+    }
+    Ok((kept, skipped))
+}
+
+/// The work following an append of `messages` that left the session with `len` messages.
+pub async fn after_append(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    messages: Vec<MemoryMessage>,
+    len: i64,
+    ttl_seconds: Option<u64>,
+    summary: SummaryOptions,
+) -> Result<(), MotorheadError> {
+    let store = tenant.store(state);
+    let scoped_session_id = tenant.scope(session_id);
+    let ttl_seconds = ttl_seconds.or(state.runtime().session_ttl_seconds);
+    let messages_len = messages.len();
+
+    if let Some(ttl_seconds) = ttl_seconds {
+        store.expire_session(session_id, ttl_seconds).await?;
+
+        if state.webhooks.wants(WebhookEvent::SessionExpired) {
+            match store.track_expiry(session_id, ttl_seconds).await {
+                Ok(()) | Err(MotorheadError::Unsupported(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Nothing was stored before these messages.
+    let created = !messages.is_empty() && len == messages.len() as i64;
+    analytics::record(
+        state,
+        store.as_ref(),
+        AnalyticsCounts {
+            sessions_created: created as u64,
+            messages_appended: messages_len as u64,
+            ..Default::default()
+        },
+    )
+    .await;
+    let languages = language::count_languages(&messages);
+    if !languages.is_empty() {
+        if let Err(e) = store.record_languages(session_id, &languages).await {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem recording the languages"
+            );
+        }
+    }
+    if created {
+        notify(
+            state,
+            tenant.id(),
+            session_id,
+            WebhookEvent::SessionCreated,
+            json!({}),
+        );
+    }
+
+    if indexes_appends(state) && !messages.is_empty() {
+        let state = Arc::clone(state);
+        let store = Arc::clone(&store);
+        let session_id = session_id.to_string();
+        let task_guard = TaskTracker::track(&state.tasks, &scoped_session_id);
+
+        match &state.embedding_batcher {
+            Some(batcher) => batcher.push(session_id, store, messages, task_guard),
+            None => {
+                tokio::spawn(
+                    async move {
+                        let _task_guard = task_guard;
+                        if let Err(e) = index_messages(session_id, state, store, messages).await {
+                            tracing::error!(
+                                error = telemetry::error_message(&e),
+                                "Problem indexing messages"
+                            );
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+        }
+    }
+
+    if titles::wants_title(state, len - messages_len as i64, len) {
+        titles::spawn_title(state, tenant, session_id);
+    }
+
+    let window_overflowed = needs_compaction(state, store.as_ref(), session_id, len).await?;
+    alerts::record_append(state, tenant, session_id, messages_len, window_overflowed);
+    if window_overflowed {
+        spawn_compaction(state, tenant, session_id, summary).await;
+    }
+
+    Ok(())
+}
+
+/// A tag of what `GET /sessions/{session_id}/memory` returns: the session's version, then a
+/// hash of its lease and of what this instance knows of its compactions. `None` if the store doesn't keep
+/// versions. Weak, as enveloped responses carry the time.
+async fn memory_etag(
+    state: &AppState,
+    tenant: &Tenant,
+    store: &dyn MemoryStore,
+    session_id: &str,
+) -> Result<Option<EntityTag>, MotorheadError> {
+    let version = match store.session_version(session_id).await {
+        Ok(Some(version)) => version,
+        Ok(None) | Err(MotorheadError::Unsupported(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let scoped_session_id = tenant.scope(session_id);
+    let compaction_in_progress = compaction_in_progress(state, tenant, session_id).await?;
+    let compaction_error = state
+        .compaction_errors
+        .lock()
+        .unwrap()
+        .get(&scoped_session_id)
+        .cloned();
+
+    let lock_expires_at = lock_expiry(store, session_id).await?;
+
+    let mut hasher = Sha1::new();
+    hasher.update(version.as_bytes());
+    hasher.update(state.runtime().window_size.to_le_bytes());
+    hasher.update([compaction_in_progress as u8]);
+    hasher.update(lock_expires_at.unwrap_or_default().to_le_bytes());
+    if let Some(error) = compaction_error {
+        hasher.update(error.as_bytes());
+    }
+    let hash: String = hasher.finalize()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    Ok(Some(EntityTag::new_weak(format!("{}-{}", version, hash))))
+}
+
+/// The session version a tag of `memory_etag` was made from.
+fn tag_version(tag: &EntityTag) -> &str {
+    tag.tag().split('-').next().unwrap_or_default()
+}
+
+/// For writes conditional on `If-Match`: fails unless the session is still at the version of
+/// one of its tags, or exists for `*`, then moves the session to a new version so that
+/// concurrent conditional writes fail.
+async fn check_if_match(
+    state: &AppState,
+    tenant: &Tenant,
+    session_id: &str,
+    if_match: &IfMatch,
+) -> actix_web::Result<()> {
+    let store = tenant.store(state);
+    let version = store.session_version(session_id).await?;
+    let matched = version.filter(|version| match if_match {
+        IfMatch::Any => true,
+        IfMatch::Items(tags) => tags.iter().any(|tag| tag_version(tag) == version),
+    });
+
+    let claimed = match matched {
+        Some(version) => store.claim_version(session_id, &version).await?,
+        None => false,
+    };
+    if !claimed {
+        return Err(ApiError::new(
+            ErrorCode::VersionMismatch,
+            "The session changed since the If-Match version",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Answers `304 Not Modified` when the client's `If-None-Match` has the current `ETag`, which
+/// takes a single read of the session's version rather than reading its messages.
 #[get("/sessions/{session_id}/memory")]
 pub async fn get_memory(
     session_id: web::Path<String>,
+    web::Query(query): web::Query<MemoryQuery>,
+    tenant: Tenant,
     data: web::Data<Arc<AppState>>,
-    redis: web::Data<redis::Client>,
+    req: HttpRequest,
 ) -> actix_web::Result<impl Responder> {
-    let mut conn = redis
-        .get_tokio_connection_manager()
-        .await
-        .map_err(error::ErrorInternalServerError)?;
-
-    let lrange_key = &*session_id;
-    let context_key = format!("{}_context", &*session_id);
-
-    let (messages, context): (Vec<String>, Option<String>) = redis::pipe()
-        .cmd("LRANGE")
-        .arg(lrange_key)
-        .arg(0)
-        .arg(data.window_size as isize)
-        .cmd("GET")
-        .arg(context_key)
-        .query_async(&mut conn)
-        .await
-        .map_err(error::ErrorInternalServerError)?;
+    // Only full reads are cached for load shedding, and served from it without a tag.
+    let cacheable = query.offset.is_none()
+        && query.limit.is_none()
+        && query.fields.is_none()
+        && query.overlap.unwrap_or(data.window_overlap) == 0;
+    let shedder = data.load_shedder.as_ref().filter(|_| cacheable);
+    if let Some((mut response, age)) =
+        shedder.and_then(|shedder| shedder.cached(&tenant, &session_id))
+    {
+        if query.order == MessageOrder::Asc {
+            response.messages.reverse();
+        }
+        let mut response = memory_http_response(&data, &session_id, response);
+        response.headers_mut().insert(
+            header::AGE,
+            HeaderValue::from_str(&age.as_secs().to_string()).unwrap(),
+        );
+        return Ok(response);
+    }
 
-    let messages: Vec<MemoryMessage> = messages
-        .into_iter()
-        .filter_map(|message| {
-            let mut parts = message.splitn(2, ": ");
-            match (parts.next(), parts.next()) {
-                (Some(role), Some(content)) => Some(MemoryMessage {
-                    role: role.to_string(),
-                    content: content.to_string(),
-                }),
-                _ => None,
+    // Both from the replica, if it's used, so the tag is as stale as the body.
+    let store = tenant.read_store(&data);
+    // Read before the memory, so a write in between makes the tag stale rather than the body.
+    let etag = memory_etag(&data, &tenant, store.as_ref(), &session_id).await?;
+    if let Some(etag) = &etag {
+        let matches = match req.get_header::<IfNoneMatch>() {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+            None => false,
+        };
+        if matches {
+            return Ok(HttpResponse::NotModified()
+                .insert_header(ETag(etag.clone()))
+                .finish());
+        }
+    }
+
+    let page = match (query.offset, query.limit) {
+        (None, None) => None,
+        (offset, limit) => {
+            let limit = match limit {
+                Some(limit) => limit,
+                None => {
+                    window_size(&data, tenant.store(&data).as_ref(), &session_id).await? as usize
+                }
+            };
+            Some(MessagePage {
+                offset: offset.unwrap_or(0),
+                limit: limit.clamp(1, MAX_PAGE_SIZE),
+            })
+        }
+    };
+
+    let fields = match &query.fields {
+        Some(fields) => MemoryFields::parse(fields).ok_or_else(|| {
+            ApiError::invalid_request("fields must be messages, context, or both comma separated")
+        })?,
+        None => MemoryFields::ALL,
+    };
+    let overlap = query.overlap.unwrap_or(data.window_overlap);
+    if overlap > 0 && !data.history_enabled {
+        return Err(ApiError::new(ErrorCode::FeatureDisabled, "History is not enabled").into());
+    }
+    if overlap > MAX_PAGE_SIZE {
+        return Err(ApiError::invalid_request(format!(
+            "overlap must be at most {}",
+            MAX_PAGE_SIZE
+        ))
+        .into());
+    }
+
+    // Full reads are cached at the tag they were read at.
+    let cached = data
+        .read_cache
+        .as_ref()
+        .zip(etag.as_ref())
+        .filter(|_| page.is_none() && query.fields.is_none())
+        .map(|(cache, etag)| {
+            let key = read_cache::cache_key(store.as_ref(), &tenant, &session_id);
+            (cache, etag, key)
+        });
+    let mut response = match cached
+        .as_ref()
+        .and_then(|(cache, etag, key)| cache.get(key, etag))
+    {
+        Some(response) => response,
+        None => {
+            let response =
+                read_memory(&data, &tenant, store.as_ref(), &session_id, page, fields).await?;
+            if let Some((cache, etag, key)) = &cached {
+                cache.insert(key, (*etag).clone(), response.clone());
+            }
+            response
+        }
+    };
+    if let Some(shedder) = shedder {
+        shedder.remember(&tenant, &session_id, &response);
+    }
+    // Only right after the window, not with pages of it.
+    if overlap > 0 && page.is_none() && fields.messages {
+        add_overlap(store.as_ref(), &session_id, &mut response, overlap).await?;
+    }
+    if query.order == MessageOrder::Asc {
+        response.messages.reverse();
+    }
+
+    let mut response = memory_http_response(&data, &session_id, response);
+    if let Some(etag) = etag {
+        response.headers_mut().insert(
+            header::ETAG,
+            HeaderValue::from_str(&etag.to_string()).unwrap(),
+        );
+    }
+    Ok(response)
+}
+
+fn memory_http_response(
+    data: &AppState,
+    session_id: &str,
+    mut response: MemoryResponse,
+) -> HttpResponse {
+    if data.legacy_api_enabled {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .json(LegacyMemoryResponse::from(response))
+    } else if response.messages.len() > STREAMED_WINDOW_MESSAGES {
+        let messages = std::mem::take(&mut response.messages);
+        streamed_read_response(data, Some(session_id), response, messages)
+    } else {
+        read_response(data, Some(session_id), response)
+    }
+}
+
+/// Acknowledges an append, with the session's memory as it reads afterwards for
+/// `?return=memory`, sparing clients the follow-up `GET`. The memory is left out if it can't
+/// be read, as the append went through.
+async fn append_response(
+    state: &AppState,
+    tenant: &Tenant,
+    session_id: &str,
+    returns: Option<AppendReturn>,
+) -> AppendResponse {
+    let memory = match returns {
+        Some(AppendReturn::Memory) => {
+            let store = tenant.store(state);
+            match read_memory(
+                state,
+                tenant,
+                store.as_ref(),
+                session_id,
+                None,
+                MemoryFields::ALL,
+            )
+            .await
+            {
+                Ok(memory) => Some(memory),
+                Err(e) => {
+                    tracing::warn!(
+                        session_id,
+                        error = telemetry::error_message(&e),
+                        "Problem reading the memory after an append"
+                    );
+                    None
+                }
             }
+        }
+        None => None,
+    };
+
+    AppendResponse {
+        status: "Ok",
+        skipped: None,
+        memory,
+    }
+}
+
+#[post("/sessions/{session_id}/memory")]
+pub async fn post_memory(
+    session_id: web::Path<String>,
+    web::Query(query): web::Query<AppendQuery>,
+    web::Json(mut memory_messages): web::Json<MemoryMessages>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    if data.legacy_api_enabled {
+        normalize_roles(&mut memory_messages.messages);
+    }
+    let ttl_header = req
+        .headers()
+        .get(SESSION_TTL_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| ApiError::invalid_request("Invalid X-Session-TTL header"))
         })
-        .collect();
+        .transpose()?;
+    let summary = summary_options(&req, &data)?;
+    check_roles(&data, &memory_messages.messages)
+        .map_err(|e| ApiError::new(ErrorCode::UnknownRole, e))?;
+    let if_match = req
+        .headers()
+        .contains_key(header::IF_MATCH)
+        .then(|| {
+            IfMatch::parse(&req).map_err(|_| ApiError::invalid_request("Invalid If-Match header"))
+        })
+        .transpose()?;
+
+    let idempotency_key = req
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .filter(|key| !key.is_empty() && key.len() <= 255)
+                .ok_or_else(|| ApiError::invalid_request("Invalid Idempotency-Key header"))
+        })
+        .transpose()?;
+
+    // A retried request is acknowledged again without appending anything. So are messages
+    // whose client-set id was already appended.
+    let store = tenant.store(&data);
+    let claim = |key: String| {
+        let store = Arc::clone(&store);
+        let session_id = session_id.clone();
+        let ttl_seconds = data.runtime().idempotency_ttl_seconds;
+        async move {
+            store
+                .claim_idempotency_key(&session_id, &key, ttl_seconds)
+                .await
+                .map(|is_new| is_new.then_some(key))
+                .map_err(actix_web::Error::from)
+        }
+    };
+
+    let mut claimed = Vec::new();
+    if let Some(key) = idempotency_key {
+        match claim(format!("request:{}", key)).await? {
+            Some(key) => claimed.push(key),
+            None => {
+                let response = append_response(&data, &tenant, &session_id, query.returns).await;
+                return Ok(HttpResponse::Ok()
+                    .insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"))
+                    .content_type("application/json")
+                    .json(response));
+            }
+        }
+    }
+
+    let mut messages = Vec::with_capacity(memory_messages.messages.len());
+    for message in memory_messages.messages {
+        match &message.id {
+            Some(id) => {
+                if let Some(key) = claim(format!("message:{}", id)).await? {
+                    claimed.push(key);
+                    messages.push(message);
+                }
+            }
+            None => messages.push(message),
+        }
+    }
+
+    let mut skipped = None;
+    let result = async {
+        let messages = if query.dedup {
+            let (messages, dropped) =
+                dedup_messages(&data, store.as_ref(), &session_id, messages).await?;
+            skipped = Some(dropped);
+            messages
+        } else {
+            messages
+        };
+        if let Some(if_match) = &if_match {
+            check_if_match(&data, &tenant, &session_id, if_match).await?;
+        }
+        append_memory(
+            &data,
+            &tenant,
+            &session_id,
+            messages,
+            memory_messages.ttl_seconds.or(ttl_header),
+            summary,
+        )
+        .await
+    }
+    .await;
+
+    if let Err(e) = result {
+        for key in &claimed {
+            if let Err(e) = store.release_idempotency_key(&session_id, key).await {
+                tracing::error!(session_id = %session_id, error = telemetry::error_message(&e), "Problem releasing idempotency key");
+            }
+        }
+        return Err(e);
+    }
+
+    // The new tag, for clients writing conditionally or reading the memory back. Read first
+    // like `get_memory` does.
+    let etag = match (&if_match, query.returns) {
+        (None, None) => None,
+        _ => memory_etag(&data, &tenant, store.as_ref(), &session_id)
+            .await
+            .ok()
+            .flatten(),
+    };
+    let mut body = append_response(&data, &tenant, &session_id, query.returns).await;
+    body.skipped = skipped;
+    let mut response = HttpResponse::Ok();
+    if let Some(etag) = etag {
+        response.insert_header(ETag(etag));
+    }
+    Ok(response.content_type("application/json").json(body))
+}
+
+/// Deletes the session and what's kept about it in memory.
+pub async fn delete_session(
+    state: &AppState,
+    tenant: &Tenant,
+    session_id: &str,
+) -> Result<(), MotorheadError> {
+    let store = tenant.store(state);
+    let cold_segments = cold_storage::cold_segments(state, store.as_ref(), session_id).await?;
+    store.delete_session(session_id).await?;
+    cold_storage::delete_segments(state, session_id, &cold_segments).await;
+    forget_session(state, tenant, session_id);
+    Ok(())
+}
+
+/// The bookkeeping for a session the store deleted.
+pub fn forget_session(state: &AppState, tenant: &Tenant, session_id: &str) {
+    state
+        .compaction_errors
+        .lock()
+        .unwrap()
+        .remove(&tenant.scope(session_id));
+    if let Some(undo) = &state.undo {
+        undo.forget(&tenant.scope(session_id));
+    }
+    shedding::forget(state, tenant, session_id);
+    read_cache::invalidate(state, tenant, session_id);
+    notify(
+        state,
+        tenant.id(),
+        session_id,
+        WebhookEvent::SessionDeleted,
+        json!({}),
+    );
+}
+
+#[delete("/sessions/{session_id}/memory")]
+pub async fn delete_memory(
+    session_id: web::Path<String>,
+    query: web::Query<DeleteQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    confirm_delete(
+        &data,
+        tenant.store(&data).as_ref(),
+        &session_id,
+        query.confirm.as_deref(),
+    )
+    .await?;
 
-    let response = MemoryResponse { messages, context };
+    match query.mode {
+        DeleteMode::Hard => delete_session(&data, &tenant, &session_id).await?,
+        DeleteMode::Soft => {
+            tenant
+                .store(&data)
+                .trash_session(&session_id, data.trash_ttl_seconds)
+                .await?;
+            forget_session(&data, &tenant, &session_id);
+        }
+    }
 
+    let response = AckResponse { status: "Ok" };
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .json(response))
 }
 
-#[post("/sessions/{session_id}/memory")]
-pub async fn post_memory(
+/// Undoes a soft delete, as long as `MOTORHEAD_TRASH_TTL_SECONDS` haven't passed and no
+/// session was created with the same id since.
+#[post("/sessions/{session_id}/restore")]
+pub async fn restore_memory(
     session_id: web::Path<String>,
-    web::Json(memory_messages): web::Json<MemoryMessages>,
+    tenant: Tenant,
     data: web::Data<Arc<AppState>>,
-    redis: web::Data<redis::Client>,
 ) -> actix_web::Result<impl Responder> {
-    let mut conn = redis
-        .get_tokio_connection_manager()
-        .await
-        .map_err(error::ErrorInternalServerError)?;
+    match tenant.store(&data).restore_session(&session_id).await? {
+        Restore::Restored => {}
+        Restore::NotTrashed => {
+            return Err(ApiError::not_found("No deleted session to restore").into())
+        }
+        Restore::SessionExists => {
+            return Err(ApiError::new(
+                ErrorCode::SessionExists,
+                "A session with this id was created since the delete",
+            )
+            .into())
+        }
+    }
+
+    // Restored sessions expire like any other.
+    if let Some(ttl_seconds) = data.runtime().session_ttl_seconds {
+        tenant
+            .store(&data)
+            .expire_session(&session_id, ttl_seconds)
+            .await?;
+    }
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+#[patch("/sessions/{session_id}/memory/messages/{message_id}")]
+pub async fn patch_message(
+    path: web::Path<(String, String)>,
+    web::Json(patch): web::Json<MessagePatch>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let (session_id, message_id) = path.into_inner();
+    let content = redact(&data, &patch.content).await?;
+
+    let before = undo::snapshot(&data, &tenant, &session_id).await;
+    let store = tenant.store(&data);
+    let updated = store
+        .update_message(&session_id, &message_id, &content)
+        .await?;
 
-    let messages: Vec<String> = memory_messages
-        .messages
+    // The pinned copy too, which may have outlived the message in the window.
+    let pinned = store
+        .get_pinned(&session_id)
+        .await?
         .into_iter()
-        .map(|memory_message| format!("{}: {}", memory_message.role, memory_message.content))
-        .collect();
+        .find(|message| message.id.as_deref() == Some(message_id.as_str()));
+    let is_pinned = pinned.is_some();
+    match pinned {
+        // Pinned by a compaction for its importance.
+        Some(pinned) if pinned.importance.is_some() => {
+            let mut auto_pinned = store.get_auto_pinned(&session_id).await?;
+            for message in &mut auto_pinned {
+                if message.id == pinned.id {
+                    message.content = content.clone();
+                }
+            }
+            store.set_auto_pinned(&session_id, &auto_pinned).await?;
+        }
+        Some(mut pinned) => {
+            pinned.content = content;
+            store.pin_message(&session_id, &pinned).await?;
+        }
+        None => {}
+    }
 
-    let res: i64 = redis::Cmd::lpush(&*session_id, messages)
-        .query_async::<_, i64>(&mut conn)
-        .await
-        .map_err(error::ErrorInternalServerError)?;
+    if !updated && !is_pinned {
+        return Err(ApiError::not_found("Message not found").into());
+    }
+    undo::record(&data, &tenant, &session_id, before);
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
 
-    if res > data.window_size {
-        let state = data.into_inner();
-        let mut session_cleanup = state.session_cleanup.lock().await;
+/// Reads the page of the window after the messages `read` already, newest first. Returns
+/// whether it was the last.
+async fn read_turn_page(
+    store: &dyn MemoryStore,
+    session_id: &str,
+    read: &mut Vec<MemoryMessage>,
+) -> Result<bool, MotorheadError> {
+    let start = read.len() as i64;
+    let page = store
+        .get_messages(session_id, start, start + TURN_PAGE_SIZE as i64 - 1)
+        .await?;
+    let ended = page.len() < TURN_PAGE_SIZE;
+    read.extend(page);
+    Ok(ended)
+}
+
+/// The message with id `message_id` and the rest of its turn, as taking out part of a turn,
+/// e.g. a tool call without its result, leaves a window models refuse. The window is read a
+/// page at a time, newest first, until the message is found and then the turn has ended.
+/// Empty if the window has no such message.
+async fn find_turn(
+    store: &dyn MemoryStore,
+    session_id: &str,
+    message_id: &str,
+) -> Result<Vec<MemoryMessage>, MotorheadError> {
+    let mut read: Vec<MemoryMessage> = Vec::new();
+    let mut ended = false;
+
+    let at = loop {
+        if let Some(at) = read
+            .iter()
+            .position(|message| message.id.as_deref() == Some(message_id))
+        {
+            break at;
+        }
+        if ended {
+            return Ok(Vec::new());
+        }
+        ended = read_turn_page(store, session_id, &mut read).await?;
+    };
+    let Some(turn_id) = read[at].turn_id.clone() else {
+        return Ok(vec![read.swap_remove(at)]);
+    };
+    let in_turn = |message: &MemoryMessage| message.turn_id.as_ref() == Some(&turn_id);
 
-        if !session_cleanup.get(&*session_id).unwrap_or_else(|| &false) {
-            session_cleanup.insert((&*session_id.to_string()).into(), true);
-            let session_cleanup = Arc::clone(&state.session_cleanup);
-            let session_id = session_id.clone();
-            let state_clone = Arc::clone(&state);
+    // Newer messages were read already, older ones may take more pages.
+    let mut newest = at;
+    while newest > 0 && in_turn(&read[newest - 1]) {
+        newest -= 1;
+    }
+    let mut oldest = at + 1;
+    loop {
+        while oldest < read.len() && in_turn(&read[oldest]) {
+            oldest += 1;
+        }
+        if oldest < read.len() || ended {
+            break;
+        }
+        ended = read_turn_page(store, session_id, &mut read).await?;
+    }
+    Ok(read.drain(newest..oldest).collect())
+}
 
-            tokio::spawn(async move {
-                log::info!("running compact");
-                let _compaction_result =
-                    handle_compaction(session_id.to_string(), state_clone, conn).await;
+/// Deletes a message, and the rest of its turn along with it if it has a `turn_id`.
+#[delete("/sessions/{session_id}/memory/messages/{message_id}")]
+pub async fn delete_message(
+    path: web::Path<(String, String)>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let (session_id, message_id) = path.into_inner();
 
-                let mut lock = session_cleanup.lock().await;
-                lock.remove(&session_id);
-            });
+    let before = undo::snapshot(&data, &tenant, &session_id).await;
+    let store = tenant.store(&data);
+    let turn = find_turn(store.as_ref(), &session_id, &message_id).await?;
+    let mut deleted = store.delete_message(&session_id, &message_id).await?;
+    let mut unpinned = store.unpin_message(&session_id, &message_id).await?;
+    for id in turn.iter().filter_map(|message| message.id.as_deref()) {
+        if id != message_id {
+            deleted |= store.delete_message(&session_id, id).await?;
+            unpinned |= store.unpin_message(&session_id, id).await?;
         }
     }
 
+    if !deleted && !unpinned {
+        return Err(ApiError::not_found("Message not found").into());
+    }
+    undo::record(&data, &tenant, &session_id, before);
+
     let response = AckResponse { status: "Ok" };
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .json(response))
 }
 
-#[delete("/sessions/{session_id}/memory")]
-pub async fn delete_memory(
+/// The messages read at a time for the rest of a turn `delete_last_messages` and
+/// `delete_message` delete.
+const TURN_PAGE_SIZE: usize = 50;
+
+/// Deletes the newest messages, to undo the last turn, e.g. after a regeneration, along with
+/// the rest of the oldest one's turn. Guards refuse it with a `409` unless the newest message
+/// is the one expected, in case another client appended since, and so does a change to the
+/// session between reading the messages and deleting them. With `If-Match`, so does a session
+/// no longer at the version given, like for appends.
+#[delete("/sessions/{session_id}/memory/last")]
+pub async fn delete_last_messages(
+    req: HttpRequest,
+    session_id: web::Path<String>,
+    web::Query(query): web::Query<DeleteLastQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if query.count == 0 || query.count > MAX_PAGE_SIZE {
+        return Err(ApiError::invalid_request(format!(
+            "count must be between 1 and {}",
+            MAX_PAGE_SIZE
+        ))
+        .into());
+    }
+    let if_match = req
+        .headers()
+        .contains_key(header::IF_MATCH)
+        .then(|| {
+            IfMatch::parse(&req).map_err(|_| ApiError::invalid_request("Invalid If-Match header"))
+        })
+        .transpose()?;
+
+    let store = tenant.store(&data);
+    let mut messages = store
+        .get_messages(&session_id, 0, query.count as i64 - 1)
+        .await?;
+    if messages.len() == query.count {
+        // Reading on for the rest of the oldest one's turn, a page at a time.
+        while let Some(turn_id) = messages.last().and_then(|message| message.turn_id.clone()) {
+            let start = messages.len() as i64;
+            let page = store
+                .get_messages(&session_id, start, start + TURN_PAGE_SIZE as i64 - 1)
+                .await?;
+            let ended = page.len() < TURN_PAGE_SIZE
+                || page
+                    .iter()
+                    .any(|message| message.turn_id.as_ref() != Some(&turn_id));
+            messages.extend(
+                page.into_iter()
+                    .take_while(|message| message.turn_id.as_ref() == Some(&turn_id)),
+            );
+            if ended {
+                break;
+            }
+        }
+    }
+    let Some(last) = messages.first() else {
+        return Err(ApiError::not_found("The session has no messages").into());
+    };
+    let matches = query
+        .message_id
+        .as_ref()
+        .is_none_or(|id| last.id.as_ref() == Some(id))
+        && query.role.as_ref().is_none_or(|role| &last.role == role)
+        && query
+            .message_text
+            .as_ref()
+            .is_none_or(|text| &last.content == text);
+    if !matches {
+        return Err(ApiError::new(
+            ErrorCode::VersionMismatch,
+            "The last message doesn't match the one given",
+        )
+        .into());
+    }
+
+    if let Some(if_match) = &if_match {
+        check_if_match(&data, &tenant, &session_id, if_match).await?;
+    }
+
+    let before = undo::snapshot(&data, &tenant, &session_id).await;
+    let ids: Vec<Option<String>> = messages.into_iter().map(|message| message.id).collect();
+    if !store.delete_newest_messages(&session_id, &ids).await? {
+        return Err(ApiError::new(
+            ErrorCode::VersionMismatch,
+            "The session changed while deleting its last messages",
+        )
+        .into());
+    }
+    undo::record(&data, &tenant, &session_id, before);
+
+    let mut response = HttpResponse::Ok();
+    if if_match.is_some() {
+        if let Ok(Some(etag)) = memory_etag(&data, &tenant, store.as_ref(), &session_id).await {
+            response.insert_header(ETag(etag));
+        }
+    }
+    Ok(response
+        .content_type("application/json")
+        .json(DeleteLastResponse {
+            status: "Ok",
+            deleted: ids.len(),
+        }))
+}
+
+/// Pins a message of the window, so that compactions don't summarize it away and reads keep
+/// returning it after the window once it has left it.
+#[post("/sessions/{session_id}/memory/messages/{message_id}/pin")]
+pub async fn pin_message(
+    path: web::Path<(String, String)>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let (session_id, message_id) = path.into_inner();
+    let store = tenant.store(&data);
+
+    let message = store
+        .get_messages(&session_id, 0, -1)
+        .await?
+        .into_iter()
+        .find(|message| message.id.as_deref() == Some(message_id.as_str()))
+        .ok_or_else(|| ApiError::not_found("Message not found"))?;
+    store.pin_message(&session_id, &message).await?;
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+/// Unpins a message. If it has left the window already, it's gone from reads too.
+#[delete("/sessions/{session_id}/memory/messages/{message_id}/pin")]
+pub async fn unpin_message(
+    path: web::Path<(String, String)>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let (session_id, message_id) = path.into_inner();
+
+    let unpinned = tenant
+        .store(&data)
+        .unpin_message(&session_id, &message_id)
+        .await?;
+
+    if !unpinned {
+        return Err(ApiError::not_found("Message is not pinned").into());
+    }
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+#[post("/sessions/{session_id}/summarize")]
+pub async fn summarize_session(
+    session_id: web::Path<String>,
+    query: web::Query<SummarizeQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    let summary = summary_options(&req, &data)?;
+    if query.dry_run {
+        let estimate = estimate_compaction(&data, &tenant, &session_id, &summary).await?;
+        return Ok(read_response(&data, Some(&session_id), estimate));
+    }
+    let context = run_compaction(&data, &tenant, &session_id, &summary)
+        .await
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::CompactionInProgress,
+                "A compaction is already running for this session",
+            )
+        })??;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        SummarizeResponse { context },
+    ))
+}
+
+/// The session's summaries with their sizes in tokens, for budgeting prompts around them.
+#[get("/sessions/{session_id}/context")]
+pub async fn get_context(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let store = tenant.store(&data);
+    let context = store.get_context(&session_id).await?;
+    let long_term_context = store.get_long_term_context(&session_id).await?;
+    let last_compaction = store.last_compaction(&session_id).await?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        ContextResponse {
+            tokens: context.as_deref().map_or(0, count_tokens),
+            context,
+            long_term_tokens: long_term_context.as_deref().map_or(0, count_tokens),
+            long_term_context,
+            last_compaction,
+        },
+    ))
+}
+
+/// Drops a bad summary without losing the messages, optionally summarizing them again.
+#[delete("/sessions/{session_id}/context")]
+pub async fn delete_context(
     session_id: web::Path<String>,
-    redis: web::Data<redis::Client>,
+    query: web::Query<ClearContextQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
 ) -> actix_web::Result<impl Responder> {
-    let mut conn = redis
-        .get_tokio_connection_manager()
+    let summary = summary_options(&req, &data)?;
+    let before = undo::snapshot(&data, &tenant, &session_id).await;
+    let context = clear_context(&data, &tenant, &session_id, query.summarize, &summary)
         .await
-        .map_err(error::ErrorInternalServerError)?;
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::CompactionInProgress,
+                "A compaction is already running for this session",
+            )
+        })??;
+    undo::record(&data, &tenant, &session_id, before);
+
+    Ok(match context {
+        Some(context) => read_response(&data, Some(&session_id), SummarizeResponse { context }),
+        None => HttpResponse::Ok()
+            .content_type("application/json")
+            .json(AckResponse { status: "Ok" }),
+    })
+}
 
-    let context_key = format!("{}_context", &*session_id);
+/// Rebuilds the context from the archived history, e.g. after the summary prompt or model
+/// changed, or to get rid of a bad summary.
+#[post("/sessions/{session_id}/summary/regenerate")]
+pub async fn regenerate_session_summary(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    if !data.history_enabled {
+        return Err(ApiError::new(ErrorCode::FeatureDisabled, "History is not enabled").into());
+    }
 
-    redis::pipe()
-        .cmd("DEL")
-        .arg(&*session_id)
-        .cmd("DEL")
-        .arg(context_key)
-        .query_async(&mut conn)
+    let summary = summary_options(&req, &data)?;
+    let context = regenerate_summary(&data, &tenant, &session_id, &summary)
         .await
-        .map_err(error::ErrorInternalServerError)?;
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::CompactionInProgress,
+                "A compaction is already running for this session",
+            )
+        })??;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        SummarizeResponse { context },
+    ))
+}
+
+#[post("/sessions/{session_id}/flush")]
+pub async fn flush_session(
+    session_id: web::Path<String>,
+    query: web::Query<FlushQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let scoped_session_id = tenant.scope(&session_id);
+    let timeout_ms = query
+        .timeout_ms
+        .unwrap_or(data.flush_timeout_ms)
+        .min(data.flush_timeout_ms);
+
+    let settled = data
+        .tasks
+        .wait_settled(&scoped_session_id, Duration::from_millis(timeout_ms))
+        .await;
+
+    if !settled {
+        return Err(ApiError::new(
+            ErrorCode::Timeout,
+            format!(
+                "Timed out waiting for {} pending tasks",
+                data.tasks.pending(&scoped_session_id)
+            ),
+        )
+        .into());
+    }
 
     let response = AckResponse { status: "Ok" };
     Ok(HttpResponse::Ok()
@@ -0,0 +1,69 @@
+use actix_web::{delete, get, put, web, HttpResponse, Responder};
+use std::sync::Arc;
+
+use crate::errors::ApiError;
+use crate::models::{AckResponse, AppState, MetadataResponse};
+use crate::response::read_response;
+use crate::tenant::Tenant;
+
+#[get("/sessions/{session_id}/metadata")]
+pub async fn get_metadata(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let store = tenant.store(&data);
+    let metadata = store.get_metadata(&session_id).await?;
+    let languages = store.get_languages(&session_id).await?;
+    // Sessions with languages but no metadata of their own get an empty object.
+    let metadata = match metadata {
+        Some(metadata) => metadata,
+        None if !languages.is_empty() => serde_json::Value::Object(Default::default()),
+        None => return Err(ApiError::not_found("Session has no metadata").into()),
+    };
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        MetadataResponse {
+            metadata,
+            languages,
+        },
+    ))
+}
+
+#[put("/sessions/{session_id}/metadata")]
+pub async fn put_metadata(
+    session_id: web::Path<String>,
+    web::Json(metadata): web::Json<serde_json::Value>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if !metadata.is_object() {
+        return Err(ApiError::invalid_request("Metadata must be a JSON object").into());
+    }
+
+    tenant
+        .store(&data)
+        .set_metadata(&session_id, &metadata)
+        .await?;
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+#[delete("/sessions/{session_id}/metadata")]
+pub async fn delete_metadata(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    tenant.store(&data).delete_metadata(&session_id).await?;
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
@@ -0,0 +1,259 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{get, HttpResponse, Responder};
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use crate::errors::ApiError;
+
+static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "motorhead_http_requests_total",
+        "HTTP requests handled, by route and status.",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "motorhead_http_request_duration_seconds",
+        "HTTP request latency, by route.",
+        &["method", "route"]
+    )
+    .unwrap()
+});
+
+static GRPC_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "motorhead_grpc_requests_total",
+        "gRPC calls handled, by method and status code.",
+        &["method", "code"]
+    )
+    .unwrap()
+});
+
+static GRPC_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "motorhead_grpc_request_duration_seconds",
+        "gRPC call latency, by method.",
+        &["method"]
+    )
+    .unwrap()
+});
+
+pub static ACTIVE_COMPACTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "motorhead_active_compactions",
+        "Compactions currently running."
+    )
+    .unwrap()
+});
+
+pub static COMPACTION_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "motorhead_compaction_duration_seconds",
+        "Time taken by a compaction, summarization included.",
+        vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0]
+    )
+    .unwrap()
+});
+
+static LLM_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "motorhead_llm_tokens_total",
+        "Tokens used by LLM completions, as reported by the provider.",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub static REDIS_ERRORS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("motorhead_redis_errors_total", "Failed Redis operations.").unwrap()
+});
+
+pub static WRITE_BUFFER_DEPTH: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "motorhead_write_buffer_depth",
+        "Appends buffered while the store is unreachable."
+    )
+    .unwrap()
+});
+
+pub static WRITE_BUFFER_DROPPED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "motorhead_write_buffer_dropped_total",
+        "Buffered appends dropped, to make room or because storing them failed."
+    )
+    .unwrap()
+});
+
+pub static WRITE_BUFFER_REJECTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "motorhead_write_buffer_rejected_total",
+        "Appends failed because the write buffer was full."
+    )
+    .unwrap()
+});
+
+pub static IDLE_SESSIONS_REAPED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "motorhead_idle_sessions_reaped_total",
+        "Sessions deleted or trashed for being idle, by action.",
+        &["action"]
+    )
+    .unwrap()
+});
+
+pub static LLM_CIRCUIT_OPEN: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "motorhead_llm_circuit_open",
+        "Whether LLM calls are being refused after repeated failures (1) or not (0)."
+    )
+    .unwrap()
+});
+
+pub static LLM_CIRCUIT_REJECTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "motorhead_llm_circuit_rejected_total",
+        "LLM calls refused while the circuit was open."
+    )
+    .unwrap()
+});
+
+pub static LLM_TIMEOUTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "motorhead_llm_timeouts_total",
+        "LLM calls given up on after MOTORHEAD_LLM_TIMEOUT_SECONDS."
+    )
+    .unwrap()
+});
+
+pub static SUMMARY_CHUNKS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "motorhead_summary_chunks_total",
+        "Chunks of split compactions, by whether they were summarized or reused from an earlier attempt.",
+        &["source"]
+    )
+    .unwrap()
+});
+
+pub static COLD_STORAGE_MESSAGES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "motorhead_cold_storage_messages_total",
+        "Archived messages moved from Redis to cold storage."
+    )
+    .unwrap()
+});
+
+pub static MALFORMED_ENTRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "motorhead_malformed_entries_total",
+        "Undecodable message entries read from Redis, by whether they were recovered or skipped.",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static LOAD_SHEDDING: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "motorhead_load_shedding",
+        "1 while the instance sheds load, the store being slow or failing."
+    )
+    .unwrap()
+});
+
+pub static SHED_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "motorhead_shed_requests_total",
+        "Requests shed while degraded, by whether they were rejected or served from the cache.",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Registers every metric up front, so all of them are exported before they're first updated.
+pub fn init() {
+    LazyLock::force(&HTTP_REQUESTS);
+    LazyLock::force(&HTTP_REQUEST_DURATION);
+    LazyLock::force(&GRPC_REQUESTS);
+    LazyLock::force(&GRPC_REQUEST_DURATION);
+    LazyLock::force(&ACTIVE_COMPACTIONS);
+    LazyLock::force(&COMPACTION_DURATION);
+    LazyLock::force(&LLM_TOKENS);
+    LazyLock::force(&REDIS_ERRORS);
+    LazyLock::force(&WRITE_BUFFER_DEPTH);
+    LazyLock::force(&WRITE_BUFFER_DROPPED);
+    LazyLock::force(&WRITE_BUFFER_REJECTED);
+    LazyLock::force(&IDLE_SESSIONS_REAPED);
+    LazyLock::force(&LLM_CIRCUIT_OPEN);
+    LazyLock::force(&LLM_CIRCUIT_REJECTED);
+    LazyLock::force(&LLM_TIMEOUTS);
+    LazyLock::force(&SUMMARY_CHUNKS);
+    LazyLock::force(&COLD_STORAGE_MESSAGES);
+    LazyLock::force(&MALFORMED_ENTRIES);
+    LazyLock::force(&LOAD_SHEDDING);
+    LazyLock::force(&SHED_REQUESTS);
+}
+
+pub fn record_llm_usage(prompt_tokens: u64, completion_tokens: u64) {
+    LLM_TOKENS
+        .with_label_values(&["prompt"])
+        .inc_by(prompt_tokens);
+    LLM_TOKENS
+        .with_label_values(&["completion"])
+        .inc_by(completion_tokens);
+}
+
+/// Records the count and latency of every request, labelled with the matched route pattern
+/// rather than the path so session ids don't end up in label values.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+
+    let response = next.call(req).await?;
+
+    let route = response
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let status = response.status().as_u16().to_string();
+
+    HTTP_REQUESTS
+        .with_label_values(&[&method, &route, &status])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+
+    Ok(response)
+}
+
+pub fn record_grpc_request(method: &str, code: &str, duration: Duration) {
+    GRPC_REQUESTS.with_label_values(&[method, code]).inc();
+    GRPC_REQUEST_DURATION
+        .with_label_values(&[method])
+        .observe(duration.as_secs_f64());
+}
+
+#[get("/metrics")]
+pub async fn get_metrics() -> actix_web::Result<impl Responder> {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(ApiError::internal)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer))
+}
@@ -1,30 +1,1155 @@
+use crate::alerts::Alerts;
+use crate::audit::AuditLog;
+use crate::auth::ApiKey;
+use crate::circuit::CircuitBreaker;
+use crate::cold_storage::ColdStorage;
+use crate::compactor::CompactionStrategy;
+use crate::embeddings::Embedder;
+use crate::encryption::TenantKeys;
+use crate::hooks::MessageHooks;
+use crate::importance::ImportanceRetention;
+use crate::jwt::JwtValidator;
+use crate::llm::{LlmClient, ModelPrice};
+use crate::metrics;
+use crate::moderation::Moderation;
+use crate::quota::SessionQuota;
+use crate::ratelimit::{LocalBuckets, RateLimit};
+use crate::read_cache::ReadCache;
+use crate::reaper::IdleReaper;
+use crate::recaps::RecapJob;
+use crate::redaction::Redactor;
+use crate::reducer::CompactionTrigger;
+use crate::replica::ReadReplica;
+use crate::retrieval::EmbeddingBatcher;
+use crate::shared_config::SharedConfig;
+use crate::shedding::LoadShedder;
+use crate::store::MemoryStore;
+use crate::tasks::TaskTracker;
+use crate::timeouts::RequestTimeouts;
+use crate::undo::UndoLog;
+use crate::webhooks::Webhooks;
+use crate::write_buffer::WriteBuffer;
+pub use motorhead_client::models::{
+    Attachment, ContextSegment, FunctionCall, MemoryMessage, MemoryMessages, MemoryResponse, Role,
+    SummarizeResponse, ToolCall,
+};
 use redis::RedisError;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
 use tokio::sync::Mutex;
 
 pub struct AppState {
-    pub window_size: i64,
+    /// The settings `PATCH /admin/config` can change, see `runtime`.
+    pub runtime: RwLock<RuntimeConfig>,
+    pub window_tokens: Option<usize>,
+    /// Compactions running in this instance, by scoped session id, for stores that can't
+    /// share claims between instances (see `MemoryStore::claim_compaction`).
     pub session_cleanup: Arc<Mutex<HashMap<String, bool>>>,
-    pub openai_client: async_openai::Client,
+    pub embedder: Arc<dyn Embedder>,
+    pub llm: Arc<dyn LlmClient>,
+    /// Guards `llm`, if `MOTORHEAD_LLM_CIRCUIT_FAILURES` is set.
+    pub llm_circuit: Option<Arc<CircuitBreaker>>,
+    /// By model, for the cost estimates of dry runs.
+    pub llm_prices: HashMap<String, ModelPrice>,
+    pub tasks: Arc<TaskTracker>,
+    pub flush_timeout_ms: u64,
+    /// Answers slow requests with a `504`, if any timeout is set.
+    pub request_timeouts: Option<RequestTimeouts>,
+    pub reducer_input_budget_tokens: Option<usize>,
+    /// Compactions over it summarize chunks of it on their own first, see `summarize_in_chunks`.
+    pub summary_chunk_tokens: Option<usize>,
+    pub long_term_threshold_tokens: Option<usize>,
+    pub store: Arc<dyn MemoryStore>,
+    pub response_envelope: bool,
+    pub retrieval_enabled: bool,
+    pub history_enabled: bool,
+    /// How many summarized messages reads return after the window, see `add_overlap`.
+    pub window_overlap: usize,
+    pub proxy_enabled: bool,
+    /// Serves `POST /dev/summarize`.
+    pub dev_endpoints_enabled: bool,
+    /// Reads and writes memory in the shape of the original API, see `legacy`.
+    pub legacy_api_enabled: bool,
+    /// Serves `GET /docs`.
+    pub swagger_ui_enabled: bool,
+    pub api_keys: Vec<ApiKey>,
+    pub jwt: Option<JwtValidator>,
+    pub readiness_check_llm: bool,
+    pub llm_max_attempts: u32,
+    pub llm_retry_base_delay_ms: u64,
+    pub compaction_retry_interval_secs: u64,
+    pub compaction_max_retries: u32,
+    pub compaction_trigger: CompactionTrigger,
+    /// What compactions do, for sessions without their own `compaction_strategy`.
+    pub compaction_strategy: CompactionStrategy,
+    /// Tokens each tenant's compactions can use a month.
+    pub monthly_token_budget: Option<u64>,
+    pub entity_extraction_enabled: bool,
+    /// Whether summaries see the text extracted from attachments.
+    pub summarize_attachments: bool,
+    /// Whether appended messages are tagged with their language.
+    pub language_detection_enabled: bool,
+    /// Whether compactions score the user's sentiment.
+    pub sentiment_analysis_enabled: bool,
+    pub session_quota: Option<SessionQuota>,
+    /// Run on appends and reads, with `MOTORHEAD_MESSAGE_HOOKS`.
+    pub message_hooks: Option<MessageHooks>,
+    /// With `MOTORHEAD_TENANT_ENCRYPTION_KEYS_ENABLED`.
+    pub tenant_keys: Option<TenantKeys>,
+    /// The environment's summarization settings, with `MOTORHEAD_SHARED_CONFIG_ENABLED`.
+    pub shared_config: Option<SharedConfig>,
+    pub segmented_summaries_enabled: bool,
+    pub rate_buckets: LocalBuckets,
+    pub import_max_bytes: usize,
+    /// How long soft-deleted sessions can be restored.
+    pub trash_ttl_seconds: u64,
+    /// Sessions holding this many messages or more are only deleted with the token of a
+    /// delete intent.
+    pub delete_confirmation_messages: Option<u64>,
+    pub max_messages_per_request: usize,
+    /// In characters.
+    pub max_message_length: usize,
+    /// Custom roles accepted on top of the standard ones, when roles are validated.
+    pub allowed_roles: Option<Vec<String>>,
+    pub moderation: Option<Moderation>,
+    pub redactor: Option<Redactor>,
+    pub webhooks: Arc<Webhooks>,
+    /// Holds appends while the store is unreachable, if enabled.
+    pub write_buffer: Option<WriteBuffer>,
+    /// Embeds appended messages in batches rather than one append at a time, if enabled.
+    pub embedding_batcher: Option<EmbeddingBatcher>,
+    /// Degrades the instance while the store is overloaded, if enabled.
+    pub load_shedder: Option<LoadShedder>,
+    /// Caches full reads of the memory, if enabled.
+    pub read_cache: Option<ReadCache>,
+    pub idle_reaper: Option<IdleReaper>,
+    pub recap_job: Option<RecapJob>,
+    /// Sessions are titled once their window reaches this many messages.
+    pub session_title_after_messages: Option<usize>,
+    pub importance_retention: Option<ImportanceRetention>,
+    /// Where archived messages are moved out of Redis, if enabled.
+    pub cold_storage: Option<ColdStorage>,
+    /// Notifies of anomalous sessions, if any threshold is set.
+    pub alerts: Option<Alerts>,
+    /// Serves memory reads and searches, if `MOTORHEAD_REDIS_REPLICA_URL` is set.
+    pub replica: Option<ReadReplica>,
+    pub audit: Option<AuditLog>,
+    /// Why the last compaction of a session failed, until one succeeds.
+    pub compaction_errors: std::sync::Mutex<HashMap<String, String>>,
+    pub undo: Option<UndoLog>,
+    /// Counts each tenant's activity per day, for `GET /admin/analytics`.
+    pub analytics_enabled: bool,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct MemoryMessage {
-    pub role: String,
+impl AppState {
+    /// A copy of the current runtime settings, so the lock isn't held across awaits.
+    pub fn runtime(&self) -> RuntimeConfig {
+        self.runtime.read().unwrap().clone()
+    }
+}
+
+/// Settings read on every use, so changes through `PATCH /admin/config` apply without a
+/// restart.
+#[derive(Clone)]
+pub struct RuntimeConfig {
+    pub window_size: i64,
+    pub summary_options: SummaryOptions,
+    pub summary_prompt: String,
+    pub session_ttl_seconds: Option<u64>,
+    pub idempotency_ttl_seconds: u64,
+    pub session_write_rate_limit: Option<RateLimit>,
+    pub api_key_rate_limit: Option<RateLimit>,
+}
+
+/// A change to a session, published by the store and streamed to subscribers.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEvent<'a> {
+    MessagesAppended { messages: &'a [MemoryMessage] },
+    MessageUpdated { id: &'a str, content: &'a str },
+    MessageDeleted { id: &'a str },
+    ContextUpdated { context: &'a str },
+    LongTermContextUpdated { long_term_context: &'a str },
+    ContextSegmentsUpdated { segments: &'a [ContextSegment] },
+    SessionDeleted,
+}
+
+/// Frames sent by WebSocket clients.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsRequest {
+    Append {
+        messages: Vec<MemoryMessage>,
+        ttl_seconds: Option<u64>,
+    },
+    Get,
+    Delete {
+        /// The token of a delete intent, for sessions that need one.
+        confirm: Option<String>,
+    },
+}
+
+/// Replies to `WsRequest`s. Session events are pushed as is, alongside these.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsResponse {
+    Memory(MemoryResponse),
+    Ack,
+    Error { error: String },
+}
+
+/// Settings of the summarization LLM calls. The defaults come from the environment; requests
+/// can override them with the `X-Summary-Options` header.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SummaryOptions {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u16>,
+    /// Most messages folded into the summary by one compaction.
+    pub max_messages: Option<usize>,
+    /// The language summaries are written in, e.g. `Spanish`, rather than the conversation's
+    /// or English. A session's `summary_language` takes precedence.
+    pub language: Option<String>,
+    /// The session's system prompt, which requests can't set.
+    #[serde(skip)]
+    pub system_prompt: Option<String>,
+}
+
+impl SummaryOptions {
+    /// Fills the options left unset from `defaults`.
+    pub fn or(self, defaults: &SummaryOptions) -> SummaryOptions {
+        SummaryOptions {
+            model: self.model.or_else(|| defaults.model.clone()),
+            temperature: self.temperature.or(defaults.temperature),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            max_messages: self.max_messages.or(defaults.max_messages),
+            language: self.language.or_else(|| defaults.language.clone()),
+            system_prompt: self
+                .system_prompt
+                .or_else(|| defaults.system_prompt.clone()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Append {
+        session_id: String,
+        messages: Vec<MemoryMessage>,
+        ttl_seconds: Option<u64>,
+    },
+    Delete {
+        session_id: String,
+        /// The token of a delete intent, for sessions that need one.
+        confirm: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+/// Outcome of one `BatchOperation`, in the order they were sent.
+#[derive(Serialize)]
+pub struct BatchResult {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BatchResponse {
+    pub results: Vec<BatchResult>,
+}
+
+#[derive(Serialize)]
+pub struct EntitiesResponse {
+    pub entities: BTreeMap<String, String>,
+}
+
+/// A conversation to summarize with `POST /dev/summarize`.
+#[derive(Deserialize)]
+pub struct DevSummarizeRequest {
+    /// Oldest first, like appends.
+    pub messages: Vec<MemoryMessage>,
+    pub previous_summary: Option<String>,
+    /// A prompt template to try instead of the server's.
+    pub summary_prompt: Option<String>,
+    #[serde(default)]
+    pub options: SummaryOptions,
+}
+
+#[derive(Serialize)]
+pub struct DevSummarizeResponse {
+    pub context: String,
+    /// How many of the messages, oldest first, a compaction would have folded in.
+    pub summarized_messages: usize,
+    pub usage: TokenUsage,
+}
+
+#[derive(Deserialize)]
+pub struct SummarizeQuery {
+    /// Estimates the compaction instead of running it.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// What summarizing the session now would send to the LLM.
+#[derive(Serialize)]
+pub struct SummarizeDryRunResponse {
+    /// The messages that would be taken out of the window, newest first.
+    pub messages: Vec<MemoryMessage>,
+    pub strategy: CompactionStrategy,
+    pub model: String,
+    pub prompt_tokens: usize,
+    /// The most the summary can take.
+    pub max_completion_tokens: u16,
+    /// In USD, with the summary at `max_completion_tokens`. None for models without a price
+    /// in `MOTORHEAD_LLM_PRICES`.
+    pub estimated_cost: Option<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct MessagePatch {
     pub content: String,
 }
 
+/// The summaries of the chunks of a compaction done so far, stored as they complete so a
+/// compaction tried again picks up where it stopped. See `MOTORHEAD_SUMMARY_CHUNK_TOKENS`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CompactionProgress {
+    /// By the sha1 of the chunk's lines.
+    pub chunks: BTreeMap<String, String>,
+}
+
+/// Archived messages moved from the history to an object in cold storage, see
+/// `MOTORHEAD_COLD_STORAGE_BUCKET`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColdSegment {
+    /// The object's key in the bucket.
+    pub key: String,
+    pub messages: usize,
+    /// Of its oldest and newest messages, in milliseconds since the Unix epoch.
+    pub first_created_at: Option<u64>,
+    pub last_created_at: Option<u64>,
+}
+
+/// A context stored by a compaction, kept with `MOTORHEAD_HISTORY_ENABLED` for replays.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContextVersion {
+    /// Milliseconds since the Unix epoch.
+    pub at: u64,
+    pub context: String,
+    /// The newest message the compaction took out of the window, which started after it.
+    pub last_message_id: Option<String>,
+    pub last_created_at: Option<u64>,
+}
+
+/// What a session keeps in the store, held to `MOTORHEAD_SESSION_MAX_STORED_*`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StoredSize {
+    pub window: u64,
+    pub history: u64,
+    /// Of the window and the history. Estimated by Redis, which samples the lists.
+    pub bytes: u64,
+}
+
+/// When a session was last compacted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct CompactionStamp {
+    /// Milliseconds since the Unix epoch.
+    pub at: u64,
+    /// The compactions of the session so far, which the last one is the number of.
+    pub count: u64,
+}
+
+#[derive(Serialize)]
+pub struct ContextResponse {
+    pub context: Option<String>,
+    /// Tokens taken by `context`.
+    pub tokens: usize,
+    pub long_term_context: Option<String>,
+    pub long_term_tokens: usize,
+    /// When a compaction last stored a context, if one ever did.
+    pub last_compaction: Option<CompactionStamp>,
+}
+
+/// LLM tokens used, counted as the provider reports them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+#[derive(Serialize)]
+pub struct UsageResponse {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl From<TokenUsage> for UsageResponse {
+    fn from(usage: TokenUsage) -> Self {
+        UsageResponse {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total(),
+        }
+    }
+}
+
 #[derive(Deserialize)]
-pub struct MemoryMessages {
+pub struct UsageQuery {
+    /// `YYYY-MM`, the current month if unset.
+    pub month: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TenantUsage {
+    /// None for the default namespace.
+    pub tenant: Option<String>,
+    #[serde(flatten)]
+    pub usage: UsageResponse,
+}
+
+#[derive(Serialize)]
+pub struct AdminUsageResponse {
+    pub month: String,
+    pub monthly_token_budget: Option<u64>,
+    pub tenants: Vec<TenantUsage>,
+}
+
+/// A tenant's activity on a day, counted with `MOTORHEAD_ANALYTICS_ENABLED`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct AnalyticsCounts {
+    /// Appends to a session that had no messages.
+    pub sessions_created: u64,
+    pub messages_appended: u64,
+    pub compactions: u64,
+    /// By compactions and recaps, as in `TokenUsage`.
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl AnalyticsCounts {
+    /// The counters by the names stores keep them under.
+    pub fn fields(&self) -> [(&'static str, u64); 5] {
+        [
+            ("sessions_created", self.sessions_created),
+            ("messages_appended", self.messages_appended),
+            ("compactions", self.compactions),
+            ("prompt_tokens", self.prompt_tokens),
+            ("completion_tokens", self.completion_tokens),
+        ]
+    }
+
+    /// Adds to the counter named `field`, ignoring unknown ones.
+    pub fn add(&mut self, field: &str, count: u64) {
+        match field {
+            "sessions_created" => self.sessions_created += count,
+            "messages_appended" => self.messages_appended += count,
+            "compactions" => self.compactions += count,
+            "prompt_tokens" => self.prompt_tokens += count,
+            "completion_tokens" => self.completion_tokens += count,
+            _ => {}
+        }
+    }
+}
+
+impl std::ops::AddAssign for AnalyticsCounts {
+    fn add_assign(&mut self, other: AnalyticsCounts) {
+        for (field, count) in other.fields() {
+            self.add(field, count);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AnalyticsQuery {
+    /// Every tenant if unset, the default namespace if empty.
+    pub tenant: Option<String>,
+    /// `YYYY-MM-DD`, 29 days before `to` if unset.
+    pub from: Option<String>,
+    /// `YYYY-MM-DD`, inclusive, today in UTC if unset.
+    pub to: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DailyAnalytics {
+    pub date: String,
+    #[serde(flatten)]
+    pub counts: AnalyticsCounts,
+}
+
+#[derive(Serialize)]
+pub struct TenantAnalytics {
+    /// None for the default namespace.
+    pub tenant: Option<String>,
+    pub total: AnalyticsCounts,
+    /// The days with some activity, oldest first.
+    pub days: Vec<DailyAnalytics>,
+}
+
+#[derive(Serialize)]
+pub struct AnalyticsResponse {
+    pub from: String,
+    pub to: String,
+    pub tenants: Vec<TenantAnalytics>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Ndjson,
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Everything kept for a session besides its messages, as exported.
+#[derive(Serialize)]
+pub struct SessionSnapshot {
+    pub session_id: String,
+    /// Milliseconds since the Unix epoch.
+    pub exported_at: u64,
+    pub context: Option<String>,
+    pub long_term_context: Option<String>,
+    pub context_segments: Vec<ContextSegment>,
+    pub metadata: Option<serde_json::Value>,
+    pub entities: BTreeMap<String, String>,
+}
+
+/// Where `POST /sessions/{session_id}/fork` copies the session to.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForkRequest {
+    /// A new UUID if unset.
+    pub session_id: Option<String>,
+    /// The id of the last message to copy. Every message is copied if unset.
+    pub until_message_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ForkResponse {
+    pub session_id: String,
+}
+
+/// A line of `GET /admin/snapshot`: the session's export, with its config and pinned
+/// messages.
+#[derive(Serialize)]
+pub struct SnapshotEntry {
+    #[serde(flatten)]
+    pub session: SessionSnapshot,
+    pub config: Option<SessionConfig>,
+    pub pinned: Vec<MemoryMessage>,
+    /// Oldest first.
     pub messages: Vec<MemoryMessage>,
 }
 
+/// A line of a snapshot given to `POST /admin/restore`.
+#[derive(Deserialize)]
+pub struct RestoreEntry {
+    pub session_id: String,
+    #[serde(flatten)]
+    pub import: SessionImport,
+}
+
 #[derive(Serialize)]
-pub struct MemoryResponse {
+pub struct RestoreResponse {
+    pub status: &'static str,
+    /// The sessions stored.
+    pub sessions: usize,
+}
+
+/// An exported session to import. Its `session_id` and `exported_at` are ignored.
+#[derive(Deserialize)]
+pub struct SessionImport {
+    #[serde(default)]
+    pub context: Option<String>,
+    #[serde(default)]
+    pub long_term_context: Option<String>,
+    #[serde(default)]
+    pub context_segments: Vec<ContextSegment>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub entities: BTreeMap<String, String>,
+    #[serde(default)]
+    pub config: Option<SessionConfig>,
+    #[serde(default)]
+    pub pinned: Vec<MemoryMessage>,
+    /// Oldest first.
+    #[serde(default)]
     pub messages: Vec<MemoryMessage>,
+}
+
+/// A message in the OpenAI chat format.
+#[derive(Deserialize)]
+pub struct OpenAIMessage {
+    pub role: String,
+    pub content: Option<OpenAIContent>,
+    #[serde(default)]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default)]
+    pub tool_call_id: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum OpenAIContent {
+    Text(String),
+    Parts(Vec<OpenAIContentPart>),
+}
+
+#[derive(Deserialize)]
+pub struct OpenAIContentPart {
+    pub text: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    pub messages: Vec<MemoryMessage>,
+    pub next_offset: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct RangeQuery {
+    /// RFC 3339, inclusive. Unbounded if not given.
+    pub from: Option<String>,
+    /// RFC 3339, exclusive. Unbounded if not given.
+    pub to: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ReplayQuery {
+    /// RFC 3339.
+    pub at: String,
+}
+
+/// The session as it read at `at`.
+#[derive(Serialize)]
+pub struct ReplayResponse {
+    /// Milliseconds since the Unix epoch.
+    pub at: u64,
+    /// Newest first, as they read now.
+    pub messages: Vec<MemoryMessage>,
+    /// `None` before the first compaction.
     pub context: Option<String>,
+    /// When the compaction that stored `context` ran.
+    pub context_at: Option<u64>,
+    /// Whether the window had more than the messages returned.
+    pub truncated: bool,
+}
+
+#[derive(Serialize)]
+pub struct RangeResponse {
+    /// Oldest first: the history, then the window.
+    pub messages: Vec<MemoryMessage>,
+    /// Whether there were more than `limit` messages in the range.
+    pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    /// Whether `q` is a regular expression rather than a plain substring.
+    #[serde(default)]
+    pub regex: bool,
+    pub limit: Option<usize>,
+}
+
+/// Where a search match was found.
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageSource {
+    /// The window. Indices are newest first, like everywhere else in the API.
+    Messages,
+    /// The archived history. Indices are oldest first, like its `offset`.
+    History,
+}
+
+#[derive(Serialize)]
+pub struct SearchMatch {
+    pub source: MessageSource,
+    pub index: usize,
+    pub message: MemoryMessage,
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    /// Oldest first: the history, then the window.
+    pub matches: Vec<SearchMatch>,
+    /// Whether the search stopped at `limit` matches.
+    pub truncated: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    #[default]
+    Keyword,
+    /// Vector search, see `/sessions/{session_id}/retrieval`.
+    Semantic,
+}
+
+#[derive(Deserialize)]
+pub struct UserSearchQuery {
+    pub q: String,
+    #[serde(default)]
+    pub mode: SearchMode,
+    /// See `SearchQuery`. Only for keyword searches.
+    #[serde(default)]
+    pub regex: bool,
+    pub limit: Option<usize>,
+}
+
+/// The hits of a user search in one of the user's sessions: `matches` for keyword searches,
+/// `results` for semantic ones.
+#[derive(Serialize)]
+pub struct SessionHits {
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<SearchMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub results: Vec<RetrievalResult>,
+}
+
+#[derive(Serialize)]
+pub struct UserSearchResponse {
+    /// Sessions with hits only. Most recently active first for keyword searches, closest
+    /// first for semantic ones.
+    pub sessions: Vec<SessionHits>,
+    /// Whether the search stopped at `limit` hits.
+    pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+pub struct PromptQuery {
+    pub max_tokens: Option<usize>,
+}
+
+/// A message in the OpenAI chat format, as sent to a model.
+#[derive(Serialize)]
+pub struct PromptMessage {
+    pub role: Role,
+    /// Null for assistant messages that only call tools.
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PromptResponse {
+    /// The system message with the summary, if there's one, then the most recent messages
+    /// oldest first.
+    pub messages: Vec<PromptMessage>,
+    /// Tokens taken by `messages`.
+    pub tokens: usize,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageOrder {
+    Asc,
+    /// Newest first, as stored.
+    #[default]
+    Desc,
+}
+
+/// What `POST /sessions/{session_id}/memory` answers with besides the acknowledgement.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AppendReturn {
+    /// The session's memory as `GET` would read it after the append.
+    Memory,
+}
+
+#[derive(Deserialize)]
+pub struct AppendQuery {
+    #[serde(rename = "return")]
+    pub returns: Option<AppendReturn>,
+    /// Drops the messages repeating the session's latest one of their role.
+    #[serde(default)]
+    pub dedup: bool,
+}
+
+#[derive(Deserialize)]
+pub struct LockQuery {
+    pub ttl_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct LockResponse {
+    pub token: String,
+    /// Milliseconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+#[derive(Deserialize)]
+pub struct MemoryQuery {
+    #[serde(default)]
+    pub order: MessageOrder,
+    /// Newest-first offset of the first message of the page.
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    /// Comma separated, see `MemoryFields::parse`.
+    pub fields: Option<String>,
+    /// Overrides `MOTORHEAD_WINDOW_OVERLAP`.
+    pub overlap: Option<usize>,
+}
+
+/// What of the memory a read fetches. The rest is left empty in the response.
+#[derive(Clone, Copy)]
+pub struct MemoryFields {
+    /// The window's messages, with the pinned ones.
+    pub messages: bool,
+    /// The context, long-term context and context segments.
+    pub context: bool,
+}
+
+impl MemoryFields {
+    pub const ALL: MemoryFields = MemoryFields {
+        messages: true,
+        context: true,
+    };
+
+    /// From a comma separated list of `messages` and `context`.
+    pub fn parse(fields: &str) -> Option<Self> {
+        let mut parsed = MemoryFields {
+            messages: false,
+            context: false,
+        };
+        for field in fields.split(',').map(str::trim) {
+            match field {
+                "messages" => parsed.messages = true,
+                "context" => parsed.context = true,
+                _ => return None,
+            }
+        }
+        Some(parsed)
+    }
+}
+
+/// A page of stored messages, newest first.
+#[derive(Clone, Copy)]
+pub struct MessagePage {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Deserialize)]
+pub struct FlushQuery {
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteMode {
+    #[default]
+    Hard,
+    /// Kept for `MOTORHEAD_TRASH_TTL_SECONDS`, see `/sessions/{session_id}/restore`.
+    Soft,
+}
+
+#[derive(Deserialize)]
+pub struct ClearContextQuery {
+    /// Compacts the session into a new context right after.
+    #[serde(default)]
+    pub summarize: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteQuery {
+    #[serde(default)]
+    pub mode: DeleteMode,
+    /// The token of `POST /sessions/:id/memory/delete-intent`.
+    pub confirm: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DeleteIntentResponse {
+    pub token: String,
+    /// Milliseconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+/// What `DELETE /sessions/:id/memory/last` deletes: the newest `count` messages, only if the
+/// newest one matches every guard given.
+#[derive(Deserialize)]
+pub struct DeleteLastQuery {
+    #[serde(default = "default_delete_count")]
+    pub count: usize,
+    pub message_id: Option<String>,
+    pub role: Option<Role>,
+    /// Compared with the content as stored, exactly.
+    pub message_text: Option<String>,
+}
+
+fn default_delete_count() -> usize {
+    1
+}
+
+/// How many undos and redos of the session are left.
+#[derive(Serialize)]
+pub struct UndoResponse {
+    pub status: &'static str,
+    pub undo: usize,
+    pub redo: usize,
+}
+
+#[derive(Serialize)]
+pub struct DeleteLastResponse {
+    pub status: &'static str,
+    pub deleted: usize,
+}
+
+/// The messages a compaction summarized, as reported to its webhooks.
+#[derive(Serialize)]
+pub struct SummarizedRange {
+    /// The oldest.
+    pub first_message_id: Option<String>,
+    /// The newest.
+    pub last_message_id: Option<String>,
+    pub first_created_at: Option<u64>,
+    pub last_created_at: Option<u64>,
+    /// Pinned messages in between aren't summarized, nor counted.
+    pub count: usize,
+}
+
+/// Settings of a session that override the server's.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SessionConfig {
+    /// Replaces `MOTORHEAD_MAX_WINDOW_SIZE`.
+    #[serde(default)]
+    pub window_size: Option<i64>,
+    /// Replaces the `language` of the summary options.
+    #[serde(default)]
+    pub summary_language: Option<String>,
+    /// Where the summaries of the session's compactions are posted, on top of the webhooks.
+    #[serde(default)]
+    pub compaction_callback_url: Option<String>,
+    /// What the conversation is about, e.g. "This is a medical intake chat", for the summaries
+    /// to take into account. Returned with the memory and sent at the top of prompts.
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    /// Replaces `MOTORHEAD_COMPACTION_STRATEGY`.
+    #[serde(default)]
+    pub compaction_strategy: Option<CompactionStrategy>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SystemPrompt {
+    pub prompt: String,
+}
+
+/// The rolling score kept in `metadata.sentiment`, from -1 (unhappy) to 1 (happy).
+#[derive(Serialize, Deserialize)]
+pub struct SentimentAnalysis {
+    /// The average of `recent`.
+    pub score: f32,
+    /// The latest compacted window's.
+    pub last: f32,
+    /// The latest windows', oldest first.
+    pub recent: Vec<f32>,
+    /// Milliseconds since the Unix epoch.
+    pub updated_at: u64,
+}
+
+#[derive(Serialize)]
+pub struct AnalysisResponse {
+    pub sentiment: SentimentAnalysis,
+}
+
+#[derive(Serialize)]
+pub struct MetadataResponse {
+    pub metadata: serde_json::Value,
+    /// How many of the session's messages were tagged with each language.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub languages: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+pub struct KvResponse {
+    pub value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+pub struct BulkDeleteQuery {
+    /// Matches the sessions whose id starts with it.
+    pub prefix: Option<String>,
+    /// Matches the sessions whose metadata has `metadata_value` as this field.
+    pub metadata_field: Option<String>,
+    pub metadata_value: Option<String>,
+    /// Counts the sessions that would be deleted instead of deleting them.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Deletes the sessions that would need a delete intent's confirmation too.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Serialize)]
+pub struct BulkDeleteResponse {
+    pub matched: usize,
+    pub deleted: usize,
+}
+
+#[derive(Deserialize)]
+pub struct SessionListQuery {
+    pub page: Option<usize>,
+    pub page_size: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    pub last_activity: u64,
+    /// The session's `metadata.title`, with `MOTORHEAD_SESSION_TITLE_AFTER_MESSAGES`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SessionListResponse {
+    pub sessions: Vec<SessionSummary>,
+    pub next_page: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct RetrievalRequest {
+    pub text: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct RetrievalResult {
+    pub role: String,
+    pub content: String,
+    pub dist: f64,
+}
+
+#[derive(Serialize)]
+pub struct RetrievalResponse {
+    pub results: Vec<RetrievalResult>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RepairAction {
+    /// Keeps what the entries hold, as `unknown` messages.
+    Rewrite,
+    Purge,
+}
+
+#[derive(Deserialize)]
+pub struct RepairRequest {
+    pub action: RepairAction,
+}
+
+#[derive(Serialize)]
+pub struct RepairResponse {
+    /// How many malformed entries were rewritten or purged.
+    pub repaired: usize,
+}
+
+/// The base64 of a tenant's own 32 byte encryption key.
+#[derive(Deserialize)]
+pub struct TenantKeyRequest {
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SummaryPrompt {
+    pub prompt: String,
+}
+
+/// The runtime settings, as `GET /admin/config` returns them. Unset rate limits are off.
+#[derive(Serialize)]
+pub struct AdminConfig {
+    pub window_size: i64,
+    pub summary: SummaryOptions,
+    pub summary_prompt: String,
+    pub session_ttl_seconds: Option<u64>,
+    pub idempotency_ttl_seconds: u64,
+    pub session_writes_per_minute: Option<u32>,
+    pub api_key_requests_per_minute: Option<u32>,
+}
+
+impl From<RuntimeConfig> for AdminConfig {
+    fn from(config: RuntimeConfig) -> Self {
+        AdminConfig {
+            window_size: config.window_size,
+            summary: config.summary_options,
+            summary_prompt: config.summary_prompt,
+            session_ttl_seconds: config.session_ttl_seconds,
+            idempotency_ttl_seconds: config.idempotency_ttl_seconds,
+            session_writes_per_minute: config
+                .session_write_rate_limit
+                .map(|limit| limit.per_minute),
+            api_key_requests_per_minute: config.api_key_rate_limit.map(|limit| limit.per_minute),
+        }
+    }
+}
+
+/// Tells a field set to `null` from a missing one.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Changes to the runtime settings. Missing fields are left as they are, and `null` unsets
+/// the nullable ones. `summary` replaces the summary options as a whole.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfigPatch {
+    pub window_size: Option<i64>,
+    pub summary: Option<SummaryOptions>,
+    pub summary_prompt: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub session_ttl_seconds: Option<Option<u64>>,
+    pub idempotency_ttl_seconds: Option<u64>,
+    #[serde(default, deserialize_with = "present")]
+    pub session_writes_per_minute: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "present")]
+    pub api_key_requests_per_minute: Option<Option<u32>>,
 }
 
 #[derive(Serialize)]
@@ -32,15 +1157,170 @@ pub struct HealthCheckResponse {
     pub now: u128,
 }
 
+/// A session whose compaction failed, queued to be retried in the background.
+#[derive(Serialize, Deserialize)]
+pub struct CompactionFailure {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub session_id: String,
+    pub error: String,
+    pub failed_at: u64,
+    /// Background retries made so far.
+    pub retries: u32,
+    /// Unset once the retries are exhausted.
+    pub next_retry_at: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct CompactionFailuresResponse {
+    pub failures: Vec<CompactionFailure>,
+}
+
+/// What happened with a user during a day, across their sessions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Recap {
+    pub user_id: String,
+    /// The UTC day recapped, `YYYY-MM-DD`.
+    pub date: String,
+    pub recap: String,
+    /// The sessions with messages that day, most recently active first.
+    pub session_ids: Vec<String>,
+    /// How many messages of the day were recapped.
+    pub messages: usize,
+    /// Milliseconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+#[derive(Deserialize)]
+pub struct RecapsQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct RecapsResponse {
+    pub recaps: Vec<Recap>,
+}
+
+/// A change recorded in the audit log, before it's made.
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch.
+    pub at: u64,
+    /// `key:{id}` for API keys (see `AuthenticatedKey`), `jwt:{subject}` for tokens,
+    /// `motorhead` for background work, `cli` for the command line and `anonymous` without
+    /// auth.
+    pub actor: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// `{method} {route}` for requests, e.g. `DELETE /sessions/{session_id}`.
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// The path and query the request was made to, for requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    /// Milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub since: u64,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct AuditResponse {
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Serialize)]
+pub struct ComponentStatus {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ProbeResponse {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub components: Option<ProbeComponents>,
+}
+
+#[derive(Serialize)]
+pub struct ProbeComponents {
+    pub store: ComponentStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm: Option<ComponentStatus>,
+}
+
 #[derive(Serialize)]
 pub struct AckResponse {
     pub status: &'static str,
 }
 
+/// A message as the original API read it back, with `Human` and `AI` roles.
+#[derive(Serialize)]
+pub struct LegacyMessage {
+    pub role: String,
+    pub content: String,
+}
+
+/// `GET /sessions/:id/memory` with `MOTORHEAD_LEGACY_API_ENABLED`, in the shape LangChain's
+/// `MotorheadMemory` reads.
+#[derive(Serialize)]
+pub struct LegacyMemoryResponse {
+    pub messages: Vec<LegacyMessage>,
+    pub context: Option<String>,
+    pub tokens: usize,
+}
+
+#[derive(Serialize)]
+pub struct AppendResponse {
+    pub status: &'static str,
+    /// With `?dedup=true`, the messages dropped as duplicates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryResponse>,
+}
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum MotorheadError {
     RedisError(RedisError),
     IncrementalSummarizationError(String),
+    SerializationError(String),
+    PostgresError(String),
+    /// No connection to Postgres could be had from the pool.
+    PostgresUnavailable(String),
+    Unsupported(&'static str),
+    EmbeddingError(String),
+    LlmError(String),
+    /// A failure worth retrying: rate limits, provider outages, timeouts.
+    LlmUnavailable(String),
+    /// LLM calls are refused for a while after too many failed in a row.
+    LlmCircuitOpen,
+    ModerationError(String),
+    /// A message hook failed, or its webhook answered with an error.
+    HookError(String),
+    /// The tenant used up its monthly token budget, of this many tokens.
+    TokenBudgetExhausted(u64),
+    /// The append would take the session over `MOTORHEAD_SESSION_MAX_STORED_*`.
+    SessionQuotaExceeded,
+    /// The messages a compaction summarized were deleted before it could commit.
+    CompactionConflict,
+    /// The store is unreachable and the write buffer is full.
+    WriteBufferFull,
+    /// The change couldn't be recorded in the audit log, so it wasn't made.
+    AuditUnavailable(String),
+    /// Object storage failed a request of the cold storage tier.
+    ColdStorageUnavailable(String),
+    /// A value couldn't be encrypted or decrypted with `MOTORHEAD_ENCRYPTION_KEY`.
+    EncryptionError(String),
+    /// The value was encrypted with a tenant's own key, which was deleted since.
+    TenantKeyDeleted,
 }
 
 impl std::fmt::Display for MotorheadError {
@@ -50,12 +1330,65 @@ impl std::fmt::Display for MotorheadError {
             MotorheadError::IncrementalSummarizationError(e) => {
                 write!(f, "Incremental summarization error: {}", e)
             }
+            MotorheadError::SerializationError(e) => write!(f, "Serialization error: {}", e),
+            MotorheadError::PostgresError(e) => write!(f, "Postgres error: {}", e),
+            MotorheadError::PostgresUnavailable(e) => write!(f, "Postgres unavailable: {}", e),
+            MotorheadError::EmbeddingError(e) => write!(f, "Embedding error: {}", e),
+            MotorheadError::LlmError(e) => write!(f, "LLM error: {}", e),
+            MotorheadError::LlmUnavailable(e) => write!(f, "LLM unavailable: {}", e),
+            MotorheadError::LlmCircuitOpen => {
+                write!(f, "LLM unavailable: too many recent calls failed")
+            }
+            MotorheadError::ModerationError(e) => write!(f, "Moderation error: {}", e),
+            MotorheadError::HookError(e) => write!(f, "Message hook error: {}", e),
+            MotorheadError::SessionQuotaExceeded => {
+                write!(f, "The session is over its storage quota")
+            }
+            MotorheadError::TokenBudgetExhausted(budget) => {
+                write!(f, "The monthly budget of {} tokens is exhausted", budget)
+            }
+            MotorheadError::Unsupported(feature) => {
+                write!(f, "{} is not supported by this storage backend", feature)
+            }
+            MotorheadError::CompactionConflict => {
+                write!(f, "The summarized messages changed during the compaction")
+            }
+            MotorheadError::WriteBufferFull => {
+                write!(f, "The store is unreachable and the write buffer is full")
+            }
+            MotorheadError::AuditUnavailable(e) => write!(f, "Audit log unavailable: {}", e),
+            MotorheadError::ColdStorageUnavailable(e) => {
+                write!(f, "Cold storage unavailable: {}", e)
+            }
+            MotorheadError::EncryptionError(e) => write!(f, "Encryption error: {}", e),
+            MotorheadError::TenantKeyDeleted => {
+                write!(
+                    f,
+                    "The tenant's encryption key was deleted, its data is gone"
+                )
+            }
         }
     }
 }
 
 impl From<RedisError> for MotorheadError {
     fn from(err: RedisError) -> Self {
+        metrics::REDIS_ERRORS.inc();
         MotorheadError::RedisError(err)
     }
 }
+
+impl From<tokio_postgres::Error> for MotorheadError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        if err.is_closed() {
+            return MotorheadError::PostgresUnavailable(err.to_string());
+        }
+        MotorheadError::PostgresError(err.to_string())
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for MotorheadError {
+    fn from(err: deadpool_postgres::PoolError) -> Self {
+        MotorheadError::PostgresUnavailable(err.to_string())
+    }
+}
@@ -0,0 +1,302 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{AppState, MemoryMessage, MotorheadError};
+use crate::telemetry;
+
+pub const OPENAI_DEFAULT_API_BASE: &str = "https://api.openai.com/v1";
+
+/// What flagged messages are replaced with when redacting, unless the moderator sends its own
+/// redaction.
+const REDACTED_CONTENT: &str = "[redacted]";
+
+/// What happens to messages that violate policy.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ModerationAction {
+    /// The whole request is refused and nothing is stored.
+    Reject,
+    /// Messages are stored as sent.
+    Flag,
+    /// The content of flagged messages is replaced before they're stored.
+    Redact,
+}
+
+impl ModerationAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            ModerationAction::Reject => "reject",
+            ModerationAction::Flag => "flag",
+            ModerationAction::Redact => "redact",
+        }
+    }
+}
+
+/// A moderator's conclusion about one message.
+#[derive(Deserialize)]
+pub struct Verdict {
+    pub flagged: bool,
+    /// The policies the message violates.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Replaces the message's content when redacting.
+    #[serde(default)]
+    pub redacted_content: Option<String>,
+}
+
+/// Classifies messages before they're stored.
+#[async_trait]
+pub trait Moderator: Send + Sync {
+    /// Returns one verdict per message, in the same order.
+    async fn check(
+        &self,
+        session_id: &str,
+        messages: &[MemoryMessage],
+    ) -> Result<Vec<Verdict>, MotorheadError>;
+}
+
+pub struct Moderation {
+    moderator: Box<dyn Moderator>,
+    action: ModerationAction,
+}
+
+impl Moderation {
+    pub fn new(moderator: Box<dyn Moderator>, action: ModerationAction) -> Self {
+        Moderation { moderator, action }
+    }
+}
+
+/// Runs messages through moderation, if enabled. Flagged messages that get stored have the
+/// verdict recorded under `moderation` in their metadata. Fails closed: messages are not
+/// stored while the moderator can't be reached.
+pub async fn moderate(
+    state: &AppState,
+    session_id: &str,
+    mut messages: Vec<MemoryMessage>,
+) -> actix_web::Result<Vec<MemoryMessage>> {
+    let Some(moderation) = &state.moderation else {
+        return Ok(messages);
+    };
+    if messages.is_empty() {
+        return Ok(messages);
+    }
+
+    let verdicts = moderation
+        .moderator
+        .check(session_id, &messages)
+        .await
+        .and_then(|verdicts| {
+            if verdicts.len() == messages.len() {
+                Ok(verdicts)
+            } else {
+                Err(MotorheadError::ModerationError(format!(
+                    "Expected {} verdicts, got {}",
+                    messages.len(),
+                    verdicts.len()
+                )))
+            }
+        })
+        .map_err(|e| {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem moderating messages"
+            );
+            ApiError::new(ErrorCode::ModerationUnavailable, e)
+        })?;
+
+    if moderation.action == ModerationAction::Reject {
+        if let Some((index, verdict)) = verdicts
+            .iter()
+            .enumerate()
+            .find(|(_, verdict)| verdict.flagged)
+        {
+            return Err(ApiError::new(
+                ErrorCode::MessageFlagged,
+                format!(
+                    "Message {} was rejected by moderation: {}",
+                    index,
+                    verdict.categories.join(", ")
+                ),
+            )
+            .with("index", index)
+            .with("categories", verdict.categories.clone())
+            .into());
+        }
+        return Ok(messages);
+    }
+
+    for (message, verdict) in messages.iter_mut().zip(verdicts) {
+        if !verdict.flagged {
+            continue;
+        }
+        if moderation.action == ModerationAction::Redact {
+            message.content = verdict
+                .redacted_content
+                .unwrap_or_else(|| REDACTED_CONTENT.to_string());
+        }
+        message
+            .metadata
+            .get_or_insert_with(Default::default)
+            .insert(
+                "moderation".to_string(),
+                json!({
+                    "flagged": true,
+                    "categories": verdict.categories,
+                    "action": moderation.action.as_str(),
+                }),
+            );
+    }
+
+    Ok(messages)
+}
+
+/// OpenAI's moderation endpoint, which ignores roles and only classifies content.
+pub struct OpenAIModerator {
+    http: reqwest::Client,
+    url: String,
+    api_key: String,
+    model: String,
+}
+
+impl OpenAIModerator {
+    pub fn new(api_base: &str, api_key: String, model: String) -> Self {
+        OpenAIModerator {
+            http: reqwest::Client::new(),
+            url: format!("{}/moderations", api_base.trim_end_matches('/')),
+            api_key,
+            model,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ModerationRequest<'a> {
+    model: &'a str,
+    input: Vec<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    categories: BTreeMap<String, bool>,
+}
+
+fn moderation_error(err: impl std::fmt::Display) -> MotorheadError {
+    MotorheadError::ModerationError(err.to_string())
+}
+
+#[async_trait]
+impl Moderator for OpenAIModerator {
+    async fn check(
+        &self,
+        _session_id: &str,
+        messages: &[MemoryMessage],
+    ) -> Result<Vec<Verdict>, MotorheadError> {
+        let body = ModerationRequest {
+            model: &self.model,
+            input: messages
+                .iter()
+                .map(|message| message.content.as_str())
+                .collect(),
+        };
+
+        let response = self
+            .http
+            .post(&self.url)
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(moderation_error)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            return Err(moderation_error(format!(
+                "OpenAI returned {}: {}",
+                status, message
+            )));
+        }
+
+        let response: ModerationResponse = response.json().await.map_err(moderation_error)?;
+
+        Ok(response
+            .results
+            .into_iter()
+            .map(|result| Verdict {
+                flagged: result.flagged,
+                categories: result
+                    .categories
+                    .into_iter()
+                    .filter_map(|(category, violated)| violated.then_some(category))
+                    .collect(),
+                redacted_content: None,
+            })
+            .collect())
+    }
+}
+
+/// Posts `{"session_id", "messages"}` to a URL, which answers with `{"results": [...]}`, one
+/// verdict per message.
+pub struct WebhookModerator {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl WebhookModerator {
+    pub fn new(url: String) -> Self {
+        WebhookModerator {
+            http: reqwest::Client::new(),
+            url,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookRequest<'a> {
+    session_id: &'a str,
+    messages: &'a [MemoryMessage],
+}
+
+#[derive(Deserialize)]
+struct WebhookResponse {
+    results: Vec<Verdict>,
+}
+
+#[async_trait]
+impl Moderator for WebhookModerator {
+    async fn check(
+        &self,
+        session_id: &str,
+        messages: &[MemoryMessage],
+    ) -> Result<Vec<Verdict>, MotorheadError> {
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&WebhookRequest {
+                session_id,
+                messages,
+            })
+            .send()
+            .await
+            .map_err(moderation_error)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(moderation_error(format!(
+                "Moderation webhook returned {}",
+                status
+            )));
+        }
+
+        let response: WebhookResponse = response.json().await.map_err(moderation_error)?;
+        Ok(response.results)
+    }
+}
@@ -0,0 +1,332 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+use crate::auth::auth_enabled;
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::AppState;
+
+/// A route in the document. Path parameters are taken from its `{...}` segments.
+struct Endpoint {
+    method: &'static str,
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    /// Schema of the JSON body, a component's name or `object`.
+    request: Option<&'static str>,
+    /// Schema of the `200` response, a component's name or `object`.
+    response: Option<&'static str>,
+}
+
+const fn endpoint(
+    method: &'static str,
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    request: Option<&'static str>,
+    response: Option<&'static str>,
+) -> Endpoint {
+    Endpoint {
+        method,
+        path,
+        tag,
+        summary,
+        request,
+        response,
+    }
+}
+
+const OBJECT: Option<&str> = Some("object");
+
+/// Every route `main` registers, kept in the order of the README. Query parameters and the
+/// bodies of the less common routes are described there rather than here.
+#[rustfmt::skip]
+const ENDPOINTS: &[Endpoint] = &[
+    endpoint("get", "/sessions/{session_id}/memory", "memory", "The session's window and summaries", None, Some("MemoryResponse")),
+    endpoint("post", "/sessions/{session_id}/memory", "memory", "Appends messages to the session", Some("MemoryMessages"), OBJECT),
+    endpoint("delete", "/sessions/{session_id}/memory", "memory", "Deletes the session", None, OBJECT),
+    endpoint("post", "/sessions/{session_id}/memory/delete-intent", "memory", "A token confirming the delete of a large session", None, OBJECT),
+    endpoint("post", "/sessions/{session_id}/undo", "memory", "Undoes the session's last mutation", None, OBJECT),
+    endpoint("post", "/sessions/{session_id}/redo", "memory", "Redoes the session's last undo", None, OBJECT),
+    endpoint("post", "/sessions/{session_id}/restore", "memory", "Restores a soft-deleted session", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/memory/stream", "memory", "A Server-Sent Events stream of the session's changes", None, None),
+    endpoint("get", "/ws/sessions/{session_id}", "memory", "A WebSocket for the session", None, None),
+    endpoint("patch", "/sessions/{session_id}/memory/messages/{message_id}", "memory", "Replaces a message's content", OBJECT, OBJECT),
+    endpoint("delete", "/sessions/{session_id}/memory/messages/{message_id}", "memory", "Deletes a message", None, OBJECT),
+    endpoint("delete", "/sessions/{session_id}/memory/last", "memory", "Deletes the newest messages", None, OBJECT),
+    endpoint("post", "/sessions/{session_id}/memory/messages/{message_id}/pin", "memory", "Pins a message", None, OBJECT),
+    endpoint("delete", "/sessions/{session_id}/memory/messages/{message_id}/pin", "memory", "Unpins a message", None, OBJECT),
+    endpoint("post", "/sessions/batch", "sessions", "Applies several operations at once", OBJECT, OBJECT),
+    endpoint("get", "/sessions", "sessions", "Lists sessions, most recently active first", None, OBJECT),
+    endpoint("delete", "/sessions", "sessions", "Deletes sessions by prefix or metadata", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/metadata", "sessions", "The session's metadata", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/analysis", "sessions", "The session's rolling sentiment score", None, OBJECT),
+    endpoint("put", "/sessions/{session_id}/metadata", "sessions", "Replaces the session's metadata", OBJECT, OBJECT),
+    endpoint("delete", "/sessions/{session_id}/metadata", "sessions", "Deletes the session's metadata", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/kv/{key}", "sessions", "A value of the session's key-value memory", None, OBJECT),
+    endpoint("put", "/sessions/{session_id}/kv/{key}", "sessions", "Stores a value in the session's key-value memory", OBJECT, OBJECT),
+    endpoint("delete", "/sessions/{session_id}/kv/{key}", "sessions", "Deletes a value of the session's key-value memory", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/config", "sessions", "The session's settings", None, OBJECT),
+    endpoint("put", "/sessions/{session_id}/config", "sessions", "Replaces the session's settings", OBJECT, OBJECT),
+    endpoint("get", "/sessions/{session_id}/system", "sessions", "The session's system prompt", None, OBJECT),
+    endpoint("put", "/sessions/{session_id}/system", "sessions", "Sets the session's system prompt", OBJECT, OBJECT),
+    endpoint("delete", "/sessions/{session_id}/system", "sessions", "Removes the session's system prompt", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/entities", "memory", "The facts extracted from the session", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/memory/search", "search", "Searches the session's messages", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/memory/range", "memory", "The messages created in a time range", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/replay", "memory", "The session's window and context at a point in time", None, OBJECT),
+    endpoint("get", "/users/{user_id}/search", "search", "Searches every session of a user", None, OBJECT),
+    endpoint("get", "/users/{user_id}/export", "users", "Exports every session of a user", None, None),
+    endpoint("get", "/users/{user_id}/recaps", "users", "The user's daily recaps", None, OBJECT),
+    endpoint("delete", "/users/{user_id}/recaps", "users", "Deletes the user's recaps", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/history", "memory", "The messages compactions moved out of the window", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/prompt", "memory", "The session as a chat model's messages", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/export", "sessions", "Exports the session", None, OBJECT),
+    endpoint("post", "/sessions/{session_id}/import", "sessions", "Replaces the session with an export", OBJECT, OBJECT),
+    endpoint("post", "/sessions/{session_id}/fork", "sessions", "Copies the session into a new one", OBJECT, OBJECT),
+    endpoint("post", "/sessions/{session_id}/retrieval", "search", "The messages most similar to a text", OBJECT, OBJECT),
+    endpoint("get", "/healthz", "health", "Liveness probe", None, OBJECT),
+    endpoint("get", "/readyz", "health", "Readiness probe", None, OBJECT),
+    endpoint("get", "/metrics", "admin", "Prometheus metrics", None, None),
+    endpoint("post", "/sessions/{session_id}/summarize", "compaction", "Compacts the session now", None, Some("SummarizeResponse")),
+    endpoint("get", "/sessions/{session_id}/context", "compaction", "The session's summaries and their sizes", None, OBJECT),
+    endpoint("delete", "/sessions/{session_id}/context", "compaction", "Clears the session's context", None, OBJECT),
+    endpoint("post", "/sessions/{session_id}/summary/regenerate", "compaction", "Rebuilds the context from the session's history", None, Some("SummarizeResponse")),
+    endpoint("post", "/sessions/{session_id}/lock", "sessions", "Takes the session's lease", None, OBJECT),
+    endpoint("delete", "/sessions/{session_id}/lock", "sessions", "Releases the session's lease", None, OBJECT),
+    endpoint("post", "/sessions/{session_id}/flush", "compaction", "Waits for the session's background work", None, OBJECT),
+    endpoint("get", "/config/summary-prompt", "admin", "The summarization prompt", None, OBJECT),
+    endpoint("put", "/config/summary-prompt", "admin", "Replaces the summarization prompt", OBJECT, OBJECT),
+    endpoint("get", "/admin/config", "admin", "The settings that apply without a restart", None, OBJECT),
+    endpoint("patch", "/admin/config", "admin", "Changes the settings that apply without a restart", OBJECT, OBJECT),
+    endpoint("put", "/tenant/encryption-key", "admin", "Registers the tenant's own encryption key", OBJECT, OBJECT),
+    endpoint("delete", "/tenant/encryption-key", "admin", "Deletes the tenant's encryption key, making what it encrypted unreadable", None, OBJECT),
+    endpoint("get", "/sessions/{session_id}/usage", "sessions", "The LLM tokens the session's compactions used", None, OBJECT),
+    endpoint("get", "/admin/usage", "admin", "The tokens compactions used in a month, per tenant", None, OBJECT),
+    endpoint("get", "/admin/analytics", "admin", "What each tenant did per day", None, OBJECT),
+    endpoint("get", "/admin/snapshot", "admin", "Streams every session of the namespace", None, None),
+    endpoint("post", "/admin/restore", "admin", "Loads a snapshot", None, OBJECT),
+    endpoint("get", "/admin/audit", "admin", "The audit log", None, OBJECT),
+    endpoint("get", "/admin/llm/circuit", "admin", "The state of the LLM circuit breaker", None, OBJECT),
+    endpoint("get", "/admin/compaction/failures", "admin", "Sessions whose last compaction failed", None, OBJECT),
+    endpoint("post", "/admin/sessions/{session_id}/repair", "admin", "Rewrites or purges the session's malformed message entries", OBJECT, OBJECT),
+    endpoint("post", "/v1/chat/completions", "proxy", "OpenAI-compatible chat completions proxy", OBJECT, OBJECT),
+    endpoint("post", "/dev/summarize", "dev", "Summarizes a conversation the way a compaction would", OBJECT, OBJECT),
+];
+
+fn schema(name: &str) -> Value {
+    match name {
+        "object" => json!({ "type": "object" }),
+        name => json!({ "$ref": format!("#/components/schemas/{}", name) }),
+    }
+}
+
+fn json_content(name: &str) -> Value {
+    json!({ "application/json": { "schema": schema(name) } })
+}
+
+/// The request and response schemas, matching the types of `motorhead_client::models`.
+fn schemas() -> Value {
+    json!({
+        "Role": {
+            "type": "string",
+            "description": "`user`, `assistant`, `system`, `tool` or a custom role, kept as sent.",
+            "example": "user",
+        },
+        "FunctionCall": {
+            "type": "object",
+            "required": ["name", "arguments"],
+            "properties": {
+                "name": { "type": "string" },
+                "arguments": { "type": "string", "description": "JSON-encoded, as sent by the model." },
+            },
+        },
+        "ToolCall": {
+            "type": "object",
+            "required": ["id", "function"],
+            "properties": {
+                "id": { "type": "string" },
+                "type": { "type": "string", "default": "function" },
+                "function": schema("FunctionCall"),
+            },
+        },
+        "Attachment": {
+            "type": "object",
+            "required": ["url", "mime_type"],
+            "properties": {
+                "url": { "type": "string" },
+                "mime_type": { "type": "string" },
+                "size": { "type": "integer", "format": "int64", "description": "In bytes." },
+                "text": { "type": "string", "description": "The text extracted from the file, e.g. a PDF's." },
+            },
+        },
+        "MemoryMessage": {
+            "type": "object",
+            "required": ["role"],
+            "properties": {
+                "role": schema("Role"),
+                "content": { "type": "string", "description": "Empty for assistant messages that only call tools." },
+                "tool_calls": { "type": "array", "items": schema("ToolCall") },
+                "tool_call_id": { "type": "string", "description": "The call a `tool` message answers." },
+                "name": { "type": "string", "description": "The participant or tool the message is from." },
+                "speaker": { "type": "string", "description": "Who said it, in group chats where several people share the `user` role." },
+                "attachments": { "type": "array", "items": schema("Attachment") },
+                "id": { "type": "string", "description": "Assigned by the server when the message is stored, unless the client sends one." },
+                "turn_id": { "type": "string", "description": "Groups the messages of a turn, e.g. a tool call, its result and the reply, which deletions and undos then take out whole." },
+                "created_at": { "type": "integer", "format": "int64", "description": "Milliseconds since the Unix epoch." },
+                "metadata": { "type": "object" },
+                "importance": { "type": "number", "format": "float", "description": "From 0 to 1, on the messages a compaction pinned for their importance." },
+            },
+        },
+        "ContextSegment": {
+            "type": "object",
+            "required": ["topic", "summary"],
+            "properties": {
+                "topic": { "type": "string" },
+                "summary": { "type": "string" },
+                "first_message_id": { "type": "string", "nullable": true },
+                "last_message_id": { "type": "string", "nullable": true },
+                "message_count": { "type": "integer" },
+            },
+        },
+        "MemoryMessages": {
+            "type": "object",
+            "required": ["messages"],
+            "properties": {
+                "messages": { "type": "array", "items": schema("MemoryMessage") },
+                "ttl_seconds": { "type": "integer", "format": "int64" },
+            },
+        },
+        "MemoryResponse": {
+            "type": "object",
+            "required": ["messages", "tokens_in_window", "messages_since_last_summary", "compaction_in_progress"],
+            "properties": {
+                "messages": { "type": "array", "items": schema("MemoryMessage"), "description": "Newest first." },
+                "context": { "type": "string", "nullable": true },
+                "long_term_context": { "type": "string", "nullable": true },
+                "context_segments": { "type": "array", "items": schema("ContextSegment") },
+                "compaction_error": { "type": "string" },
+                "system_prompt": { "type": "string" },
+                "tokens_in_window": { "type": "integer" },
+                "messages_since_last_summary": { "type": "integer" },
+                "compaction_in_progress": { "type": "boolean" },
+                "lock_expires_at": { "type": "integer", "format": "int64" },
+                "next_offset": { "type": "integer" },
+                "overlap": { "type": "integer", "description": "How many of the oldest messages were already summarized." },
+            },
+        },
+        "SummarizeResponse": {
+            "type": "object",
+            "required": ["context"],
+            "properties": { "context": { "type": "string" } },
+        },
+        "Error": {
+            "type": "object",
+            "required": ["error"],
+            "properties": {
+                "error": {
+                    "type": "object",
+                    "required": ["code", "message"],
+                    "properties": {
+                        "code": { "type": "string", "example": "NOT_FOUND" },
+                        "message": { "type": "string" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+fn operation(endpoint: &Endpoint) -> Value {
+    let parameters: Vec<Value> = endpoint
+        .path
+        .split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            })
+        })
+        .collect();
+
+    let ok = match endpoint.response {
+        Some(name) => json!({ "description": "OK", "content": json_content(name) }),
+        None => json!({ "description": "OK" }),
+    };
+    let mut operation = json!({
+        "tags": [endpoint.tag],
+        "summary": endpoint.summary,
+        "parameters": parameters,
+        "responses": {
+            "200": ok,
+            "default": { "description": "Error", "content": json_content("Error") },
+        },
+    });
+    if let Some(name) = endpoint.request {
+        operation["requestBody"] = json!({ "required": true, "content": json_content(name) });
+    }
+    operation
+}
+
+/// The OpenAPI 3 document of the API, with bearer auth when API keys or JWTs are configured.
+pub fn document(state: &AppState) -> Value {
+    let mut paths = Map::new();
+    for endpoint in ENDPOINTS {
+        let path = paths.entry(endpoint.path).or_insert_with(|| json!({}));
+        path[endpoint.method] = operation(endpoint);
+    }
+
+    let mut document = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Motörhead",
+            "description": "A memory and information retrieval server for LLMs.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": schemas() },
+    });
+    if auth_enabled(state) {
+        document["components"]["securitySchemes"] =
+            json!({ "bearer": { "type": "http", "scheme": "bearer" } });
+        document["security"] = json!([{ "bearer": [] }]);
+    }
+    document
+}
+
+#[get("/openapi.json")]
+pub async fn get_openapi(data: web::Data<Arc<AppState>>) -> impl Responder {
+    web::Json(document(&data))
+}
+
+/// Swagger UI for `/openapi.json`, loaded from its CDN. Requires `MOTORHEAD_SWAGGER_UI_ENABLED`.
+#[get("/docs")]
+pub async fn get_docs(data: web::Data<Arc<AppState>>) -> actix_web::Result<impl Responder> {
+    if !data.swagger_ui_enabled {
+        return Err(ApiError::new(ErrorCode::FeatureDisabled, "Swagger UI is not enabled").into());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI))
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Motörhead API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
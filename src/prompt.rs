@@ -0,0 +1,145 @@
+use actix_web::{get, web, Responder};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::memory::pinned_outside;
+use crate::models::{AppState, MemoryMessage, PromptMessage, PromptQuery, PromptResponse, Role};
+use crate::response::read_response;
+use crate::tenant::Tenant;
+use crate::tokens::{count_message_tokens, count_tokens};
+
+/// Introduces the long-term summary in the system message.
+const LONG_TERM_PREAMBLE: &str = "Summary of the earlier conversation:";
+
+/// Introduces the summary in the system message.
+const SUMMARY_PREAMBLE: &str = "Summary of the conversation so far:";
+
+/// Introduces the extracted facts in the system message.
+const ENTITIES_PREAMBLE: &str = "Known facts:";
+
+/// The session's system prompt, summaries and extracted facts, as the content of a system
+/// message. None when there's none of them.
+pub fn system_message(
+    system_prompt: Option<String>,
+    long_term_context: Option<String>,
+    context: Option<String>,
+    entities: BTreeMap<String, String>,
+) -> Option<String> {
+    let mut sections = Vec::new();
+    if let Some(system_prompt) = system_prompt {
+        sections.push(system_prompt);
+    }
+    if let Some(long_term_context) = long_term_context.filter(|context| !context.is_empty()) {
+        sections.push(format!("{}\n{}", LONG_TERM_PREAMBLE, long_term_context));
+    }
+    if let Some(context) = context.filter(|context| !context.is_empty()) {
+        sections.push(format!("{}\n{}", SUMMARY_PREAMBLE, context));
+    }
+    if !entities.is_empty() {
+        let facts: Vec<String> = entities
+            .into_iter()
+            .map(|(name, value)| format!("- {}: {}", name, value))
+            .collect();
+        sections.push(format!("{}\n{}", ENTITIES_PREAMBLE, facts.join("\n")));
+    }
+
+    (!sections.is_empty()).then(|| sections.join("\n\n"))
+}
+
+/// Whether OpenAI takes it as a message `name`.
+fn valid_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn prompt_message(message: MemoryMessage) -> PromptMessage {
+    let content =
+        (!message.content.is_empty() || message.tool_calls.is_none()).then_some(message.content);
+    // The model tells the humans of a group chat apart by their names.
+    let name = message
+        .name
+        .or_else(|| message.speaker.filter(|speaker| valid_name(speaker)));
+
+    PromptMessage {
+        role: message.role,
+        content,
+        tool_calls: message.tool_calls,
+        tool_call_id: message.tool_call_id,
+        name,
+    }
+}
+
+/// The session as a messages array ready to send to a chat model: a system message with the
+/// session's system prompt, summaries and extracted facts, then as many of the most recent messages as fit in
+/// `max_tokens` (`MOTORHEAD_MAX_WINDOW_TOKENS` by default, the whole window without either).
+/// Pinned messages that have left the window come right after the system message, whatever
+/// the budget.
+#[get("/sessions/{session_id}/prompt")]
+pub async fn get_prompt(
+    session_id: web::Path<String>,
+    query: web::Query<PromptQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let store = tenant.store(&data);
+    let config = store
+        .get_session_config(&session_id)
+        .await?
+        .unwrap_or_default();
+    let window_size = config.window_size.unwrap_or(data.runtime().window_size);
+    let (window, context) = store.get_memory(&session_id, 0, window_size).await?;
+    let long_term_context = store.get_long_term_context(&session_id).await?;
+    let entities = store.get_entities(&session_id).await?;
+    let pinned = pinned_outside(store.as_ref(), &session_id, &window).await?;
+    let budget = query
+        .max_tokens
+        .or(data.window_tokens)
+        .unwrap_or(usize::MAX);
+
+    let system = system_message(config.system_prompt, long_term_context, context, entities);
+    let mut tokens = system
+        .as_ref()
+        .map(|system| count_tokens(Role::System.as_str()) + count_tokens(system))
+        .unwrap_or(0)
+        + pinned.iter().map(count_message_tokens).sum::<usize>();
+
+    // Newest first, stopping at the first message that doesn't fit.
+    let mut recent = Vec::new();
+    for message in window {
+        let message_tokens = count_message_tokens(&message);
+        if tokens + message_tokens > budget {
+            break;
+        }
+        tokens += message_tokens;
+        recent.push(message);
+    }
+    // Tool results whose call didn't fit would be rejected by chat models.
+    while recent
+        .last()
+        .is_some_and(|message| message.role == Role::Tool)
+    {
+        let message = recent.pop().expect("checked above");
+        tokens -= count_message_tokens(&message);
+    }
+
+    let messages = system
+        .map(|system| PromptMessage {
+            role: Role::System,
+            content: Some(system),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        })
+        .into_iter()
+        .chain(pinned.into_iter().map(prompt_message))
+        .chain(recent.into_iter().rev().map(prompt_message))
+        .collect();
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        PromptResponse { messages, tokens },
+    ))
+}
@@ -0,0 +1,164 @@
+use actix_web::http::StatusCode;
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::archive::from_openai;
+use crate::errors::{ApiError, ErrorCode};
+use crate::memory::{append_memory, check_roles, summary_options};
+use crate::metrics;
+use crate::models::{AppState, MemoryMessage, OpenAIMessage};
+use crate::moderation::moderate;
+use crate::prompt::system_message;
+use crate::telemetry;
+use crate::tenant::Tenant;
+
+pub const SESSION_HEADER: &str = "X-Motorhead-Session-Id";
+
+/// Roles whose messages are instructions for the model rather than part of the conversation,
+/// so they aren't recorded.
+const INSTRUCTION_ROLES: [&str; 2] = ["system", "developer"];
+
+fn role(message: &Value) -> Option<&str> {
+    message.get("role").and_then(Value::as_str)
+}
+
+fn is_instruction(message: &Value) -> bool {
+    role(message).is_some_and(|role| INSTRUCTION_ROLES.contains(&role))
+}
+
+/// The new turn of a chat completions request: what follows the last assistant message, so
+/// clients resending the whole conversation don't get it recorded twice.
+fn new_messages(messages: &[Value]) -> actix_web::Result<Vec<MemoryMessage>> {
+    let start = messages
+        .iter()
+        .rposition(|message| role(message) == Some("assistant"))
+        .map_or(0, |index| index + 1);
+
+    messages
+        .iter()
+        .enumerate()
+        .skip(start)
+        .filter(|(_, message)| !is_instruction(message))
+        .map(|(index, message)| {
+            let message: OpenAIMessage = serde_json::from_value(message.clone()).map_err(|e| {
+                ApiError::invalid_request(format!("Invalid message {}: {}", index, e))
+            })?;
+            from_openai(index, message)
+        })
+        .collect()
+}
+
+/// Forwards an OpenAI chat completions request to the configured provider, for the session
+/// named by the `X-Motorhead-Session-Id` header. The session's summary and extracted facts are
+/// added as a system message after the client's own, and the new messages are recorded in the
+/// session along with the reply. Requires `MOTORHEAD_PROXY_ENABLED`.
+#[post("/v1/chat/completions")]
+pub async fn chat_completions(
+    web::Json(mut body): web::Json<Value>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    if !data.proxy_enabled {
+        return Err(ApiError::new(
+            ErrorCode::FeatureDisabled,
+            "The chat completions proxy is not enabled",
+        )
+        .into());
+    }
+    let session_id = req
+        .headers()
+        .get(SESSION_HEADER)
+        .ok_or_else(|| ApiError::invalid_request("Missing X-Motorhead-Session-Id header"))?
+        .to_str()
+        .ok()
+        .filter(|session_id| !session_id.is_empty())
+        .ok_or_else(|| ApiError::invalid_request("Invalid X-Motorhead-Session-Id header"))?
+        .to_string();
+    if body.get("stream").and_then(Value::as_bool) == Some(true) {
+        return Err(ApiError::invalid_request("Streaming is not supported by the proxy").into());
+    }
+    let summary = summary_options(&req, &data)?;
+    let request = data.llm.chat_completions_request().ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::Unsupported,
+            "The LLM provider has no OpenAI-compatible chat API",
+        )
+    })?;
+
+    let messages = body
+        .get_mut("messages")
+        .and_then(Value::as_array_mut)
+        .ok_or_else(|| ApiError::invalid_request("messages must be an array"))?;
+    let recorded = new_messages(messages)?;
+    check_roles(&data, &recorded).map_err(|e| ApiError::new(ErrorCode::UnknownRole, e))?;
+    // Moderated again as they're recorded, but refused before they reach the model.
+    let recorded = moderate(&data, &session_id, recorded).await?;
+
+    let store = tenant.store(&data);
+    let context = store.get_context(&session_id).await?;
+    let long_term_context = store.get_long_term_context(&session_id).await?;
+    let entities = store.get_entities(&session_id).await?;
+    let system_prompt = store
+        .get_session_config(&session_id)
+        .await?
+        .and_then(|config| config.system_prompt);
+    if let Some(system) = system_message(system_prompt, long_term_context, context, entities) {
+        let position = messages
+            .iter()
+            .position(|message| !is_instruction(message))
+            .unwrap_or(messages.len());
+        messages.insert(position, json!({ "role": "system", "content": system }));
+    }
+
+    let response = request.json(&body).send().await.map_err(|e| {
+        tracing::error!(session_id = %session_id, error = %e, "Problem proxying chat completion");
+        ApiError::new(ErrorCode::UpstreamError, e)
+    })?;
+    let status =
+        StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let reply: Value = response
+        .json()
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::UpstreamError, e))?;
+
+    // Errors are passed on as they are, and nothing is recorded.
+    if !status.is_success() {
+        return Ok(HttpResponse::build(status)
+            .content_type("application/json")
+            .json(reply));
+    }
+
+    if let Some(usage) = reply.get("usage") {
+        let count = |field: &str| usage.get(field).and_then(Value::as_u64).unwrap_or(0);
+        metrics::record_llm_usage(count("prompt_tokens"), count("completion_tokens"));
+    }
+
+    let answer = reply
+        .pointer("/choices/0/message")
+        .cloned()
+        .and_then(|message| serde_json::from_value::<OpenAIMessage>(message).ok())
+        .and_then(|message| from_openai(0, message).ok());
+    let mut messages = recorded;
+    match answer {
+        Some(answer) => messages.push(answer),
+        None => tracing::warn!(
+            session_id = %session_id,
+            "Proxied chat completion has no message to record"
+        ),
+    }
+
+    // The client already has its completion, so failing to record it doesn't fail the request.
+    if let Err(e) = append_memory(&data, &tenant, &session_id, messages, None, summary).await {
+        tracing::error!(
+            session_id = %session_id,
+            error = telemetry::redact(&e.to_string()),
+            "Problem recording proxied chat completion"
+        );
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(reply))
+}
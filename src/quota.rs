@@ -0,0 +1,136 @@
+use crate::cold_storage;
+use crate::models::{AppState, MemoryMessage, MotorheadError, StoredSize};
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+/// What happens to the appends that would take a session over its quota.
+#[derive(Clone, Copy, PartialEq)]
+pub enum QuotaPolicy {
+    /// Refuses them with `SESSION_QUOTA_EXCEEDED`.
+    Reject,
+    /// Makes room for them by taking the session's oldest messages out of the store.
+    Evict,
+}
+
+/// Caps on what each session keeps in the store, its window and history, so that a runaway
+/// session can't fill it. Set with `MOTORHEAD_SESSION_MAX_STORED_MESSAGES` and
+/// `MOTORHEAD_SESSION_MAX_STORED_BYTES`.
+pub struct SessionQuota {
+    pub max_messages: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub policy: QuotaPolicy,
+}
+
+fn bytes(messages: &[MemoryMessage]) -> u64 {
+    messages
+        .iter()
+        .map(|message| message.content.len() as u64)
+        .sum()
+}
+
+/// Checks that `messages` fit in an empty session, as when they make up a new one: no
+/// eviction makes room for more.
+pub fn check_new(state: &AppState, messages: &[MemoryMessage]) -> Result<(), MotorheadError> {
+    let Some(quota) = &state.session_quota else {
+        return Ok(());
+    };
+    if quota
+        .max_messages
+        .is_some_and(|max| messages.len() as u64 > max)
+        || quota.max_bytes.is_some_and(|max| bytes(messages) > max)
+    {
+        return Err(MotorheadError::SessionQuotaExceeded);
+    }
+    Ok(())
+}
+
+/// Checks that the session has room for `messages`, making it with `QuotaPolicy::Evict`.
+pub async fn enforce(
+    state: &AppState,
+    tenant: &Tenant,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    messages: &[MemoryMessage],
+) -> Result<(), MotorheadError> {
+    let Some(quota) = &state.session_quota else {
+        return Ok(());
+    };
+    check_new(state, messages)?;
+    let incoming = messages.len() as u64;
+    let incoming_bytes = bytes(messages);
+
+    let size = store.stored_size(session_id).await?;
+    let stored = size.window + size.history;
+    let excess_messages = quota
+        .max_messages
+        .map_or(0, |max| (stored + incoming).saturating_sub(max));
+    let excess_bytes = quota
+        .max_bytes
+        .map_or(0, |max| (size.bytes + incoming_bytes).saturating_sub(max));
+    if excess_messages == 0 && excess_bytes == 0 {
+        return Ok(());
+    }
+    if quota.policy == QuotaPolicy::Reject {
+        return Err(MotorheadError::SessionQuotaExceeded);
+    }
+
+    // Bytes are freed a message at a time, counting the session's average size.
+    let average = (size.bytes / stored.max(1)).max(1);
+    let count = excess_messages
+        .max(excess_bytes.div_ceil(average))
+        .min(stored);
+    evict(state, tenant, store, session_id, size, count).await
+}
+
+/// Takes the `count` oldest messages out of the store: from the history first, to cold storage
+/// if it's configured, then from the window, unsummarized.
+async fn evict(
+    state: &AppState,
+    tenant: &Tenant,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    size: StoredSize,
+    count: u64,
+) -> Result<(), MotorheadError> {
+    let from_history = count.min(size.history);
+    if from_history > 0 {
+        match &state.cold_storage {
+            Some(cold_storage) => {
+                cold_storage::move_session(
+                    cold_storage,
+                    store,
+                    tenant,
+                    session_id,
+                    u64::MAX,
+                    from_history as usize,
+                )
+                .await?;
+            }
+            None => {
+                store
+                    .drop_history(session_id, from_history as usize)
+                    .await?
+            }
+        }
+    }
+
+    let from_window = count - from_history;
+    if from_window > 0 {
+        // The window is newest first, and an empty range empties it.
+        let keep_until = size.window as i64 - from_window as i64 - 1;
+        let (start, stop) = if keep_until < 0 {
+            (1, 0)
+        } else {
+            (0, keep_until)
+        };
+        store.trim_messages(session_id, start, stop, false).await?;
+    }
+
+    tracing::info!(
+        session_id,
+        from_history,
+        from_window,
+        "Evicted the oldest messages over the session quota"
+    );
+    Ok(())
+}
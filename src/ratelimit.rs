@@ -0,0 +1,155 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{error, http::header, http::Method, web, FromRequest, HttpMessage, ResponseError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::auth::AuthenticatedKey;
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{AppState, MotorheadError};
+use crate::telemetry;
+use crate::tenant::Tenant;
+
+/// Buckets beyond this many are pruned of the ones that have refilled completely.
+const MAX_LOCAL_BUCKETS: usize = 10_000;
+
+/// A token bucket holding up to `per_minute` tokens, refilled at `per_minute` a minute.
+#[derive(Clone, Copy)]
+pub struct RateLimit {
+    pub per_minute: u32,
+}
+
+impl RateLimit {
+    pub fn refill_per_ms(&self) -> f64 {
+        self.per_minute as f64 / 60_000.0
+    }
+}
+
+/// In-process buckets, used when the store can't hold them.
+#[derive(Default)]
+pub struct LocalBuckets(Mutex<HashMap<String, (f64, Instant)>>);
+
+impl LocalBuckets {
+    /// Takes a token from `bucket`. Returns how many ms to wait when it is empty.
+    fn take(&self, bucket: &str, limit: RateLimit) -> Option<u64> {
+        let capacity = limit.per_minute as f64;
+        let rate = limit.refill_per_ms();
+        let now = Instant::now();
+        let refilled = |tokens: f64, at: Instant| {
+            (tokens + now.duration_since(at).as_millis() as f64 * rate).min(capacity)
+        };
+
+        let mut buckets = self.0.lock().unwrap();
+        if buckets.len() > MAX_LOCAL_BUCKETS {
+            buckets.retain(|_, (tokens, at)| refilled(*tokens, *at) < capacity);
+        }
+
+        let (tokens, at) = buckets.entry(bucket.to_string()).or_insert((capacity, now));
+        *tokens = refilled(*tokens, *at);
+        *at = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            None
+        } else {
+            Some(((1.0 - *tokens) / rate).ceil() as u64)
+        }
+    }
+}
+
+fn too_many_requests(retry_after_ms: u64) -> actix_web::Error {
+    let mut response = ApiError::new(ErrorCode::RateLimited, "Rate limit exceeded")
+        .with("retry_after_ms", retry_after_ms)
+        .error_response();
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        header::HeaderValue::from(retry_after_ms.div_ceil(1000)),
+    );
+    error::InternalError::from_response("", response).into()
+}
+
+/// The session a `/sessions/{session_id}/...` request is for.
+fn session_id(path: &str) -> Option<&str> {
+    path.strip_prefix("/sessions/")?
+        .split_once('/')
+        .map(|(session_id, _)| session_id)
+        .filter(|session_id| !session_id.is_empty())
+}
+
+/// Takes a token from `bucket`, returning how many ms to wait if it's empty.
+async fn take(state: &AppState, bucket: &str, limit: RateLimit) -> Option<u64> {
+    match state
+        .store
+        .take_rate_token(bucket, limit.per_minute, limit.refill_per_ms())
+        .await
+    {
+        Ok(wait) => wait,
+        Err(MotorheadError::Unsupported(_)) => state.rate_buckets.take(bucket, limit),
+        // Failing open: an unreachable store already fails the request itself when it matters.
+        Err(e) => {
+            tracing::warn!(
+                bucket,
+                error = telemetry::error_message(&e),
+                "Could not check the rate limit"
+            );
+            None
+        }
+    }
+}
+
+/// Counts a write to the session against `MOTORHEAD_SESSION_WRITES_PER_MINUTE`, returning
+/// how many ms to wait if it's over the limit.
+pub async fn take_session_write(
+    state: &AppState,
+    tenant: &Tenant,
+    session_id: &str,
+) -> Option<u64> {
+    let limit = state.runtime().session_write_rate_limit?;
+    take(
+        state,
+        &format!("session:{}", tenant.scope(session_id)),
+        limit,
+    )
+    .await
+}
+
+/// Counts a request against `MOTORHEAD_API_KEY_REQUESTS_PER_MINUTE`, returning how many ms
+/// to wait if it's over the limit.
+pub async fn take_key_request(state: &AppState, key: &AuthenticatedKey) -> Option<u64> {
+    let limit = state.runtime().api_key_rate_limit?;
+    take(state, &format!("key:{}", key.0), limit).await
+}
+
+/// Limits the requests of each API key, and the writes to each session, when configured.
+/// Runs after authentication so the key is known.
+pub async fn limit_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let state = req
+        .app_data::<web::Data<Arc<AppState>>>()
+        .expect("AppState is registered")
+        .clone();
+
+    let key = req.extensions().get::<AuthenticatedKey>().cloned();
+    if let Some(key) = key {
+        if let Some(wait) = take_key_request(&state, &key).await {
+            return Err(too_many_requests(wait));
+        }
+    }
+
+    let is_write = !matches!(*req.method(), Method::GET | Method::HEAD);
+    if let Some(session_id) = session_id(req.path()).filter(|_| is_write) {
+        // An invalid tenant is rejected by the handler, the default scope does until then.
+        let tenant = Tenant::extract(req.request())
+            .await
+            .unwrap_or_else(|_| Tenant::new(None));
+        if let Some(wait) = take_session_write(&state, &tenant, session_id).await {
+            return Err(too_many_requests(wait));
+        }
+    }
+
+    next.call(req).await
+}
@@ -0,0 +1,144 @@
+use actix_web::http::header::EntityTag;
+use futures_util::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::models::{AppState, MemoryResponse, MotorheadError};
+use crate::store::MemoryStore;
+use crate::telemetry;
+use crate::tenant::Tenant;
+
+/// How long to wait before subscribing again after losing the subscription.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+struct Entry {
+    /// The `ETag` of the memory, which changes along with the session's version.
+    etag: EntityTag,
+    response: MemoryResponse,
+    /// When it was last read, in `ReadCache::clock` ticks.
+    used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    clock: u64,
+    by_key: HashMap<String, Entry>,
+    /// The keys by when they were last read, least recently first.
+    by_use: BTreeMap<u64, String>,
+}
+
+/// The latest full read of the memory of the `capacity` sessions read last, for the chat UIs
+/// reading it again on every keystroke. Entries are only served for the session's current
+/// version, so a write is never missed, and are dropped as soon as the session changes. Set
+/// with `MOTORHEAD_READ_CACHE_SESSIONS`.
+pub struct ReadCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl ReadCache {
+    pub fn new(capacity: usize) -> Self {
+        ReadCache {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// The session's memory, if it was cached at `etag`.
+    pub fn get(&self, key: &str, etag: &EntityTag) -> Option<MemoryResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+        let entry = entries
+            .by_key
+            .get_mut(key)
+            .filter(|entry| entry.etag == *etag)?;
+        let used = std::mem::replace(&mut entry.used, clock);
+        let response = entry.response.clone();
+        entries.by_use.remove(&used);
+        entries.by_use.insert(clock, key.to_string());
+        Some(response)
+    }
+
+    /// Caches the session's memory read at `etag`, evicting the session read least recently
+    /// if it's full.
+    pub fn insert(&self, key: &str, etag: EntityTag, response: MemoryResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let used = entries.clock;
+        let replaced = entries.by_key.insert(
+            key.to_string(),
+            Entry {
+                etag,
+                response,
+                used,
+            },
+        );
+        match replaced {
+            Some(replaced) => {
+                entries.by_use.remove(&replaced.used);
+            }
+            None if entries.by_key.len() > self.capacity => {
+                if let Some((_, evicted)) = entries.by_use.pop_first() {
+                    entries.by_key.remove(&evicted);
+                }
+            }
+            None => {}
+        }
+        entries.by_use.insert(used, key.to_string());
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.by_key.remove(key) {
+            entries.by_use.remove(&entry.used);
+        }
+    }
+
+    fn clear(&self) {
+        *self.entries.lock().unwrap() = Entries::default();
+    }
+}
+
+/// The session's key in the cache: the channel its changes are published on, where the store
+/// has one, so that notifications name the entry to drop.
+pub fn cache_key(store: &dyn MemoryStore, tenant: &Tenant, session_id: &str) -> String {
+    store
+        .changes_channel(session_id)
+        .unwrap_or_else(|| tenant.scope(session_id))
+}
+
+/// Drops the session's cached memory on a write, if the cache is enabled.
+pub fn invalidate(state: &AppState, tenant: &Tenant, session_id: &str) {
+    if let Some(cache) = &state.read_cache {
+        cache.remove(&cache_key(tenant.store(state).as_ref(), tenant, session_id));
+    }
+}
+
+/// Drops the cached memory of the sessions changed through any instance, for as long as the
+/// server runs, so that the cache isn't filled with versions that can no longer be served.
+/// Exits if the store doesn't notify changes, leaving entries to be replaced when read.
+pub async fn run_read_cache_invalidator(state: Arc<AppState>) {
+    let Some(cache) = &state.read_cache else {
+        return;
+    };
+    loop {
+        match state.store.session_changes().await {
+            Ok(mut changes) => {
+                // Changes made while the subscription was lost went unnoticed.
+                cache.clear();
+                while let Some(channel) = changes.next().await {
+                    cache.remove(&channel);
+                }
+                tracing::warn!("Lost the subscription to session changes, resubscribing");
+            }
+            Err(MotorheadError::Unsupported(_)) => return,
+            Err(e) => tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem listening for session changes"
+            ),
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
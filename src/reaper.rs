@@ -0,0 +1,136 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::audit;
+use crate::memory::{delete_session, forget_session};
+use crate::metrics;
+use crate::models::{AppState, MotorheadError};
+use crate::telemetry;
+use crate::tenant::{listed_tenants, Tenant};
+
+/// Sessions listed at a time while looking for idle ones.
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// What happens to the sessions found idle.
+#[derive(Clone, Copy, PartialEq)]
+pub enum IdleAction {
+    Delete,
+    /// Soft deleted, so they can be restored during `MOTORHEAD_TRASH_TTL_SECONDS`.
+    Trash,
+}
+
+impl IdleAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            IdleAction::Delete => "delete",
+            IdleAction::Trash => "trash",
+        }
+    }
+}
+
+/// Reaps the sessions without activity for `idle_seconds`, checking every `interval`. Set
+/// with `MOTORHEAD_IDLE_SESSION_SECONDS`.
+pub struct IdleReaper {
+    pub idle_seconds: u64,
+    pub action: IdleAction,
+    pub interval: Duration,
+}
+
+/// The sessions of the tenant whose last activity is before `cutoff_ms`.
+async fn idle_sessions(
+    state: &AppState,
+    tenant: &Tenant,
+    cutoff_ms: u64,
+) -> Result<Vec<String>, MotorheadError> {
+    let store = tenant.store(state);
+    let mut idle = Vec::new();
+
+    let mut offset = 0;
+    loop {
+        let page = store.list_sessions(offset, LIST_PAGE_SIZE).await?;
+        let page_len = page.len();
+        // Most recently active first, so the idle ones are at the end.
+        idle.extend(
+            page.into_iter()
+                .filter(|(_, last_activity)| *last_activity < cutoff_ms)
+                .map(|(session_id, _)| session_id),
+        );
+        if page_len < LIST_PAGE_SIZE {
+            break;
+        }
+        offset += page_len;
+    }
+
+    Ok(idle)
+}
+
+async fn reap(state: &AppState, tenant: &Tenant, session_id: &str, action: IdleAction) {
+    let result = async {
+        audit::record(
+            state,
+            audit::BACKGROUND_ACTOR,
+            tenant,
+            &format!("idle {}", action.as_str()),
+            Some(session_id),
+            None,
+        )
+        .await?;
+        match action {
+            IdleAction::Delete => delete_session(state, tenant, session_id).await,
+            IdleAction::Trash => tenant
+                .store(state)
+                .trash_session(session_id, state.trash_ttl_seconds)
+                .await
+                .map(|()| forget_session(state, tenant, session_id)),
+        }
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            tracing::info!(session_id, action = action.as_str(), "Reaped idle session");
+            metrics::IDLE_SESSIONS_REAPED
+                .with_label_values(&[action.as_str()])
+                .inc();
+        }
+        Err(e) => tracing::error!(
+            session_id,
+            error = telemetry::error_message(&e),
+            "Error reaping idle session"
+        ),
+    }
+}
+
+pub async fn run_idle_reaper(state: Arc<AppState>) {
+    let Some(reaper) = &state.idle_reaper else {
+        return;
+    };
+    let mut interval = tokio::time::interval(reaper.interval);
+
+    loop {
+        interval.tick().await;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let cutoff_ms = now.saturating_sub(reaper.idle_seconds * 1000);
+
+        for tenant in listed_tenants(&state) {
+            let sessions = match idle_sessions(&state, &tenant, cutoff_ms).await {
+                Ok(sessions) => sessions,
+                Err(e) => {
+                    tracing::error!(
+                        tenant = tenant.id(),
+                        error = telemetry::error_message(&e),
+                        "Error listing idle sessions"
+                    );
+                    continue;
+                }
+            };
+            for session_id in sessions {
+                reap(&state, &tenant, &session_id, reaper.action).await;
+            }
+        }
+    }
+}
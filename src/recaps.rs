@@ -0,0 +1,248 @@
+use actix_web::{delete, get, web, HttpResponse, Responder};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::{Date, OffsetDateTime};
+
+use crate::models::{AckResponse, AppState, MotorheadError, Recap, RecapsQuery, RecapsResponse};
+use crate::reducer::recap_day;
+use crate::response::read_response;
+use crate::session_config::window_size;
+use crate::store::MemoryStore;
+use crate::telemetry;
+use crate::tenant::{listed_tenants, Tenant};
+use crate::usage::{check_budget, record_usage};
+
+/// Sessions listed at a time while looking for the active users.
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// How often the job checks whether yesterday's recaps are due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+const DEFAULT_LIMIT: usize = 30;
+const MAX_LIMIT: usize = 365;
+
+/// Recaps each active user's previous UTC day once it's `hour` UTC. Set with
+/// `MOTORHEAD_RECAP_HOUR`.
+pub struct RecapJob {
+    pub hour: u8,
+    pub retention_days: u16,
+}
+
+fn day_start_ms(date: Date) -> u64 {
+    (date.midnight().assume_utc().unix_timestamp() * 1000) as u64
+}
+
+/// The sessions of the tenant active since `since_ms` by the user in their metadata, most
+/// recently active first.
+async fn active_users(
+    store: &dyn MemoryStore,
+    since_ms: u64,
+) -> Result<BTreeMap<String, Vec<String>>, MotorheadError> {
+    let mut users: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    let mut offset = 0;
+    loop {
+        let page = store.list_sessions(offset, LIST_PAGE_SIZE).await?;
+        let page_len = page.len();
+        let mut done = page_len < LIST_PAGE_SIZE;
+        for (session_id, last_activity) in page {
+            // Most recently active first, so the rest are older.
+            if last_activity < since_ms {
+                done = true;
+                break;
+            }
+            let user_id = store.get_metadata(&session_id).await?.and_then(|metadata| {
+                metadata
+                    .get("user_id")
+                    .and_then(serde_json::Value::as_str)
+                    .map(str::to_string)
+            });
+            if let Some(user_id) = user_id {
+                users.entry(user_id).or_default().push(session_id);
+            }
+        }
+        if done {
+            return Ok(users);
+        }
+        offset += page_len;
+    }
+}
+
+/// Recaps the user's day from the messages of that day still in the windows of their
+/// sessions. Returns false if they had none.
+async fn recap_user(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    user_id: &str,
+    session_ids: &[String],
+    date: Date,
+    oldest_date: &str,
+) -> Result<bool, MotorheadError> {
+    let start_ms = day_start_ms(date);
+    let end_ms = day_start_ms(date.next_day().expect("Dates are in range"));
+
+    let mut conversations = Vec::new();
+    let mut recapped_sessions = Vec::new();
+    let mut messages = 0;
+    // The least recently active first, so the budget keeps the latest.
+    for session_id in session_ids.iter().rev() {
+        let window_size = window_size(state, store, session_id).await?;
+        let (window, context) = store.get_memory(session_id, 0, window_size).await?;
+        let lines: Vec<String> = window
+            .iter()
+            .rev()
+            .filter(|message| {
+                message
+                    .created_at
+                    .is_some_and(|created_at| (start_ms..end_ms).contains(&created_at))
+            })
+            .map(|message| message.transcript_line(state.summarize_attachments))
+            .collect();
+        if lines.is_empty() {
+            continue;
+        }
+
+        conversations.push(format!("Conversation {}:", session_id));
+        if let Some(context) = context.filter(|context| !context.is_empty()) {
+            conversations.push(format!("Summary so far: {}", context));
+        }
+        messages += lines.len();
+        conversations.extend(lines);
+        recapped_sessions.push(session_id.clone());
+    }
+    if recapped_sessions.is_empty() {
+        return Ok(false);
+    }
+    recapped_sessions.reverse();
+
+    let options = state.runtime().summary_options;
+    let completion = recap_day(state, conversations, &options).await?;
+    // Counted in the usage of the user's most recently active session.
+    record_usage(state, store, &recapped_sessions[0], completion.usage).await;
+
+    let recap = Recap {
+        user_id: user_id.to_string(),
+        date: date.to_string(),
+        recap: completion.content,
+        session_ids: recapped_sessions,
+        messages,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64,
+    };
+    store.set_recap(&recap, oldest_date).await?;
+    Ok(true)
+}
+
+/// Recaps `date` for the users of the tenant without a recap of it yet. Returns whether all
+/// of them were, so the ones that failed are tried again on the next check.
+async fn recap_tenant(state: &AppState, tenant: &Tenant, date: Date, retention_days: u16) -> bool {
+    let store = tenant.store(state);
+    let users = match active_users(store.as_ref(), day_start_ms(date)).await {
+        Ok(users) => users,
+        Err(e) => {
+            tracing::error!(
+                tenant = tenant.id(),
+                error = telemetry::error_message(&e),
+                "Error listing the active users"
+            );
+            return false;
+        }
+    };
+    let oldest_date = (date - time::Duration::days(i64::from(retention_days))).to_string();
+
+    let mut complete = true;
+    for (user_id, session_ids) in users {
+        let result = async {
+            if store.has_recap(&user_id, &date.to_string()).await? {
+                return Ok(false);
+            }
+            check_budget(state, store.as_ref()).await?;
+            recap_user(
+                state,
+                store.as_ref(),
+                &user_id,
+                &session_ids,
+                date,
+                &oldest_date,
+            )
+            .await
+        }
+        .await;
+        match result {
+            Ok(true) => tracing::info!(tenant = tenant.id(), user_id, %date, "Recapped user"),
+            Ok(false) => {}
+            Err(e) => {
+                tracing::error!(
+                    tenant = tenant.id(),
+                    user_id,
+                    error = telemetry::error_message(&e),
+                    "Error recapping user"
+                );
+                complete = false;
+            }
+        }
+    }
+    complete
+}
+
+pub async fn run_recap_job(state: Arc<AppState>) {
+    let Some(job) = &state.recap_job else {
+        return;
+    };
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    // The day each tenant was last recapped entirely, by this instance.
+    let mut recapped: HashMap<Option<String>, Date> = HashMap::new();
+
+    loop {
+        interval.tick().await;
+
+        let now = OffsetDateTime::now_utc();
+        if now.hour() < job.hour {
+            continue;
+        }
+        let Some(yesterday) = now.date().previous_day() else {
+            continue;
+        };
+
+        for tenant in listed_tenants(&state) {
+            let key = tenant.id().map(str::to_string);
+            if recapped.get(&key) == Some(&yesterday) {
+                continue;
+            }
+            if recap_tenant(&state, &tenant, yesterday, job.retention_days).await {
+                recapped.insert(key, yesterday);
+            }
+        }
+    }
+}
+
+/// The user's latest daily recaps, newest first.
+#[get("/users/{user_id}/recaps")]
+pub async fn get_recaps(
+    user_id: web::Path<String>,
+    query: web::Query<RecapsQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let recaps = tenant.store(&data).get_recaps(&user_id, limit).await?;
+
+    Ok(read_response(&data, None, RecapsResponse { recaps }))
+}
+
+#[delete("/users/{user_id}/recaps")]
+pub async fn delete_recaps(
+    user_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    tenant.store(&data).delete_recaps(&user_id).await?;
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
@@ -0,0 +1,183 @@
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::llm::CompletionRequest;
+use crate::models::{AppState, MemoryMessage, MotorheadError};
+use crate::tokens::count_tokens;
+
+struct Builtin {
+    name: &'static str,
+    pattern: &'static str,
+    /// Decides whether a match is really the kind of data the rule is after.
+    check: fn(&str) -> bool,
+}
+
+/// The built-in rules, in the order they're applied: card numbers go first, so the phone rule
+/// doesn't see their digits.
+const BUILTIN_RULES: [Builtin; 3] = [
+    Builtin {
+        name: "credit_card",
+        // 13 to 19 digits, optionally grouped with spaces or dashes.
+        pattern: r"\b\d(?:[ -]?\d){12,18}\b",
+        check: luhn_valid,
+    },
+    Builtin {
+        name: "email",
+        pattern: r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+        check: |_| true,
+    },
+    Builtin {
+        name: "phone",
+        // An optional country and area code, then groups of digits, e.g. `+1 (555) 010-9999`
+        // or `06 12 34 56 78`. Dates and short numbers have too few digits to pass.
+        pattern: r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{1,4}\)[ .-]?)?\b\d{2,4}(?:[ .-]?\d{2,4}){1,4}\b",
+        check: |phone| (9..=15).contains(&digit_count(phone)),
+    },
+];
+
+const LLM_REDACTION_PROMPT: &str = r#"
+        Rewrite the text below with every piece of personal data (names of people, addresses, emails, phone numbers, account, card and ID numbers, dates of birth...) replaced by [REDACTED]. Keep everything else exactly as it is. Reply with the rewritten text only.
+
+        Text:
+        {text}
+"#;
+
+struct Rule {
+    pattern: Regex,
+    replacement: String,
+    check: fn(&str) -> bool,
+}
+
+/// Scrubs personal data from messages before they're stored, and so before any summarizer
+/// sees them.
+pub struct Redactor {
+    rules: Vec<Rule>,
+    llm: bool,
+}
+
+fn digit_count(text: &str) -> usize {
+    text.chars().filter(char::is_ascii_digit).count()
+}
+
+fn luhn_valid(digits: &str) -> bool {
+    let digits: Vec<u32> = digits.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match (i % 2, digit * 2) {
+            (0, _) => digit,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+impl Redactor {
+    /// `builtins` names the built-in rules to apply (`email`, `phone`, `credit_card`), and
+    /// `custom` maps names to patterns. Matches are replaced with `[REDACTED_<NAME>]`.
+    pub fn new(
+        builtins: &[&str],
+        custom: BTreeMap<String, String>,
+        llm: bool,
+    ) -> Result<Self, String> {
+        if let Some(unknown) = builtins
+            .iter()
+            .find(|name| !BUILTIN_RULES.iter().any(|builtin| builtin.name == **name))
+        {
+            return Err(format!("unknown rule {}", unknown));
+        }
+
+        let builtins = BUILTIN_RULES
+            .iter()
+            .filter(|builtin| builtins.contains(&builtin.name))
+            .map(|builtin| {
+                (
+                    builtin.name.to_string(),
+                    builtin.pattern.to_string(),
+                    builtin.check,
+                )
+            });
+        let custom = custom
+            .into_iter()
+            .map(|(name, pattern)| (name, pattern, (|_| true) as fn(&str) -> bool));
+
+        let rules = builtins
+            .chain(custom)
+            .map(|(name, pattern, check)| {
+                Ok(Rule {
+                    pattern: Regex::new(&pattern).map_err(|e| format!("{}: {}", name, e))?,
+                    replacement: format!("[REDACTED_{}]", name.to_uppercase()),
+                    check,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Redactor { rules, llm })
+    }
+
+    pub fn redact_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for rule in &self.rules {
+            let replaced = rule.pattern.replace_all(&text, |captures: &Captures| {
+                let matched = &captures[0];
+                if (rule.check)(matched) {
+                    rule.replacement.clone()
+                } else {
+                    matched.to_string()
+                }
+            });
+            if let Cow::Owned(replaced) = replaced {
+                text = Cow::Owned(replaced);
+            }
+        }
+        text
+    }
+}
+
+/// Runs the configured rules, then the LLM pass if enabled, over the content of `messages`.
+/// A failed LLM pass fails the write rather than storing the messages as sent.
+pub async fn redact_messages(
+    state: &AppState,
+    mut messages: Vec<MemoryMessage>,
+) -> Result<Vec<MemoryMessage>, MotorheadError> {
+    if state.redactor.is_some() {
+        for message in &mut messages {
+            message.content = redact(state, &message.content).await?;
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Redacts a single piece of content, e.g. a message edit.
+pub async fn redact(state: &AppState, content: &str) -> Result<String, MotorheadError> {
+    let Some(redactor) = &state.redactor else {
+        return Ok(content.to_string());
+    };
+
+    let content = redactor.redact_text(content);
+    if !redactor.llm || content.trim().is_empty() {
+        return Ok(content.into_owned());
+    }
+
+    // Room for the rewritten text, which is about as long as the original.
+    let max_tokens = (count_tokens(&content) * 2 + 16).min(u16::MAX as usize) as u16;
+    let prompt = LLM_REDACTION_PROMPT.replace("{text}", &content);
+    let model = state.runtime().summary_options.model;
+    let redacted = state
+        .llm
+        .complete(CompletionRequest {
+            system: "You are a careful data protection assistant.",
+            prompt: &prompt,
+            max_tokens,
+            model: model.as_deref(),
+            temperature: Some(0.0),
+        })
+        .await?;
+
+    Ok(redacted.content.trim().to_string())
+}
@@ -1,22 +1,102 @@
-use crate::models::{AppState, MotorheadError};
-use async_openai::{
-    types::{ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, Role},
-    Client,
+use crate::alerts;
+use crate::analysis::score_sentiment;
+use crate::analytics;
+use crate::audit;
+use crate::cold_storage;
+use crate::compactor::{CompactionStrategy, CompactorInput};
+use crate::importance::{retain_important, ImportanceScorer};
+use crate::llm::{Completion, CompletionRequest, LlmClient};
+use crate::metrics;
+use crate::models::{
+    AnalyticsCounts, AppState, CompactionFailure, CompactionProgress, ContextSegment,
+    ContextVersion, MemoryMessage, MotorheadError, SummarizeDryRunResponse, SummarizedRange,
+    SummaryOptions, TokenUsage,
 };
-use std::error::Error;
+use crate::session_config::{compaction_strategy, session_summary_options, window_size};
+use crate::store::MemoryStore;
+use crate::tasks::TaskTracker;
+use crate::telemetry;
+use crate::tenant::Tenant;
+use crate::tokens::{count_message_tokens, count_tokens, fit_within_tokens};
+use crate::usage::{check_budget, record_usage};
+use crate::webhooks::{call_back, notify, WebhookEvent};
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
 
-pub async fn incremental_summarization(
-    openai_client: Client,
-    context: Option<String>,
-    messages: Vec<String>,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let messages_joined = messages.join("\n");
-    let prev_summary = context.as_deref().unwrap_or_default();
-    // Taken from langchain
-    let progresive_prompt = format!(
-        r#"
-        Progressively summarize the lines of conversation provided, adding onto the previous summary returning a new summary. If the lines are meaningless just return NONE
+/// When to compact a session after an append, picked with `MOTORHEAD_COMPACTION_TRIGGER`.
+/// Whatever the trigger, a session is compacted once it's over its window size, as the
+/// messages past it would be dropped unsummarized otherwise.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CompactionTrigger {
+    /// Only once over the window size, summarizing the older half.
+    Messages,
+    /// Once the window is over this many tokens, keeping the newest messages that fit in
+    /// half of them.
+    Tokens(usize),
+    /// Once the oldest message not summarized yet was appended this long ago, summarizing
+    /// the older half of the window.
+    Elapsed(Duration),
+    /// Once over the window size, summarizing this share of the window, oldest first.
+    Ratio(f64),
+}
+
+/// Whether the append that left the session with `len` messages calls for a compaction.
+pub async fn needs_compaction(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    len: i64,
+) -> Result<bool, MotorheadError> {
+    let window_size = window_size(state, store, session_id).await?;
+    if len > window_size {
+        return Ok(true);
+    }
+
+    let window_tokens = match (state.window_tokens, state.compaction_trigger) {
+        (_, CompactionTrigger::Tokens(tokens)) => Some(tokens),
+        (window_tokens, _) => window_tokens,
+    };
+    if let Some(window_tokens) = window_tokens {
+        let window = store.get_messages(session_id, 0, window_size).await?;
+        if window.iter().map(count_message_tokens).sum::<usize>() > window_tokens {
+            return Ok(true);
+        }
+    }
+
+    if let CompactionTrigger::Elapsed(after) = state.compaction_trigger {
+        let unsummarized = (store.messages_since_summary(session_id).await? as i64).min(len);
+        // A single message can't be split, it waits for the next one.
+        if unsummarized < 2 {
+            return Ok(false);
+        }
+        let oldest = store
+            .get_messages(session_id, unsummarized - 1, unsummarized - 1)
+            .await?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let appended_at = oldest.first().and_then(|message| message.created_at);
+        return Ok(appended_at.is_some_and(|appended_at| {
+            now.saturating_sub(appended_at) >= after.as_millis() as u64
+        }));
+    }
+
+    Ok(false)
+}
+
+/// Completion length of the summaries unless configured otherwise.
+pub const DEFAULT_SUMMARY_MAX_TOKENS: u16 = 512;
+
+// Taken from langchain
+pub const DEFAULT_SUMMARY_PROMPT: &str = r#"
+        Progressively summarize the lines of conversation provided, adding onto the previous summary returning a new summary. When lines name who said them, as in "user (Alice):", keep track of who said what. If the lines are meaningless just return NONE
 
         EXAMPLE
         Current summary:
@@ -29,63 +109,772 @@ pub async fn incremental_summarization(
         END OF EXAMPLE
 
         Current summary:
-        {prev_summary}
+        {previous_summary}
         New lines of conversation:
-        {messages_joined}
+        {messages}
         New summary:
-        "#
+        "#;
+
+/// Folds a summary grown too long into the long-term summary, using the same placeholders as
+/// the summary prompt.
+pub const LONG_TERM_PROMPT: &str = r#"
+        Condense the summary of the recent conversation into the long-term summary provided, returning a new long-term summary. Keep what matters to the conversation as a whole, and who said or decided it, and leave out the details. If there is nothing worth keeping just return NONE
+
+        Long-term summary:
+        {previous_summary}
+        Summary of the recent conversation:
+        {messages}
+        New long-term summary:
+        "#;
+
+/// Merges the summaries of the chunks of a split compaction into the summary, using the same
+/// placeholders as the summary prompt.
+pub const CHUNK_MERGE_PROMPT: &str = r#"
+        Merge the summaries of consecutive parts of the conversation provided, most recent first, into the previous summary, returning a new summary of the whole conversation. If there is nothing worth keeping just return NONE
+
+        Current summary:
+        {previous_summary}
+        Summaries of the new parts of the conversation:
+        {messages}
+        New summary:
+        "#;
+
+pub const RECAP_PROMPT: &str = r#"
+        Write a recap of what happened with the user during the day, from the conversations they had, for someone catching up on them: what they asked for or talked about, what was decided or done, and what was left open. Each conversation starts with its summary so far, if any, followed by the messages of the day. Keep it short.
+
+        Conversations:
+        {messages}
+        Recap:
+        "#;
+
+pub const ENTITY_PROMPT: &str = r#"
+        Extract the concrete facts worth remembering verbatim from the lines of conversation provided: names, preferences, decisions, dates, amounts. The facts already known are given as a JSON object. Return a JSON object with only the new or changed facts, using short snake_case keys and string values. If there are none just return {}
+
+        Known facts:
+        {entities}
+        New lines of conversation:
+        {messages}
+        Facts:
+        "#;
+
+/// Parses the facts out of a completion, tolerating text around the JSON object.
+fn parse_entities(completion: &str) -> Result<BTreeMap<String, String>, MotorheadError> {
+    let object = match (completion.find('{'), completion.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &completion[start..=end],
+        _ => return Ok(BTreeMap::new()),
+    };
+
+    let entities: BTreeMap<String, serde_json::Value> = serde_json::from_str(object)
+        .map_err(|e| MotorheadError::LlmError(format!("Invalid entities: {}", e)))?;
+
+    Ok(entities
+        .into_iter()
+        .filter_map(|(name, value)| match value {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(value) => Some((name, value)),
+            value => Some((name, value.to_string())),
+        })
+        .collect())
+}
+
+/// Asks the LLM for the facts in `messages` and merges them into the session's entities.
+#[tracing::instrument(skip_all)]
+async fn extract_entities(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    messages: &[String],
+    options: &SummaryOptions,
+    usage: &mut TokenUsage,
+) -> Result<(), MotorheadError> {
+    let known = store.get_entities(session_id).await?;
+    let known = serde_json::to_string(&known)
+        .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+    let prompt = ENTITY_PROMPT
+        .replace("{entities}", &known)
+        .replace("{messages}", &messages.join("\n"));
+
+    let completion = state
+        .llm
+        .complete(CompletionRequest {
+            system: "You are a helpful AI assistant.",
+            prompt: &prompt,
+            max_tokens: 512,
+            model: options.model.as_deref(),
+            temperature: options.temperature,
+        })
+        .await?;
+    *usage += completion.usage;
+
+    let entities = parse_entities(&completion.content)?;
+    if entities.is_empty() {
+        return Ok(());
+    }
+
+    store.merge_entities(session_id, &entities).await
+}
+
+pub const SEGMENT_PROMPT: &str = r#"
+        Split the numbered lines of conversation provided into segments, one per topic, in order. The segment the conversation was last on is given as a JSON object, or NONE. Return a JSON array with one object per segment: "topic", a few words naming it; "summary", a short summary of it; "lines", how many consecutive lines it covers; and "continues", true only for a first segment carrying on the last one, in which case its summary covers both.
+
+        Last segment:
+        {segment}
+        New lines of conversation:
+        {messages}
+        Segments:
+        "#;
+
+/// Segments kept per session, the oldest are dropped beyond it.
+const MAX_CONTEXT_SEGMENTS: usize = 100;
+
+#[derive(Deserialize)]
+struct SegmentCompletion {
+    topic: String,
+    summary: String,
+    lines: usize,
+    #[serde(default)]
+    continues: bool,
+}
+
+/// Parses the segments out of a completion, tolerating text around the JSON array.
+fn parse_segments(completion: &str) -> Result<Vec<SegmentCompletion>, MotorheadError> {
+    let array = match (completion.find('['), completion.rfind(']')) {
+        (Some(start), Some(end)) if start < end => &completion[start..=end],
+        _ => return Ok(Vec::new()),
+    };
+
+    serde_json::from_str(array)
+        .map_err(|e| MotorheadError::LlmError(format!("Invalid segments: {}", e)))
+}
+
+/// Asks the LLM where the topic changes in `messages`, newest first as compacted, and adds
+/// the summary of each topic to the session's segments, or extends the last one.
+#[tracing::instrument(skip_all)]
+async fn update_segments(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    messages: &[MemoryMessage],
+    options: &SummaryOptions,
+    usage: &mut TokenUsage,
+) -> Result<(), MotorheadError> {
+    let mut segments = store.get_context_segments(session_id).await?;
+    let last = match segments.last() {
+        Some(last) => serde_json::to_string(&serde_json::json!({
+            "topic": last.topic,
+            "summary": last.summary,
+        }))
+        .map_err(|e| MotorheadError::SerializationError(e.to_string()))?,
+        None => "NONE".to_string(),
+    };
+    let oldest_first: Vec<&MemoryMessage> = messages.iter().rev().collect();
+    let lines: Vec<String> = oldest_first
+        .iter()
+        .enumerate()
+        .map(|(i, message)| {
+            format!(
+                "{}. {}",
+                i + 1,
+                message.transcript_line(state.summarize_attachments)
+            )
+        })
+        .collect();
+    let prompt = SEGMENT_PROMPT
+        .replace("{segment}", &last)
+        .replace("{messages}", &lines.join("\n"));
+
+    let completion = state
+        .llm
+        .complete(CompletionRequest {
+            system: &summary_system(options),
+            prompt: &prompt,
+            max_tokens: options.max_tokens.unwrap_or(DEFAULT_SUMMARY_MAX_TOKENS),
+            model: options.model.as_deref(),
+            temperature: options.temperature,
+        })
+        .await?;
+    *usage += completion.usage;
+
+    let found = parse_segments(&completion.content)?;
+    if found.is_empty() {
+        return Err(MotorheadError::LlmError("No segments returned".to_string()));
+    }
+
+    // The line counts are trusted as far as they go; the last segment takes whatever lines
+    // are left over.
+    let mut rest = &oldest_first[..];
+    let count = found.len();
+    for (i, segment) in found.into_iter().enumerate() {
+        let taken = if i + 1 == count {
+            rest.len()
+        } else {
+            segment.lines.min(rest.len())
+        };
+        if taken == 0 {
+            continue;
+        }
+        let (covered, remaining) = rest.split_at(taken);
+        rest = remaining;
+
+        let last_message_id = covered.last().and_then(|message| message.id.clone());
+        match segments.last_mut() {
+            Some(last) if i == 0 && segment.continues => {
+                last.summary = segment.summary;
+                last.last_message_id = last_message_id;
+                last.message_count += taken;
+            }
+            _ => segments.push(ContextSegment {
+                topic: segment.topic,
+                summary: segment.summary,
+                first_message_id: covered.first().and_then(|message| message.id.clone()),
+                last_message_id,
+                message_count: taken,
+            }),
+        }
+    }
+    if segments.len() > MAX_CONTEXT_SEGMENTS {
+        segments.drain(..segments.len() - MAX_CONTEXT_SEGMENTS);
+    }
+
+    store.set_context_segments(session_id, &segments).await
+}
+
+/// Once the context grows over `MOTORHEAD_LONG_TERM_THRESHOLD_TOKENS`, summarizes it into the
+/// long-term context and clears it, so that very long sessions don't end up with a single
+/// summary too long to be of use.
+#[tracing::instrument(skip_all)]
+async fn fold_into_long_term(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    context: &str,
+    options: &SummaryOptions,
+    usage: &mut TokenUsage,
+) -> Result<(), MotorheadError> {
+    let Some(threshold) = state.long_term_threshold_tokens else {
+        return Ok(());
+    };
+    if count_tokens(context) <= threshold {
+        return Ok(());
+    }
+
+    let long_term_context = store.get_long_term_context(session_id).await?;
+    let long_term_context = summarize_with_retry(
+        state,
+        LONG_TERM_PROMPT,
+        long_term_context,
+        vec![context.to_string()],
+        options,
+    )
+    .await?;
+    *usage += long_term_context.usage;
+
+    store
+        .fold_context(session_id, &long_term_context.content)
+        .await
+}
+
+/// The system prompt of the calls writing summaries, asking for the summary language if any,
+/// with the session's own system prompt for context.
+fn summary_system(options: &SummaryOptions) -> String {
+    let system = match &options.language {
+        Some(language) => format!(
+            "You are a helpful AI assistant. Always write the summaries in {}, whatever the language of the conversation.",
+            language
+        ),
+        None => "You are a helpful AI assistant.".to_string(),
+    };
+    match &options.system_prompt {
+        Some(prompt) => format!(
+            "{}\n\nThe conversation was held under this system prompt:\n{}",
+            system, prompt
+        ),
+        None => system,
+    }
+}
+
+/// The prompt of the call folding `messages` into the previous summary.
+fn summary_prompt(prompt_template: &str, context: Option<&str>, messages: &[String]) -> String {
+    prompt_template
+        .replace("{previous_summary}", context.unwrap_or_default())
+        .replace("{messages}", &messages.join("\n"))
+}
+
+#[tracing::instrument(name = "summarize", skip_all)]
+pub async fn incremental_summarization(
+    llm: &dyn LlmClient,
+    prompt_template: &str,
+    context: Option<String>,
+    messages: Vec<String>,
+    options: &SummaryOptions,
+) -> Result<Completion, MotorheadError> {
+    let progresive_prompt = summary_prompt(prompt_template, context.as_deref(), &messages);
+
+    llm.complete(CompletionRequest {
+        system: &summary_system(options),
+        prompt: &progresive_prompt,
+        max_tokens: options.max_tokens.unwrap_or(DEFAULT_SUMMARY_MAX_TOKENS),
+        model: options.model.as_deref(),
+        temperature: options.temperature,
+    })
+    .await
+}
+
+/// Takes the oldest messages (the tail of the Redis list) that fit within `budget` tokens out
+/// of `messages`. The oldest message is always taken so compaction makes progress even if it
+/// alone is over budget; the rest are deferred to the next compaction pass.
+fn select_within_budget(messages: &mut Vec<String>, budget: Option<usize>) -> Vec<String> {
+    let Some(budget) = budget else {
+        return std::mem::take(messages);
+    };
+
+    let mut used = 0;
+    let mut split_at = messages.len();
+    while split_at > 0 {
+        let tokens = count_tokens(&messages[split_at - 1]);
+        if used + tokens > budget && split_at < messages.len() {
+            break;
+        }
+        used += tokens;
+        split_at -= 1;
+    }
+
+    messages.split_off(split_at)
+}
+
+/// The messages a single summarization call takes out of `messages`, within `budget` and the
+/// `max_messages` of `options`. The oldest ones, the rest wait for the next call.
+fn select_lines(
+    messages: &mut Vec<String>,
+    budget: Option<usize>,
+    options: &SummaryOptions,
+) -> Vec<String> {
+    let mut lines = select_within_budget(messages, budget);
+    if let Some(max_messages) = options.max_messages {
+        let rest = lines.len().saturating_sub(max_messages.max(1));
+        messages.extend(lines.drain(..rest));
+    }
+    lines
+}
+
+/// Writes the recap of a user's day from `conversations`, the summary and lines of the day of
+/// each of their sessions, within `reducer_input_budget_tokens` like the lines of a summary.
+pub async fn recap_day(
+    state: &AppState,
+    mut conversations: Vec<String>,
+    options: &SummaryOptions,
+) -> Result<Completion, MotorheadError> {
+    let lines = select_within_budget(&mut conversations, state.reducer_input_budget_tokens);
+    summarize_with_retry(state, RECAP_PROMPT, None, lines, options).await
+}
+
+/// Summarizes `messages` (newest first) into `context` with `prompt_template` the way a
+/// compaction of the summarize strategy would, without a session: once, on the messages
+/// one compaction would take within `reducer_input_budget_tokens` and `max_messages`.
+/// Returns the completion and how many messages it summarized.
+pub async fn summarize_messages(
+    state: &AppState,
+    prompt_template: &str,
+    context: Option<String>,
+    messages: &[MemoryMessage],
+    options: &SummaryOptions,
+) -> Result<(Completion, usize), MotorheadError> {
+    let mut lines: Vec<String> = messages
+        .iter()
+        .map(|message| message.transcript_line(state.summarize_attachments))
+        .collect();
+    let lines = select_lines(&mut lines, state.reducer_input_budget_tokens, options);
+    let summarized = lines.len();
+
+    let completion = summarize_with_retry(state, prompt_template, context, lines, options).await?;
+    Ok((completion, summarized))
+}
+
+/// Upper bound for a single retry delay, however many attempts are configured.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Exponential backoff with jitter: a random delay between half and all of
+/// `base * 2^(attempt - 1)`, so retries from concurrent compactions spread out.
+pub fn retry_delay(base_ms: u64, attempt: u32) -> Duration {
+    let delay = base_ms
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_RETRY_DELAY.as_millis() as u64);
+    let jitter = RandomState::new().build_hasher().finish() % (delay / 2 + 1);
+    Duration::from_millis(delay - jitter)
+}
+
+/// Runs the summarization, retrying transient LLM failures up to `llm_max_attempts` times.
+pub async fn summarize_with_retry(
+    state: &AppState,
+    prompt_template: &str,
+    context: Option<String>,
+    messages: Vec<String>,
+    options: &SummaryOptions,
+) -> Result<Completion, MotorheadError> {
+    let mut attempt = 1;
+    loop {
+        let result = incremental_summarization(
+            state.llm.as_ref(),
+            prompt_template,
+            context.clone(),
+            messages.clone(),
+            options,
+        )
+        .await;
+
+        match result {
+            Err(ref error @ MotorheadError::LlmUnavailable(_))
+                if attempt < state.llm_max_attempts =>
+            {
+                let delay = retry_delay(state.llm_retry_base_delay_ms, attempt);
+                tracing::warn!(
+                    attempt,
+                    retry_in_ms = delay.as_millis() as u64,
+                    error = telemetry::error_message(error),
+                    "Summarization attempt failed"
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Summarizes `messages` (newest first) into `context` like `summarize_with_retry`, but over
+/// `MOTORHEAD_SUMMARY_CHUNK_TOKENS` it summarizes chunks of them on their own first, oldest
+/// first, then merges their summaries into the context. Each chunk's summary is stored as it
+/// completes, and reused when the compaction is tried again with the same chunk, so a failure
+/// only loses the chunk it happened on.
+pub async fn summarize_in_chunks(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    prompt_template: &str,
+    context: Option<String>,
+    mut messages: Vec<String>,
+    options: &SummaryOptions,
+) -> Result<Completion, MotorheadError> {
+    let chunk_tokens = state.summary_chunk_tokens.filter(|chunk_tokens| {
+        messages
+            .iter()
+            .map(|line| count_tokens(line))
+            .sum::<usize>()
+            > *chunk_tokens
+    });
+    let Some(chunk_tokens) = chunk_tokens else {
+        return summarize_with_retry(state, prompt_template, context, messages, options).await;
+    };
+
+    // Stored progress only helps, a compaction goes on without it.
+    let stored = match store.get_compaction_progress(session_id).await {
+        Ok(progress) => progress.unwrap_or_default(),
+        Err(e) => {
+            tracing::warn!(
+                error = telemetry::error_message(&e),
+                "Error reading the compaction progress"
+            );
+            CompactionProgress::default()
+        }
+    };
+    let mut progress = CompactionProgress::default();
+    let mut usage = TokenUsage::default();
+    let mut summaries = Vec::new();
+    while !messages.is_empty() {
+        let chunk = select_within_budget(&mut messages, Some(chunk_tokens));
+        let digest: String = Sha1::digest(chunk.join("\n").as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let summary = match stored.chunks.get(&digest) {
+            Some(summary) => {
+                metrics::SUMMARY_CHUNKS.with_label_values(&["reused"]).inc();
+                summary.clone()
+            }
+            None => {
+                let completion =
+                    summarize_with_retry(state, prompt_template, None, chunk, options).await?;
+                usage += completion.usage;
+                metrics::SUMMARY_CHUNKS
+                    .with_label_values(&["summarized"])
+                    .inc();
+                completion.content
+            }
+        };
+        progress.chunks.insert(digest, summary.clone());
+        summaries.push(summary);
+        if let Err(e) = store
+            .set_compaction_progress(session_id, Some(&progress))
+            .await
+        {
+            tracing::warn!(
+                error = telemetry::error_message(&e),
+                "Error storing the compaction progress"
+            );
+        }
+    }
+
+    // Most recent first, like the lines of a summary.
+    summaries.reverse();
+    let completion =
+        summarize_with_retry(state, CHUNK_MERGE_PROMPT, context, summaries, options).await?;
+    usage += completion.usage;
+    Ok(Completion {
+        content: completion.content,
+        usage,
+    })
+}
+
+/// What a compaction left in the session.
+pub struct Compaction {
+    pub context: String,
+    /// The messages summarized into the context, if any were.
+    pub summarized: Option<SummarizedRange>,
+}
+
+/// The messages a compaction takes out of the window.
+struct Selection {
+    /// The window index from which messages are kept.
+    keep_until: i64,
+    newest_summarized: Option<String>,
+    /// Newest first, without the pinned ones.
+    summarized: Vec<MemoryMessage>,
+    /// The transcript lines of `summarized`.
+    lines: Vec<String>,
+}
+
+/// What a compaction of the session would do now.
+struct CompactionPlan {
+    /// The session's summary options.
+    options: SummaryOptions,
+    strategy: CompactionStrategy,
+    context: Option<String>,
+    /// None if there is nothing to take out of the window.
+    selection: Option<Selection>,
+}
+
+/// Keeps the context the compaction stored, with where the window started after it for
+/// replays: the newest message it archived, which may be older than the newest it summarized.
+async fn record_context_version(
+    store: &dyn MemoryStore,
+    session_id: &str,
+    at: u64,
+    compaction: &Compaction,
+    summarized: &SummarizedRange,
+) -> Result<(), MotorheadError> {
+    let archived = store.get_history_tail(session_id, 1).await?.pop();
+    let (last_message_id, last_created_at) = match archived {
+        Some(message) => (message.id, message.created_at),
+        None => (
+            summarized.last_message_id.clone(),
+            summarized.last_created_at,
+        ),
+    };
+    let version = ContextVersion {
+        at,
+        context: compaction.context.clone(),
+        last_message_id,
+        last_created_at,
+    };
+    store.record_context_version(session_id, &version).await
+}
+
+/// Picks the older part of the window a compaction summarizes. With `force`, a session still
+/// under the window is split in half too instead of being left as is.
+async fn plan_compaction(
+    state_clone: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    force: bool,
+    options: &SummaryOptions,
+) -> Result<CompactionPlan, MotorheadError> {
+    let window_size = window_size(state_clone, store, session_id).await?;
+    let options = session_summary_options(store, session_id, options).await?;
+    let strategy = compaction_strategy(state_clone, store, session_id).await?;
+    let trigger = state_clone.compaction_trigger;
+    let window =
+        if force || state_clone.window_tokens.is_some() || trigger != CompactionTrigger::Messages {
+            store.get_messages(session_id, 0, window_size).await?
+        } else {
+            Vec::new()
+        };
+    let len = window.len() as i64;
+    // The number of newest messages kept out of the summary.
+    let mut half = match (state_clone.window_tokens, trigger) {
+        // Keep the newest messages that fit in half the token budget and summarize the rest.
+        (Some(window_tokens), _) | (None, CompactionTrigger::Tokens(window_tokens)) => {
+            fit_within_tokens(&window, window_tokens / 2) as i64
+        }
+        (None, CompactionTrigger::Ratio(ratio)) => len - (len as f64 * ratio).ceil() as i64,
+        (None, CompactionTrigger::Elapsed(_)) => len / 2,
+        (None, CompactionTrigger::Messages) => window_size / 2,
+    };
+    if force && !matches!(trigger, CompactionTrigger::Ratio(_)) {
+        half = half.min(len / 2);
+    }
+    let (messages, context) = store.get_memory(session_id, half, window_size).await?;
+
+    let fetched = messages.len() as i64;
+    if fetched == 0 {
+        return Ok(CompactionPlan {
+            options,
+            strategy,
+            context,
+            selection: None,
+        });
+    }
+    let pinned = store.get_pinned(session_id).await?;
+    let is_pinned: Vec<bool> = messages
+        .iter()
+        .map(|message| pinned.iter().any(|pinned| pinned.id == message.id))
+        .collect();
+    let mut lines: Vec<String> = messages
+        .iter()
+        .map(|message| message.transcript_line(state_clone.summarize_attachments))
+        .collect();
+    let lines = select_lines(
+        &mut lines,
+        state_clone.reducer_input_budget_tokens,
+        &options,
     );
+    let keep_until = (half + fetched - lines.len() as i64 - 1).max(half);
 
-    let request = CreateChatCompletionRequestArgs::default()
-        .max_tokens(512u16)
-        .model("gpt-3.5-turbo")
-        .messages([
-            ChatCompletionRequestMessageArgs::default()
-                .role(Role::System)
-                .content("You are a helpful AI assistant.")
-                .build()?,
-            ChatCompletionRequestMessageArgs::default()
-                .role(Role::User)
-                .content(progresive_prompt)
-                .build()?,
-        ])
-        .build()?;
-
-    let response = openai_client.chat().create(request).await?;
-
-    let completion = response
-        .choices
-        .first()
-        .ok_or("No completion found")?
-        .message
-        .content
-        .clone();
-
-    Ok(completion.to_string())
+    // Pinned messages leave the window without being summarized, reads serve their copy.
+    let first_selected = messages.len() - lines.len();
+    let newest_summarized = messages
+        .get(first_selected)
+        .and_then(|message| message.id.clone());
+    let (summarized, lines): (Vec<MemoryMessage>, Vec<String>) = messages
+        .into_iter()
+        .skip(first_selected)
+        .zip(lines)
+        .zip(&is_pinned[first_selected..])
+        .filter(|(_, is_pinned)| !**is_pinned)
+        .map(|(selected, _)| selected)
+        .unzip();
+
+    Ok(CompactionPlan {
+        options,
+        strategy,
+        context,
+        selection: Some(Selection {
+            keep_until,
+            newest_summarized,
+            summarized,
+            lines,
+        }),
+    })
 }
 
+/// Summarizes the older part of the session into the context and returns the new context.
+/// With `force`, a session still under the window is split in half too instead of being left
+/// as is.
 pub async fn handle_compaction(
     session_id: String,
-    state_clone: Arc<Arc<AppState>>,
-    mut conn: redis::aio::ConnectionManager,
-) -> Result<(), MotorheadError> {
-    let half = state_clone.window_size / 2;
-    let context_key = format!("{}_context", &*session_id);
-    let (messages, context): (Vec<String>, Option<String>) = redis::pipe()
-        .cmd("LRANGE")
-        .arg(&*session_id)
-        .arg(i64::try_from(half).unwrap())
-        .arg(i64::try_from(state_clone.window_size).unwrap())
-        .cmd("GET")
-        .arg(context_key.clone())
-        .query_async(&mut conn)
-        .await?;
+    state_clone: Arc<AppState>,
+    store: Arc<dyn MemoryStore>,
+    force: bool,
+    options: &SummaryOptions,
+) -> Result<Compaction, MotorheadError> {
+    let plan = plan_compaction(&state_clone, store.as_ref(), &session_id, force, options).await?;
+    let options = &plan.options;
+    let context = plan.context;
+    let Some(Selection {
+        keep_until,
+        newest_summarized,
+        mut summarized,
+        lines: mut messages,
+    }) = plan.selection
+    else {
+        return Ok(Compaction {
+            context: context.unwrap_or_default(),
+            summarized: None,
+        });
+    };
+    let compactor = plan.strategy.compactor();
+    let retention = state_clone
+        .importance_retention
+        .as_ref()
+        .filter(|_| !summarized.is_empty());
+    if compactor.uses_llm()
+        || retention.is_some_and(|retention| retention.scorer == ImportanceScorer::Llm)
+    {
+        check_budget(&state_clone, store.as_ref()).await?;
+    }
+
+    let entity_messages =
+        (compactor.uses_llm() && state_clone.entity_extraction_enabled).then(|| messages.clone());
+    let sentiment_messages =
+        (compactor.uses_llm() && state_clone.sentiment_analysis_enabled).then(|| messages.clone());
+    let segment_messages =
+        (compactor.uses_llm() && state_clone.segmented_summaries_enabled && !summarized.is_empty())
+            .then(|| summarized.clone());
+
+    let mut usage = TokenUsage::default();
+    // Scored before the compactor runs, but only stored once it succeeded.
+    let mut auto_pinned = None;
+    if retention.is_some() {
+        match retain_important(
+            &state_clone,
+            store.as_ref(),
+            &session_id,
+            &summarized,
+            &messages,
+            options,
+            &mut usage,
+        )
+        .await
+        {
+            Ok(Some(retained)) => {
+                summarized = retained.summarized;
+                messages = retained.lines;
+                auto_pinned = Some(retained.auto_pinned);
+            }
+            Ok(None) => {}
+            // Like entities, a best effort: the messages are summarized as usual.
+            Err(e) => tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem scoring the importance of messages"
+            ),
+        }
+    }
 
-    let new_context_result =
-        incremental_summarization(state_clone.openai_client.clone(), context, messages).await;
+    // Newest first.
+    let summarized_range = match (summarized.last(), summarized.first()) {
+        (Some(oldest), Some(newest)) => Some(SummarizedRange {
+            first_message_id: oldest.id.clone(),
+            last_message_id: newest.id.clone(),
+            first_created_at: oldest.created_at,
+            last_created_at: newest.created_at,
+            count: summarized.len(),
+        }),
+        _ => None,
+    };
+
+    let new_context_result = if messages.is_empty() {
+        Ok(context.unwrap_or_default())
+    } else {
+        compactor
+            .compact(
+                CompactorInput {
+                    state: &state_clone,
+                    store: &store,
+                    session_id: &session_id,
+                    context,
+                    messages: summarized,
+                    lines: messages,
+                    options,
+                },
+                &mut usage,
+            )
+            .await
+    };
 
     if let Err(ref error) = new_context_result {
-        log::error!("Problem getting summary: {:?}", error);
+        tracing::error!(
+            error = telemetry::error_message(error),
+            "Problem getting summary"
+        );
         return Err(MotorheadError::IncrementalSummarizationError(
             error.to_string(),
         ));
@@ -93,22 +882,763 @@ pub async fn handle_compaction(
 
     let new_context = new_context_result.unwrap_or_default();
 
-    let redis_pipe_response_result: Result<((), ()), redis::RedisError> = redis::pipe()
-        .cmd("LTRIM")
-        .arg(&*session_id)
-        .arg(0)
-        .arg(i64::try_from(half).unwrap())
-        .cmd("SET")
-        .arg(context_key)
-        .arg(new_context)
-        .query_async(&mut conn)
+    // Pinned before the messages leave the window, so none get lost if this fails.
+    if let Some(auto_pinned) = &auto_pinned {
+        if let Err(e) = store.set_auto_pinned(&session_id, auto_pinned).await {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Error pinning the important messages"
+            );
+            record_usage(&state_clone, store.as_ref(), &session_id, usage).await;
+            return Err(e);
+        }
+    }
+
+    let commit_result = store
+        .commit_compaction(
+            &session_id,
+            keep_until,
+            newest_summarized.as_deref(),
+            &new_context,
+            state_clone.history_enabled,
+        )
         .await;
 
-    match redis_pipe_response_result {
-        Ok(_) => Ok(()),
+    if let Err(ref e) = commit_result {
+        tracing::error!(
+            error = telemetry::error_message(e),
+            "Error storing the compaction result"
+        );
+    }
+
+    if commit_result.is_ok() && compactor.uses_llm() && state_clone.summary_chunk_tokens.is_some() {
+        if let Err(e) = store.set_compaction_progress(&session_id, None).await {
+            tracing::warn!(
+                error = telemetry::error_message(&e),
+                "Error clearing the compaction progress"
+            );
+        }
+    }
+
+    if commit_result.is_ok() && compactor.uses_llm() {
+        // Like entities below, a best effort on top of the summary.
+        if let Err(e) = fold_into_long_term(
+            &state_clone,
+            store.as_ref(),
+            &session_id,
+            &new_context,
+            options,
+            &mut usage,
+        )
+        .await
+        {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem updating the long-term context"
+            );
+        }
+    }
+
+    // Entities are a best effort on top of the summary, failing to update them doesn't fail
+    // the compaction.
+    if let (Ok(()), Some(messages)) = (&commit_result, entity_messages) {
+        if let Err(e) = extract_entities(
+            &state_clone,
+            store.as_ref(),
+            &session_id,
+            &messages,
+            options,
+            &mut usage,
+        )
+        .await
+        {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem extracting entities"
+            );
+        }
+    }
+
+    if let (Ok(()), Some(messages)) = (&commit_result, segment_messages) {
+        if let Err(e) = update_segments(
+            &state_clone,
+            store.as_ref(),
+            &session_id,
+            &messages,
+            options,
+            &mut usage,
+        )
+        .await
+        {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem updating the context segments"
+            );
+        }
+    }
+
+    if let (Ok(()), Some(messages)) = (&commit_result, sentiment_messages) {
+        if let Err(e) = score_sentiment(
+            &state_clone,
+            store.as_ref(),
+            &session_id,
+            &messages,
+            options,
+            &mut usage,
+        )
+        .await
+        {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem scoring the sentiment"
+            );
+        }
+    }
+
+    record_usage(&state_clone, store.as_ref(), &session_id, usage).await;
+
+    commit_result.map(|_| Compaction {
+        context: new_context,
+        summarized: summarized_range,
+    })
+}
+
+/// How long a compaction claim lasts unless it's renewed, so the sessions of an instance that
+/// stopped mid-compaction can be compacted again. Renewed every third of it.
+const COMPACTION_CLAIM_TTL: Duration = Duration::from_secs(60);
+
+/// A compaction of the session, claimed so no other runs at once, on any instance when the
+/// store shares claims. Given back with `release`, or in the background once dropped, e.g.
+/// with the request holding it when its client disconnects.
+pub struct CompactionClaim {
+    state: Arc<AppState>,
+    tenant: Tenant,
+    session_id: String,
+    /// The token of the store's claim, `None` when it's only this instance's.
+    token: Option<String>,
+    renewal: Option<tokio::task::JoinHandle<()>>,
+    released: bool,
+}
+
+/// Marks the session as being compacted. Returns `None` if a compaction is already running,
+/// or the claim couldn't be checked.
+pub async fn claim_compaction(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+) -> Option<CompactionClaim> {
+    let store = tenant.store(state);
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    let ttl_ms = COMPACTION_CLAIM_TTL.as_millis() as u64;
+
+    match store.claim_compaction(session_id, &token, ttl_ms).await {
+        Ok(false) => None,
+        Ok(true) => {
+            let renewal = {
+                let session_id = session_id.to_string();
+                let token = token.clone();
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(COMPACTION_CLAIM_TTL / 3);
+                    interval.tick().await;
+                    loop {
+                        interval.tick().await;
+                        match store.renew_compaction(&session_id, &token, ttl_ms).await {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                tracing::warn!(session_id, "The compaction claim was lost");
+                                return;
+                            }
+                            Err(e) => tracing::error!(
+                                error = telemetry::error_message(&e),
+                                "Problem renewing a compaction claim"
+                            ),
+                        }
+                    }
+                })
+            };
+            Some(CompactionClaim {
+                state: Arc::clone(state),
+                tenant: tenant.clone(),
+                session_id: session_id.to_string(),
+                token: Some(token),
+                renewal: Some(renewal),
+                released: false,
+            })
+        }
+        Err(MotorheadError::Unsupported(_)) => {
+            let mut session_cleanup = state.session_cleanup.lock().await;
+            let scoped_session_id = tenant.scope(session_id);
+            if *session_cleanup.get(&scoped_session_id).unwrap_or(&false) {
+                return None;
+            }
+            session_cleanup.insert(scoped_session_id, true);
+            Some(CompactionClaim {
+                state: Arc::clone(state),
+                tenant: tenant.clone(),
+                session_id: session_id.to_string(),
+                token: None,
+                renewal: None,
+                released: false,
+            })
+        }
         Err(e) => {
-            log::error!("Error executing the redis pipeline: {:?}", e);
-            Err(MotorheadError::RedisError(e))
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem claiming a compaction"
+            );
+            None
+        }
+    }
+}
+
+async fn give_back_claim(state: &AppState, tenant: &Tenant, session_id: &str, token: Option<&str>) {
+    let Some(token) = token else {
+        let mut session_cleanup = state.session_cleanup.lock().await;
+        session_cleanup.remove(&tenant.scope(session_id));
+        return;
+    };
+    if let Err(e) = tenant
+        .store(state)
+        .release_compaction(session_id, token)
+        .await
+    {
+        // It runs out with its TTL instead.
+        tracing::error!(
+            error = telemetry::error_message(&e),
+            "Problem releasing a compaction claim"
+        );
+    }
+}
+
+impl CompactionClaim {
+    pub async fn release(mut self) {
+        if let Some(renewal) = self.renewal.take() {
+            renewal.abort();
+        }
+        give_back_claim(
+            &self.state,
+            &self.tenant,
+            &self.session_id,
+            self.token.as_deref(),
+        )
+        .await;
+        self.released = true;
+    }
+}
+
+impl Drop for CompactionClaim {
+    fn drop(&mut self) {
+        if let Some(renewal) = self.renewal.take() {
+            renewal.abort();
+        }
+        if self.released {
+            return;
+        }
+        // Without a runtime left, the store's claim runs out with its TTL.
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let state = Arc::clone(&self.state);
+        let tenant = self.tenant.clone();
+        let session_id = std::mem::take(&mut self.session_id);
+        let token = self.token.take();
+        runtime.spawn(async move {
+            give_back_claim(&state, &tenant, &session_id, token.as_deref()).await;
+        });
+    }
+}
+
+/// Whether a compaction of the session is running, on any instance when the store shares
+/// claims.
+pub async fn compaction_in_progress(
+    state: &AppState,
+    tenant: &Tenant,
+    session_id: &str,
+) -> Result<bool, MotorheadError> {
+    match tenant.store(state).compaction_claimed(session_id).await {
+        Err(MotorheadError::Unsupported(_)) => Ok(*state
+            .session_cleanup
+            .lock()
+            .await
+            .get(&tenant.scope(session_id))
+            .unwrap_or(&false)),
+        result => result,
+    }
+}
+
+/// Compacts the session right away and returns the new context, or `None` if a compaction
+/// is already running for it.
+pub async fn run_compaction(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    options: &SummaryOptions,
+) -> Option<Result<String, MotorheadError>> {
+    let claim = claim_compaction(state, tenant, session_id).await?;
+
+    let _task_guard = TaskTracker::track(&state.tasks, &tenant.scope(session_id));
+    let result = compact(state, tenant, session_id, 0, true, options).await;
+    claim.release().await;
+
+    Some(result)
+}
+
+/// Rebuilds the context from the session's whole history instead of from the previous one,
+/// and returns it, or `None` if a compaction is already running for the session, which
+/// regenerations count as.
+pub async fn regenerate_summary(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    options: &SummaryOptions,
+) -> Option<Result<String, MotorheadError>> {
+    let claim = claim_compaction(state, tenant, session_id).await?;
+
+    let _task_guard = TaskTracker::track(&state.tasks, &tenant.scope(session_id));
+    let result = regenerate(state, tenant.store(state).as_ref(), session_id, options).await;
+    claim.release().await;
+
+    Some(result)
+}
+
+/// What `run_compaction` would send to the LLM for the summary, without calling it or
+/// changing the session. The calls on top of it (long-term context, entities, segments)
+/// aren't counted, nor are the embeddings of the archive strategy.
+pub async fn estimate_compaction(
+    state: &AppState,
+    tenant: &Tenant,
+    session_id: &str,
+    options: &SummaryOptions,
+) -> Result<SummarizeDryRunResponse, MotorheadError> {
+    let store = tenant.store(state);
+    let plan = plan_compaction(state, store.as_ref(), session_id, true, options).await?;
+    let options = &plan.options;
+    let strategy = plan.strategy;
+    let model = state.llm.model(options.model.as_deref()).to_string();
+    let Some(selection) = plan
+        .selection
+        .filter(|selection| !selection.lines.is_empty())
+    else {
+        return Ok(SummarizeDryRunResponse {
+            messages: Vec::new(),
+            strategy,
+            model,
+            prompt_tokens: 0,
+            max_completion_tokens: 0,
+            estimated_cost: Some(0.0),
+        });
+    };
+    if !strategy.compactor().uses_llm() {
+        return Ok(SummarizeDryRunResponse {
+            messages: selection.summarized,
+            strategy,
+            model,
+            prompt_tokens: 0,
+            max_completion_tokens: 0,
+            estimated_cost: Some(0.0),
+        });
+    }
+
+    let prompt_template = state.runtime().summary_prompt;
+    let prompt = summary_prompt(&prompt_template, plan.context.as_deref(), &selection.lines);
+    let prompt_tokens = count_tokens(&summary_system(options)) + count_tokens(&prompt);
+    let max_completion_tokens = options.max_tokens.unwrap_or(DEFAULT_SUMMARY_MAX_TOKENS);
+    let estimated_cost = state
+        .llm_prices
+        .get(&model)
+        .map(|price| price.cost(prompt_tokens, max_completion_tokens as usize));
+
+    Ok(SummarizeDryRunResponse {
+        messages: selection.summarized,
+        strategy,
+        model,
+        prompt_tokens,
+        max_completion_tokens,
+        estimated_cost,
+    })
+}
+
+/// Clears the session's context, leaving its messages and long-term context, then with
+/// `summarize` compacts it right away into a new one, which is returned. `None` if a
+/// compaction is running for the session, as it would store its context afterwards.
+pub async fn clear_context(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    summarize: bool,
+    options: &SummaryOptions,
+) -> Option<Result<Option<String>, MotorheadError>> {
+    let claim = claim_compaction(state, tenant, session_id).await?;
+
+    let _task_guard = TaskTracker::track(&state.tasks, &tenant.scope(session_id));
+    let store = tenant.store(state);
+    let result = async {
+        let long_term_context = store.get_long_term_context(session_id).await?;
+        store
+            .replace_contexts(session_id, None, long_term_context.as_deref())
+            .await?;
+        tracing::info!(session_id, "Context cleared");
+        if !summarize {
+            return Ok(None);
+        }
+        compact(state, tenant, session_id, 0, true, options)
+            .await
+            .map(Some)
+    }
+    .await;
+    claim.release().await;
+
+    Some(result)
+}
+
+/// Messages read from the history at a time when regenerating.
+const HISTORY_PAGE_SIZE: usize = 1000;
+
+/// Summarizes the history like successive compactions would have, oldest messages first,
+/// folding into a new long-term context along the way, and replaces both contexts at once.
+/// Nothing is stored if a summarization fails.
+#[tracing::instrument(name = "regeneration", skip(state, store, options))]
+async fn regenerate(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    options: &SummaryOptions,
+) -> Result<String, MotorheadError> {
+    tracing::info!("Regenerating the summary");
+    let options = &session_summary_options(store, session_id, options).await?;
+    let pinned = store.get_pinned(session_id).await?;
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    loop {
+        let page =
+            cold_storage::get_history(state, store, session_id, offset, HISTORY_PAGE_SIZE).await?;
+        let page_len = page.len();
+        lines.extend(
+            page.iter()
+                .filter(|message| !pinned.iter().any(|pinned| pinned.id == message.id))
+                .map(|message| message.transcript_line(state.summarize_attachments)),
+        );
+
+        if page_len < HISTORY_PAGE_SIZE {
+            break;
+        }
+        offset += page_len;
+    }
+    if lines.is_empty() {
+        return Ok(store.get_context(session_id).await?.unwrap_or_default());
+    }
+    check_budget(state, store).await?;
+
+    // Newest first, like the window compactions read.
+    lines.reverse();
+    let prompt_template = state.runtime().summary_prompt;
+    let mut usage = TokenUsage::default();
+    let mut context = None;
+    let mut long_term_context = None;
+    let result = async {
+        while !lines.is_empty() {
+            let selected = select_lines(&mut lines, state.reducer_input_budget_tokens, options);
+            let completion =
+                summarize_with_retry(state, &prompt_template, context.take(), selected, options)
+                    .await?;
+            usage += completion.usage;
+
+            let Some(threshold) = state.long_term_threshold_tokens else {
+                context = Some(completion.content);
+                continue;
+            };
+            if count_tokens(&completion.content) <= threshold {
+                context = Some(completion.content);
+                continue;
+            }
+            let completion = summarize_with_retry(
+                state,
+                LONG_TERM_PROMPT,
+                long_term_context.take(),
+                vec![completion.content],
+                options,
+            )
+            .await?;
+            usage += completion.usage;
+            long_term_context = Some(completion.content);
+        }
+
+        store
+            .replace_contexts(session_id, context.as_deref(), long_term_context.as_deref())
+            .await
+    }
+    .await;
+    record_usage(state, store, session_id, usage).await;
+
+    if let Err(ref e) = result {
+        tracing::error!(
+            error = telemetry::error_message(e),
+            "Problem regenerating the summary"
+        );
+    }
+    result.map(|_| context.unwrap_or_default())
+}
+
+/// Compacts the session in the background, unless a compaction is already running for it.
+pub async fn spawn_compaction(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    options: SummaryOptions,
+) {
+    spawn(state, tenant, session_id, 0, options).await;
+}
+
+/// Like `spawn_compaction`, for the retry worker. `retries` counts this attempt. Retries use
+/// the default summary options.
+pub async fn spawn_compaction_retry(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    retries: u32,
+) {
+    spawn(
+        state,
+        tenant,
+        session_id,
+        retries,
+        state.runtime().summary_options,
+    )
+    .await;
+}
+
+async fn spawn(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    retries: u32,
+    options: SummaryOptions,
+) {
+    let Some(claim) = claim_compaction(state, tenant, session_id).await else {
+        return;
+    };
+
+    let task_guard = TaskTracker::track(&state.tasks, &tenant.scope(session_id));
+    let state = Arc::clone(state);
+    let tenant = tenant.clone();
+    let session_id = session_id.to_string();
+
+    tokio::spawn(
+        async move {
+            let _task_guard = task_guard;
+            let _compaction_result =
+                compact(&state, &tenant, &session_id, retries, false, &options).await;
+            claim.release().await;
+        }
+        .in_current_span(),
+    );
+}
+
+/// Runs `handle_compaction` and keeps track of the outcome: failures are queued for the
+/// background retry worker and cleared again once a compaction succeeds.
+#[tracing::instrument(name = "compaction", skip(state, tenant, options))]
+async fn compact(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    retries: u32,
+    force: bool,
+    options: &SummaryOptions,
+) -> Result<String, MotorheadError> {
+    audit::record(
+        state,
+        audit::BACKGROUND_ACTOR,
+        tenant,
+        "compaction",
+        Some(session_id),
+        None,
+    )
+    .await?;
+    tracing::info!("Compacting");
+    metrics::ACTIVE_COMPACTIONS.inc();
+    let timer = metrics::COMPACTION_DURATION.start_timer();
+    let result = handle_compaction(
+        session_id.to_string(),
+        Arc::clone(state),
+        tenant.store(state),
+        force,
+        options,
+    )
+    .await;
+    timer.observe_duration();
+    metrics::ACTIVE_COMPACTIONS.dec();
+
+    if let Ok(compaction) = &result {
+        let data = serde_json::json!({
+            "context": compaction.context,
+            "messages": compaction.summarized,
+        });
+        notify(
+            state,
+            tenant.id(),
+            session_id,
+            WebhookEvent::CompactionCompleted,
+            data.clone(),
+        );
+        if let Some(summarized) = &compaction.summarized {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            if let Err(e) = tenant.store(state).record_compaction(session_id, now).await {
+                tracing::error!(
+                    error = telemetry::error_message(&e),
+                    "Problem recording the compaction"
+                );
+            }
+            if state.history_enabled {
+                let store = tenant.store(state);
+                if let Err(e) =
+                    record_context_version(store.as_ref(), session_id, now, compaction, summarized)
+                        .await
+                {
+                    tracing::error!(
+                        error = telemetry::error_message(&e),
+                        "Problem recording the context version"
+                    );
+                }
+            }
+            analytics::record(
+                state,
+                tenant.store(state).as_ref(),
+                AnalyticsCounts {
+                    compactions: 1,
+                    ..Default::default()
+                },
+            )
+            .await;
+            match tenant.store(state).get_session_config(session_id).await {
+                Ok(config) => {
+                    if let Some(url) = config.and_then(|config| config.compaction_callback_url) {
+                        call_back(
+                            state,
+                            url,
+                            tenant.id(),
+                            session_id,
+                            WebhookEvent::CompactionCompleted,
+                            data,
+                        );
+                    }
+                }
+                Err(e) => tracing::error!(
+                    error = telemetry::error_message(&e),
+                    "Problem reading the compaction callback URL"
+                ),
+            }
         }
     }
+    let result = result.map(|compaction| compaction.context);
+    alerts::record_compaction(state, tenant, session_id, result.is_err());
+
+    let scoped_session_id = tenant.scope(session_id);
+    let queue_result = match &result {
+        Ok(_) => {
+            state
+                .compaction_errors
+                .lock()
+                .unwrap()
+                .remove(&scoped_session_id);
+            state
+                .store
+                .clear_compaction_failure(tenant.id(), session_id)
+                .await
+        }
+        Err(error) => {
+            state
+                .compaction_errors
+                .lock()
+                .unwrap()
+                .insert(scoped_session_id, error.to_string());
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            // Backs off exponentially between background retries, then gives up.
+            let next_retry_at = (retries < state.compaction_max_retries).then(|| {
+                now + state.compaction_retry_interval_secs * 1000 * (1 << retries.min(16))
+            });
+
+            state
+                .store
+                .record_compaction_failure(&CompactionFailure {
+                    tenant: tenant.id().map(str::to_string),
+                    session_id: session_id.to_string(),
+                    error: error.to_string(),
+                    failed_at: now,
+                    retries,
+                    next_retry_at,
+                })
+                .await
+        }
+    };
+
+    match queue_result {
+        Ok(()) | Err(MotorheadError::Unsupported(_)) => {}
+        Err(e) => tracing::error!(
+            error = telemetry::error_message(&e),
+            "Error updating the compaction retry queue"
+        ),
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Newest first, like the lines compactions summarize.
+    fn verbose_lines() -> Vec<String> {
+        (0..6)
+            .rev()
+            .map(|i| format!("user: message {} {}", i, "with a lot to say ".repeat(20)))
+            .collect()
+    }
+
+    #[test]
+    fn select_within_budget_takes_the_oldest_lines_that_fit() {
+        let mut lines = verbose_lines();
+        let per_line = count_tokens(&lines[0]);
+        // Room for two lines and most of a third.
+        let budget = per_line * 3 - per_line / 2;
+
+        let selected = select_within_budget(&mut lines, Some(budget));
+
+        assert_eq!(selected, verbose_lines()[4..]);
+        assert_eq!(lines, verbose_lines()[..4]);
+        assert!(
+            selected
+                .iter()
+                .map(|line| count_tokens(line))
+                .sum::<usize>()
+                <= budget
+        );
+    }
+
+    #[test]
+    fn select_within_budget_takes_the_oldest_line_over_budget() {
+        let mut lines = verbose_lines();
+
+        let selected = select_within_budget(&mut lines, Some(1));
+
+        assert_eq!(selected, verbose_lines()[5..]);
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn select_within_budget_takes_everything_without_a_budget() {
+        let mut lines = verbose_lines();
+
+        let selected = select_within_budget(&mut lines, None);
+
+        assert_eq!(selected, verbose_lines());
+        assert!(lines.is_empty());
+    }
 }
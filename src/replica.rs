@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::models::AppState;
+use crate::store::MemoryStore;
+use crate::telemetry;
+
+/// A read-only replica of the Redis store, serving the memory reads and searches of
+/// `Tenant::read_store` while it's no more than `max_staleness` behind the primary. Writes,
+/// and the reads they depend on, always go to the primary.
+pub struct ReadReplica {
+    pub store: Arc<dyn MemoryStore>,
+    pub max_staleness: Duration,
+    fresh: AtomicBool,
+}
+
+impl ReadReplica {
+    /// Not used until `run_replica_monitor` first finds it fresh.
+    pub fn new(store: Arc<dyn MemoryStore>, max_staleness: Duration) -> Self {
+        ReadReplica {
+            store,
+            max_staleness,
+            fresh: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.fresh.load(Ordering::Relaxed)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Writes a heartbeat to the primary every quarter of the staleness allowed, and reads the
+/// last one back from the replica: it's fresh while that's no older than allowed. Reads go
+/// back to the primary when the replica falls behind or can't be reached.
+pub async fn run_replica_monitor(state: Arc<AppState>) {
+    let Some(replica) = &state.replica else {
+        return;
+    };
+    let mut interval = tokio::time::interval(replica.max_staleness / 4);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let fresh = match replica.store.read_heartbeat().await {
+            Ok(heartbeat) => heartbeat.is_some_and(|heartbeat| {
+                now_ms().saturating_sub(heartbeat) <= replica.max_staleness.as_millis() as u64
+            }),
+            Err(e) => {
+                tracing::error!(
+                    error = telemetry::error_message(&e),
+                    "Problem reading the heartbeat from the read replica"
+                );
+                false
+            }
+        };
+        if fresh != replica.fresh.swap(fresh, Ordering::Relaxed) {
+            if fresh {
+                tracing::info!("Reading from the read replica");
+            } else {
+                tracing::warn!("The read replica is behind, reading from the primary");
+            }
+        }
+
+        if let Err(e) = state.store.write_heartbeat(now_ms()).await {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem writing the replication heartbeat"
+            );
+        }
+    }
+}
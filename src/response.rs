@@ -0,0 +1,118 @@
+use actix_web::web::Bytes;
+use actix_web::HttpResponse;
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors::ApiError;
+use crate::models::{AppState, MemoryMessage};
+
+/// Messages serialized into each chunk of a streamed response.
+const STREAM_CHUNK_MESSAGES: usize = 100;
+
+/// Where the empty `messages` of a payload streamed by `streamed_read_response` are filled in.
+const MESSAGES_PLACEHOLDER: &[u8] = b"\"messages\":[]";
+
+#[derive(Serialize)]
+pub struct ResponseMeta<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<&'a str>,
+    pub now: u128,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, T: Serialize> {
+    data: T,
+    meta: ResponseMeta<'a>,
+}
+
+/// Builds the response for a successful read, wrapping it in a `{ data, meta }` envelope when
+/// `MOTORHEAD_RESPONSE_ENVELOPE` is enabled and returning the bare payload otherwise.
+pub fn read_response<T: Serialize>(
+    state: &AppState,
+    session_id: Option<&str>,
+    payload: T,
+) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    response.content_type("application/json");
+
+    if !state.response_envelope {
+        return response.json(payload);
+    }
+
+    response.json(Envelope {
+        data: payload,
+        meta: ResponseMeta {
+            session_id,
+            now: now(),
+        },
+    })
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+/// Like `read_response`, for a payload whose first field is `messages`, taken out of it
+/// and left empty: the messages are serialized as the body is sent instead of all at once,
+/// so large windows don't take a copy of their whole JSON in memory.
+pub fn streamed_read_response<T: Serialize>(
+    state: &AppState,
+    session_id: Option<&str>,
+    payload: T,
+    messages: Vec<MemoryMessage>,
+) -> HttpResponse {
+    let rest = if state.response_envelope {
+        serde_json::to_vec(&Envelope {
+            data: payload,
+            meta: ResponseMeta {
+                session_id,
+                now: now(),
+            },
+        })
+    } else {
+        serde_json::to_vec(&payload)
+    };
+    let rest = match rest {
+        Ok(rest) => rest,
+        Err(e) => return actix_web::Error::from(ApiError::internal(e)).error_response(),
+    };
+    // The placeholder is the first field serialized, before any content that could match it.
+    let Some(at) = rest
+        .windows(MESSAGES_PLACEHOLDER.len())
+        .position(|window| window == MESSAGES_PLACEHOLDER)
+    else {
+        return HttpResponse::Ok()
+            .content_type("application/json")
+            .body(rest);
+    };
+    let rest = Bytes::from(rest);
+    // Up to the opening bracket, then from the closing one.
+    let split = at + MESSAGES_PLACEHOLDER.len() - 1;
+    let head = rest.slice(..split);
+    let tail = rest.slice(split..);
+
+    let chunks = stream::iter(messages)
+        .chunks(STREAM_CHUNK_MESSAGES)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut bytes = Vec::new();
+            for (j, message) in chunk.iter().enumerate() {
+                if i > 0 || j > 0 {
+                    bytes.push(b',');
+                }
+                serde_json::to_writer(&mut bytes, message).map_err(ApiError::internal)?;
+            }
+            Ok::<_, actix_web::Error>(Bytes::from(bytes))
+        });
+    let body = stream::once(async move { Ok(head) })
+        .chain(chunks)
+        .chain(stream::once(async move { Ok(tail) }));
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .streaming(body)
+}
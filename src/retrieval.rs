@@ -0,0 +1,258 @@
+use actix_web::{post, web, Responder};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::embeddings::EmbeddingKind;
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{AppState, MemoryMessage, MotorheadError, RetrievalRequest, RetrievalResponse};
+use crate::response::read_response;
+use crate::store::MemoryStore;
+use crate::tasks::TaskGuard;
+use crate::telemetry;
+use crate::tenant::Tenant;
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 100;
+
+/// Embeds freshly appended messages and stores their vectors. Runs in the background after
+/// `post_memory`, or batched with other appends' by `run_embedding_batcher`, so searches
+/// right after an append may not see the newest messages yet.
+#[tracing::instrument(skip_all, fields(session_id = %session_id))]
+pub async fn index_messages(
+    session_id: String,
+    state: Arc<AppState>,
+    store: Arc<dyn MemoryStore>,
+    messages: Vec<MemoryMessage>,
+) -> Result<(), MotorheadError> {
+    let inputs = messages
+        .iter()
+        .map(|message| message.transcript_line(state.summarize_attachments))
+        .collect();
+
+    let vectors = state
+        .embedder
+        .embed(inputs, EmbeddingKind::Document)
+        .await?;
+    let entries = messages.into_iter().zip(vectors).collect();
+
+    store.add_vectors(&session_id, entries).await
+}
+
+struct PendingIndex {
+    session_id: String,
+    store: Arc<dyn MemoryStore>,
+    messages: Vec<MemoryMessage>,
+    /// Keeps the session unsettled until its messages are indexed.
+    _task_guard: TaskGuard,
+}
+
+/// Appended messages waiting to be embedded together by `run_embedding_batcher`, set with
+/// `MOTORHEAD_EMBEDDING_BATCH_WINDOW_MS`.
+pub struct EmbeddingBatcher {
+    /// Most inputs of an embedding call, within the provider's.
+    max_inputs: usize,
+    /// How long the first message queued waits for others.
+    window: Duration,
+    pending: Mutex<Vec<PendingIndex>>,
+    queued: Notify,
+}
+
+impl EmbeddingBatcher {
+    pub fn new(max_inputs: usize, window: Duration) -> Self {
+        EmbeddingBatcher {
+            max_inputs,
+            window,
+            pending: Mutex::new(Vec::new()),
+            queued: Notify::new(),
+        }
+    }
+
+    /// Queues freshly appended messages to be embedded with the next batch.
+    pub fn push(
+        &self,
+        session_id: String,
+        store: Arc<dyn MemoryStore>,
+        messages: Vec<MemoryMessage>,
+        task_guard: TaskGuard,
+    ) {
+        self.pending.lock().unwrap().push(PendingIndex {
+            session_id,
+            store,
+            messages,
+            _task_guard: task_guard,
+        });
+        self.queued.notify_one();
+    }
+
+    fn is_full(&self) -> bool {
+        let pending = self.pending.lock().unwrap();
+        pending
+            .iter()
+            .map(|index| index.messages.len())
+            .sum::<usize>()
+            >= self.max_inputs
+    }
+
+    /// The oldest appends, up to `max_inputs` messages unless the first alone has more.
+    fn take(&self) -> Vec<PendingIndex> {
+        let mut pending = self.pending.lock().unwrap();
+        let mut inputs = 0;
+        let count = pending
+            .iter()
+            .take_while(|index| {
+                let fits = inputs == 0 || inputs + index.messages.len() <= self.max_inputs;
+                inputs += index.messages.len();
+                fits
+            })
+            .count();
+        pending.drain(..count).collect()
+    }
+}
+
+/// Embeds the messages queued in the batcher with as few calls as it can, for as long as the
+/// server runs: the first message queued waits up to the window for others, unless enough to
+/// fill a call come first. Each session's vectors are then stored along with its messages.
+pub async fn run_embedding_batcher(state: Arc<AppState>) {
+    let Some(batcher) = &state.embedding_batcher else {
+        return;
+    };
+
+    loop {
+        batcher.queued.notified().await;
+        let _ = tokio::time::timeout(batcher.window, async {
+            while !batcher.is_full() {
+                batcher.queued.notified().await;
+            }
+        })
+        .await;
+
+        loop {
+            let batch = batcher.take();
+            if batch.is_empty() {
+                break;
+            }
+            if let Err(e) = index_batch(&state, batcher.max_inputs, batch).await {
+                tracing::error!(
+                    error = telemetry::error_message(&e),
+                    "Problem indexing messages"
+                );
+            }
+        }
+    }
+}
+
+/// Embeds the messages of several appends in one call, or of an append over `max_inputs` in
+/// as many as it takes, then stores each append's vectors.
+async fn index_batch(
+    state: &AppState,
+    max_inputs: usize,
+    batch: Vec<PendingIndex>,
+) -> Result<(), MotorheadError> {
+    let inputs: Vec<String> = batch
+        .iter()
+        .flat_map(|index| &index.messages)
+        .map(|message| message.transcript_line(state.summarize_attachments))
+        .collect();
+    let mut vectors = Vec::with_capacity(inputs.len());
+    for chunk in inputs.chunks(max_inputs) {
+        vectors.extend(
+            state
+                .embedder
+                .embed(chunk.to_vec(), EmbeddingKind::Document)
+                .await?,
+        );
+    }
+    let mut vectors = vectors.into_iter();
+
+    // The vectors come in the order of the inputs, so each append takes as many as it has
+    // messages, in turn.
+    for index in batch {
+        let entries = index.messages.into_iter().zip(vectors.by_ref()).collect();
+        if let Err(e) = index.store.add_vectors(&index.session_id, entries).await {
+            tracing::error!(
+                session_id = %index.session_id,
+                error = telemetry::error_message(&e),
+                "Problem storing vectors"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[post("/sessions/{session_id}/retrieval")]
+pub async fn run_retrieval(
+    session_id: web::Path<String>,
+    web::Json(request): web::Json<RetrievalRequest>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if !data.retrieval_enabled {
+        return Err(ApiError::new(ErrorCode::FeatureDisabled, "Retrieval is not enabled").into());
+    }
+
+    let limit = request.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let vector = data
+        .embedder
+        .embed(vec![request.text], EmbeddingKind::Query)
+        .await?
+        .pop()
+        .ok_or_else(|| ApiError::internal("No embedding returned"))?;
+
+    let results = tenant
+        .read_store(&data)
+        .search_vectors(&session_id, vector, limit)
+        .await?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        RetrievalResponse { results },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::InMemoryStore;
+    use crate::tasks::TaskTracker;
+
+    fn messages(count: usize) -> Vec<MemoryMessage> {
+        (0..count)
+            .map(|i| {
+                serde_json::from_value(
+                    serde_json::json!({ "role": "user", "content": i.to_string() }),
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batches_take_appends_up_to_the_size() {
+        let batcher = EmbeddingBatcher::new(5, Duration::from_millis(10));
+        let tasks = Arc::new(TaskTracker::default());
+        let store: Arc<dyn MemoryStore> = Arc::new(InMemoryStore::new());
+        for (session_id, count) in [("a", 2), ("b", 2), ("c", 2), ("d", 7)] {
+            let guard = TaskTracker::track(&tasks, session_id);
+            batcher.push(
+                session_id.to_string(),
+                Arc::clone(&store),
+                messages(count),
+                guard,
+            );
+        }
+        assert!(batcher.is_full());
+
+        let sessions = |batch: Vec<PendingIndex>| -> Vec<String> {
+            batch.into_iter().map(|index| index.session_id).collect()
+        };
+        assert_eq!(sessions(batcher.take()), ["a", "b"]);
+        assert_eq!(sessions(batcher.take()), ["c"]);
+        // Too many to share a call, and split across calls by `index_batch`.
+        assert_eq!(sessions(batcher.take()), ["d"]);
+        assert!(batcher.take().is_empty());
+        assert_eq!(tasks.total_pending(), 0);
+    }
+}
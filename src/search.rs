@@ -0,0 +1,236 @@
+use actix_web::{get, web, Responder};
+use regex::Regex;
+use std::sync::Arc;
+
+use crate::cold_storage;
+use crate::embeddings::EmbeddingKind;
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{
+    AppState, MessageSource, MotorheadError, SearchMatch, SearchMode, SearchQuery, SearchResponse,
+    SessionHits, UserSearchQuery, UserSearchResponse,
+};
+use crate::response::read_response;
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Messages read from the history at a time.
+const HISTORY_PAGE_SIZE: usize = 1000;
+
+enum Pattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn new(q: String, regex: bool) -> actix_web::Result<Self> {
+        if q.is_empty() {
+            return Err(ApiError::invalid_request("q must not be empty").into());
+        }
+        if !regex {
+            return Ok(Pattern::Substring(q));
+        }
+
+        Regex::new(&q)
+            .map(Pattern::Regex)
+            .map_err(|e| ApiError::invalid_request(format!("Invalid regex: {}", e)).into())
+    }
+
+    fn matches(&self, content: &str) -> bool {
+        match self {
+            Pattern::Substring(substring) => content.contains(substring.as_str()),
+            Pattern::Regex(regex) => regex.is_match(content),
+        }
+    }
+}
+
+/// Up to `limit` of the messages of the session matching `pattern`, oldest first, and
+/// whether there were more.
+async fn search_session(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    pattern: &Pattern,
+    limit: usize,
+) -> Result<(Vec<SearchMatch>, bool), MotorheadError> {
+    let mut matches = Vec::new();
+
+    let mut offset = 0;
+    loop {
+        let page =
+            cold_storage::get_history(state, store, session_id, offset, HISTORY_PAGE_SIZE).await?;
+        let page_len = page.len();
+
+        for (i, message) in page.into_iter().enumerate() {
+            if !pattern.matches(&message.content) {
+                continue;
+            }
+            if matches.len() == limit {
+                return Ok((matches, true));
+            }
+            matches.push(SearchMatch {
+                source: MessageSource::History,
+                index: offset + i,
+                message,
+            });
+        }
+
+        if page_len < HISTORY_PAGE_SIZE {
+            break;
+        }
+        offset += page_len;
+    }
+
+    let window = store.get_messages(session_id, 0, -1).await?;
+    // Oldest first, keeping the newest-first indices.
+    for (index, message) in window.into_iter().enumerate().rev() {
+        if !pattern.matches(&message.content) {
+            continue;
+        }
+        if matches.len() == limit {
+            return Ok((matches, true));
+        }
+        matches.push(SearchMatch {
+            source: MessageSource::Messages,
+            index,
+            message,
+        });
+    }
+
+    Ok((matches, false))
+}
+
+/// Finds the session's messages whose content contains `q`, or matches it with `regex`,
+/// looking through the archived history as well as the window.
+#[get("/sessions/{session_id}/memory/search")]
+pub async fn search_memory(
+    session_id: web::Path<String>,
+    query: web::Query<SearchQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let query = query.into_inner();
+    let pattern = Pattern::new(query.q, query.regex)?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let (matches, truncated) = search_session(
+        &data,
+        tenant.read_store(&data).as_ref(),
+        &session_id,
+        &pattern,
+        limit,
+    )
+    .await?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        SearchResponse { matches, truncated },
+    ))
+}
+
+/// Searches every session whose metadata has the user's `user_id`, by keyword like
+/// `search_memory` or semantically like `run_retrieval`, and groups the hits by session.
+#[get("/users/{user_id}/search")]
+pub async fn search_user(
+    user_id: web::Path<String>,
+    query: web::Query<UserSearchQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let store = tenant.read_store(&data);
+
+    let response = match query.mode {
+        SearchMode::Keyword => {
+            let pattern = Pattern::new(query.q, query.regex)?;
+            let session_ids = store.user_sessions(&user_id).await?;
+
+            let mut sessions = Vec::new();
+            let mut found = 0;
+            let mut truncated = false;
+            for session_id in session_ids {
+                let (matches, more) =
+                    search_session(&data, store.as_ref(), &session_id, &pattern, limit - found)
+                        .await?;
+                found += matches.len();
+                if !matches.is_empty() {
+                    sessions.push(SessionHits {
+                        session_id,
+                        matches,
+                        results: Vec::new(),
+                    });
+                }
+                // Once `limit` is reached, the next sessions are only searched for one more
+                // match, to tell whether there are more.
+                if more {
+                    truncated = true;
+                    break;
+                }
+            }
+
+            UserSearchResponse {
+                sessions,
+                truncated,
+            }
+        }
+        SearchMode::Semantic => {
+            if !data.retrieval_enabled {
+                return Err(
+                    ApiError::new(ErrorCode::FeatureDisabled, "Retrieval is not enabled").into(),
+                );
+            }
+            if query.q.is_empty() {
+                return Err(ApiError::invalid_request("q must not be empty").into());
+            }
+            let session_ids = store.user_sessions(&user_id).await?;
+            let vector = data
+                .embedder
+                .embed(vec![query.q], EmbeddingKind::Query)
+                .await?
+                .pop()
+                .ok_or_else(|| ApiError::internal("No embedding returned"))?;
+
+            let mut sessions = Vec::new();
+            for session_id in session_ids {
+                let results = store
+                    .search_vectors(&session_id, vector.clone(), limit)
+                    .await?;
+                sessions.extend(
+                    results
+                        .into_iter()
+                        .map(|result| (session_id.clone(), result)),
+                );
+            }
+            sessions.sort_by(|a, b| a.1.dist.total_cmp(&b.1.dist));
+            let truncated = sessions.len() > limit;
+            sessions.truncate(limit);
+
+            // Grouped in the order of each session's closest result.
+            let mut grouped: Vec<SessionHits> = Vec::new();
+            for (session_id, result) in sessions {
+                match grouped
+                    .iter_mut()
+                    .find(|hits| hits.session_id == session_id)
+                {
+                    Some(hits) => hits.results.push(result),
+                    None => grouped.push(SessionHits {
+                        session_id,
+                        matches: Vec::new(),
+                        results: vec![result],
+                    }),
+                }
+            }
+
+            UserSearchResponse {
+                sessions: grouped,
+                truncated,
+            }
+        }
+    };
+
+    Ok(read_response(&data, None, response))
+}
@@ -0,0 +1,193 @@
+use actix_web::{delete, get, put, web, HttpResponse, Responder};
+use std::sync::Arc;
+
+use crate::compactor::CompactionStrategy;
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{
+    AckResponse, AppState, MotorheadError, SessionConfig, SummaryOptions, SystemPrompt,
+};
+use crate::response::read_response;
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+/// The session's window size: its own if set, `MOTORHEAD_MAX_WINDOW_SIZE` otherwise.
+pub async fn window_size(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+) -> Result<i64, MotorheadError> {
+    let config = store.get_session_config(session_id).await?;
+    Ok(config
+        .and_then(|config| config.window_size)
+        .unwrap_or(state.runtime().window_size))
+}
+
+/// What the session's compactions do: its own strategy if set, `MOTORHEAD_COMPACTION_STRATEGY`
+/// otherwise.
+pub async fn compaction_strategy(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+) -> Result<CompactionStrategy, MotorheadError> {
+    let config = store.get_session_config(session_id).await?;
+    Ok(config
+        .and_then(|config| config.compaction_strategy)
+        .unwrap_or(state.compaction_strategy))
+}
+
+/// `options` with the session's summary language and system prompt, if it has them.
+pub async fn session_summary_options(
+    store: &dyn MemoryStore,
+    session_id: &str,
+    options: &SummaryOptions,
+) -> Result<SummaryOptions, MotorheadError> {
+    let config = store
+        .get_session_config(session_id)
+        .await?
+        .unwrap_or_default();
+    let mut options = options.clone();
+    if let Some(language) = config.summary_language {
+        options.language = Some(language);
+    }
+    options.system_prompt = config.system_prompt;
+    Ok(options)
+}
+
+#[get("/sessions/{session_id}/config")]
+pub async fn get_session_config(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let config = tenant
+        .store(&data)
+        .get_session_config(&session_id)
+        .await?
+        .unwrap_or_default();
+
+    Ok(read_response(&data, Some(&session_id), config))
+}
+
+#[put("/sessions/{session_id}/config")]
+pub async fn put_session_config(
+    session_id: web::Path<String>,
+    web::Json(config): web::Json<SessionConfig>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if config
+        .window_size
+        .is_some_and(|window_size| window_size < 1)
+    {
+        return Err(ApiError::invalid_request("window_size must be at least 1").into());
+    }
+    if config
+        .summary_language
+        .as_ref()
+        .is_some_and(|language| language.trim().is_empty())
+    {
+        return Err(ApiError::invalid_request("summary_language must not be empty").into());
+    }
+    if config
+        .system_prompt
+        .as_ref()
+        .is_some_and(|prompt| prompt.trim().is_empty())
+    {
+        return Err(ApiError::invalid_request("system_prompt must not be empty").into());
+    }
+    if config.compaction_strategy == Some(CompactionStrategy::Archive) && !data.retrieval_enabled {
+        return Err(ApiError::new(
+            ErrorCode::FeatureDisabled,
+            "The archive compaction strategy needs retrieval to be enabled",
+        )
+        .into());
+    }
+    if let Some(url) = &config.compaction_callback_url {
+        let valid = reqwest::Url::parse(url)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+        if !valid {
+            return Err(ApiError::invalid_request(
+                "compaction_callback_url must be an http(s) URL",
+            )
+            .into());
+        }
+    }
+
+    tenant
+        .store(&data)
+        .set_session_config(&session_id, &config)
+        .await?;
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+#[get("/sessions/{session_id}/system")]
+pub async fn get_system_prompt(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let prompt = tenant
+        .store(&data)
+        .get_session_config(&session_id)
+        .await?
+        .and_then(|config| config.system_prompt)
+        .ok_or_else(|| ApiError::not_found("Session has no system prompt"))?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        SystemPrompt { prompt },
+    ))
+}
+
+/// Sets the session's system prompt, leaving the rest of its config as is.
+#[put("/sessions/{session_id}/system")]
+pub async fn put_system_prompt(
+    session_id: web::Path<String>,
+    web::Json(SystemPrompt { prompt }): web::Json<SystemPrompt>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if prompt.trim().is_empty() {
+        return Err(ApiError::invalid_request("prompt must not be empty").into());
+    }
+    set_system_prompt(&tenant, &data, &session_id, Some(prompt)).await?;
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+#[delete("/sessions/{session_id}/system")]
+pub async fn delete_system_prompt(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    set_system_prompt(&tenant, &data, &session_id, None).await?;
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+async fn set_system_prompt(
+    tenant: &Tenant,
+    state: &AppState,
+    session_id: &str,
+    prompt: Option<String>,
+) -> Result<(), MotorheadError> {
+    let store = tenant.store(state);
+    let mut config = store
+        .get_session_config(session_id)
+        .await?
+        .unwrap_or_default();
+    config.system_prompt = prompt;
+    store.set_session_config(session_id, &config).await
+}
@@ -0,0 +1,194 @@
+use actix_web::{delete, get, web, Responder};
+use futures_util::future::try_join_all;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::delete_intent::confirmation_needed;
+use crate::errors::{ApiError, ErrorCode};
+use crate::memory::delete_session;
+use crate::models::{
+    AppState, BulkDeleteQuery, BulkDeleteResponse, MotorheadError, SessionListQuery,
+    SessionListResponse, SessionSummary,
+};
+use crate::response::read_response;
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Sessions listed at a time while looking for those to delete.
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// Sessions deleted at once by bulk deletes.
+const DELETE_BATCH_SIZE: usize = 50;
+
+#[get("/sessions")]
+pub async fn list_sessions(
+    query: web::Query<SessionListQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let page = query.page.unwrap_or(0);
+    let page_size = query
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    // The stores take offsets as signed integers, so the last entry read must fit in one.
+    let offset = page
+        .checked_mul(page_size)
+        .filter(|offset| offset.saturating_add(page_size) <= i64::MAX as usize)
+        .ok_or_else(|| ApiError::invalid_request("page is too large"))?;
+
+    // Fetch one extra entry to know whether there is a next page.
+    let mut entries = tenant
+        .store(&data)
+        .list_sessions(offset, page_size + 1)
+        .await?;
+
+    let next_page = (entries.len() > page_size).then_some(page + 1);
+    entries.truncate(page_size);
+
+    let store = tenant.store(&data);
+    let titles = if data.session_title_after_messages.is_some() {
+        try_join_all(entries.iter().map(|(session_id, _)| async {
+            let metadata = store.get_metadata(session_id).await?;
+            Ok::<_, MotorheadError>(
+                metadata.and_then(|metadata| metadata.get("title")?.as_str().map(str::to_string)),
+            )
+        }))
+        .await?
+    } else {
+        vec![None; entries.len()]
+    };
+
+    let sessions = entries
+        .into_iter()
+        .zip(titles)
+        .map(|((session_id, last_activity), title)| SessionSummary {
+            session_id,
+            last_activity,
+            title,
+        })
+        .collect();
+
+    let response = SessionListResponse {
+        sessions,
+        next_page,
+    };
+
+    Ok(read_response(&data, None, response))
+}
+
+/// Whether `metadata` has `value` as its `field`, a string or the JSON of another type.
+fn metadata_matches(metadata: Option<&Value>, field: &str, value: &str) -> bool {
+    match metadata.and_then(|metadata| metadata.get(field)) {
+        Some(Value::String(found)) => found == value,
+        Some(found) => serde_json::from_str::<Value>(value).is_ok_and(|value| *found == value),
+        None => false,
+    }
+}
+
+/// The listed sessions that match `query`, user ids going through the store's index of them.
+async fn matching_sessions(
+    store: &dyn MemoryStore,
+    query: &BulkDeleteQuery,
+) -> Result<Vec<String>, MotorheadError> {
+    let prefix = query.prefix.as_deref().unwrap_or_default();
+    let metadata = query
+        .metadata_field
+        .as_deref()
+        .zip(query.metadata_value.as_deref());
+
+    if let Some(("user_id", user_id)) = metadata {
+        let mut sessions = store.user_sessions(user_id).await?;
+        sessions.retain(|session_id| session_id.starts_with(prefix));
+        return Ok(sessions);
+    }
+
+    let mut sessions = Vec::new();
+    let mut offset = 0;
+    loop {
+        let page = store.list_sessions(offset, LIST_PAGE_SIZE).await?;
+        let page_len = page.len();
+        for (session_id, _) in page {
+            if !session_id.starts_with(prefix) {
+                continue;
+            }
+            if let Some((field, value)) = metadata {
+                let session_metadata = store.get_metadata(&session_id).await?;
+                if !metadata_matches(session_metadata.as_ref(), field, value) {
+                    continue;
+                }
+            }
+            sessions.push(session_id);
+        }
+        if page_len < LIST_PAGE_SIZE {
+            return Ok(sessions);
+        }
+        offset += page_len;
+    }
+}
+
+/// Deletes the sessions of the namespace whose id starts with `prefix` and/or whose metadata
+/// has `metadata_value` as its `metadata_field`, e.g. every session of a user asking for their
+/// data to be erased. The matching sessions are listed first, then deleted in batches; those
+/// deleted before a failing batch stay deleted, so the request can just be sent again. Unless
+/// forced, nothing is deleted if any of them would need a delete intent's confirmation.
+#[delete("/sessions")]
+pub async fn delete_sessions(
+    query: web::Query<BulkDeleteQuery>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    if query.metadata_field.is_some() != query.metadata_value.is_some() {
+        return Err(
+            ApiError::invalid_request("metadata_field and metadata_value go together").into(),
+        );
+    }
+    if query.prefix.as_deref().unwrap_or_default().is_empty() && query.metadata_field.is_none() {
+        return Err(ApiError::invalid_request("A prefix or metadata filter is required").into());
+    }
+
+    let store = tenant.store(&data);
+    let sessions = matching_sessions(store.as_ref(), &query).await?;
+    let matched = sessions.len();
+    let mut deleted = 0;
+    if !query.dry_run && !query.force && data.delete_confirmation_messages.is_some() {
+        // One delete per session would take a token each, so they're all refused together.
+        let mut large = 0;
+        for batch in sessions.chunks(DELETE_BATCH_SIZE) {
+            let sizes = batch
+                .iter()
+                .map(|session_id| confirmation_needed(&data, store.as_ref(), session_id));
+            large += try_join_all(sizes).await?.into_iter().flatten().count();
+        }
+        if large > 0 {
+            return Err(ApiError::new(
+                ErrorCode::ConfirmationRequired,
+                "Some of the sessions are large enough to need a confirmation, given with force=true",
+            )
+            .with("sessions", large)
+            .into());
+        }
+    }
+    if !query.dry_run {
+        for batch in sessions.chunks(DELETE_BATCH_SIZE) {
+            let deletes = batch
+                .iter()
+                .map(|session_id| delete_session(&data, &tenant, session_id));
+            if let Err(e) = try_join_all(deletes).await {
+                tracing::warn!(matched, deleted, "Bulk delete stopped");
+                return Err(e.into());
+            }
+            deleted += batch.len();
+        }
+        tracing::info!(deleted, "Sessions deleted in bulk");
+    }
+
+    Ok(read_response(
+        &data,
+        None,
+        BulkDeleteResponse { matched, deleted },
+    ))
+}
@@ -0,0 +1,134 @@
+use futures_util::StreamExt;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::models::{AppState, MotorheadError, SummaryOptions};
+use crate::telemetry;
+
+/// How long to wait before subscribing again after losing the subscription.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// The summarization settings from the environment. With `MOTORHEAD_SHARED_CONFIG_ENABLED` the
+/// ones set in the shared hash take precedence, and those missing from it fall back to these.
+pub struct SharedConfig {
+    pub summary_prompt: String,
+    pub summary_options: SummaryOptions,
+}
+
+/// The hash fields of the summary options, `None` to remove those left unset.
+pub fn summary_fields(options: &SummaryOptions) -> Vec<(&'static str, Option<String>)> {
+    vec![
+        ("model", options.model.clone()),
+        ("temperature", options.temperature.map(|t| t.to_string())),
+        ("max_tokens", options.max_tokens.map(|t| t.to_string())),
+        ("max_messages", options.max_messages.map(|m| m.to_string())),
+        ("language", options.language.clone()),
+    ]
+}
+
+/// The value of the field, or `None` when it's missing or doesn't parse.
+fn parse<T: FromStr>(fields: &BTreeMap<String, String>, field: &str) -> Option<T> {
+    let value = fields.get(field)?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        tracing::warn!(
+            field,
+            value,
+            "Ignoring an invalid value of the shared config"
+        );
+    }
+    parsed
+}
+
+/// Reads the shared settings again and applies them over the environment's.
+pub async fn reload(state: &AppState) -> Result<(), MotorheadError> {
+    let Some(defaults) = &state.shared_config else {
+        return Ok(());
+    };
+    let fields = state.store.get_shared_config().await?;
+
+    let summary_prompt = fields
+        .get("summary_prompt")
+        .filter(|prompt| {
+            let valid = prompt.contains("{messages}");
+            if !valid {
+                tracing::warn!(
+                    "Ignoring a shared summary_prompt without the {{messages}} placeholder"
+                );
+            }
+            valid
+        })
+        .cloned()
+        .unwrap_or_else(|| defaults.summary_prompt.clone());
+    let summary_options = SummaryOptions {
+        model: fields
+            .get("model")
+            .cloned()
+            .or_else(|| defaults.summary_options.model.clone()),
+        temperature: parse(&fields, "temperature").or(defaults.summary_options.temperature),
+        max_tokens: parse(&fields, "max_tokens").or(defaults.summary_options.max_tokens),
+        max_messages: parse(&fields, "max_messages").or(defaults.summary_options.max_messages),
+        language: fields
+            .get("language")
+            .cloned()
+            .or_else(|| defaults.summary_options.language.clone()),
+        system_prompt: None,
+    };
+
+    let mut runtime = state.runtime.write().unwrap();
+    runtime.summary_prompt = summary_prompt;
+    runtime.summary_options = summary_options;
+    tracing::info!(fields = fields.len(), "Shared config applied");
+    Ok(())
+}
+
+/// Writes changed settings to the shared hash and applies it, so every instance uses them.
+/// `false`, leaving it to the caller to apply them, without `MOTORHEAD_SHARED_CONFIG_ENABLED`.
+pub async fn update(
+    state: &AppState,
+    fields: &[(&str, Option<String>)],
+) -> Result<bool, MotorheadError> {
+    if state.shared_config.is_none() {
+        return Ok(false);
+    }
+    state.store.set_shared_config(fields).await?;
+    reload(state).await?;
+    Ok(true)
+}
+
+/// Applies the shared settings each time they change, for as long as the server runs. They're
+/// read again on every subscription, as changes made while it was lost went unnoticed.
+pub async fn run_shared_config_listener(state: Arc<AppState>) {
+    if state.shared_config.is_none() {
+        return;
+    }
+    loop {
+        match state.store.shared_config_changes().await {
+            Ok(mut changes) => {
+                loop {
+                    if let Err(e) = reload(&state).await {
+                        tracing::error!(
+                            error = telemetry::error_message(&e),
+                            "Problem reloading the shared config"
+                        );
+                    }
+                    if changes.next().await.is_none() {
+                        break;
+                    }
+                }
+                tracing::warn!("Lost the subscription to shared config changes, resubscribing");
+            }
+            Err(e @ MotorheadError::Unsupported(_)) => {
+                tracing::warn!("{}, shared config changes won't be applied", e);
+                return;
+            }
+            Err(e) => tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem listening for shared config changes"
+            ),
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
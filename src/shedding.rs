@@ -0,0 +1,201 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::metrics;
+use crate::models::{AppState, MemoryResponse};
+use crate::tenant::Tenant;
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+/// Pings slower than this count as failed.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many of the latest pings the latency and error rate are measured over.
+const PROBE_WINDOW: usize = 10;
+const MAX_CACHED_SESSIONS: usize = 10_000;
+
+/// The routes still served while shedding load: the memory itself, and the probes and
+/// metrics telling how the instance is doing.
+const ESSENTIAL_ROUTES: &[&str] = &[
+    "/sessions/{session_id}/memory",
+    "/",
+    "/healthz",
+    "/readyz",
+    "/metrics",
+];
+
+/// Switches the instance to a degraded mode while the store is slow or failing, as measured by
+/// pinging it every second: reads of the memory are served from a short-lived cache of the
+/// last ones, and every route but the essential ones is refused with `LOAD_SHED`, so that
+/// appends and reads get what the store can still take. Set with `MOTORHEAD_SHED_LATENCY_MS`
+/// or `MOTORHEAD_SHED_ERROR_RATE`. Each instance sheds on its own.
+pub struct LoadShedder {
+    latency_threshold: Option<Duration>,
+    error_rate_threshold: Option<f64>,
+    cache_ttl: Duration,
+    degraded: AtomicBool,
+    /// Requests shed since the instance last became degraded.
+    shed: AtomicU64,
+    /// How long each of the latest pings took, and whether it succeeded.
+    probes: Mutex<VecDeque<(Duration, bool)>>,
+    /// The last full read of each session's memory, by `Tenant::scope`, and when it was made.
+    cache: Mutex<HashMap<String, (Instant, MemoryResponse)>>,
+}
+
+impl LoadShedder {
+    pub fn new(
+        latency_threshold: Option<Duration>,
+        error_rate_threshold: Option<f64>,
+        cache_ttl: Duration,
+    ) -> Self {
+        LoadShedder {
+            latency_threshold,
+            error_rate_threshold,
+            cache_ttl,
+            degraded: AtomicBool::new(false),
+            shed: AtomicU64::new(0),
+            probes: Mutex::new(VecDeque::with_capacity(PROBE_WINDOW)),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    fn record_probe(&self, took: Duration, ok: bool) {
+        let (latency, error_rate) = {
+            let mut probes = self.probes.lock().unwrap();
+            if probes.len() == PROBE_WINDOW {
+                probes.pop_front();
+            }
+            probes.push_back((took, ok));
+            let count = probes.len() as u32;
+            (
+                probes.iter().map(|(took, _)| *took).sum::<Duration>() / count,
+                probes.iter().filter(|(_, ok)| !ok).count() as f64 / count as f64,
+            )
+        };
+        let overloaded = self
+            .latency_threshold
+            .is_some_and(|threshold| latency > threshold)
+            || self
+                .error_rate_threshold
+                .is_some_and(|threshold| error_rate > threshold);
+
+        if overloaded != self.degraded.swap(overloaded, Ordering::Relaxed) {
+            metrics::LOAD_SHEDDING.set(overloaded as i64);
+            let latency_ms = latency.as_millis() as u64;
+            if overloaded {
+                self.shed.store(0, Ordering::Relaxed);
+                tracing::warn!(
+                    latency_ms,
+                    error_rate,
+                    "The store is overloaded, shedding load"
+                );
+            } else {
+                tracing::info!(
+                    latency_ms,
+                    error_rate,
+                    shed = self.shed.load(Ordering::Relaxed),
+                    "The store recovered, no longer shedding load"
+                );
+            }
+        }
+    }
+
+    fn count_shed(&self, outcome: &str) {
+        self.shed.fetch_add(1, Ordering::Relaxed);
+        metrics::SHED_REQUESTS.with_label_values(&[outcome]).inc();
+    }
+
+    /// The session's cached memory and how old it is, while degraded and it's fresh.
+    pub fn cached(&self, tenant: &Tenant, session_id: &str) -> Option<(MemoryResponse, Duration)> {
+        if !self.is_degraded() {
+            return None;
+        }
+        let cache = self.cache.lock().unwrap();
+        let (cached_at, response) = cache.get(&tenant.scope(session_id))?;
+        let age = cached_at.elapsed();
+        if age > self.cache_ttl {
+            return None;
+        }
+        self.count_shed("cache");
+        Some((response.clone(), age))
+    }
+
+    /// Keeps a full read of the session's memory, for when the instance becomes degraded.
+    pub fn remember(&self, tenant: &Tenant, session_id: &str, response: &MemoryResponse) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED_SESSIONS {
+            cache.retain(|_, (cached_at, _)| cached_at.elapsed() <= self.cache_ttl);
+            if cache.len() >= MAX_CACHED_SESSIONS {
+                return;
+            }
+        }
+        cache.insert(tenant.scope(session_id), (Instant::now(), response.clone()));
+    }
+
+    /// Drops the session's cached memory once it's written to.
+    pub fn forget(&self, tenant: &Tenant, session_id: &str) {
+        self.cache.lock().unwrap().remove(&tenant.scope(session_id));
+    }
+}
+
+/// Drops the session's cached memory, if load shedding is enabled.
+pub fn forget(state: &AppState, tenant: &Tenant, session_id: &str) {
+    if let Some(shedder) = &state.load_shedder {
+        shedder.forget(tenant, session_id);
+    }
+}
+
+/// Pings the store every second, for as long as the server runs, switching the degraded mode
+/// on and off.
+pub async fn run_load_monitor(state: Arc<AppState>) {
+    let Some(shedder) = &state.load_shedder else {
+        return;
+    };
+    let mut interval = tokio::time::interval(PROBE_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let started = Instant::now();
+        let ok = matches!(
+            tokio::time::timeout(PROBE_TIMEOUT, state.store.ping()).await,
+            Ok(Ok(()))
+        );
+        shedder.record_probe(started.elapsed(), ok);
+    }
+}
+
+/// Refuses the requests to non-essential routes with a `503` while degraded.
+pub async fn shed_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let shedder = req
+        .app_data::<web::Data<Arc<AppState>>>()
+        .and_then(|state| state.load_shedder.as_ref())
+        .filter(|shedder| shedder.is_degraded());
+    if let Some(shedder) = shedder {
+        let pattern = req.match_pattern();
+        if !pattern.is_some_and(|pattern| ESSENTIAL_ROUTES.contains(&pattern.as_str())) {
+            shedder.count_shed("rejected");
+            tracing::debug!(path = req.path(), "Shedding request");
+            return Err(ApiError::new(
+                ErrorCode::LoadShed,
+                "The server is shedding load, only appends and reads of the memory are served",
+            )
+            .into());
+        }
+    }
+
+    next.call(req).await
+}
@@ -0,0 +1,36 @@
+use actix_web::dev::ServerHandle;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::tasks::TaskTracker;
+
+/// On SIGTERM or SIGINT, stops accepting connections and gives the background tasks still
+/// running (compactions, indexing) up to `grace_period` to finish before stopping the server.
+/// Stopping it any earlier would cancel them, as they run on the server's workers.
+pub async fn shutdown_on_signal(
+    server: ServerHandle,
+    tasks: Arc<TaskTracker>,
+    grace_period: Duration,
+) {
+    let mut terminate = signal(SignalKind::terminate()).expect("Could not listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+
+    tracing::info!(
+        grace_period_ms = grace_period.as_millis() as u64,
+        pending_tasks = tasks.total_pending(),
+        "Shutting down, waiting for background tasks"
+    );
+    server.pause().await;
+    if !tasks.wait_idle(grace_period).await {
+        tracing::warn!(
+            pending_tasks = tasks.total_pending(),
+            "Stopping with background tasks still running"
+        );
+    }
+
+    server.stop(true).await;
+}
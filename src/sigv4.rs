@@ -0,0 +1,99 @@
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+/// Access keys for AWS, or for the S3-compatible APIs that take the same signatures.
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl Credentials {
+    /// From `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`, for `feature` to panic about.
+    pub fn from_env(feature: &str) -> Self {
+        let var = |name: &str| {
+            std::env::var(name).unwrap_or_else(|_| panic!("${} is required for {}", name, feature))
+        };
+        Credentials {
+            access_key_id: var("AWS_ACCESS_KEY_ID"),
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY"),
+        }
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The headers signing a request to `url` with AWS Signature Version 4, for `service` in
+/// `region`. Only the host, payload hash and date are signed, so other headers can be added
+/// as is. `url` has no query string.
+pub fn sign(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    url: &reqwest::Url,
+    body: &[u8],
+) -> Result<[(&'static str, String); 3], String> {
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(format!("{} has no host", url)),
+    };
+
+    let now = OffsetDateTime::now_utc();
+    let date = format!("{:04}{:02}{:02}", now.year(), now.month() as u8, now.day());
+    let timestamp = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+    let payload_hash = hex(&Sha256::digest(body));
+    let canonical_request = format!(
+        "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n\
+         host;x-amz-content-sha256;x-amz-date\n{}",
+        method,
+        url.path(),
+        host,
+        payload_hash,
+        timestamp,
+        payload_hash
+    );
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        timestamp,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let signing_key = [region, service, "aws4_request"].iter().fold(
+        hmac_sha256(
+            format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+            &date,
+        ),
+        |key, part| hmac_sha256(&key, part),
+    );
+    let signature = hex(&hmac_sha256(&signing_key, &string_to_sign));
+
+    Ok([
+        ("x-amz-content-sha256", payload_hash),
+        ("x-amz-date", timestamp),
+        (
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, \
+                 SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                credentials.access_key_id, scope, signature
+            ),
+        ),
+    ])
+}
@@ -0,0 +1,275 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use flate2::write::{GzDecoder, GzEncoder};
+use flate2::Compression;
+use futures_util::stream::{self, StreamExt};
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::archive::{replace_session, snapshot};
+use crate::config::check_admin;
+use crate::errors::{ApiError, ErrorCode};
+use crate::memory::summary_options;
+use crate::models::{AppState, MotorheadError, RestoreEntry, RestoreResponse, SnapshotEntry};
+use crate::store::MemoryStore;
+use crate::tenant::Tenant;
+
+/// Sessions listed at a time while taking a snapshot.
+const LIST_PAGE_SIZE: usize = 1000;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+async fn snapshot_entry(
+    store: &dyn MemoryStore,
+    session_id: &str,
+) -> Result<SnapshotEntry, MotorheadError> {
+    let (session, messages) = snapshot(store, session_id).await?;
+    let config = store.get_session_config(session_id).await?;
+    let pinned = store.get_pinned(session_id).await?;
+    Ok(SnapshotEntry {
+        session,
+        config,
+        pinned,
+        messages,
+    })
+}
+
+/// Streams every session of the namespace (the default one, or the `X-Tenant-Id` one) as
+/// gzipped NDJSON, a session per line with its messages, summaries, metadata, entities,
+/// config and pinned messages, in a format that doesn't depend on the backend. Archived
+/// history isn't included. Sessions are read one at a time, so the snapshot isn't taken at
+/// a single point in time.
+#[get("/admin/snapshot")]
+pub async fn get_snapshot(
+    req: HttpRequest,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+    let store = tenant.store(&data);
+
+    let mut session_ids = Vec::new();
+    loop {
+        let page = store
+            .list_sessions(session_ids.len(), LIST_PAGE_SIZE)
+            .await?;
+        let page_len = page.len();
+        session_ids.extend(page.into_iter().map(|(session_id, _)| session_id));
+        if page_len < LIST_PAGE_SIZE {
+            break;
+        }
+    }
+
+    Ok(snapshot_response(
+        store,
+        session_ids,
+        "motorhead-snapshot.ndjson.gz",
+    ))
+}
+
+/// Every session of the user (those whose metadata has it as `user_id`) in the format of
+/// `GET /admin/snapshot`, to answer their requests for their data in one download.
+#[get("/users/{user_id}/export")]
+pub async fn export_user(
+    user_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let store = tenant.store(&data);
+    let session_ids = store.user_sessions(&user_id).await?;
+    if session_ids.is_empty() {
+        return Err(ApiError::not_found("User has no sessions").into());
+    }
+
+    Ok(snapshot_response(
+        store,
+        session_ids,
+        "motorhead-user-export.ndjson.gz",
+    ))
+}
+
+/// Streams the sessions as gzipped NDJSON, reading them one at a time.
+fn snapshot_response(
+    store: Arc<dyn MemoryStore>,
+    session_ids: Vec<String>,
+    filename: &str,
+) -> HttpResponse {
+    // A failure past the first session can only cut the stream short, which leaves the
+    // gzip stream unterminated for the client to notice.
+    let encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let body = stream::unfold(Some((session_ids.into_iter(), encoder)), move |state| {
+        let store = Arc::clone(&store);
+        async move {
+            let (mut session_ids, mut encoder) = state?;
+            loop {
+                let Some(session_id) = session_ids.next() else {
+                    let chunk = encoder.finish().map_err(ApiError::internal);
+                    return Some((chunk.map(Bytes::from).map_err(Into::into), None));
+                };
+                let line = match snapshot_entry(store.as_ref(), &session_id).await {
+                    Ok(entry) => serde_json::to_vec(&entry).map_err(ApiError::internal),
+                    Err(e) => return Some((Err(e.into()), None)),
+                };
+                let written = line.and_then(|line| {
+                    encoder
+                        .write_all(&line)
+                        .and_then(|()| encoder.write_all(b"\n"))
+                        .map_err(ApiError::internal)
+                });
+                if let Err(e) = written {
+                    return Some((Err(e.into()), None));
+                }
+                // The encoder only outputs once it has enough input to compress.
+                let chunk = std::mem::take(encoder.get_mut());
+                if !chunk.is_empty() {
+                    return Some((Ok(Bytes::from(chunk)), Some((session_ids, encoder))));
+                }
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/gzip")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename.to_string())],
+        })
+        .streaming::<_, actix_web::Error>(body)
+}
+
+/// The decompressed lines of a restore body, gzipped or not, as its chunks come in.
+struct RestoreLines {
+    decoder: Option<GzDecoder<Vec<u8>>>,
+    buffer: Vec<u8>,
+    started: bool,
+}
+
+impl RestoreLines {
+    fn push(&mut self, chunk: &[u8]) -> actix_web::Result<()> {
+        if !self.started {
+            self.started = true;
+            if chunk.starts_with(&GZIP_MAGIC) {
+                self.decoder = Some(GzDecoder::new(Vec::new()));
+            }
+        }
+        match &mut self.decoder {
+            Some(decoder) => {
+                decoder.write_all(chunk).map_err(invalid_gzip)?;
+                self.buffer.append(decoder.get_mut());
+            }
+            None => self.buffer.extend_from_slice(chunk),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> actix_web::Result<()> {
+        if let Some(decoder) = self.decoder.take() {
+            let rest = decoder.finish().map_err(invalid_gzip)?;
+            self.buffer.extend_from_slice(&rest);
+        }
+        // The last line doesn't need a newline.
+        if !self.buffer.is_empty() && !self.buffer.ends_with(b"\n") {
+            self.buffer.push(b'\n');
+        }
+        Ok(())
+    }
+
+    fn next_line(&mut self) -> Option<Vec<u8>> {
+        let end = self.buffer.iter().position(|byte| *byte == b'\n')?;
+        let mut line: Vec<u8> = self.buffer.drain(..=end).collect();
+        line.pop();
+        Some(line)
+    }
+}
+
+fn invalid_gzip(e: std::io::Error) -> actix_web::Error {
+    ApiError::invalid_request(format!("Invalid gzip: {}", e)).into()
+}
+
+/// Loads a snapshot from `GET /admin/snapshot` into the namespace, replacing the sessions
+/// it has like `POST /sessions/{session_id}/import` does and leaving the others alone. The
+/// snapshot can come from another backend. Sessions are stored as their lines are read, so
+/// those before a failing one stay restored, and restoring again is safe.
+#[post("/admin/restore")]
+pub async fn post_restore(
+    req: HttpRequest,
+    mut payload: web::Payload,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+    let summary = summary_options(&req, &data)?;
+
+    let mut lines = RestoreLines {
+        decoder: None,
+        buffer: Vec::new(),
+        started: false,
+    };
+    let mut line_number = 0;
+    let mut sessions = 0;
+    let mut done = false;
+    while !done {
+        match payload.next().await {
+            Some(chunk) => lines.push(&chunk?)?,
+            None => {
+                lines.finish()?;
+                done = true;
+            }
+        }
+
+        while let Some(line) = lines.next_line() {
+            line_number += 1;
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let entry: RestoreEntry = serde_json::from_slice(&line).map_err(|e| {
+                ApiError::invalid_request(format!("Invalid session on line {}: {}", line_number, e))
+                    .with("line", line_number)
+            })?;
+            if entry.session_id.is_empty() {
+                return Err(ApiError::invalid_request(format!(
+                    "No session_id on line {}",
+                    line_number
+                ))
+                .with("line", line_number)
+                .into());
+            }
+
+            if let Err(e) = replace_session(
+                &data,
+                &tenant,
+                &entry.session_id,
+                entry.import,
+                summary.clone(),
+            )
+            .await
+            {
+                tracing::warn!(
+                    session_id = entry.session_id,
+                    restored = sessions,
+                    "Restore stopped"
+                );
+                return Err(e);
+            }
+            sessions += 1;
+        }
+
+        if lines.buffer.len() > data.import_max_bytes {
+            return Err(ApiError::new(
+                ErrorCode::PayloadTooLarge,
+                format!("Sessions are limited to {} bytes", data.import_max_bytes),
+            )
+            .with("line", line_number + 1)
+            .into());
+        }
+    }
+
+    tracing::info!(sessions, "Snapshot restored");
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(RestoreResponse {
+            status: "Ok",
+            sessions,
+        }))
+}
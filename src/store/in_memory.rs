@@ -0,0 +1,1056 @@
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{merge_pinned, MemoryStore, Restore};
+use crate::models::{
+    AnalyticsCounts, CompactionProgress, CompactionStamp, ContextSegment, ContextVersion,
+    MemoryMessage, MotorheadError, Recap, SessionConfig, StoredSize, TokenUsage,
+};
+
+/// `(tenant, session_id)`, the tenant being empty for the default namespace.
+type SessionKey = (String, String);
+
+#[derive(Default)]
+struct Session {
+    /// Oldest first, unlike the indices of the `MemoryStore` methods.
+    messages: Vec<MemoryMessage>,
+    /// Oldest first.
+    history: Vec<MemoryMessage>,
+    context: Option<String>,
+    long_term_context: Option<String>,
+    context_segments: Vec<ContextSegment>,
+    metadata: Option<serde_json::Value>,
+    config: Option<SessionConfig>,
+    entities: BTreeMap<String, String>,
+    kv: BTreeMap<String, serde_json::Value>,
+    /// Oldest first.
+    pinned: Vec<MemoryMessage>,
+    /// Oldest first.
+    pinned_auto: Vec<MemoryMessage>,
+    usage: TokenUsage,
+    languages: BTreeMap<String, u64>,
+    /// Oldest first.
+    context_versions: Vec<ContextVersion>,
+    last_compaction: Option<CompactionStamp>,
+    compaction_progress: Option<CompactionProgress>,
+    unsummarized: u64,
+    /// Milliseconds since the Unix epoch of the last append.
+    last_activity: Option<u64>,
+    expires_at: Option<Instant>,
+    version: u64,
+}
+
+impl Session {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Resolves Redis-style (possibly negative, inclusive) newest-first indices into the
+    /// oldest-first indices they cover, newest first.
+    fn range(&self, start: i64, stop: i64) -> Vec<usize> {
+        let len = self.messages.len() as i64;
+        let start = if start < 0 {
+            (len + start).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+
+        (start..=stop).map(|i| (len - 1 - i) as usize).collect()
+    }
+}
+
+#[derive(Default)]
+struct Data {
+    sessions: HashMap<SessionKey, Session>,
+    /// Expiry of each claimed `(tenant, session_id, key)`. Kept apart from the sessions, so
+    /// deleting one doesn't forget them.
+    idempotency_keys: HashMap<(String, String, String), Instant>,
+    /// Soft-deleted sessions, expiring when they can no longer be restored.
+    trash: HashMap<SessionKey, Session>,
+    /// Token usage of each `(month, tenant)`.
+    usage: BTreeMap<(String, String), TokenUsage>,
+    /// Analytics of each `(day, tenant)`.
+    analytics: BTreeMap<(String, String), AnalyticsCounts>,
+    /// By tenant, user and day.
+    recaps: BTreeMap<(String, String, String), Recap>,
+    /// The token holding each session's lease and when it runs out (ms since the Unix
+    /// epoch). Kept apart from the sessions, like in Redis.
+    locks: HashMap<SessionKey, (String, u64)>,
+    /// The token of each session's pending delete intent and when it runs out, like `locks`.
+    delete_intents: HashMap<SessionKey, (String, u64)>,
+    /// The last version given to a session. Shared by all of them, so that a session created
+    /// again doesn't reuse the versions of the one it replaces.
+    last_version: u64,
+}
+
+impl Data {
+    /// The session, unless it doesn't exist or its TTL ran out, in which case it's dropped.
+    fn session(&mut self, key: &SessionKey) -> Option<&mut Session> {
+        if self
+            .sessions
+            .get(key)
+            .is_some_and(|session| session.is_expired(Instant::now()))
+        {
+            self.sessions.remove(key);
+        }
+        self.sessions.get_mut(key)
+    }
+
+    fn session_or_default(&mut self, key: SessionKey) -> &mut Session {
+        self.session(&key);
+        self.sessions.entry(key).or_default()
+    }
+
+    /// The session about to be changed, created if needed, with a new version.
+    fn session_to_change(&mut self, key: SessionKey) -> &mut Session {
+        let version = self.next_version();
+        let session = self.session_or_default(key);
+        session.version = version;
+        session
+    }
+
+    /// Like `session`, for changing it: gives it a new version if it exists.
+    fn existing_session_to_change(&mut self, key: &SessionKey) -> Option<&mut Session> {
+        let version = self.next_version();
+        let session = self.session(key)?;
+        session.version = version;
+        Some(session)
+    }
+
+    fn next_version(&mut self) -> u64 {
+        self.last_version += 1;
+        self.last_version
+    }
+}
+
+/// Keeps everything in the process's memory, for tests and local development: nothing
+/// survives a restart and several instances don't share sessions.
+pub struct InMemoryStore {
+    data: Arc<Mutex<Data>>,
+    /// Empty for the default namespace.
+    tenant: String,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        InMemoryStore {
+            data: Arc::new(Mutex::new(Data::default())),
+            tenant: String::new(),
+        }
+    }
+
+    fn key(&self, session_id: &str) -> SessionKey {
+        (self.tenant.clone(), session_id.to_string())
+    }
+}
+
+#[async_trait]
+impl MemoryStore for InMemoryStore {
+    fn for_tenant(&self, tenant: &str) -> Arc<dyn MemoryStore> {
+        Arc::new(InMemoryStore {
+            data: Arc::clone(&self.data),
+            tenant: tenant.to_string(),
+        })
+    }
+
+    async fn ping(&self) -> Result<(), MotorheadError> {
+        Ok(())
+    }
+
+    async fn get_messages(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(session) = data.session(&self.key(session_id)) else {
+            return Ok(Vec::new());
+        };
+
+        Ok(session
+            .range(start, stop)
+            .into_iter()
+            .map(|i| session.messages[i].clone())
+            .collect())
+    }
+
+    async fn append_messages(
+        &self,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<i64, MotorheadError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_to_change(self.key(session_id));
+
+        session.unsummarized += messages.len() as u64;
+        session.messages.extend(messages);
+        session.last_activity = Some(now);
+
+        Ok(session.messages.len() as i64)
+    }
+
+    async fn trim_messages(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+        archive: bool,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(session) = data.existing_session_to_change(&self.key(session_id)) else {
+            return Ok(());
+        };
+
+        let kept = session.range(start, stop);
+        let (kept, removed): (Vec<_>, Vec<_>) = std::mem::take(&mut session.messages)
+            .into_iter()
+            .enumerate()
+            .partition(|(i, _)| kept.contains(i));
+        session.messages = kept.into_iter().map(|(_, message)| message).collect();
+        if archive {
+            session
+                .history
+                .extend(removed.into_iter().map(|(_, message)| message));
+        }
+
+        Ok(())
+    }
+
+    async fn stored_size(&self, session_id: &str) -> Result<StoredSize, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(session) = data.session(&self.key(session_id)) else {
+            return Ok(StoredSize::default());
+        };
+
+        Ok(StoredSize {
+            window: session.messages.len() as u64,
+            history: session.history.len() as u64,
+            bytes: session
+                .messages
+                .iter()
+                .chain(&session.history)
+                .map(|message| message.content.len() as u64)
+                .sum(),
+        })
+    }
+
+    async fn drop_history(&self, session_id: &str, count: usize) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(session) = data.session(&self.key(session_id)) {
+            session.history.drain(..count.min(session.history.len()));
+        }
+        Ok(())
+    }
+
+    async fn get_history(
+        &self,
+        session_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(session) = data.session(&self.key(session_id)) else {
+            return Ok(Vec::new());
+        };
+
+        Ok(session
+            .history
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_history_tail(
+        &self,
+        session_id: &str,
+        count: usize,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(session) = data.session(&self.key(session_id)) else {
+            return Ok(Vec::new());
+        };
+
+        let start = session.history.len().saturating_sub(count);
+        Ok(session.history[start..].to_vec())
+    }
+
+    async fn get_context(&self, session_id: &str) -> Result<Option<String>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .and_then(|session| session.context.clone()))
+    }
+
+    async fn set_context(&self, session_id: &str, context: &str) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_to_change(self.key(session_id));
+
+        session.context = Some(context.to_string());
+        session.unsummarized = 0;
+
+        Ok(())
+    }
+
+    async fn get_long_term_context(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<String>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .and_then(|session| session.long_term_context.clone()))
+    }
+
+    async fn set_long_term_context(
+        &self,
+        session_id: &str,
+        long_term_context: &str,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.session_to_change(self.key(session_id))
+            .long_term_context = Some(long_term_context.to_string());
+        Ok(())
+    }
+
+    async fn fold_context(
+        &self,
+        session_id: &str,
+        long_term_context: &str,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_to_change(self.key(session_id));
+
+        session.long_term_context = Some(long_term_context.to_string());
+        session.context = None;
+
+        Ok(())
+    }
+
+    async fn replace_contexts(
+        &self,
+        session_id: &str,
+        context: Option<&str>,
+        long_term_context: Option<&str>,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_to_change(self.key(session_id));
+
+        session.context = context.map(str::to_string);
+        session.long_term_context = long_term_context.map(str::to_string);
+
+        Ok(())
+    }
+
+    async fn get_context_segments(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ContextSegment>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .map(|session| session.context_segments.clone())
+            .unwrap_or_default())
+    }
+
+    async fn set_context_segments(
+        &self,
+        session_id: &str,
+        segments: &[ContextSegment],
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.session_to_change(self.key(session_id))
+            .context_segments = segments.to_vec();
+        Ok(())
+    }
+
+    async fn messages_since_summary(&self, session_id: &str) -> Result<u64, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .map_or(0, |session| session.unsummarized))
+    }
+
+    async fn get_metadata(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<serde_json::Value>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .and_then(|session| session.metadata.clone()))
+    }
+
+    async fn set_metadata(
+        &self,
+        session_id: &str,
+        metadata: &serde_json::Value,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.session_or_default(self.key(session_id)).metadata = Some(metadata.clone());
+        Ok(())
+    }
+
+    async fn delete_metadata(&self, session_id: &str) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(session) = data.session(&self.key(session_id)) {
+            session.metadata = None;
+        }
+        Ok(())
+    }
+
+    async fn get_session_config(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<SessionConfig>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .and_then(|session| session.config.clone()))
+    }
+
+    async fn set_session_config(
+        &self,
+        session_id: &str,
+        config: &SessionConfig,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.session_to_change(self.key(session_id)).config = Some(config.clone());
+        Ok(())
+    }
+
+    async fn get_entities(
+        &self,
+        session_id: &str,
+    ) -> Result<BTreeMap<String, String>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .map(|session| session.entities.clone())
+            .unwrap_or_default())
+    }
+
+    async fn merge_entities(
+        &self,
+        session_id: &str,
+        entities: &BTreeMap<String, String>,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.session_or_default(self.key(session_id))
+            .entities
+            .extend(entities.clone());
+        Ok(())
+    }
+
+    async fn get_kv(
+        &self,
+        session_id: &str,
+        key: &str,
+    ) -> Result<Option<serde_json::Value>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .and_then(|session| session.kv.get(key).cloned()))
+    }
+
+    async fn set_kv(
+        &self,
+        session_id: &str,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.session_or_default(self.key(session_id))
+            .kv
+            .insert(key.to_string(), value.clone());
+        Ok(())
+    }
+
+    async fn delete_kv(&self, session_id: &str, key: &str) -> Result<bool, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .is_some_and(|session| session.kv.remove(key).is_some()))
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        session_id: &str,
+        key: &str,
+        ttl_seconds: u64,
+    ) -> Result<bool, MotorheadError> {
+        let now = Instant::now();
+        let mut data = self.data.lock().unwrap();
+        data.idempotency_keys
+            .retain(|_, expires_at| *expires_at > now);
+
+        let key = (self.tenant.clone(), session_id.to_string(), key.to_string());
+        if data.idempotency_keys.contains_key(&key) {
+            return Ok(false);
+        }
+        data.idempotency_keys
+            .insert(key, now + Duration::from_secs(ttl_seconds));
+
+        Ok(true)
+    }
+
+    async fn release_idempotency_key(
+        &self,
+        session_id: &str,
+        key: &str,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.idempotency_keys.remove(&(
+            self.tenant.clone(),
+            session_id.to_string(),
+            key.to_string(),
+        ));
+        Ok(())
+    }
+
+    async fn acquire_lock(
+        &self,
+        session_id: &str,
+        token: &str,
+        ttl_ms: u64,
+        now: u64,
+    ) -> Result<bool, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let key = self.key(session_id);
+        if data
+            .locks
+            .get(&key)
+            .is_some_and(|(held_by, expires_at)| *expires_at > now && held_by != token)
+        {
+            return Ok(false);
+        }
+        data.locks.insert(key, (token.to_string(), now + ttl_ms));
+        Ok(true)
+    }
+
+    async fn release_lock(&self, session_id: &str, token: &str) -> Result<bool, MotorheadError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let mut data = self.data.lock().unwrap();
+        let key = self.key(session_id);
+        let held = data
+            .locks
+            .get(&key)
+            .is_some_and(|(held_by, expires_at)| *expires_at > now && held_by == token);
+        if held {
+            data.locks.remove(&key);
+        }
+        Ok(held)
+    }
+
+    async fn set_delete_intent(
+        &self,
+        session_id: &str,
+        token: &str,
+        ttl_ms: u64,
+    ) -> Result<(), MotorheadError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let mut data = self.data.lock().unwrap();
+        data.delete_intents
+            .insert(self.key(session_id), (token.to_string(), now + ttl_ms));
+        Ok(())
+    }
+
+    async fn take_delete_intent(
+        &self,
+        session_id: &str,
+        token: &str,
+    ) -> Result<bool, MotorheadError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let mut data = self.data.lock().unwrap();
+        let key = self.key(session_id);
+        let valid = data
+            .delete_intents
+            .get(&key)
+            .is_some_and(|(intent, expires_at)| *expires_at > now && intent == token);
+        if valid {
+            data.delete_intents.remove(&key);
+        }
+        Ok(valid)
+    }
+
+    async fn lock_expiry(&self, session_id: &str) -> Result<Option<u64>, MotorheadError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .locks
+            .get(&self.key(session_id))
+            .map(|(_, expires_at)| *expires_at)
+            .filter(|expires_at| *expires_at > now))
+    }
+
+    async fn list_sessions(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(String, u64)>, MotorheadError> {
+        let now = Instant::now();
+        let data = self.data.lock().unwrap();
+
+        let mut sessions: Vec<(String, u64)> = data
+            .sessions
+            .iter()
+            .filter(|((tenant, _), session)| *tenant == self.tenant && !session.is_expired(now))
+            .filter_map(|((_, session_id), session)| {
+                session
+                    .last_activity
+                    .map(|last_activity| (session_id.clone(), last_activity))
+            })
+            .collect();
+        sessions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        Ok(sessions.into_iter().skip(offset).take(limit).collect())
+    }
+
+    async fn get_pinned(&self, session_id: &str) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .map(|session| merge_pinned(session.pinned.clone(), session.pinned_auto.clone()))
+            .unwrap_or_default())
+    }
+
+    async fn get_auto_pinned(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .map(|session| session.pinned_auto.clone())
+            .unwrap_or_default())
+    }
+
+    async fn set_auto_pinned(
+        &self,
+        session_id: &str,
+        messages: &[MemoryMessage],
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_to_change(self.key(session_id));
+
+        session.pinned_auto = messages.to_vec();
+        session.pinned_auto.sort_by_key(|pinned| pinned.created_at);
+        Ok(())
+    }
+
+    async fn pin_message(
+        &self,
+        session_id: &str,
+        message: &MemoryMessage,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_to_change(self.key(session_id));
+
+        session.pinned.retain(|pinned| pinned.id != message.id);
+        session.pinned.push(message.clone());
+        session.pinned.sort_by_key(|pinned| pinned.created_at);
+        Ok(())
+    }
+
+    async fn unpin_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+    ) -> Result<bool, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(session) = data.existing_session_to_change(&self.key(session_id)) else {
+            return Ok(false);
+        };
+
+        let len = session.pinned.len() + session.pinned_auto.len();
+        session
+            .pinned
+            .retain(|pinned| pinned.id.as_deref() != Some(message_id));
+        session
+            .pinned_auto
+            .retain(|pinned| pinned.id.as_deref() != Some(message_id));
+        Ok(session.pinned.len() + session.pinned_auto.len() < len)
+    }
+
+    async fn record_usage(
+        &self,
+        session_id: &str,
+        month: &str,
+        usage: TokenUsage,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.session_or_default(self.key(session_id)).usage += usage;
+        *data
+            .usage
+            .entry((month.to_string(), self.tenant.clone()))
+            .or_default() += usage;
+        Ok(())
+    }
+
+    async fn get_session_usage(&self, session_id: &str) -> Result<TokenUsage, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .map(|session| session.usage)
+            .unwrap_or_default())
+    }
+
+    async fn record_languages(
+        &self,
+        session_id: &str,
+        counts: &BTreeMap<String, u64>,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_or_default(self.key(session_id));
+        for (language, count) in counts {
+            *session.languages.entry(language.clone()).or_default() += count;
+        }
+        Ok(())
+    }
+
+    async fn get_languages(
+        &self,
+        session_id: &str,
+    ) -> Result<BTreeMap<String, u64>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .map(|session| session.languages.clone())
+            .unwrap_or_default())
+    }
+
+    async fn record_context_version(
+        &self,
+        session_id: &str,
+        version: &ContextVersion,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_or_default(self.key(session_id));
+        session.context_versions.push(version.clone());
+        Ok(())
+    }
+
+    async fn get_context_versions(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ContextVersion>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .map(|session| session.context_versions.clone())
+            .unwrap_or_default())
+    }
+
+    async fn record_compaction(&self, session_id: &str, at: u64) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_or_default(self.key(session_id));
+        let count = session.last_compaction.map_or(0, |stamp| stamp.count) + 1;
+        session.last_compaction = Some(CompactionStamp { at, count });
+        Ok(())
+    }
+
+    async fn last_compaction(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<CompactionStamp>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .and_then(|session| session.last_compaction))
+    }
+
+    async fn get_compaction_progress(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<CompactionProgress>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .and_then(|session| session.compaction_progress.clone()))
+    }
+
+    async fn set_compaction_progress(
+        &self,
+        session_id: &str,
+        progress: Option<&CompactionProgress>,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.session_or_default(self.key(session_id));
+        session.compaction_progress = progress.cloned();
+        Ok(())
+    }
+
+    async fn get_tenant_usage(&self, month: &str) -> Result<TokenUsage, MotorheadError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .usage
+            .get(&(month.to_string(), self.tenant.clone()))
+            .copied()
+            .unwrap_or_default())
+    }
+
+    async fn usage_by_tenant(
+        &self,
+        month: &str,
+    ) -> Result<Vec<(Option<String>, TokenUsage)>, MotorheadError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .usage
+            .iter()
+            .filter(|((usage_month, _), _)| usage_month == month)
+            .map(|((_, tenant), usage)| {
+                let tenant = (!tenant.is_empty()).then(|| tenant.clone());
+                (tenant, *usage)
+            })
+            .collect())
+    }
+
+    async fn record_analytics(
+        &self,
+        day: &str,
+        counts: &AnalyticsCounts,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        *data
+            .analytics
+            .entry((day.to_string(), self.tenant.clone()))
+            .or_default() += *counts;
+        Ok(())
+    }
+
+    async fn analytics_by_tenant(
+        &self,
+        day: &str,
+    ) -> Result<Vec<(Option<String>, AnalyticsCounts)>, MotorheadError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .analytics
+            .iter()
+            .filter(|((analytics_day, _), _)| analytics_day == day)
+            .map(|((_, tenant), counts)| {
+                let tenant = (!tenant.is_empty()).then(|| tenant.clone());
+                (tenant, *counts)
+            })
+            .collect())
+    }
+
+    async fn set_recap(&self, recap: &Recap, oldest_date: &str) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.recaps.retain(|(tenant, user_id, date), _| {
+            *tenant != self.tenant || *user_id != recap.user_id || date.as_str() >= oldest_date
+        });
+        data.recaps.insert(
+            (
+                self.tenant.clone(),
+                recap.user_id.clone(),
+                recap.date.clone(),
+            ),
+            recap.clone(),
+        );
+        Ok(())
+    }
+
+    async fn has_recap(&self, user_id: &str, date: &str) -> Result<bool, MotorheadError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .recaps
+            .contains_key(&(self.tenant.clone(), user_id.to_string(), date.to_string())))
+    }
+
+    async fn get_recaps(&self, user_id: &str, limit: usize) -> Result<Vec<Recap>, MotorheadError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .recaps
+            .iter()
+            .rev()
+            .filter(|((tenant, recap_user_id, _), _)| {
+                *tenant == self.tenant && recap_user_id == user_id
+            })
+            .take(limit)
+            .map(|(_, recap)| recap.clone())
+            .collect())
+    }
+
+    async fn delete_recaps(&self, user_id: &str) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.recaps.retain(|(tenant, recap_user_id, _), _| {
+            *tenant != self.tenant || recap_user_id != user_id
+        });
+        Ok(())
+    }
+
+    async fn update_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> Result<bool, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let message = data
+            .existing_session_to_change(&self.key(session_id))
+            .and_then(|session| {
+                session
+                    .messages
+                    .iter_mut()
+                    .find(|message| message.id.as_deref() == Some(message_id))
+            });
+
+        match message {
+            Some(message) => {
+                message.content = content.to_string();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+    ) -> Result<bool, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(session) = data.existing_session_to_change(&self.key(session_id)) else {
+            return Ok(false);
+        };
+
+        let len = session.messages.len();
+        session
+            .messages
+            .retain(|message| message.id.as_deref() != Some(message_id));
+
+        Ok(session.messages.len() < len)
+    }
+
+    async fn delete_newest_messages(
+        &self,
+        session_id: &str,
+        ids: &[Option<String>],
+    ) -> Result<bool, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(session) = data.existing_session_to_change(&self.key(session_id)) else {
+            return Ok(false);
+        };
+
+        let Some(kept) = session.messages.len().checked_sub(ids.len()) else {
+            return Ok(false);
+        };
+        if !session.messages[kept..]
+            .iter()
+            .rev()
+            .map(|message| &message.id)
+            .eq(ids)
+        {
+            return Ok(false);
+        }
+
+        session.messages.truncate(kept);
+        session
+            .pinned
+            .retain(|pinned| pinned.id.is_none() || !ids.contains(&pinned.id));
+        session
+            .pinned_auto
+            .retain(|pinned| pinned.id.is_none() || !ids.contains(&pinned.id));
+        Ok(true)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        data.sessions.remove(&self.key(session_id));
+        Ok(())
+    }
+
+    async fn trash_session(
+        &self,
+        session_id: &str,
+        ttl_seconds: u64,
+    ) -> Result<(), MotorheadError> {
+        let now = Instant::now();
+        let mut data = self.data.lock().unwrap();
+        data.trash.retain(|_, session| !session.is_expired(now));
+
+        let key = self.key(session_id);
+        if data.session(&key).is_some() {
+            let mut session = data.sessions.remove(&key).unwrap();
+            session.expires_at = Some(now + Duration::from_secs(ttl_seconds));
+            data.trash.insert(key, session);
+        }
+        Ok(())
+    }
+
+    async fn session_version(&self, session_id: &str) -> Result<Option<String>, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data
+            .session(&self.key(session_id))
+            .map(|session| session.version.to_string()))
+    }
+
+    async fn claim_version(&self, session_id: &str, version: &str) -> Result<bool, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let next_version = data.next_version();
+        match data.session(&self.key(session_id)) {
+            Some(session) if session.version.to_string() == version => {
+                session.version = next_version;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn restore_session(&self, session_id: &str) -> Result<Restore, MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        let key = self.key(session_id);
+
+        let Some(mut session) = data.trash.remove(&key) else {
+            return Ok(Restore::NotTrashed);
+        };
+        if session.is_expired(Instant::now()) {
+            return Ok(Restore::NotTrashed);
+        }
+        if data.session(&key).is_some() {
+            data.trash.insert(key, session);
+            return Ok(Restore::SessionExists);
+        }
+
+        session.expires_at = None;
+        data.sessions.insert(key, session);
+        Ok(Restore::Restored)
+    }
+
+    fn expires_sessions(&self) -> bool {
+        true
+    }
+
+    async fn expire_session(
+        &self,
+        session_id: &str,
+        ttl_seconds: u64,
+    ) -> Result<(), MotorheadError> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(session) = data.session(&self.key(session_id)) {
+            session.expires_at = Some(Instant::now() + Duration::from_secs(ttl_seconds));
+        }
+        Ok(())
+    }
+}
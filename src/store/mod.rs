@@ -0,0 +1,855 @@
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::models::{
+    AnalyticsCounts, AuditEntry, ColdSegment, CompactionFailure, CompactionProgress,
+    CompactionStamp, ContextSegment, ContextVersion, MemoryFields, MemoryMessage, MotorheadError,
+    Recap, RetrievalResult, SessionConfig, StoredSize, TokenUsage,
+};
+
+mod in_memory;
+mod postgres;
+mod redis;
+mod topology;
+pub use self::in_memory::InMemoryStore;
+pub use self::postgres::PostgresStore;
+pub use self::redis::{MessageLog, RedisStore};
+pub use self::topology::{RedisAuth, RedisPool, RedisTopology};
+
+/// One operation of a batch, see `MemoryStore::apply_batch`.
+pub enum BatchOp<'a> {
+    Append {
+        session_id: &'a str,
+        messages: &'a [MemoryMessage],
+    },
+    Delete {
+        session_id: &'a str,
+    },
+}
+
+/// What became of a `MemoryStore::restore_session`.
+#[derive(PartialEq)]
+pub enum Restore {
+    Restored,
+    /// The session wasn't soft-deleted, or can't be restored anymore.
+    NotTrashed,
+    /// A session with the same id was created since; it's left alone.
+    SessionExists,
+}
+
+/// What `GET /sessions/{session_id}/memory` reads, see `MemoryStore::read_window`.
+pub struct SessionWindow {
+    pub messages: Vec<MemoryMessage>,
+    pub context: Option<String>,
+    pub long_term_context: Option<String>,
+    pub context_segments: Vec<ContextSegment>,
+    pub messages_since_summary: u64,
+    /// Oldest first, whether in the window or not.
+    pub pinned: Vec<MemoryMessage>,
+}
+
+/// The pinned messages of a session, oldest first: those pinned by hand, then those pinned by
+/// compactions that weren't also pinned by hand.
+pub fn merge_pinned(
+    mut pinned: Vec<MemoryMessage>,
+    auto_pinned: Vec<MemoryMessage>,
+) -> Vec<MemoryMessage> {
+    for message in auto_pinned {
+        if !pinned.iter().any(|pinned| pinned.id == message.id) {
+            pinned.push(message);
+        }
+    }
+    pinned.sort_by_key(|message| message.created_at);
+    pinned
+}
+
+/// Applies a batch one operation at a time, for backends that can't do better.
+pub async fn apply_batch_sequentially<S: MemoryStore + ?Sized>(
+    store: &S,
+    ops: &[BatchOp<'_>],
+) -> Result<Vec<Option<i64>>, MotorheadError> {
+    let mut lengths = Vec::with_capacity(ops.len());
+    for op in ops {
+        match op {
+            BatchOp::Append {
+                session_id,
+                messages,
+            } => lengths.push(Some(
+                store.append_messages(session_id, messages.to_vec()).await?,
+            )),
+            BatchOp::Delete { session_id } => {
+                store.delete_session(session_id).await?;
+                lengths.push(None);
+            }
+        }
+    }
+
+    Ok(lengths)
+}
+
+/// Storage backend for session memory.
+///
+/// Messages are addressed newest-first: index 0 is the most recently appended message, like
+/// the Redis list the original implementation used. `stop` bounds are inclusive.
+#[async_trait]
+pub trait MemoryStore: Send + Sync {
+    /// Returns a store whose sessions live in `tenant`'s namespace, isolated from other
+    /// tenants and from the default namespace.
+    fn for_tenant(&self, tenant: &str) -> Arc<dyn MemoryStore>;
+
+    /// Round-trips to the backend to check it's reachable.
+    async fn ping(&self) -> Result<(), MotorheadError>;
+
+    /// Records `now` (milliseconds since the Unix epoch), for `read_heartbeat` on a replica of
+    /// the store to tell how far behind it is.
+    async fn write_heartbeat(&self, _now: u64) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("read replicas"))
+    }
+
+    /// The last `now` given to `write_heartbeat`, as far as this store has seen.
+    async fn read_heartbeat(&self) -> Result<Option<u64>, MotorheadError> {
+        Err(MotorheadError::Unsupported("read replicas"))
+    }
+
+    /// The base64 of each tenant's own encryption key, see `TenantKeys`.
+    async fn get_tenant_keys(&self) -> Result<BTreeMap<String, String>, MotorheadError> {
+        Err(MotorheadError::Unsupported("tenant encryption keys"))
+    }
+
+    /// Stores the tenant's key, unless it already has one. Whether it was stored.
+    async fn add_tenant_key(&self, _tenant: &str, _key: &str) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("tenant encryption keys"))
+    }
+
+    /// Whether the tenant had a key to delete.
+    async fn delete_tenant_key(&self, _tenant: &str) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("tenant encryption keys"))
+    }
+
+    /// The fields of the summarization settings shared by all instances, see `shared_config`.
+    async fn get_shared_config(&self) -> Result<BTreeMap<String, String>, MotorheadError> {
+        Err(MotorheadError::Unsupported("shared config"))
+    }
+
+    /// Sets the given fields of the shared settings, removing those that are `None`.
+    async fn set_shared_config(
+        &self,
+        _fields: &[(&str, Option<String>)],
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("shared config"))
+    }
+
+    /// Yields each time the shared settings change, whoever changed them.
+    async fn shared_config_changes(&self) -> Result<BoxStream<'static, ()>, MotorheadError> {
+        Err(MotorheadError::Unsupported("shared config notifications"))
+    }
+
+    async fn get_messages(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError>;
+
+    /// Appends messages in the order given and returns the new length of the session.
+    async fn append_messages(
+        &self,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<i64, MotorheadError>;
+
+    /// Keeps only the messages between `start` and `stop`. The rest are moved to the
+    /// session's history with `archive`, and dropped otherwise.
+    async fn trim_messages(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+        archive: bool,
+    ) -> Result<(), MotorheadError>;
+
+    async fn get_context(&self, session_id: &str) -> Result<Option<String>, MotorheadError>;
+
+    /// Replaces the context and resets the count of messages since the last summary.
+    async fn set_context(&self, session_id: &str, context: &str) -> Result<(), MotorheadError>;
+
+    /// The summary older contexts were folded into, see `fold_context`.
+    async fn get_long_term_context(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<String>, MotorheadError>;
+
+    async fn set_long_term_context(
+        &self,
+        session_id: &str,
+        long_term_context: &str,
+    ) -> Result<(), MotorheadError>;
+
+    /// Replaces the long-term context with one the context was summarized into, and clears
+    /// the context. The count of messages since the last summary is left as is.
+    async fn fold_context(
+        &self,
+        session_id: &str,
+        long_term_context: &str,
+    ) -> Result<(), MotorheadError>;
+
+    /// Replaces both the context and the long-term context, clearing those that are `None`.
+    /// The count of messages since the last summary is left as is.
+    async fn replace_contexts(
+        &self,
+        session_id: &str,
+        context: Option<&str>,
+        long_term_context: Option<&str>,
+    ) -> Result<(), MotorheadError>;
+
+    /// The summaries per topic, see `ContextSegment`, oldest first.
+    async fn get_context_segments(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ContextSegment>, MotorheadError>;
+
+    async fn set_context_segments(
+        &self,
+        session_id: &str,
+        segments: &[ContextSegment],
+    ) -> Result<(), MotorheadError>;
+
+    /// Stores the result of a compaction: keeps messages `0..=keep_until` and replaces the
+    /// context. See `trim_messages` for `archive`. `newest_summarized` is the id of the
+    /// message at `keep_until + 1` when the compaction read the window: backends that can
+    /// should cut right before it instead, atomically, so messages appended meanwhile aren't
+    /// lost, and fail with `CompactionConflict` if it's gone.
+    async fn commit_compaction(
+        &self,
+        session_id: &str,
+        keep_until: i64,
+        _newest_summarized: Option<&str>,
+        context: &str,
+        archive: bool,
+    ) -> Result<(), MotorheadError> {
+        self.trim_messages(session_id, 0, keep_until, archive)
+            .await?;
+        self.set_context(session_id, context).await
+    }
+
+    /// The messages of the window and the history and their size, without pinned messages
+    /// and the ones in cold storage.
+    async fn stored_size(&self, session_id: &str) -> Result<StoredSize, MotorheadError>;
+
+    /// Deletes the oldest `count` messages of the history.
+    async fn drop_history(&self, session_id: &str, count: usize) -> Result<(), MotorheadError>;
+
+    /// Returns the messages compactions moved out of the window, oldest first.
+    async fn get_history(
+        &self,
+        session_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError>;
+
+    /// The newest `count` messages of the history, oldest first: the last ones a compaction
+    /// summarized.
+    async fn get_history_tail(
+        &self,
+        session_id: &str,
+        count: usize,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError>;
+
+    /// The objects the session's oldest archived messages were moved to, oldest first. They
+    /// come before `get_history`.
+    async fn get_cold_segments(
+        &self,
+        _session_id: &str,
+    ) -> Result<Vec<ColdSegment>, MotorheadError> {
+        Err(MotorheadError::Unsupported("cold storage"))
+    }
+
+    /// Records that the oldest `segment.messages` of the history were stored in `segment`,
+    /// and removes them from the history. Returns false, changing nothing, if the history no
+    /// longer has as many.
+    async fn move_to_cold(
+        &self,
+        _session_id: &str,
+        _segment: &ColdSegment,
+    ) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("cold storage"))
+    }
+
+    /// Fetches a window of messages along with the context. Backends that can do this in a
+    /// single round trip should override it.
+    async fn get_memory(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<(Vec<MemoryMessage>, Option<String>), MotorheadError> {
+        let messages = self.get_messages(session_id, start, stop).await?;
+        let context = self.get_context(session_id).await?;
+        Ok((messages, context))
+    }
+
+    /// Fetches a window of messages along with the rest of what's read with them, leaving out
+    /// what `fields` doesn't ask for. Backends that can do this in a single round trip should
+    /// override it, reads of the memory being the most frequent requests.
+    async fn read_window(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+        fields: MemoryFields,
+    ) -> Result<SessionWindow, MotorheadError> {
+        let mut window = SessionWindow {
+            messages: Vec::new(),
+            context: None,
+            long_term_context: None,
+            context_segments: Vec::new(),
+            messages_since_summary: self.messages_since_summary(session_id).await?,
+            pinned: Vec::new(),
+        };
+        if fields.messages {
+            window.messages = self.get_messages(session_id, start, stop).await?;
+            window.pinned = self.get_pinned(session_id).await?;
+        }
+        if fields.context {
+            window.context = self.get_context(session_id).await?;
+            window.long_term_context = self.get_long_term_context(session_id).await?;
+            window.context_segments = self.get_context_segments(session_id).await?;
+        }
+        Ok(window)
+    }
+
+    /// Counts the messages appended since the context was last updated.
+    async fn messages_since_summary(&self, session_id: &str) -> Result<u64, MotorheadError>;
+
+    async fn get_metadata(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<serde_json::Value>, MotorheadError>;
+
+    async fn set_metadata(
+        &self,
+        session_id: &str,
+        metadata: &serde_json::Value,
+    ) -> Result<(), MotorheadError>;
+
+    async fn delete_metadata(&self, session_id: &str) -> Result<(), MotorheadError>;
+
+    async fn get_session_config(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<SessionConfig>, MotorheadError>;
+
+    async fn set_session_config(
+        &self,
+        session_id: &str,
+        config: &SessionConfig,
+    ) -> Result<(), MotorheadError>;
+
+    async fn get_entities(
+        &self,
+        session_id: &str,
+    ) -> Result<BTreeMap<String, String>, MotorheadError>;
+
+    /// Adds `entities` to the session's, replacing the values of existing names.
+    async fn merge_entities(
+        &self,
+        session_id: &str,
+        entities: &BTreeMap<String, String>,
+    ) -> Result<(), MotorheadError>;
+
+    /// The value of `key` in the session's key-value memory.
+    async fn get_kv(
+        &self,
+        session_id: &str,
+        key: &str,
+    ) -> Result<Option<serde_json::Value>, MotorheadError>;
+
+    async fn set_kv(
+        &self,
+        session_id: &str,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<(), MotorheadError>;
+
+    /// Returns false if the session had no `key`.
+    async fn delete_kv(&self, session_id: &str, key: &str) -> Result<bool, MotorheadError>;
+
+    /// Applies `ops` in order, in as few round trips as the backend allows. Returns the
+    /// session's length after each append, and `None` for deletes.
+    async fn apply_batch(&self, ops: &[BatchOp<'_>]) -> Result<Vec<Option<i64>>, MotorheadError> {
+        apply_batch_sequentially(self, ops).await
+    }
+
+    /// Records `key` as seen for the session during `ttl_seconds`. Returns false if it
+    /// already was.
+    async fn claim_idempotency_key(
+        &self,
+        session_id: &str,
+        key: &str,
+        ttl_seconds: u64,
+    ) -> Result<bool, MotorheadError>;
+
+    /// Forgets a claimed key, so that the request it guarded can be retried.
+    async fn release_idempotency_key(
+        &self,
+        session_id: &str,
+        key: &str,
+    ) -> Result<(), MotorheadError>;
+
+    /// Lists sessions as `(session_id, last_activity_ms)`, most recently active first.
+    async fn list_sessions(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(String, u64)>, MotorheadError>;
+
+    /// Moves the sessions stored before `MOTORHEAD_KEY_PREFIX` was set under it, returning how
+    /// many were moved.
+    async fn migrate_legacy_sessions(&self) -> Result<usize, MotorheadError> {
+        Err(MotorheadError::Unsupported("key prefixes"))
+    }
+
+    /// The ids of the sessions whose metadata has `user_id` as its `user_id`, most recently
+    /// active first. The default looks through the metadata of every session, backends that
+    /// can query it should override it.
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<String>, MotorheadError> {
+        const PAGE_SIZE: usize = 1000;
+
+        let mut sessions = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = self.list_sessions(offset, PAGE_SIZE).await?;
+            let page_len = page.len();
+
+            for (session_id, _) in page {
+                let metadata = self.get_metadata(&session_id).await?;
+                if metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.get("user_id"))
+                    .and_then(serde_json::Value::as_str)
+                    == Some(user_id)
+                {
+                    sessions.push(session_id);
+                }
+            }
+
+            if page_len < PAGE_SIZE {
+                return Ok(sessions);
+            }
+            offset += page_len;
+        }
+    }
+
+    /// The session's pinned messages, oldest first, those compactions pinned included.
+    async fn get_pinned(&self, session_id: &str) -> Result<Vec<MemoryMessage>, MotorheadError>;
+
+    /// The messages compactions pinned for their importance, oldest first.
+    async fn get_auto_pinned(&self, session_id: &str)
+        -> Result<Vec<MemoryMessage>, MotorheadError>;
+
+    /// Replaces the messages compactions pinned for their importance.
+    async fn set_auto_pinned(
+        &self,
+        session_id: &str,
+        messages: &[MemoryMessage],
+    ) -> Result<(), MotorheadError>;
+
+    /// Keeps a copy of `message`, replacing the one with the same id if any. Compactions
+    /// don't summarize pinned messages, and reads serve the copy once they've left the window.
+    async fn pin_message(
+        &self,
+        session_id: &str,
+        message: &MemoryMessage,
+    ) -> Result<(), MotorheadError>;
+
+    /// Returns false if no message with id `message_id` was pinned, by hand or by a compaction.
+    async fn unpin_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+    ) -> Result<bool, MotorheadError>;
+
+    /// Adds `usage` to the session's token usage and to its tenant's for `month` (`YYYY-MM`).
+    async fn record_usage(
+        &self,
+        session_id: &str,
+        month: &str,
+        usage: TokenUsage,
+    ) -> Result<(), MotorheadError>;
+
+    async fn get_session_usage(&self, session_id: &str) -> Result<TokenUsage, MotorheadError>;
+
+    /// Adds to how many of the session's messages were tagged with each language.
+    async fn record_languages(
+        &self,
+        session_id: &str,
+        counts: &BTreeMap<String, u64>,
+    ) -> Result<(), MotorheadError>;
+
+    async fn get_languages(
+        &self,
+        session_id: &str,
+    ) -> Result<BTreeMap<String, u64>, MotorheadError>;
+
+    /// Keeps a context a compaction stored, for replays.
+    async fn record_context_version(
+        &self,
+        session_id: &str,
+        version: &ContextVersion,
+    ) -> Result<(), MotorheadError>;
+
+    /// Oldest first.
+    async fn get_context_versions(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ContextVersion>, MotorheadError>;
+
+    /// Notes that a compaction stored the session's context at `at` (ms since the Unix
+    /// epoch), counting it.
+    async fn record_compaction(&self, session_id: &str, at: u64) -> Result<(), MotorheadError>;
+
+    async fn last_compaction(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<CompactionStamp>, MotorheadError>;
+
+    /// The chunks summarized so far by the session's compaction, if it was split.
+    async fn get_compaction_progress(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<CompactionProgress>, MotorheadError>;
+
+    /// Replaces the chunks summarized so far, or with `None` clears them once the compaction
+    /// is done.
+    async fn set_compaction_progress(
+        &self,
+        session_id: &str,
+        progress: Option<&CompactionProgress>,
+    ) -> Result<(), MotorheadError>;
+
+    /// The usage of the store's tenant in `month`.
+    async fn get_tenant_usage(&self, month: &str) -> Result<TokenUsage, MotorheadError>;
+
+    /// The usage of every tenant with some in `month`, the default namespace as `None`. Like
+    /// `compaction_failures`, this ignores the store's own tenant.
+    async fn usage_by_tenant(
+        &self,
+        month: &str,
+    ) -> Result<Vec<(Option<String>, TokenUsage)>, MotorheadError>;
+
+    /// Adds `counts` to the store's tenant's analytics for `day` (`YYYY-MM-DD`).
+    async fn record_analytics(
+        &self,
+        _day: &str,
+        _counts: &AnalyticsCounts,
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("analytics"))
+    }
+
+    /// The analytics of every tenant with some activity on `day`, the default namespace as
+    /// `None`. Like `usage_by_tenant`, this ignores the store's own tenant.
+    async fn analytics_by_tenant(
+        &self,
+        _day: &str,
+    ) -> Result<Vec<(Option<String>, AnalyticsCounts)>, MotorheadError> {
+        Err(MotorheadError::Unsupported("analytics"))
+    }
+
+    /// Replaces the content of the message with id `message_id`. Returns false if the session
+    /// has no such message.
+    async fn update_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> Result<bool, MotorheadError>;
+
+    /// Removes the message with id `message_id`. Returns false if the session has no such
+    /// message.
+    async fn delete_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+    ) -> Result<bool, MotorheadError>;
+
+    /// Removes the newest messages, and their pinned copies, if they still have the `ids`
+    /// given, newest first (`None` for messages stored without one). Returns false without
+    /// removing anything otherwise, e.g. if another client appended meanwhile.
+    async fn delete_newest_messages(
+        &self,
+        session_id: &str,
+        ids: &[Option<String>],
+    ) -> Result<bool, MotorheadError>;
+
+    /// Removes the session's messages, context, metadata, vectors and listing entry.
+    async fn delete_session(&self, session_id: &str) -> Result<(), MotorheadError>;
+
+    /// Moves the session out of reach, like `delete_session`, but keeps it for `ttl_seconds`
+    /// during which `restore_session` can bring it back.
+    async fn trash_session(
+        &self,
+        _session_id: &str,
+        _ttl_seconds: u64,
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("soft delete"))
+    }
+
+    /// Brings back a session `trash_session` moved, without a TTL.
+    async fn restore_session(&self, _session_id: &str) -> Result<Restore, MotorheadError> {
+        Err(MotorheadError::Unsupported("soft delete"))
+    }
+
+    /// Whether `expire_session` is supported, for TTLs to be refused before anything is
+    /// written otherwise.
+    fn expires_sessions(&self) -> bool {
+        false
+    }
+
+    /// Makes every key of the session expire in `ttl_seconds`.
+    async fn expire_session(
+        &self,
+        _session_id: &str,
+        _ttl_seconds: u64,
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("session TTL"))
+    }
+
+    /// Sets a marker expiring along with the session, so that `expirations` reports it.
+    async fn track_expiry(
+        &self,
+        _session_id: &str,
+        _ttl_seconds: u64,
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("expiry notifications"))
+    }
+
+    /// Streams the tenant and id of the tracked sessions whose TTL ran out, across tenants.
+    async fn expirations(
+        &self,
+    ) -> Result<BoxStream<'static, (Option<String>, String)>, MotorheadError> {
+        Err(MotorheadError::Unsupported("expiry notifications"))
+    }
+
+    /// An opaque version of the session, changed by every write to its messages, contexts,
+    /// pinned messages or config, or `None` if there's no such session.
+    async fn session_version(&self, _session_id: &str) -> Result<Option<String>, MotorheadError> {
+        Err(MotorheadError::Unsupported("session versions"))
+    }
+
+    /// Gives the session a new version if it's still at `version`, for writes conditional on
+    /// it: concurrent ones then fail. Returns whether it was.
+    async fn claim_version(
+        &self,
+        _session_id: &str,
+        _version: &str,
+    ) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("session versions"))
+    }
+
+    /// Takes the session's lease for `token`, or renews it if `token` already holds it, for
+    /// `ttl_ms` from `now` (milliseconds since the Unix epoch). Returns false if another
+    /// token holds it.
+    async fn acquire_lock(
+        &self,
+        _session_id: &str,
+        _token: &str,
+        _ttl_ms: u64,
+        _now: u64,
+    ) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("session locks"))
+    }
+
+    /// Gives up the session's lease if `token` holds it. Returns whether it did.
+    async fn release_lock(&self, _session_id: &str, _token: &str) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("session locks"))
+    }
+
+    /// When the session's lease runs out (milliseconds since the Unix epoch), if it's held.
+    async fn lock_expiry(&self, _session_id: &str) -> Result<Option<u64>, MotorheadError> {
+        Err(MotorheadError::Unsupported("session locks"))
+    }
+
+    /// Notes that the session is about to be deleted, for `ttl_ms`, replacing any earlier
+    /// intent: deleting it then takes `token`.
+    async fn set_delete_intent(
+        &self,
+        _session_id: &str,
+        _token: &str,
+        _ttl_ms: u64,
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("delete confirmations"))
+    }
+
+    /// Consumes the session's delete intent if it was made with `token` and hasn't run out.
+    /// Returns whether it did.
+    async fn take_delete_intent(
+        &self,
+        _session_id: &str,
+        _token: &str,
+    ) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("delete confirmations"))
+    }
+
+    /// Marks the session as being compacted by `token` for `ttl_ms`, unless another token
+    /// already does, across every instance sharing the store. Returns whether it did. Stores
+    /// without it leave each instance to keep track of its own compactions.
+    async fn claim_compaction(
+        &self,
+        _session_id: &str,
+        _token: &str,
+        _ttl_ms: u64,
+    ) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("shared compaction claims"))
+    }
+
+    /// Extends the session's compaction claim by `ttl_ms` if `token` still holds it. Returns
+    /// whether it did.
+    async fn renew_compaction(
+        &self,
+        _session_id: &str,
+        _token: &str,
+        _ttl_ms: u64,
+    ) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("shared compaction claims"))
+    }
+
+    /// Gives up the session's compaction claim if `token` holds it.
+    async fn release_compaction(
+        &self,
+        _session_id: &str,
+        _token: &str,
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("shared compaction claims"))
+    }
+
+    /// Whether any instance has claimed a compaction of the session.
+    async fn compaction_claimed(&self, _session_id: &str) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("shared compaction claims"))
+    }
+
+    /// Streams the session's change events, JSON-encoded `SessionEvent`s, as they happen.
+    async fn subscribe(
+        &self,
+        _session_id: &str,
+    ) -> Result<BoxStream<'static, String>, MotorheadError> {
+        Err(MotorheadError::Unsupported("session event stream"))
+    }
+
+    /// The channel `session_changes` reports the session's changes on, if the store has any.
+    fn changes_channel(&self, _session_id: &str) -> Option<String> {
+        None
+    }
+
+    /// Streams the `changes_channel` of every session changed, across tenants, as it happens.
+    async fn session_changes(&self) -> Result<BoxStream<'static, String>, MotorheadError> {
+        Err(MotorheadError::Unsupported("session change notifications"))
+    }
+
+    /// Rewrites the entries of the session's window and history that aren't in any format
+    /// messages were ever stored in as `unknown` messages holding them, or deletes them with
+    /// `purge`. Returns how many it did.
+    async fn repair_entries(
+        &self,
+        _session_id: &str,
+        _purge: bool,
+    ) -> Result<usize, MotorheadError> {
+        Err(MotorheadError::Unsupported("repairing message entries"))
+    }
+
+    /// Adds or replaces the failure recorded for a session. The retry queue is shared by all
+    /// tenants, so this ignores the store's own tenant.
+    async fn record_compaction_failure(
+        &self,
+        _failure: &CompactionFailure,
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("compaction retry queue"))
+    }
+
+    async fn clear_compaction_failure(
+        &self,
+        _tenant: Option<&str>,
+        _session_id: &str,
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("compaction retry queue"))
+    }
+
+    async fn compaction_failures(&self) -> Result<Vec<CompactionFailure>, MotorheadError> {
+        Err(MotorheadError::Unsupported("compaction retry queue"))
+    }
+
+    /// Stores the recap of the user's day, replacing any, and removes their recaps of days
+    /// before `oldest_date` (`YYYY-MM-DD`).
+    async fn set_recap(&self, _recap: &Recap, _oldest_date: &str) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("user recaps"))
+    }
+
+    async fn has_recap(&self, _user_id: &str, _date: &str) -> Result<bool, MotorheadError> {
+        Err(MotorheadError::Unsupported("user recaps"))
+    }
+
+    /// The user's latest `limit` recaps, newest first.
+    async fn get_recaps(
+        &self,
+        _user_id: &str,
+        _limit: usize,
+    ) -> Result<Vec<Recap>, MotorheadError> {
+        Err(MotorheadError::Unsupported("user recaps"))
+    }
+
+    async fn delete_recaps(&self, _user_id: &str) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("user recaps"))
+    }
+
+    /// Appends to the audit log, shared by all tenants like the compaction retry queue.
+    async fn append_audit(&self, _entry: &AuditEntry) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("audit log"))
+    }
+
+    /// Up to `limit` audit entries from `since` (milliseconds since the Unix epoch) on,
+    /// oldest first.
+    async fn audit_entries(
+        &self,
+        _since: u64,
+        _limit: usize,
+    ) -> Result<Vec<AuditEntry>, MotorheadError> {
+        Err(MotorheadError::Unsupported("audit log"))
+    }
+
+    /// Takes a token from the rate limit bucket `bucket`, holding up to `capacity` tokens and
+    /// refilled at `refill_per_ms` tokens a millisecond. Returns how many ms to wait when the
+    /// bucket is empty. Buckets are shared by all tenants, like the compaction retry queue.
+    async fn take_rate_token(
+        &self,
+        _bucket: &str,
+        _capacity: u32,
+        _refill_per_ms: f64,
+    ) -> Result<Option<u64>, MotorheadError> {
+        Err(MotorheadError::Unsupported("rate limit buckets"))
+    }
+
+    /// Prepares the backend to store vectors of `dimensions` floats. Backends without vector
+    /// search keep the default, which makes retrieval unavailable.
+    async fn init_vectors(&self, _dimensions: usize) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("vector retrieval"))
+    }
+
+    async fn add_vectors(
+        &self,
+        _session_id: &str,
+        _entries: Vec<(MemoryMessage, Vec<f32>)>,
+    ) -> Result<(), MotorheadError> {
+        Err(MotorheadError::Unsupported("vector retrieval"))
+    }
+
+    /// Returns the `limit` stored messages closest to `vector`, nearest first.
+    async fn search_vectors(
+        &self,
+        _session_id: &str,
+        _vector: Vec<f32>,
+        _limit: usize,
+    ) -> Result<Vec<RetrievalResult>, MotorheadError> {
+        Err(MotorheadError::Unsupported("vector retrieval"))
+    }
+}
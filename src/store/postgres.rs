@@ -0,0 +1,1432 @@
+use async_trait::async_trait;
+use deadpool_postgres::{Manager, Pool};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_postgres::types::Json;
+use tokio_postgres::{NoTls, Row};
+
+use super::{merge_pinned, MemoryStore};
+use crate::models::{
+    Attachment, CompactionProgress, CompactionStamp, ContextSegment, ContextVersion, MemoryMessage,
+    MotorheadError, Recap, SessionConfig, StoredSize, TokenUsage, ToolCall,
+};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS motorhead_sessions (
+    tenant TEXT NOT NULL DEFAULT '',
+    session_id TEXT NOT NULL,
+    context TEXT,
+    metadata JSONB,
+    last_activity TIMESTAMPTZ,
+    unsummarized BIGINT NOT NULL DEFAULT 0,
+    entities JSONB,
+    config JSONB,
+    long_term_context TEXT,
+    pinned JSONB,
+    pinned_auto JSONB,
+    compaction_progress JSONB,
+    context_segments JSONB,
+    prompt_tokens BIGINT NOT NULL DEFAULT 0,
+    completion_tokens BIGINT NOT NULL DEFAULT 0,
+    compacted_at BIGINT,
+    compactions BIGINT NOT NULL DEFAULT 0,
+    kv JSONB
+);
+
+CREATE TABLE IF NOT EXISTS motorhead_usage (
+    tenant TEXT NOT NULL DEFAULT '',
+    month TEXT NOT NULL,
+    prompt_tokens BIGINT NOT NULL DEFAULT 0,
+    completion_tokens BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (month, tenant)
+);
+
+CREATE TABLE IF NOT EXISTS motorhead_recaps (
+    tenant TEXT NOT NULL DEFAULT '',
+    user_id TEXT NOT NULL,
+    date TEXT NOT NULL,
+    recap JSONB NOT NULL,
+    PRIMARY KEY (tenant, user_id, date)
+);
+
+CREATE TABLE IF NOT EXISTS motorhead_idempotency_keys (
+    tenant TEXT NOT NULL DEFAULT '',
+    session_id TEXT NOT NULL,
+    key TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (tenant, session_id, key)
+);
+
+CREATE TABLE IF NOT EXISTS motorhead_messages (
+    id BIGSERIAL PRIMARY KEY,
+    tenant TEXT NOT NULL DEFAULT '',
+    session_id TEXT NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    message_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    metadata JSONB,
+    tool_calls JSONB,
+    tool_call_id TEXT,
+    name TEXT,
+    speaker TEXT,
+    attachments JSONB,
+    turn_id TEXT
+);
+
+-- Messages compactions moved out of the window, keeping their ids from motorhead_messages.
+CREATE TABLE IF NOT EXISTS motorhead_history (
+    id BIGINT PRIMARY KEY,
+    tenant TEXT NOT NULL DEFAULT '',
+    session_id TEXT NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    message_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL,
+    metadata JSONB,
+    tool_calls JSONB,
+    tool_call_id TEXT,
+    name TEXT,
+    speaker TEXT,
+    attachments JSONB,
+    turn_id TEXT
+);
+
+CREATE INDEX IF NOT EXISTS motorhead_history_tenant_session_id_idx
+    ON motorhead_history (tenant, session_id, id);
+
+-- Tables created before tenants existed are keyed by session_id alone.
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS tenant TEXT NOT NULL DEFAULT '';
+ALTER TABLE motorhead_messages ADD COLUMN IF NOT EXISTS tenant TEXT NOT NULL DEFAULT '';
+ALTER TABLE motorhead_messages ADD COLUMN IF NOT EXISTS message_id TEXT;
+ALTER TABLE motorhead_messages ADD COLUMN IF NOT EXISTS metadata JSONB;
+ALTER TABLE motorhead_history ADD COLUMN IF NOT EXISTS metadata JSONB;
+ALTER TABLE motorhead_messages ADD COLUMN IF NOT EXISTS tool_calls JSONB;
+ALTER TABLE motorhead_messages ADD COLUMN IF NOT EXISTS tool_call_id TEXT;
+ALTER TABLE motorhead_messages ADD COLUMN IF NOT EXISTS name TEXT;
+ALTER TABLE motorhead_history ADD COLUMN IF NOT EXISTS tool_calls JSONB;
+ALTER TABLE motorhead_history ADD COLUMN IF NOT EXISTS tool_call_id TEXT;
+ALTER TABLE motorhead_history ADD COLUMN IF NOT EXISTS name TEXT;
+ALTER TABLE motorhead_messages ADD COLUMN IF NOT EXISTS speaker TEXT;
+ALTER TABLE motorhead_history ADD COLUMN IF NOT EXISTS speaker TEXT;
+ALTER TABLE motorhead_messages ADD COLUMN IF NOT EXISTS attachments JSONB;
+ALTER TABLE motorhead_history ADD COLUMN IF NOT EXISTS attachments JSONB;
+ALTER TABLE motorhead_messages ADD COLUMN IF NOT EXISTS turn_id TEXT;
+ALTER TABLE motorhead_history ADD COLUMN IF NOT EXISTS turn_id TEXT;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS unsummarized BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS entities JSONB;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS config JSONB;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS long_term_context TEXT;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS pinned JSONB;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS context_segments JSONB;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS prompt_tokens BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS completion_tokens BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS compacted_at BIGINT;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS compactions BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS kv JSONB;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS pinned_auto JSONB;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS compaction_progress JSONB;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS languages JSONB;
+ALTER TABLE motorhead_sessions ADD COLUMN IF NOT EXISTS context_versions JSONB;
+ALTER TABLE motorhead_sessions DROP CONSTRAINT IF EXISTS motorhead_sessions_pkey;
+DROP INDEX IF EXISTS motorhead_messages_session_id_idx;
+DROP INDEX IF EXISTS motorhead_sessions_last_activity_idx;
+
+CREATE UNIQUE INDEX IF NOT EXISTS motorhead_sessions_tenant_session_id_idx
+    ON motorhead_sessions (tenant, session_id);
+
+CREATE INDEX IF NOT EXISTS motorhead_messages_tenant_session_id_idx
+    ON motorhead_messages (tenant, session_id, id DESC);
+
+CREATE INDEX IF NOT EXISTS motorhead_sessions_tenant_last_activity_idx
+    ON motorhead_sessions (tenant, last_activity DESC);
+"#;
+
+/// Decodes the columns selected for messages, in order: role, content, message_id, created_at
+/// (ms), metadata, tool_calls, tool_call_id, name, speaker, attachments and turn_id.
+fn message_from_row(row: Row) -> MemoryMessage {
+    MemoryMessage {
+        role: row.get::<_, String>(0).into(),
+        content: row.get(1),
+        id: row.get(2),
+        created_at: Some(row.get::<_, i64>(3) as u64),
+        metadata: row
+            .get::<_, Option<Json<serde_json::Map<String, serde_json::Value>>>>(4)
+            .map(|Json(metadata)| metadata),
+        tool_calls: row
+            .get::<_, Option<Json<Vec<ToolCall>>>>(5)
+            .map(|Json(calls)| calls),
+        tool_call_id: row.get(6),
+        name: row.get(7),
+        speaker: row.get(8),
+        attachments: row
+            .get::<_, Option<Json<Vec<Attachment>>>>(9)
+            .map(|Json(attachments)| attachments),
+        turn_id: row.get(10),
+        importance: None,
+    }
+}
+
+/// Decodes the prompt_tokens and completion_tokens columns, selected first.
+fn usage_from_row(row: Row) -> TokenUsage {
+    TokenUsage {
+        prompt_tokens: row.get::<_, i64>(0) as u64,
+        completion_tokens: row.get::<_, i64>(1) as u64,
+    }
+}
+
+pub struct PostgresStore {
+    pool: Pool,
+    /// Empty for the default namespace.
+    tenant: String,
+}
+
+impl PostgresStore {
+    /// Connects to `url` and creates the tables if they don't exist yet.
+    pub async fn connect(url: &str, pool_size: usize) -> Result<Self, MotorheadError> {
+        let config = tokio_postgres::Config::from_str(url)?;
+        let manager = Manager::new(config, NoTls);
+        let pool = Pool::builder(manager)
+            .max_size(pool_size)
+            .build()
+            .map_err(|e| MotorheadError::PostgresError(e.to_string()))?;
+
+        let client = pool.get().await?;
+        client.batch_execute(SCHEMA).await?;
+
+        Ok(PostgresStore {
+            pool,
+            tenant: String::new(),
+        })
+    }
+
+    /// Resolves Redis-style (possibly negative) newest-first indices into an offset and an
+    /// optional limit.
+    async fn resolve_range(
+        &self,
+        client: &deadpool_postgres::Client,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<(i64, Option<i64>), MotorheadError> {
+        if start >= 0 && stop >= 0 {
+            return Ok((start, Some((stop - start + 1).max(0))));
+        }
+        if start >= 0 && stop == -1 {
+            return Ok((start, None));
+        }
+
+        let row = client
+            .query_one(
+                "SELECT COUNT(*) FROM motorhead_messages WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+        let len: i64 = row.get(0);
+
+        let start = if start < 0 {
+            (len + start).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 { len + stop } else { stop };
+
+        Ok((start, Some((stop - start + 1).max(0))))
+    }
+}
+
+#[async_trait]
+impl MemoryStore for PostgresStore {
+    fn for_tenant(&self, tenant: &str) -> Arc<dyn MemoryStore> {
+        Arc::new(PostgresStore {
+            pool: self.pool.clone(),
+            tenant: tenant.to_string(),
+        })
+    }
+
+    async fn ping(&self) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client.execute("SELECT 1", &[]).await?;
+
+        Ok(())
+    }
+
+    async fn get_messages(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let client = self.pool.get().await?;
+        let (offset, limit) = self.resolve_range(&client, session_id, start, stop).await?;
+
+        let rows = client
+            .query(
+                "SELECT role, content, message_id, \
+                 (EXTRACT(EPOCH FROM created_at) * 1000)::BIGINT, metadata, \
+                 tool_calls, tool_call_id, name, speaker, attachments, turn_id \
+                 FROM motorhead_messages WHERE tenant = $1 AND session_id = $2 \
+                 ORDER BY id DESC OFFSET $3 LIMIT $4",
+                &[&self.tenant, &session_id, &offset, &limit],
+            )
+            .await?;
+
+        Ok(rows.into_iter().map(message_from_row).collect())
+    }
+
+    async fn append_messages(
+        &self,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<i64, MotorheadError> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        let insert = transaction
+            .prepare(
+                "INSERT INTO motorhead_messages \
+                 (tenant, session_id, role, content, message_id, created_at, metadata, \
+                 tool_calls, tool_call_id, name, speaker, attachments, turn_id) \
+                 VALUES ($1, $2, $3, $4, $5, \
+                 COALESCE(to_timestamp($6::BIGINT / 1000.0), now()), $7, $8, $9, $10, $11, $12, \
+                 $13)",
+            )
+            .await?;
+        for message in &messages {
+            transaction
+                .execute(
+                    &insert,
+                    &[
+                        &self.tenant,
+                        &session_id,
+                        &message.role.as_str(),
+                        &message.content,
+                        &message.id,
+                        &message.created_at.map(|ms| ms as i64),
+                        &message.metadata.as_ref().map(Json),
+                        &message.tool_calls.as_ref().map(Json),
+                        &message.tool_call_id,
+                        &message.name,
+                        &message.speaker,
+                        &message.attachments.as_ref().map(Json),
+                        &message.turn_id,
+                    ],
+                )
+                .await?;
+        }
+
+        transaction
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, last_activity, unsummarized) \
+                 VALUES ($1, $2, now(), $3) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET last_activity = now(), \
+                 unsummarized = motorhead_sessions.unsummarized + EXCLUDED.unsummarized",
+                &[&self.tenant, &session_id, &(messages.len() as i64)],
+            )
+            .await?;
+
+        let row = transaction
+            .query_one(
+                "SELECT COUNT(*) FROM motorhead_messages WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(row.get(0))
+    }
+
+    async fn trim_messages(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+        archive: bool,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+        let (offset, limit) = self.resolve_range(&client, session_id, start, stop).await?;
+
+        let delete = "DELETE FROM motorhead_messages WHERE tenant = $1 AND session_id = $2 \
+             AND id NOT IN ( \
+                 SELECT id FROM motorhead_messages WHERE tenant = $1 AND session_id = $2 \
+                 ORDER BY id DESC OFFSET $3 LIMIT $4 \
+             )";
+        let query = if archive {
+            format!(
+                "WITH removed AS ({} \
+                 RETURNING id, tenant, session_id, role, content, message_id, created_at, \
+                 metadata, tool_calls, tool_call_id, name, speaker, attachments, turn_id) \
+                 INSERT INTO motorhead_history \
+                 (id, tenant, session_id, role, content, message_id, created_at, metadata, \
+                 tool_calls, tool_call_id, name, speaker, attachments, turn_id) \
+                 SELECT * FROM removed",
+                delete
+            )
+        } else {
+            delete.to_string()
+        };
+
+        client
+            .execute(&query, &[&self.tenant, &session_id, &offset, &limit])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn stored_size(&self, session_id: &str) -> Result<StoredSize, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_one(
+                "SELECT \
+                 (SELECT COUNT(*) FROM motorhead_messages WHERE tenant = $1 AND session_id = $2), \
+                 (SELECT COUNT(*) FROM motorhead_history WHERE tenant = $1 AND session_id = $2), \
+                 (SELECT COALESCE(SUM(octet_length(content)), 0) FROM motorhead_messages \
+                  WHERE tenant = $1 AND session_id = $2) \
+                 + (SELECT COALESCE(SUM(octet_length(content)), 0) FROM motorhead_history \
+                  WHERE tenant = $1 AND session_id = $2)",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(StoredSize {
+            window: row.get::<_, i64>(0) as u64,
+            history: row.get::<_, i64>(1) as u64,
+            bytes: row.get::<_, i64>(2) as u64,
+        })
+    }
+
+    async fn drop_history(&self, session_id: &str, count: usize) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "DELETE FROM motorhead_history WHERE id IN ( \
+                     SELECT id FROM motorhead_history WHERE tenant = $1 AND session_id = $2 \
+                     ORDER BY id LIMIT $3 \
+                 )",
+                &[&self.tenant, &session_id, &(count as i64)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_history(
+        &self,
+        session_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT role, content, message_id, \
+                 (EXTRACT(EPOCH FROM created_at) * 1000)::BIGINT, metadata, \
+                 tool_calls, tool_call_id, name, speaker, attachments, turn_id \
+                 FROM motorhead_history WHERE tenant = $1 AND session_id = $2 \
+                 ORDER BY id OFFSET $3 LIMIT $4",
+                &[&self.tenant, &session_id, &(offset as i64), &(limit as i64)],
+            )
+            .await?;
+
+        Ok(rows.into_iter().map(message_from_row).collect())
+    }
+
+    async fn get_history_tail(
+        &self,
+        session_id: &str,
+        count: usize,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT role, content, message_id, \
+                 (EXTRACT(EPOCH FROM created_at) * 1000)::BIGINT, metadata, \
+                 tool_calls, tool_call_id, name, speaker, attachments, turn_id \
+                 FROM motorhead_history WHERE tenant = $1 AND session_id = $2 \
+                 ORDER BY id DESC LIMIT $3",
+                &[&self.tenant, &session_id, &(count as i64)],
+            )
+            .await?;
+
+        let mut messages: Vec<MemoryMessage> = rows.into_iter().map(message_from_row).collect();
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn get_context(&self, session_id: &str) -> Result<Option<String>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT context FROM motorhead_sessions WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(row.and_then(|row| row.get(0)))
+    }
+
+    async fn set_context(&self, session_id: &str, context: &str) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, context) VALUES ($1, $2, $3) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET context = EXCLUDED.context, \
+                 unsummarized = 0",
+                &[&self.tenant, &session_id, &context],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_long_term_context(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<String>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT long_term_context FROM motorhead_sessions \
+                 WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(row.and_then(|row| row.get(0)))
+    }
+
+    async fn set_long_term_context(
+        &self,
+        session_id: &str,
+        long_term_context: &str,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, long_term_context) \
+                 VALUES ($1, $2, $3) ON CONFLICT (tenant, session_id) \
+                 DO UPDATE SET long_term_context = EXCLUDED.long_term_context",
+                &[&self.tenant, &session_id, &long_term_context],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_context_segments(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ContextSegment>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT context_segments FROM motorhead_sessions \
+                 WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        let segments: Option<Json<Vec<ContextSegment>>> = row.and_then(|row| row.get(0));
+        Ok(segments.map(|Json(segments)| segments).unwrap_or_default())
+    }
+
+    async fn set_context_segments(
+        &self,
+        session_id: &str,
+        segments: &[ContextSegment],
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let segments = serde_json::to_value(segments)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, context_segments) \
+                 VALUES ($1, $2, $3) ON CONFLICT (tenant, session_id) \
+                 DO UPDATE SET context_segments = EXCLUDED.context_segments",
+                &[&self.tenant, &session_id, &segments],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn fold_context(
+        &self,
+        session_id: &str,
+        long_term_context: &str,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, long_term_context) \
+                 VALUES ($1, $2, $3) ON CONFLICT (tenant, session_id) \
+                 DO UPDATE SET long_term_context = EXCLUDED.long_term_context, context = NULL",
+                &[&self.tenant, &session_id, &long_term_context],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn replace_contexts(
+        &self,
+        session_id: &str,
+        context: Option<&str>,
+        long_term_context: Option<&str>,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, context, long_term_context) \
+                 VALUES ($1, $2, $3, $4) ON CONFLICT (tenant, session_id) \
+                 DO UPDATE SET context = EXCLUDED.context, \
+                 long_term_context = EXCLUDED.long_term_context",
+                &[&self.tenant, &session_id, &context, &long_term_context],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn messages_since_summary(&self, session_id: &str) -> Result<u64, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT unsummarized FROM motorhead_sessions WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(row.map_or(0, |row| row.get::<_, i64>(0) as u64))
+    }
+
+    async fn get_metadata(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<serde_json::Value>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT metadata FROM motorhead_sessions WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(row.and_then(|row| row.get(0)))
+    }
+
+    async fn set_metadata(
+        &self,
+        session_id: &str,
+        metadata: &serde_json::Value,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, metadata) VALUES ($1, $2, $3) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET metadata = EXCLUDED.metadata",
+                &[&self.tenant, &session_id, metadata],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_metadata(&self, session_id: &str) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "UPDATE motorhead_sessions SET metadata = NULL WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_session_config(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<SessionConfig>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT config FROM motorhead_sessions WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(row
+            .and_then(|row| row.get::<_, Option<Json<SessionConfig>>>(0))
+            .map(|Json(config)| config))
+    }
+
+    async fn set_session_config(
+        &self,
+        session_id: &str,
+        config: &SessionConfig,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, config) VALUES ($1, $2, $3) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET config = EXCLUDED.config",
+                &[&self.tenant, &session_id, &Json(config)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_entities(
+        &self,
+        session_id: &str,
+    ) -> Result<BTreeMap<String, String>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT entities FROM motorhead_sessions WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        let entities: Option<serde_json::Value> = row.and_then(|row| row.get(0));
+        entities
+            .map(serde_json::from_value)
+            .transpose()
+            .map(Option::unwrap_or_default)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    async fn merge_entities(
+        &self,
+        session_id: &str,
+        entities: &BTreeMap<String, String>,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let entities = serde_json::to_value(entities)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, entities) VALUES ($1, $2, $3) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET \
+                 entities = COALESCE(motorhead_sessions.entities, '{}'::jsonb) || EXCLUDED.entities",
+                &[&self.tenant, &session_id, &entities],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_kv(
+        &self,
+        session_id: &str,
+        key: &str,
+    ) -> Result<Option<serde_json::Value>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT kv -> $3::text FROM motorhead_sessions \
+                 WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id, &key],
+            )
+            .await?;
+
+        Ok(row.and_then(|row| row.get(0)))
+    }
+
+    async fn set_kv(
+        &self,
+        session_id: &str,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, kv) \
+                 VALUES ($1, $2, jsonb_build_object($3::text, $4::jsonb)) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET \
+                 kv = COALESCE(motorhead_sessions.kv, '{}'::jsonb) || EXCLUDED.kv",
+                &[&self.tenant, &session_id, &key, value],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_kv(&self, session_id: &str, key: &str) -> Result<bool, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let deleted = client
+            .execute(
+                "UPDATE motorhead_sessions SET kv = kv - $3::text \
+                 WHERE tenant = $1 AND session_id = $2 AND kv -> $3::text IS NOT NULL",
+                &[&self.tenant, &session_id, &key],
+            )
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        session_id: &str,
+        key: &str,
+        ttl_seconds: u64,
+    ) -> Result<bool, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        // Expired keys are swept by the session's next claim.
+        client
+            .execute(
+                "DELETE FROM motorhead_idempotency_keys \
+                 WHERE tenant = $1 AND session_id = $2 AND expires_at < now()",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+        let claimed = client
+            .execute(
+                "INSERT INTO motorhead_idempotency_keys (tenant, session_id, key, expires_at) \
+                 VALUES ($1, $2, $3, now() + $4::BIGINT * interval '1 second') \
+                 ON CONFLICT DO NOTHING",
+                &[&self.tenant, &session_id, &key, &(ttl_seconds as i64)],
+            )
+            .await?;
+
+        Ok(claimed == 1)
+    }
+
+    async fn release_idempotency_key(
+        &self,
+        session_id: &str,
+        key: &str,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "DELETE FROM motorhead_idempotency_keys \
+                 WHERE tenant = $1 AND session_id = $2 AND key = $3",
+                &[&self.tenant, &session_id, &key],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn list_sessions(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(String, u64)>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT session_id, (EXTRACT(EPOCH FROM last_activity) * 1000)::BIGINT \
+                 FROM motorhead_sessions WHERE tenant = $1 AND last_activity IS NOT NULL \
+                 ORDER BY last_activity DESC, session_id OFFSET $2 LIMIT $3",
+                &[&self.tenant, &(offset as i64), &(limit as i64)],
+            )
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get(0), row.get::<_, i64>(1) as u64))
+            .collect())
+    }
+
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<String>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT session_id FROM motorhead_sessions \
+                 WHERE tenant = $1 AND last_activity IS NOT NULL AND metadata->>'user_id' = $2 \
+                 ORDER BY last_activity DESC, session_id",
+                &[&self.tenant, &user_id],
+            )
+            .await?;
+
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn get_pinned(&self, session_id: &str) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT pinned, pinned_auto FROM motorhead_sessions \
+                 WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+        let Some(row) = row else {
+            return Ok(Vec::new());
+        };
+
+        let pinned: Option<Json<BTreeMap<String, MemoryMessage>>> = row.get(0);
+        let auto_pinned: Option<Json<Vec<MemoryMessage>>> = row.get(1);
+        Ok(merge_pinned(
+            pinned
+                .map(|Json(pinned)| pinned.into_values().collect())
+                .unwrap_or_default(),
+            auto_pinned
+                .map(|Json(auto_pinned)| auto_pinned)
+                .unwrap_or_default(),
+        ))
+    }
+
+    async fn get_auto_pinned(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT pinned_auto FROM motorhead_sessions WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        let auto_pinned: Option<Json<Vec<MemoryMessage>>> = row.and_then(|row| row.get(0));
+        Ok(auto_pinned
+            .map(|Json(auto_pinned)| auto_pinned)
+            .unwrap_or_default())
+    }
+
+    async fn set_auto_pinned(
+        &self,
+        session_id: &str,
+        messages: &[MemoryMessage],
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let mut messages = messages.to_vec();
+        messages.sort_by_key(|message| message.created_at);
+        let encoded = serde_json::to_value(&messages)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, pinned_auto) \
+                 VALUES ($1, $2, $3::JSONB) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET pinned_auto = EXCLUDED.pinned_auto",
+                &[&self.tenant, &session_id, &encoded],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn pin_message(
+        &self,
+        session_id: &str,
+        message: &MemoryMessage,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let encoded = serde_json::to_value(message)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, pinned) \
+                 VALUES ($1, $2, jsonb_build_object($3::TEXT, $4::JSONB)) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET \
+                 pinned = COALESCE(motorhead_sessions.pinned, '{}'::jsonb) || EXCLUDED.pinned",
+                &[
+                    &self.tenant,
+                    &session_id,
+                    &message.id.as_deref().unwrap_or_default(),
+                    &encoded,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn unpin_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+    ) -> Result<bool, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        // Auto-pinned messages are kept as an array, filtered by id.
+        let unpinned = client
+            .execute(
+                "UPDATE motorhead_sessions SET \
+                 pinned = COALESCE(pinned, '{}'::jsonb) - $3::TEXT, \
+                 pinned_auto = (SELECT COALESCE(jsonb_agg(message), '[]'::jsonb) \
+                 FROM jsonb_array_elements(COALESCE(pinned_auto, '[]'::jsonb)) AS message \
+                 WHERE message->>'id' IS DISTINCT FROM $3::TEXT) \
+                 WHERE tenant = $1 AND session_id = $2 \
+                 AND (pinned ? $3 OR pinned_auto @> jsonb_build_array(jsonb_build_object('id', $3::TEXT)))",
+                &[&self.tenant, &session_id, &message_id],
+            )
+            .await?;
+
+        Ok(unpinned > 0)
+    }
+
+    async fn record_usage(
+        &self,
+        session_id: &str,
+        month: &str,
+        usage: TokenUsage,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let prompt_tokens = usage.prompt_tokens as i64;
+        let completion_tokens = usage.completion_tokens as i64;
+        client
+            .execute(
+                "WITH session AS ( \
+                     INSERT INTO motorhead_sessions \
+                     (tenant, session_id, prompt_tokens, completion_tokens) \
+                     VALUES ($1, $2, $4, $5) \
+                     ON CONFLICT (tenant, session_id) DO UPDATE SET \
+                     prompt_tokens = motorhead_sessions.prompt_tokens + EXCLUDED.prompt_tokens, \
+                     completion_tokens = \
+                     motorhead_sessions.completion_tokens + EXCLUDED.completion_tokens \
+                 ) \
+                 INSERT INTO motorhead_usage (tenant, month, prompt_tokens, completion_tokens) \
+                 VALUES ($1, $3, $4, $5) \
+                 ON CONFLICT (month, tenant) DO UPDATE SET \
+                 prompt_tokens = motorhead_usage.prompt_tokens + EXCLUDED.prompt_tokens, \
+                 completion_tokens = motorhead_usage.completion_tokens + EXCLUDED.completion_tokens",
+                &[
+                    &self.tenant,
+                    &session_id,
+                    &month,
+                    &prompt_tokens,
+                    &completion_tokens,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_session_usage(&self, session_id: &str) -> Result<TokenUsage, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT prompt_tokens, completion_tokens FROM motorhead_sessions \
+                 WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(row.map(usage_from_row).unwrap_or_default())
+    }
+
+    async fn record_languages(
+        &self,
+        session_id: &str,
+        counts: &BTreeMap<String, u64>,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        // Sums the counts of each language of both objects.
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, languages) \
+                 VALUES ($1, $2, $3) ON CONFLICT (tenant, session_id) DO UPDATE SET \
+                 languages = ( \
+                     SELECT jsonb_object_agg(key, total) FROM ( \
+                         SELECT key, SUM(value::BIGINT) AS total FROM ( \
+                             SELECT * FROM jsonb_each_text( \
+                                 COALESCE(motorhead_sessions.languages, '{}'::JSONB)) \
+                             UNION ALL SELECT * FROM jsonb_each_text(EXCLUDED.languages) \
+                         ) AS counts GROUP BY key \
+                     ) AS totals \
+                 )",
+                &[&self.tenant, &session_id, &Json(counts)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_languages(
+        &self,
+        session_id: &str,
+    ) -> Result<BTreeMap<String, u64>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT languages FROM motorhead_sessions WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(row
+            .and_then(|row| row.get::<_, Option<Json<BTreeMap<String, u64>>>>(0))
+            .map(|Json(counts)| counts)
+            .unwrap_or_default())
+    }
+
+    async fn record_context_version(
+        &self,
+        session_id: &str,
+        version: &ContextVersion,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, context_versions) \
+                 VALUES ($1, $2, jsonb_build_array($3::JSONB)) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET context_versions = \
+                 COALESCE(motorhead_sessions.context_versions, '[]'::JSONB) \
+                 || EXCLUDED.context_versions",
+                &[&self.tenant, &session_id, &Json(version)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_context_versions(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ContextVersion>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT context_versions FROM motorhead_sessions \
+                 WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(row
+            .and_then(|row| row.get::<_, Option<Json<Vec<ContextVersion>>>>(0))
+            .map(|Json(versions)| versions)
+            .unwrap_or_default())
+    }
+
+    async fn record_compaction(&self, session_id: &str, at: u64) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, compacted_at, compactions) \
+                 VALUES ($1, $2, $3, 1) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET \
+                 compacted_at = EXCLUDED.compacted_at, \
+                 compactions = motorhead_sessions.compactions + 1",
+                &[&self.tenant, &session_id, &(at as i64)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_compaction_progress(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<CompactionProgress>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT compaction_progress FROM motorhead_sessions \
+                 WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        let progress: Option<Json<CompactionProgress>> = row.and_then(|row| row.get(0));
+        Ok(progress.map(|Json(progress)| progress))
+    }
+
+    async fn set_compaction_progress(
+        &self,
+        session_id: &str,
+        progress: Option<&CompactionProgress>,
+    ) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "INSERT INTO motorhead_sessions (tenant, session_id, compaction_progress) \
+                 VALUES ($1, $2, $3) \
+                 ON CONFLICT (tenant, session_id) DO UPDATE SET \
+                 compaction_progress = EXCLUDED.compaction_progress",
+                &[&self.tenant, &session_id, &progress.map(Json)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn last_compaction(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<CompactionStamp>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT compacted_at, compactions FROM motorhead_sessions \
+                 WHERE tenant = $1 AND session_id = $2 AND compacted_at IS NOT NULL",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        Ok(row.map(|row| CompactionStamp {
+            at: row.get::<_, i64>(0) as u64,
+            count: row.get::<_, i64>(1) as u64,
+        }))
+    }
+
+    async fn get_tenant_usage(&self, month: &str) -> Result<TokenUsage, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT prompt_tokens, completion_tokens FROM motorhead_usage \
+                 WHERE month = $1 AND tenant = $2",
+                &[&month, &self.tenant],
+            )
+            .await?;
+
+        Ok(row.map(usage_from_row).unwrap_or_default())
+    }
+
+    async fn set_recap(&self, recap: &Recap, oldest_date: &str) -> Result<(), MotorheadError> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        transaction
+            .execute(
+                "INSERT INTO motorhead_recaps (tenant, user_id, date, recap) \
+                 VALUES ($1, $2, $3, $4) \
+                 ON CONFLICT (tenant, user_id, date) DO UPDATE SET recap = EXCLUDED.recap",
+                &[&self.tenant, &recap.user_id, &recap.date, &Json(recap)],
+            )
+            .await?;
+        transaction
+            .execute(
+                "DELETE FROM motorhead_recaps WHERE tenant = $1 AND user_id = $2 AND date < $3",
+                &[&self.tenant, &recap.user_id, &oldest_date],
+            )
+            .await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn has_recap(&self, user_id: &str, date: &str) -> Result<bool, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_opt(
+                "SELECT 1 FROM motorhead_recaps WHERE tenant = $1 AND user_id = $2 AND date = $3",
+                &[&self.tenant, &user_id, &date],
+            )
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn get_recaps(&self, user_id: &str, limit: usize) -> Result<Vec<Recap>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT recap FROM motorhead_recaps WHERE tenant = $1 AND user_id = $2 \
+                 ORDER BY date DESC LIMIT $3",
+                &[&self.tenant, &user_id, &(limit as i64)],
+            )
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| row.get::<_, Json<Recap>>(0).0)
+            .collect())
+    }
+
+    async fn delete_recaps(&self, user_id: &str) -> Result<(), MotorheadError> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "DELETE FROM motorhead_recaps WHERE tenant = $1 AND user_id = $2",
+                &[&self.tenant, &user_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn usage_by_tenant(
+        &self,
+        month: &str,
+    ) -> Result<Vec<(Option<String>, TokenUsage)>, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT prompt_tokens, completion_tokens, tenant FROM motorhead_usage \
+                 WHERE month = $1 ORDER BY tenant",
+                &[&month],
+            )
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let tenant: String = row.get(2);
+                ((!tenant.is_empty()).then_some(tenant), usage_from_row(row))
+            })
+            .collect())
+    }
+
+    async fn update_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> Result<bool, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let updated = client
+            .execute(
+                "UPDATE motorhead_messages SET content = $4 \
+                 WHERE tenant = $1 AND session_id = $2 AND message_id = $3",
+                &[&self.tenant, &session_id, &message_id, &content],
+            )
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn delete_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+    ) -> Result<bool, MotorheadError> {
+        let client = self.pool.get().await?;
+
+        let deleted = client
+            .execute(
+                "DELETE FROM motorhead_messages \
+                 WHERE tenant = $1 AND session_id = $2 AND message_id = $3",
+                &[&self.tenant, &session_id, &message_id],
+            )
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn delete_newest_messages(
+        &self,
+        session_id: &str,
+        ids: &[Option<String>],
+    ) -> Result<bool, MotorheadError> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        // Locked so that a concurrent delete can't take them meanwhile.
+        let rows = transaction
+            .query(
+                "SELECT id, message_id FROM motorhead_messages \
+                 WHERE tenant = $1 AND session_id = $2 \
+                 ORDER BY id DESC LIMIT $3 FOR UPDATE",
+                &[&self.tenant, &session_id, &(ids.len() as i64)],
+            )
+            .await?;
+        if rows.len() != ids.len()
+            || !rows
+                .iter()
+                .map(|row| row.get::<_, Option<String>>(1))
+                .eq(ids.iter().cloned())
+        {
+            return Ok(false);
+        }
+
+        let rows: Vec<i64> = rows.iter().map(|row| row.get(0)).collect();
+        transaction
+            .execute(
+                "DELETE FROM motorhead_messages WHERE id = ANY($1)",
+                &[&rows],
+            )
+            .await?;
+        let message_ids: Vec<&str> = ids.iter().filter_map(|id| id.as_deref()).collect();
+        transaction
+            .execute(
+                "UPDATE motorhead_sessions SET \
+                 pinned = COALESCE(pinned, '{}'::jsonb) - $3::TEXT[], \
+                 pinned_auto = (SELECT COALESCE(jsonb_agg(message), '[]'::jsonb) \
+                 FROM jsonb_array_elements(COALESCE(pinned_auto, '[]'::jsonb)) AS message \
+                 WHERE COALESCE(message->>'id' <> ALL($3::TEXT[]), true)) \
+                 WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id, &message_ids],
+            )
+            .await?;
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), MotorheadError> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        transaction
+            .execute(
+                "DELETE FROM motorhead_messages WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+        transaction
+            .execute(
+                "DELETE FROM motorhead_history WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+        transaction
+            .execute(
+                "DELETE FROM motorhead_sessions WHERE tenant = $1 AND session_id = $2",
+                &[&self.tenant, &session_id],
+            )
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
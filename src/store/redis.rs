@@ -0,0 +1,3177 @@
+use async_trait::async_trait;
+use futures_util::stream::{BoxStream, StreamExt};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::topology::{RedisConnection, RedisPool};
+use super::{apply_batch_sequentially, merge_pinned, BatchOp, MemoryStore, Restore, SessionWindow};
+use crate::compression::{decompress, Compression};
+use crate::encryption::{Cipher, TenantKeys};
+use crate::keys::{SessionKeys, VECTOR_INDEX, VECTOR_PREFIX};
+use crate::metrics;
+use crate::models::{
+    AnalyticsCounts, AuditEntry, ColdSegment, CompactionFailure, CompactionProgress,
+    CompactionStamp, ContextSegment, ContextVersion, MemoryFields, MemoryMessage, MotorheadError,
+    Recap, RetrievalResult, SessionConfig, SessionEvent, StoredSize, TokenUsage,
+};
+
+/// How each session's messages are kept, picked with `MOTORHEAD_REDIS_MESSAGE_LOG`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MessageLog {
+    /// A list, newest message first.
+    List,
+    /// A stream with an entry per message, holding it in its `message` field. Entries get
+    /// server-generated ids and can be read with consumer groups.
+    Stream,
+}
+
+#[derive(Clone)]
+pub struct RedisStore {
+    pool: RedisPool,
+    keys: SessionKeys,
+    log: MessageLog,
+    /// Encrypts message contents and summaries, if enabled.
+    cipher: Option<Arc<Cipher>>,
+    /// Tenants encrypting with their own key instead of `cipher`, if enabled.
+    tenant_keys: Option<TenantKeys>,
+    /// Compresses long message contents, if enabled.
+    compression: Option<Arc<Compression>>,
+    /// Returns undecodable entries as `unknown` messages rather than skipping them.
+    recover_malformed: bool,
+}
+
+impl RedisStore {
+    pub fn new(
+        pool: RedisPool,
+        keys: SessionKeys,
+        log: MessageLog,
+        cipher: Option<Arc<Cipher>>,
+        tenant_keys: Option<TenantKeys>,
+        compression: Option<Arc<Compression>>,
+        recover_malformed: bool,
+    ) -> Self {
+        RedisStore {
+            pool,
+            keys,
+            log,
+            cipher,
+            tenant_keys,
+            compression,
+            recover_malformed,
+        }
+    }
+
+    /// The same store read from a replica through `pool`.
+    pub fn replica(&self, pool: RedisPool) -> Self {
+        RedisStore {
+            pool,
+            keys: self.keys.clone(),
+            log: self.log,
+            cipher: self.cipher.clone(),
+            tenant_keys: self.tenant_keys.clone(),
+            compression: self.compression.clone(),
+            recover_malformed: self.recover_malformed,
+        }
+    }
+
+    /// The tenant's own key, if it registered one.
+    fn tenant_cipher(&self) -> Option<Arc<Cipher>> {
+        let tenant = self.keys.tenant()?;
+        self.tenant_keys.as_ref()?.get(tenant)
+    }
+
+    /// The value as written to Redis: encrypted with the tenant's key or the server's, if
+    /// enabled.
+    fn seal(&self, value: &str) -> Result<String, MotorheadError> {
+        if let Some(cipher) = self.tenant_cipher() {
+            return cipher.encrypt(value);
+        }
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(value),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    /// A value read back from Redis. Values written before encryption was enabled are read
+    /// as they are.
+    fn unseal(&self, value: String) -> Result<String, MotorheadError> {
+        if Cipher::is_tenant_sealed(&value) {
+            return match self.tenant_cipher() {
+                Some(cipher) => cipher.decrypt(value),
+                None => Err(MotorheadError::TenantKeyDeleted),
+            };
+        }
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(value),
+            None => Ok(value),
+        }
+    }
+
+    fn unseal_option(&self, value: Option<String>) -> Result<Option<String>, MotorheadError> {
+        value.map(|value| self.unseal(value)).transpose()
+    }
+
+    /// Compressed, then encrypted, if enabled. Only the content is, so entries stay JSON for
+    /// the scripts looking messages up by id.
+    fn encode_message(&self, message: &MemoryMessage) -> Result<String, MotorheadError> {
+        let compressed = match &self.compression {
+            Some(compression) => compression.compress(&message.content)?,
+            None => None,
+        };
+        if compressed.is_none() && self.cipher.is_none() && self.tenant_cipher().is_none() {
+            return serde_json::to_string(message)
+                .map_err(|e| MotorheadError::SerializationError(e.to_string()));
+        }
+
+        let content = compressed.as_deref().unwrap_or(&message.content);
+        serde_json::to_string(&MemoryMessage {
+            content: self.seal(content)?,
+            ..message.clone()
+        })
+        .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    fn decode_messages(&self, messages: Vec<String>) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        messages
+            .into_iter()
+            .filter_map(|message| decode_message(message, self.recover_malformed))
+            .map(|message| {
+                Ok(MemoryMessage {
+                    content: decompress(self.unseal(message.content)?),
+                    ..message
+                })
+            })
+            .collect()
+    }
+
+    fn encode_segments(&self, segments: &[ContextSegment]) -> Result<String, MotorheadError> {
+        let segments = segments
+            .iter()
+            .map(|segment| {
+                Ok(ContextSegment {
+                    summary: self.seal(&segment.summary)?,
+                    ..segment.clone()
+                })
+            })
+            .collect::<Result<Vec<_>, MotorheadError>>()?;
+        serde_json::to_string(&segments)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    fn decode_segments(
+        &self,
+        segments: Option<String>,
+    ) -> Result<Vec<ContextSegment>, MotorheadError> {
+        let segments: Vec<ContextSegment> = segments
+            .map(|segments| serde_json::from_str(&segments))
+            .transpose()
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?
+            .unwrap_or_default();
+        segments
+            .into_iter()
+            .map(|segment| {
+                Ok(ContextSegment {
+                    summary: self.unseal(segment.summary)?,
+                    ..segment
+                })
+            })
+            .collect()
+    }
+
+    async fn conn(&self) -> Result<RedisConnection, MotorheadError> {
+        Ok(self.pool.get().await?)
+    }
+
+    /// Queues moving the session from its unnamespaced keys to the current ones.
+    fn queue_migration(&self, pipe: &mut redis::Pipeline, legacy: &SessionKeys, session_id: &str) {
+        let mut script = redis::cmd("EVAL");
+        script
+            .arg(MIGRATE_SESSION_SCRIPT)
+            .arg(4 + 2 * OWN_KEYS)
+            .arg(self.keys.sessions())
+            .arg(legacy.sessions())
+            .arg(self.keys.session_ids())
+            .arg(legacy.session_ids());
+        for (key, legacy_key) in Self::own_keys(&self.keys, session_id)
+            .into_iter()
+            .zip(Self::own_keys(legacy, session_id))
+        {
+            script.arg(key).arg(legacy_key);
+        }
+        script.arg(self.keys.id(session_id));
+        pipe.add_command(script).ignore();
+    }
+
+    /// Queues the writes of an append to the session's own keys. Only the command returning
+    /// the new length isn't ignored.
+    fn queue_append(
+        &self,
+        pipe: &mut redis::Pipeline,
+        session_id: &str,
+        messages: &[MemoryMessage],
+    ) -> Result<(), MotorheadError> {
+        let encoded = messages
+            .iter()
+            .map(|message| self.encode_message(message))
+            .collect::<Result<Vec<String>, _>>()?;
+
+        match self.log {
+            MessageLog::List => {
+                pipe.lpush(self.keys.messages(session_id), encoded);
+            }
+            MessageLog::Stream => {
+                for message in encoded {
+                    pipe.cmd("XADD")
+                        .arg(self.keys.messages(session_id))
+                        .arg("*")
+                        .arg("message")
+                        .arg(message)
+                        .ignore();
+                }
+                pipe.cmd("XLEN").arg(self.keys.messages(session_id));
+            }
+        }
+        pipe.incr(self.keys.unsummarized(session_id), messages.len())
+            .ignore();
+        inherit_ttl(
+            pipe,
+            &self.keys.messages(session_id),
+            &self.keys.unsummarized(session_id),
+        );
+        self.publish(
+            pipe,
+            session_id,
+            &SessionEvent::MessagesAppended { messages },
+        )
+    }
+
+    /// Queues the update of the session indexes after an append. On a cluster these live on
+    /// other slots than the session's keys, so callers send them apart.
+    fn queue_index(&self, pipe: &mut redis::Pipeline, session_id: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        pipe.zadd(self.keys.sessions(), self.keys.id(session_id), now)
+            .ignore();
+        if self.keys.is_hashed() {
+            pipe.hset_nx(
+                self.keys.session_ids(),
+                self.keys.id(session_id),
+                session_id,
+            )
+            .ignore();
+        }
+    }
+
+    /// Queues a read of the session's messages between the newest-first indices `start` and
+    /// `stop`, like LRANGE.
+    fn queue_range(&self, pipe: &mut redis::Pipeline, session_id: &str, start: i64, stop: i64) {
+        match self.log {
+            MessageLog::List => pipe.lrange(
+                self.keys.messages(session_id),
+                start as isize,
+                stop as isize,
+            ),
+            MessageLog::Stream => pipe
+                .cmd("EVAL")
+                .arg(STREAM_RANGE_SCRIPT)
+                .arg(1)
+                .arg(self.keys.messages(session_id))
+                .arg(start)
+                .arg(stop),
+        };
+    }
+
+    /// Queues a trim of the session's messages like LTRIM, moving the ones removed to its
+    /// history with `archive`.
+    fn queue_trim(
+        &self,
+        pipe: &mut redis::Pipeline,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+        archive: bool,
+    ) {
+        if self.log == MessageLog::Stream {
+            pipe.cmd("EVAL")
+                .arg(STREAM_TRIM_SCRIPT)
+                .arg(2)
+                .arg(self.keys.messages(session_id))
+                .arg(self.keys.history(session_id))
+                .arg(start)
+                .arg(stop)
+                .arg(archive as u8)
+                .ignore();
+        } else if archive {
+            pipe.cmd("EVAL")
+                .arg(ARCHIVE_TRIM_SCRIPT)
+                .arg(2)
+                .arg(self.keys.messages(session_id))
+                .arg(self.keys.history(session_id))
+                .arg(start)
+                .arg(stop)
+                .ignore();
+        } else {
+            pipe.ltrim(
+                self.keys.messages(session_id),
+                start as isize,
+                stop as isize,
+            )
+            .ignore();
+        }
+
+        if archive {
+            inherit_ttl(
+                pipe,
+                &self.keys.messages(session_id),
+                &self.keys.history(session_id),
+            );
+        }
+    }
+
+    /// Queues the deletion of the session's own keys, `vector_keys` being its vector hashes.
+    fn queue_delete(
+        &self,
+        pipe: &mut redis::Pipeline,
+        session_id: &str,
+        vector_keys: Vec<String>,
+    ) -> Result<(), MotorheadError> {
+        if !vector_keys.is_empty() {
+            pipe.del(vector_keys).ignore();
+        }
+        pipe.del(&Self::own_keys(&self.keys, session_id)).ignore();
+        self.publish(pipe, session_id, &SessionEvent::SessionDeleted)
+    }
+
+    /// Queues the removal of the session from the session indexes, see `queue_index`.
+    fn queue_unindex(&self, pipe: &mut redis::Pipeline, session_id: &str) {
+        pipe.hdel(self.keys.session_ids(), self.keys.id(session_id))
+            .ignore()
+            .zrem(self.keys.sessions(), self.keys.id(session_id))
+            .ignore()
+            .del(self.keys.expiry(session_id))
+            .ignore();
+    }
+
+    /// The session's own keys, its vector set first, named by `keys`.
+    fn own_keys(keys: &SessionKeys, session_id: &str) -> [String; OWN_KEYS] {
+        [
+            keys.vectors(session_id),
+            keys.messages(session_id),
+            keys.context(session_id),
+            keys.long_term_context(session_id),
+            keys.metadata(session_id),
+            keys.config(session_id),
+            keys.unsummarized(session_id),
+            keys.entities(session_id),
+            keys.history(session_id),
+            keys.cold(session_id),
+            keys.pinned(session_id),
+            keys.pinned_auto(session_id),
+            keys.context_segments(session_id),
+            keys.session_usage(session_id),
+            keys.compaction(session_id),
+            keys.compaction_progress(session_id),
+            keys.kv(session_id),
+            keys.version(session_id),
+            keys.languages(session_id),
+            keys.context_versions(session_id),
+        ]
+    }
+
+    /// The field of the store's tenant's `kind` of tokens in the usage hashes.
+    fn usage_field(&self, kind: &str) -> String {
+        format!("{}:{}", self.keys.tenant().unwrap_or_default(), kind)
+    }
+
+    /// Queues a new version for the session, gone along with it.
+    fn queue_version(&self, pipe: &mut redis::Pipeline, session_id: &str) {
+        pipe.set(
+            self.keys.version(session_id),
+            uuid::Uuid::new_v4().simple().to_string(),
+        )
+        .ignore();
+        inherit_ttl(
+            pipe,
+            &self.keys.messages(session_id),
+            &self.keys.version(session_id),
+        );
+    }
+
+    /// Queues a PUBLISH of `event` on the session's channel. Every change is published, so it
+    /// also queues the session's new version, or its removal for `SessionDeleted`.
+    fn publish(
+        &self,
+        pipe: &mut redis::Pipeline,
+        session_id: &str,
+        event: &SessionEvent,
+    ) -> Result<(), MotorheadError> {
+        let payload = serde_json::to_string(event)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+        pipe.cmd("PUBLISH")
+            .arg(self.keys.events(session_id))
+            .arg(payload)
+            .ignore();
+        match event {
+            SessionEvent::SessionDeleted => {
+                pipe.del(self.keys.version(session_id)).ignore();
+            }
+            _ => self.queue_version(pipe, session_id),
+        }
+        Ok(())
+    }
+
+    /// Looks up a message by id, returning its raw entry along with the decoded message.
+    async fn find_message(
+        &self,
+        conn: &mut RedisConnection,
+        session_id: &str,
+        message_id: &str,
+    ) -> Result<Option<(String, MemoryMessage)>, MotorheadError> {
+        let mut pipe = redis::pipe();
+        self.queue_range(&mut pipe, session_id, 0, -1);
+        let (entries,): (Vec<String>,) = pipe.query_async(conn).await?;
+
+        Ok(entries.into_iter().find_map(|entry| {
+            decode_message(entry.clone(), self.recover_malformed)
+                .filter(|message| message.id.as_deref() == Some(message_id))
+                .map(|message| (entry, message))
+        }))
+    }
+}
+
+/// Decodes a list entry. Entries written before messages were stored as JSON use the
+/// `role: content` format and are still read back. Those in neither are skipped, or returned
+/// whole as `unknown` messages with `recover`.
+fn decode_message(message: String, recover: bool) -> Option<MemoryMessage> {
+    if let Ok(message) = serde_json::from_str::<MemoryMessage>(&message) {
+        return Some(message);
+    }
+
+    // Split in place rather than copying both halves.
+    match message.find(": ") {
+        Some(at) => {
+            let mut role = message;
+            let content = role.split_off(at + 2);
+            role.truncate(at);
+            Some(legacy_message(role, content))
+        }
+        None if recover => {
+            tracing::warn!("Recovering undecodable message entry");
+            metrics::MALFORMED_ENTRIES
+                .with_label_values(&["recovered"])
+                .inc();
+            Some(legacy_message(UNKNOWN_ROLE.to_string(), message))
+        }
+        None => {
+            tracing::warn!("Skipping undecodable message entry");
+            metrics::MALFORMED_ENTRIES
+                .with_label_values(&["skipped"])
+                .inc();
+            None
+        }
+    }
+}
+
+/// The role of the messages recovered from undecodable entries.
+const UNKNOWN_ROLE: &str = "unknown";
+
+fn legacy_message(role: String, content: String) -> MemoryMessage {
+    MemoryMessage {
+        role: role.into(),
+        content,
+        tool_calls: None,
+        tool_call_id: None,
+        name: None,
+        speaker: None,
+        attachments: None,
+        id: None,
+        turn_id: None,
+        created_at: None,
+        metadata: None,
+        importance: None,
+    }
+}
+
+/// Whether the entry is in neither of the formats `decode_message` reads.
+fn is_malformed(entry: &str) -> bool {
+    serde_json::from_str::<MemoryMessage>(entry).is_err() && !entry.contains(": ")
+}
+
+/// Moves the ARGV[1] oldest messages of the history (KEYS[1]) to cold storage, by trimming
+/// them and appending their segment (ARGV[2]) to KEYS[2], unless the history got shorter.
+const MOVE_TO_COLD_SCRIPT: &str = r#"
+local count = tonumber(ARGV[1])
+if redis.call('LLEN', KEYS[1]) < count then
+    return 0
+end
+local ttl = redis.call('PTTL', KEYS[1])
+redis.call('LTRIM', KEYS[1], count, -1)
+redis.call('RPUSH', KEYS[2], ARGV[2])
+if ttl > 0 then
+    redis.call('PEXPIRE', KEYS[2], ttl)
+end
+return 1
+"#;
+
+/// Gives KEYS[2] the same expiry as KEYS[1], so keys created after a session's TTL was set
+/// (e.g. the first context summary) expire along with it.
+const INHERIT_TTL_SCRIPT: &str = r#"
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl > 0 then
+    redis.call('PEXPIRE', KEYS[2], ttl)
+end
+return 0
+"#;
+
+/// Replaces the session's version KEYS[1] with ARGV[2] if it's still ARGV[1], returning 1, or
+/// returns 0. The new version inherits the TTL of the old one.
+const CLAIM_VERSION_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[2], 'KEEPTTL')
+return 1
+"#;
+
+/// Sets the session's lease KEYS[1] to `ARGV[1]:ARGV[3]`, expiring in ARGV[2] ms, unless held by
+/// another token than ARGV[1]. Returns whether it was set.
+const ACQUIRE_LOCK_SCRIPT: &str = r#"
+local held = redis.call('GET', KEYS[1])
+if held and string.sub(held, 1, #ARGV[1] + 1) ~= ARGV[1] .. ':' then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[1] .. ':' .. ARGV[3], 'PX', ARGV[2])
+return 1
+"#;
+
+/// Deletes the delete intent KEYS[1] if it's the token ARGV[1]. Returns whether it was.
+const TAKE_DELETE_INTENT_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// Deletes the session's lease KEYS[1] if held by the token ARGV[1]. Returns whether it was.
+const RELEASE_LOCK_SCRIPT: &str = r#"
+local held = redis.call('GET', KEYS[1])
+if held and string.sub(held, 1, #ARGV[1] + 1) == ARGV[1] .. ':' then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// Extends the compaction claim KEYS[1] by ARGV[2] ms if it's still held by the token
+/// ARGV[1]. Returns whether it was.
+const RENEW_COMPACTION_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+/// Deletes the compaction claim KEYS[1] if it's held by the token ARGV[1].
+const RELEASE_COMPACTION_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// The number of keys of a session, see `RedisStore::own_keys`.
+const OWN_KEYS: usize = 20;
+/// Sessions moved under the key prefix at a time.
+const MIGRATION_PAGE_SIZE: usize = 100;
+
+/// Sets the TTL (ARGV[1] seconds) on every key of a session at once. KEYS[1] is the set of the
+/// session's vector keys, which are expired as well.
+const EXPIRE_SESSION_SCRIPT: &str = r#"
+local ttl = tonumber(ARGV[1])
+for _, key in ipairs(KEYS) do
+    redis.call('EXPIRE', key, ttl)
+end
+for _, key in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    redis.call('EXPIRE', key, ttl)
+end
+return 0
+"#;
+
+/// Soft-deletes a session: renames each of its keys KEYS[i] to the trashed name KEYS[i + 1],
+/// and its vector hashes (members of the set KEYS[1]) to their names prefixed by ARGV[2],
+/// all expiring in ARGV[1] seconds. Then sets the deleted marker KEYS[#KEYS] to ARGV[3].
+const TRASH_SESSION_SCRIPT: &str = r#"
+local ttl = tonumber(ARGV[1])
+for _, key in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    if redis.call('EXISTS', key) == 1 then
+        redis.call('RENAME', key, ARGV[2] .. key)
+        redis.call('EXPIRE', ARGV[2] .. key, ttl)
+    end
+end
+local trashed = 0
+for i = 1, #KEYS - 1, 2 do
+    redis.call('DEL', KEYS[i + 1])
+    if redis.call('EXISTS', KEYS[i]) == 1 then
+        redis.call('RENAME', KEYS[i], KEYS[i + 1])
+        redis.call('EXPIRE', KEYS[i + 1], ttl)
+        trashed = trashed + 1
+    end
+end
+if trashed > 0 then
+    redis.call('SET', KEYS[#KEYS], ARGV[3], 'EX', ttl)
+end
+return trashed
+"#;
+
+/// Undoes `TRASH_SESSION_SCRIPT`, KEYS[i] being the trashed names and KEYS[i + 1] the live
+/// ones. Returns 0 without the deleted marker KEYS[#KEYS], and -1 if the session has live
+/// keys again.
+const RESTORE_SESSION_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[#KEYS]) == 0 then
+    return 0
+end
+for i = 1, #KEYS - 1, 2 do
+    if redis.call('EXISTS', KEYS[i + 1]) == 1 then
+        return -1
+    end
+end
+for i = 1, #KEYS - 1, 2 do
+    if redis.call('EXISTS', KEYS[i]) == 1 then
+        redis.call('RENAME', KEYS[i], KEYS[i + 1])
+        redis.call('PERSIST', KEYS[i + 1])
+    end
+end
+for _, key in ipairs(redis.call('SMEMBERS', KEYS[2])) do
+    if redis.call('EXISTS', ARGV[1] .. key) == 1 then
+        redis.call('RENAME', ARGV[1] .. key, key)
+        redis.call('PERSIST', key)
+    end
+end
+redis.call('DEL', KEYS[#KEYS])
+return 1
+"#;
+
+/// Runs in the same EVAL right before one of the trim scripts (keys and arguments shared),
+/// so a compaction commits atomically. The trim stops at ARGV[2] are from the read made
+/// before summarizing, and messages appended since moved the summarized ones further from
+/// the front: when ARGV[4] has the id of the newest summarized message, the stop is moved
+/// to just before it. The context KEYS[3] is set to ARGV[5], and the count of unsummarized
+/// messages KEYS[4] to the messages appended meanwhile. ARGV[6] is the message log, `list`
+/// or `stream`. Returns -1 without writing anything if the message is gone.
+const COMMIT_COMPACTION_SCRIPT: &str = r#"
+local appended = 0
+if ARGV[4] ~= '' then
+    local entries
+    if ARGV[6] == 'stream' then
+        entries = {}
+        for _, entry in ipairs(redis.call('XREVRANGE', KEYS[1], '+', '-')) do
+            local message = ''
+            local fields = entry[2]
+            for j = 1, #fields, 2 do
+                if fields[j] == 'message' then message = fields[j + 1] end
+            end
+            table.insert(entries, message)
+        end
+    else
+        entries = redis.call('LRANGE', KEYS[1], 0, -1)
+    end
+    local found = nil
+    for i, entry in ipairs(entries) do
+        local ok, message = pcall(cjson.decode, entry)
+        if ok and type(message) == 'table' and message['id'] == ARGV[4] then
+            found = i - 1
+            break
+        end
+    end
+    if found == nil then return -1 end
+    appended = math.max(found - (tonumber(ARGV[2]) + 1), 0)
+    -- LTRIM reads a stop of -1 as the last message; like before, the newest one is kept.
+    ARGV[2] = tostring(math.max(found - 1, 0))
+end
+redis.call('SET', KEYS[3], ARGV[5])
+if appended > 0 then
+    redis.call('SET', KEYS[4], appended)
+else
+    redis.call('DEL', KEYS[4])
+end
+"#;
+
+/// Trims the messages list KEYS[1] to indices ARGV[1]..=ARGV[2].
+const LIST_TRIM_SCRIPT: &str = r#"
+redis.call('LTRIM', KEYS[1], ARGV[1], ARGV[2])
+return 0
+"#;
+
+/// Trims the messages list KEYS[1] to indices ARGV[1]..=ARGV[2] like LTRIM, appending the
+/// messages removed to the history list KEYS[2] oldest first.
+const ARCHIVE_TRIM_SCRIPT: &str = r#"
+local len = redis.call('LLEN', KEYS[1])
+local start = tonumber(ARGV[1])
+local stop = tonumber(ARGV[2])
+if start < 0 then start = math.max(len + start, 0) end
+if stop < 0 then stop = len + stop end
+local function archive(first, last)
+    if first > last then return end
+    local removed = redis.call('LRANGE', KEYS[1], first, last)
+    for i = #removed, 1, -1 do
+        redis.call('RPUSH', KEYS[2], removed[i])
+    end
+end
+archive(math.max(stop + 1, start), len - 1)
+archive(0, math.min(start, len) - 1)
+redis.call('LTRIM', KEYS[1], ARGV[1], ARGV[2])
+return 0
+"#;
+
+/// Reads the messages of the stream KEYS[1] between the newest-first indices ARGV[1] and
+/// ARGV[2], like LRANGE on a list.
+const STREAM_RANGE_SCRIPT: &str = r#"
+local len = redis.call('XLEN', KEYS[1])
+local start = tonumber(ARGV[1])
+local stop = tonumber(ARGV[2])
+if start < 0 then start = math.max(len + start, 0) end
+if stop < 0 then stop = len + stop end
+stop = math.min(stop, len - 1)
+local messages = {}
+if start > stop then return messages end
+local entries = redis.call('XREVRANGE', KEYS[1], '+', '-', 'COUNT', stop + 1)
+for i = start + 1, #entries do
+    local fields = entries[i][2]
+    for j = 1, #fields, 2 do
+        if fields[j] == 'message' then table.insert(messages, fields[j + 1]) end
+    end
+end
+return messages
+"#;
+
+/// Trims the stream KEYS[1] to the newest-first indices ARGV[1]..=ARGV[2] like LTRIM. With
+/// ARGV[3] set, the messages removed are appended to the history list KEYS[2] oldest first.
+const STREAM_TRIM_SCRIPT: &str = r#"
+local len = redis.call('XLEN', KEYS[1])
+local start = tonumber(ARGV[1])
+local stop = tonumber(ARGV[2])
+local archive = ARGV[3] == '1'
+if start < 0 then start = math.max(len + start, 0) end
+if stop < 0 then stop = len + stop end
+local keep = math.max(math.min(stop + 1, len), 0)
+local function remove(entry)
+    if not archive then return end
+    local fields = entry[2]
+    for j = 1, #fields, 2 do
+        if fields[j] == 'message' then redis.call('RPUSH', KEYS[2], fields[j + 1]) end
+    end
+end
+if keep < len then
+    for _, entry in ipairs(redis.call('XRANGE', KEYS[1], '-', '+', 'COUNT', len - keep)) do
+        remove(entry)
+    end
+    redis.call('XTRIM', KEYS[1], 'MAXLEN', keep)
+end
+if start > 0 then
+    local newest = redis.call('XREVRANGE', KEYS[1], '+', '-', 'COUNT', start)
+    for i = #newest, 1, -1 do
+        remove(newest[i])
+        redis.call('XDEL', KEYS[1], newest[i][1])
+    end
+end
+return 0
+"#;
+
+/// Deletes the entry of the stream KEYS[1] whose message is ARGV[1].
+const REMOVE_STREAM_ENTRY_SCRIPT: &str = r#"
+for _, entry in ipairs(redis.call('XRANGE', KEYS[1], '-', '+')) do
+    local fields = entry[2]
+    for j = 1, #fields, 2 do
+        if fields[j] == 'message' and fields[j + 1] == ARGV[1] then
+            return redis.call('XDEL', KEYS[1], entry[1])
+        end
+    end
+end
+return 0
+"#;
+
+/// Removes the newest messages of the list or stream KEYS[1] if their ids are still ARGV[2..],
+/// newest first (empty for entries without one), along with their copies in the pinned
+/// hashes KEYS[2] and KEYS[3]. ARGV[1] is `stream` for streams. Returns 0 if they aren't.
+const DELETE_NEWEST_SCRIPT: &str = r#"
+local stream = ARGV[1] == 'stream'
+local count = #ARGV - 1
+local entries = {}
+if stream then
+    for _, entry in ipairs(redis.call('XREVRANGE', KEYS[1], '+', '-', 'COUNT', count)) do
+        local fields = entry[2]
+        for j = 1, #fields, 2 do
+            if fields[j] == 'message' then table.insert(entries, {entry[1], fields[j + 1]}) end
+        end
+    end
+else
+    for _, entry in ipairs(redis.call('LRANGE', KEYS[1], 0, count - 1)) do
+        table.insert(entries, {nil, entry})
+    end
+end
+if #entries ~= count then return 0 end
+for i, entry in ipairs(entries) do
+    local ok, message = pcall(cjson.decode, entry[2])
+    local id = ok and type(message) == 'table' and message.id or ''
+    if type(id) ~= 'string' then id = '' end
+    if id ~= ARGV[i + 1] then return 0 end
+end
+if stream then
+    for _, entry in ipairs(entries) do redis.call('XDEL', KEYS[1], entry[1]) end
+else
+    redis.call('LTRIM', KEYS[1], count, -1)
+end
+for i = 2, #ARGV do
+    if ARGV[i] ~= '' then
+        redis.call('HDEL', KEYS[2], ARGV[i])
+        redis.call('HDEL', KEYS[3], ARGV[i])
+    end
+end
+return 1
+"#;
+
+/// Moves a session from its legacy keys to the current ones. KEYS[1..4] are the current and
+/// legacy sessions set and session ids hash, which hold the session's id ARGV[1]; the rest are
+/// `(current, legacy)` pairs of the session's own keys. Keys already present are left alone.
+const MIGRATE_SESSION_SCRIPT: &str = r#"
+local score = redis.call('ZSCORE', KEYS[2], ARGV[1])
+if score then
+    redis.call('ZADD', KEYS[1], 'NX', score, ARGV[1])
+    redis.call('ZREM', KEYS[2], ARGV[1])
+end
+local original = redis.call('HGET', KEYS[4], ARGV[1])
+if original then
+    redis.call('HSETNX', KEYS[3], ARGV[1], original)
+    redis.call('HDEL', KEYS[4], ARGV[1])
+end
+for i = 5, #KEYS, 2 do
+    if redis.call('EXISTS', KEYS[i]) == 0 and redis.call('EXISTS', KEYS[i + 1]) == 1 then
+        redis.call('RENAME', KEYS[i + 1], KEYS[i])
+    end
+end
+return 0
+"#;
+
+/// Takes a token from the bucket hash KEYS[1], holding up to ARGV[1] tokens and refilled at
+/// ARGV[2] tokens a ms. Returns 0, or the ms to wait for a token when the bucket is empty.
+/// The server's clock is used so every instance agrees on the refills.
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'at')
+local tokens = tonumber(bucket[1]) or capacity
+local at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - at) * rate)
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) / rate)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'at', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / rate))
+return wait
+"#;
+
+/// Replaces the entry ARGV[1] of the list KEYS[1] with ARGV[2]. Looking it up inside the script
+/// keeps the index valid even if messages are appended or compacted concurrently.
+const REPLACE_ENTRY_SCRIPT: &str = r#"
+local entries = redis.call('LRANGE', KEYS[1], 0, -1)
+for i, entry in ipairs(entries) do
+    if entry == ARGV[1] then
+        redis.call('LSET', KEYS[1], i - 1, ARGV[2])
+        return 1
+    end
+end
+return 0
+"#;
+
+fn inherit_ttl(pipe: &mut redis::Pipeline, source: &str, target: &str) {
+    pipe.cmd("EVAL")
+        .arg(INHERIT_TTL_SCRIPT)
+        .arg(2)
+        .arg(source)
+        .arg(target)
+        .ignore();
+}
+
+fn compaction_failure_field(tenant: Option<&str>, session_id: &str) -> String {
+    format!("{}:{}", tenant.unwrap_or_default(), session_id)
+}
+
+fn vector_bytes(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Escapes a value for use inside a RediSearch TAG query.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if !c.is_alphanumeric() && c != '_' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Finds the `dim` of the vector field in an `FT.INFO` reply, nested in its attributes.
+fn index_dimensions(reply: &redis::Value) -> Option<usize> {
+    let redis::Value::Bulk(items) = reply else {
+        return None;
+    };
+
+    items.iter().enumerate().find_map(|(i, item)| {
+        let is_dim = redis::from_redis_value::<String>(item)
+            .is_ok_and(|field| field.eq_ignore_ascii_case("dim"));
+        if is_dim {
+            items
+                .get(i + 1)
+                .and_then(|value| redis::from_redis_value(value).ok())
+        } else {
+            index_dimensions(item)
+        }
+    })
+}
+
+/// Parses an `FT.SEARCH` reply of the form `[total, key, [field, value, ...], ...]`.
+fn parse_search_results(reply: redis::Value) -> Result<Vec<RetrievalResult>, MotorheadError> {
+    let redis::Value::Bulk(items) = reply else {
+        return Ok(Vec::new());
+    };
+
+    let mut results = Vec::new();
+    for document in items.into_iter().skip(2).step_by(2) {
+        let fields: Vec<String> = redis::from_redis_value(&document)?;
+        let mut result = RetrievalResult {
+            role: String::new(),
+            content: String::new(),
+            dist: 0.0,
+        };
+        for pair in fields.chunks(2) {
+            if let [field, value] = pair {
+                match field.as_str() {
+                    "role" => result.role = value.clone(),
+                    "content" => result.content = value.clone(),
+                    "dist" => result.dist = value.parse().unwrap_or_default(),
+                    _ => {}
+                }
+            }
+        }
+        results.push(result);
+    }
+
+    Ok(results)
+}
+
+#[async_trait]
+impl MemoryStore for RedisStore {
+    fn for_tenant(&self, tenant: &str) -> Arc<dyn MemoryStore> {
+        Arc::new(RedisStore {
+            pool: self.pool.clone(),
+            keys: self.keys.for_tenant(tenant),
+            log: self.log,
+            cipher: self.cipher.clone(),
+            tenant_keys: self.tenant_keys.clone(),
+            compression: self.compression.clone(),
+            recover_malformed: self.recover_malformed,
+        })
+    }
+
+    async fn ping(&self) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        redis::cmd("PING").query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn write_heartbeat(&self, now: u64) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        redis::Cmd::set(self.keys.heartbeat(), now)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_heartbeat(&self) -> Result<Option<u64>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        Ok(redis::Cmd::get(self.keys.heartbeat())
+            .query_async(&mut conn)
+            .await?)
+    }
+
+    /// Kept encrypted with the server key, if any.
+    async fn get_tenant_keys(&self) -> Result<BTreeMap<String, String>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let keys: BTreeMap<String, String> = redis::Cmd::hgetall(self.keys.tenant_keys())
+            .query_async(&mut conn)
+            .await?;
+        keys.into_iter()
+            .map(|(tenant, key)| {
+                let key = match &self.cipher {
+                    Some(cipher) => cipher.decrypt(key)?,
+                    None => key,
+                };
+                Ok((tenant, key))
+            })
+            .collect()
+    }
+
+    async fn add_tenant_key(&self, tenant: &str, key: &str) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+        let key = match &self.cipher {
+            Some(cipher) => cipher.encrypt(key)?,
+            None => key.to_string(),
+        };
+
+        Ok(redis::Cmd::hset_nx(self.keys.tenant_keys(), tenant, key)
+            .query_async(&mut conn)
+            .await?)
+    }
+
+    async fn delete_tenant_key(&self, tenant: &str) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let deleted: u64 = redis::Cmd::hdel(self.keys.tenant_keys(), tenant)
+            .query_async(&mut conn)
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    async fn get_shared_config(&self) -> Result<BTreeMap<String, String>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        Ok(redis::Cmd::hgetall(self.keys.shared_config())
+            .query_async(&mut conn)
+            .await?)
+    }
+
+    async fn set_shared_config(
+        &self,
+        fields: &[(&str, Option<String>)],
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+        let key = self.keys.shared_config();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (field, value) in fields {
+            match value {
+                Some(value) => pipe.hset(&key, *field, value).ignore(),
+                None => pipe.hdel(&key, *field).ignore(),
+            };
+        }
+        pipe.query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+
+    /// Needs keyspace notifications for hash commands (`notify-keyspace-events` with `Kgh`).
+    async fn shared_config_changes(&self) -> Result<BoxStream<'static, ()>, MotorheadError> {
+        if self.keys.is_hash_tagged() {
+            return Err(MotorheadError::Unsupported(
+                "shared config notifications on Redis Cluster",
+            ));
+        }
+        let mut pubsub = self.pool.pubsub().await?;
+        pubsub
+            .psubscribe(format!("__keyspace@*__:{}", self.keys.shared_config()))
+            .await?;
+
+        Ok(pubsub.into_on_message().map(|_| ()).boxed())
+    }
+
+    async fn get_messages(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        self.queue_range(&mut pipe, session_id, start, stop);
+        let (messages,): (Vec<String>,) = pipe.query_async(&mut conn).await?;
+
+        self.decode_messages(messages)
+    }
+
+    async fn append_messages(
+        &self,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+    ) -> Result<i64, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        self.queue_append(&mut pipe, session_id, &messages)?;
+        let (len,): (i64,) = pipe.query_async(&mut conn).await?;
+
+        let mut index = redis::pipe();
+        self.queue_index(&mut index, session_id);
+        index.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(len)
+    }
+
+    async fn trim_messages(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+        archive: bool,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        self.queue_trim(&mut pipe, session_id, start, stop, archive);
+        self.queue_version(&mut pipe, session_id);
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_context(&self, session_id: &str) -> Result<Option<String>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let context = redis::Cmd::get(self.keys.context(session_id))
+            .query_async(&mut conn)
+            .await?;
+        self.unseal_option(context)
+    }
+
+    async fn set_context(&self, session_id: &str, context: &str) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.set(self.keys.context(session_id), self.seal(context)?)
+            .ignore()
+            .del(self.keys.unsummarized(session_id))
+            .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.context(session_id),
+        );
+        self.publish(
+            &mut pipe,
+            session_id,
+            &SessionEvent::ContextUpdated { context },
+        )?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_long_term_context(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<String>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let long_term_context = redis::Cmd::get(self.keys.long_term_context(session_id))
+            .query_async(&mut conn)
+            .await?;
+        self.unseal_option(long_term_context)
+    }
+
+    async fn set_long_term_context(
+        &self,
+        session_id: &str,
+        long_term_context: &str,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.set(
+            self.keys.long_term_context(session_id),
+            self.seal(long_term_context)?,
+        )
+        .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.long_term_context(session_id),
+        );
+        self.publish(
+            &mut pipe,
+            session_id,
+            &SessionEvent::LongTermContextUpdated { long_term_context },
+        )?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_compaction_progress(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<CompactionProgress>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let progress: Option<String> = redis::Cmd::get(self.keys.compaction_progress(session_id))
+            .query_async(&mut conn)
+            .await?;
+
+        self.unseal_option(progress)?
+            .map(|progress| serde_json::from_str(&progress))
+            .transpose()
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    async fn set_compaction_progress(
+        &self,
+        session_id: &str,
+        progress: Option<&CompactionProgress>,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        match progress {
+            Some(progress) => {
+                let encoded = serde_json::to_string(progress)
+                    .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+                pipe.set(
+                    self.keys.compaction_progress(session_id),
+                    self.seal(&encoded)?,
+                )
+                .ignore();
+                inherit_ttl(
+                    &mut pipe,
+                    &self.keys.messages(session_id),
+                    &self.keys.compaction_progress(session_id),
+                );
+            }
+            None => {
+                pipe.del(self.keys.compaction_progress(session_id)).ignore();
+            }
+        }
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_context_segments(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ContextSegment>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let segments: Option<String> = redis::Cmd::get(self.keys.context_segments(session_id))
+            .query_async(&mut conn)
+            .await?;
+
+        self.decode_segments(segments)
+    }
+
+    async fn set_context_segments(
+        &self,
+        session_id: &str,
+        segments: &[ContextSegment],
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let encoded = self.encode_segments(segments)?;
+        let mut pipe = redis::pipe();
+        pipe.set(self.keys.context_segments(session_id), encoded)
+            .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.context_segments(session_id),
+        );
+        self.publish(
+            &mut pipe,
+            session_id,
+            &SessionEvent::ContextSegmentsUpdated { segments },
+        )?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn fold_context(
+        &self,
+        session_id: &str,
+        long_term_context: &str,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.set(
+            self.keys.long_term_context(session_id),
+            self.seal(long_term_context)?,
+        )
+        .ignore()
+        .del(self.keys.context(session_id))
+        .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.long_term_context(session_id),
+        );
+        self.publish(
+            &mut pipe,
+            session_id,
+            &SessionEvent::LongTermContextUpdated { long_term_context },
+        )?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn replace_contexts(
+        &self,
+        session_id: &str,
+        context: Option<&str>,
+        long_term_context: Option<&str>,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        for (key, value) in [
+            (self.keys.context(session_id), context),
+            (self.keys.long_term_context(session_id), long_term_context),
+        ] {
+            match value {
+                Some(value) => {
+                    pipe.set(&key, self.seal(value)?).ignore();
+                    inherit_ttl(&mut pipe, &self.keys.messages(session_id), &key);
+                }
+                None => {
+                    pipe.del(&key).ignore();
+                }
+            }
+        }
+        self.publish(
+            &mut pipe,
+            session_id,
+            &SessionEvent::ContextUpdated {
+                context: context.unwrap_or_default(),
+            },
+        )?;
+        self.publish(
+            &mut pipe,
+            session_id,
+            &SessionEvent::LongTermContextUpdated {
+                long_term_context: long_term_context.unwrap_or_default(),
+            },
+        )?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn commit_compaction(
+        &self,
+        session_id: &str,
+        keep_until: i64,
+        newest_summarized: Option<&str>,
+        context: &str,
+        archive: bool,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let trim = match (self.log, archive) {
+            (MessageLog::Stream, _) => STREAM_TRIM_SCRIPT,
+            (MessageLog::List, true) => ARCHIVE_TRIM_SCRIPT,
+            (MessageLog::List, false) => LIST_TRIM_SCRIPT,
+        };
+        let committed: i64 = redis::cmd("EVAL")
+            .arg(format!("{}{}", COMMIT_COMPACTION_SCRIPT, trim))
+            .arg(4)
+            .arg(self.keys.messages(session_id))
+            .arg(self.keys.history(session_id))
+            .arg(self.keys.context(session_id))
+            .arg(self.keys.unsummarized(session_id))
+            .arg(0)
+            .arg(keep_until)
+            .arg(archive as u8)
+            .arg(newest_summarized.unwrap_or_default())
+            .arg(self.seal(context)?)
+            .arg(match self.log {
+                MessageLog::List => "list",
+                MessageLog::Stream => "stream",
+            })
+            .query_async(&mut conn)
+            .await?;
+        if committed < 0 {
+            return Err(MotorheadError::CompactionConflict);
+        }
+
+        let mut pipe = redis::pipe();
+        for key in [
+            self.keys.context(session_id),
+            self.keys.unsummarized(session_id),
+        ] {
+            inherit_ttl(&mut pipe, &self.keys.messages(session_id), &key);
+        }
+        if archive {
+            inherit_ttl(
+                &mut pipe,
+                &self.keys.messages(session_id),
+                &self.keys.history(session_id),
+            );
+        }
+        self.publish(
+            &mut pipe,
+            session_id,
+            &SessionEvent::ContextUpdated { context },
+        )?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_memory(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<(Vec<MemoryMessage>, Option<String>), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        self.queue_range(&mut pipe, session_id, start, stop);
+        let (messages, context): (Vec<String>, Option<String>) = pipe
+            .get(self.keys.context(session_id))
+            .query_async(&mut conn)
+            .await?;
+
+        Ok((
+            self.decode_messages(messages)?,
+            self.unseal_option(context)?,
+        ))
+    }
+
+    async fn read_window(
+        &self,
+        session_id: &str,
+        start: i64,
+        stop: i64,
+        fields: MemoryFields,
+    ) -> Result<SessionWindow, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        // Only what's asked for is queued, so the replies are taken in the same order.
+        let mut pipe = redis::pipe();
+        pipe.get(self.keys.unsummarized(session_id));
+        if fields.messages {
+            self.queue_range(&mut pipe, session_id, start, stop);
+            pipe.hvals(self.keys.pinned(session_id))
+                .hvals(self.keys.pinned_auto(session_id));
+        }
+        if fields.context {
+            pipe.get(self.keys.context(session_id))
+                .get(self.keys.long_term_context(session_id))
+                .get(self.keys.context_segments(session_id));
+        }
+        let replies: Vec<redis::Value> = pipe.query_async(&mut conn).await?;
+        let mut replies = replies.iter();
+        let mut next = || replies.next().unwrap_or(&redis::Value::Nil);
+
+        let unsummarized: Option<u64> = redis::from_redis_value(next())?;
+        let mut window = SessionWindow {
+            messages: Vec::new(),
+            context: None,
+            long_term_context: None,
+            context_segments: Vec::new(),
+            messages_since_summary: unsummarized.unwrap_or(0),
+            pinned: Vec::new(),
+        };
+        if fields.messages {
+            window.messages = self.decode_messages(redis::from_redis_value(next())?)?;
+            let pinned = self.decode_messages(redis::from_redis_value(next())?)?;
+            let auto_pinned = self.decode_messages(redis::from_redis_value(next())?)?;
+            window.pinned = merge_pinned(pinned, auto_pinned);
+        }
+        if fields.context {
+            window.context = self.unseal_option(redis::from_redis_value(next())?)?;
+            window.long_term_context = self.unseal_option(redis::from_redis_value(next())?)?;
+            window.context_segments = self.decode_segments(redis::from_redis_value(next())?)?;
+        }
+
+        Ok(window)
+    }
+
+    async fn messages_since_summary(&self, session_id: &str) -> Result<u64, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let count: Option<u64> = redis::Cmd::get(self.keys.unsummarized(session_id))
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    async fn get_metadata(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<serde_json::Value>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let metadata: Option<String> = redis::Cmd::get(self.keys.metadata(session_id))
+            .query_async(&mut conn)
+            .await?;
+
+        metadata
+            .map(|metadata| serde_json::from_str(&metadata))
+            .transpose()
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    async fn set_metadata(
+        &self,
+        session_id: &str,
+        metadata: &serde_json::Value,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.set(self.keys.metadata(session_id), metadata.to_string())
+            .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.metadata(session_id),
+        );
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn delete_metadata(&self, session_id: &str) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        redis::Cmd::del(self.keys.metadata(session_id))
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_session_config(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<SessionConfig>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let config: Option<String> = redis::Cmd::get(self.keys.config(session_id))
+            .query_async(&mut conn)
+            .await?;
+
+        config
+            .map(|config| serde_json::from_str(&config))
+            .transpose()
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    async fn set_session_config(
+        &self,
+        session_id: &str,
+        config: &SessionConfig,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+        let config = serde_json::to_string(config)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+
+        let mut pipe = redis::pipe();
+        pipe.set(self.keys.config(session_id), config).ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.config(session_id),
+        );
+        self.queue_version(&mut pipe, session_id);
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn stored_size(&self, session_id: &str) -> Result<StoredSize, MotorheadError> {
+        let mut conn = self.conn().await?;
+        let messages_key = self.keys.messages(session_id);
+        let history_key = self.keys.history(session_id);
+
+        let (window, history, window_bytes, history_bytes): (u64, u64, Option<u64>, Option<u64>) =
+            redis::pipe()
+                .llen(&messages_key)
+                .llen(&history_key)
+                .cmd("MEMORY")
+                .arg("USAGE")
+                .arg(&messages_key)
+                .cmd("MEMORY")
+                .arg("USAGE")
+                .arg(&history_key)
+                .query_async(&mut conn)
+                .await?;
+
+        Ok(StoredSize {
+            window,
+            history,
+            bytes: window_bytes.unwrap_or(0) + history_bytes.unwrap_or(0),
+        })
+    }
+
+    async fn drop_history(&self, session_id: &str, count: usize) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        redis::Cmd::ltrim(self.keys.history(session_id), count as isize, -1)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_history(
+        &self,
+        session_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let messages: Vec<String> = redis::Cmd::lrange(
+            self.keys.history(session_id),
+            offset as isize,
+            (offset + limit) as isize - 1,
+        )
+        .query_async(&mut conn)
+        .await?;
+
+        self.decode_messages(messages)
+    }
+
+    async fn get_history_tail(
+        &self,
+        session_id: &str,
+        count: usize,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.conn().await?;
+
+        let messages: Vec<String> =
+            redis::Cmd::lrange(self.keys.history(session_id), -(count as isize), -1)
+                .query_async(&mut conn)
+                .await?;
+
+        self.decode_messages(messages)
+    }
+
+    async fn get_cold_segments(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ColdSegment>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let segments: Vec<String> = redis::Cmd::lrange(self.keys.cold(session_id), 0, -1)
+            .query_async(&mut conn)
+            .await?;
+
+        segments
+            .iter()
+            .map(|segment| serde_json::from_str(segment))
+            .collect::<Result<_, _>>()
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    async fn move_to_cold(
+        &self,
+        session_id: &str,
+        segment: &ColdSegment,
+    ) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let encoded = serde_json::to_string(segment)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+        let moved: i64 = redis::cmd("EVAL")
+            .arg(MOVE_TO_COLD_SCRIPT)
+            .arg(2)
+            .arg(self.keys.history(session_id))
+            .arg(self.keys.cold(session_id))
+            .arg(segment.messages)
+            .arg(encoded)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(moved == 1)
+    }
+
+    async fn get_entities(
+        &self,
+        session_id: &str,
+    ) -> Result<BTreeMap<String, String>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        Ok(redis::Cmd::hgetall(self.keys.entities(session_id))
+            .query_async(&mut conn)
+            .await?)
+    }
+
+    async fn merge_entities(
+        &self,
+        session_id: &str,
+        entities: &BTreeMap<String, String>,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let fields: Vec<(&String, &String)> = entities.iter().collect();
+        let mut pipe = redis::pipe();
+        pipe.hset_multiple(self.keys.entities(session_id), &fields)
+            .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.entities(session_id),
+        );
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_kv(
+        &self,
+        session_id: &str,
+        key: &str,
+    ) -> Result<Option<serde_json::Value>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let value: Option<String> = redis::Cmd::hget(self.keys.kv(session_id), key)
+            .query_async(&mut conn)
+            .await?;
+
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    async fn set_kv(
+        &self,
+        session_id: &str,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.hset(self.keys.kv(session_id), key, value.to_string())
+            .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.kv(session_id),
+        );
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn delete_kv(&self, session_id: &str, key: &str) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let deleted: u64 = redis::Cmd::hdel(self.keys.kv(session_id), key)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn get_pinned(&self, session_id: &str) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let (pinned, auto_pinned): (Vec<String>, Vec<String>) = redis::pipe()
+            .hvals(self.keys.pinned(session_id))
+            .hvals(self.keys.pinned_auto(session_id))
+            .query_async(&mut conn)
+            .await?;
+        Ok(merge_pinned(
+            self.decode_messages(pinned)?,
+            self.decode_messages(auto_pinned)?,
+        ))
+    }
+
+    async fn get_auto_pinned(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<MemoryMessage>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let auto_pinned: Vec<String> = redis::Cmd::hvals(self.keys.pinned_auto(session_id))
+            .query_async(&mut conn)
+            .await?;
+        let mut auto_pinned = self.decode_messages(auto_pinned)?;
+        auto_pinned.sort_by_key(|message| message.created_at);
+        Ok(auto_pinned)
+    }
+
+    async fn set_auto_pinned(
+        &self,
+        session_id: &str,
+        messages: &[MemoryMessage],
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut fields = Vec::with_capacity(messages.len());
+        for message in messages {
+            fields.push((
+                message.id.clone().unwrap_or_default(),
+                self.encode_message(message)?,
+            ));
+        }
+        let mut pipe = redis::pipe();
+        pipe.del(self.keys.pinned_auto(session_id)).ignore();
+        if !fields.is_empty() {
+            pipe.hset_multiple(self.keys.pinned_auto(session_id), &fields)
+                .ignore();
+            inherit_ttl(
+                &mut pipe,
+                &self.keys.messages(session_id),
+                &self.keys.pinned_auto(session_id),
+            );
+        }
+        self.queue_version(&mut pipe, session_id);
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn pin_message(
+        &self,
+        session_id: &str,
+        message: &MemoryMessage,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.hset(
+            self.keys.pinned(session_id),
+            message.id.as_deref().unwrap_or_default(),
+            self.encode_message(message)?,
+        )
+        .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.pinned(session_id),
+        );
+        self.queue_version(&mut pipe, session_id);
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn unpin_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+    ) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.hdel(self.keys.pinned(session_id), message_id)
+            .hdel(self.keys.pinned_auto(session_id), message_id);
+        self.queue_version(&mut pipe, session_id);
+        let (removed, auto_removed): (i64, i64) = pipe.query_async(&mut conn).await?;
+        Ok(removed > 0 || auto_removed > 0)
+    }
+
+    async fn record_usage(
+        &self,
+        session_id: &str,
+        month: &str,
+        usage: TokenUsage,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.hincr(
+            self.keys.session_usage(session_id),
+            "prompt_tokens",
+            usage.prompt_tokens,
+        )
+        .ignore()
+        .hincr(
+            self.keys.session_usage(session_id),
+            "completion_tokens",
+            usage.completion_tokens,
+        )
+        .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.session_usage(session_id),
+        );
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        // On another slot than the session's keys on a cluster.
+        redis::pipe()
+            .hincr(
+                self.keys.usage(month),
+                self.usage_field("prompt_tokens"),
+                usage.prompt_tokens,
+            )
+            .ignore()
+            .hincr(
+                self.keys.usage(month),
+                self.usage_field("completion_tokens"),
+                usage.completion_tokens,
+            )
+            .ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_session_usage(&self, session_id: &str) -> Result<TokenUsage, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let (prompt_tokens, completion_tokens): (Option<u64>, Option<u64>) = redis::Cmd::hget(
+            self.keys.session_usage(session_id),
+            &["prompt_tokens", "completion_tokens"],
+        )
+        .query_async(&mut conn)
+        .await?;
+
+        Ok(TokenUsage {
+            prompt_tokens: prompt_tokens.unwrap_or_default(),
+            completion_tokens: completion_tokens.unwrap_or_default(),
+        })
+    }
+
+    async fn record_languages(
+        &self,
+        session_id: &str,
+        counts: &BTreeMap<String, u64>,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        for (language, count) in counts {
+            pipe.hincr(self.keys.languages(session_id), language, *count)
+                .ignore();
+        }
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.languages(session_id),
+        );
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_languages(
+        &self,
+        session_id: &str,
+    ) -> Result<BTreeMap<String, u64>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        Ok(redis::Cmd::hgetall(self.keys.languages(session_id))
+            .query_async(&mut conn)
+            .await?)
+    }
+
+    async fn record_context_version(
+        &self,
+        session_id: &str,
+        version: &ContextVersion,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let value = serde_json::to_string(&ContextVersion {
+            context: self.seal(&version.context)?,
+            ..version.clone()
+        })
+        .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+        let mut pipe = redis::pipe();
+        pipe.rpush(self.keys.context_versions(session_id), value)
+            .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.context_versions(session_id),
+        );
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_context_versions(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ContextVersion>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let values: Vec<String> = redis::Cmd::lrange(self.keys.context_versions(session_id), 0, -1)
+            .query_async(&mut conn)
+            .await?;
+        values
+            .into_iter()
+            .filter_map(|value| serde_json::from_str::<ContextVersion>(&value).ok())
+            .map(|version| {
+                Ok(ContextVersion {
+                    context: self.unseal(version.context)?,
+                    ..version
+                })
+            })
+            .collect()
+    }
+
+    async fn record_compaction(&self, session_id: &str, at: u64) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        pipe.hset(self.keys.compaction(session_id), "at", at)
+            .ignore()
+            .hincr(self.keys.compaction(session_id), "count", 1)
+            .ignore();
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.compaction(session_id),
+        );
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn last_compaction(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<CompactionStamp>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let (at, count): (Option<u64>, Option<u64>) =
+            redis::Cmd::hget(self.keys.compaction(session_id), &["at", "count"])
+                .query_async(&mut conn)
+                .await?;
+
+        Ok(at.map(|at| CompactionStamp {
+            at,
+            count: count.unwrap_or_default(),
+        }))
+    }
+
+    async fn get_tenant_usage(&self, month: &str) -> Result<TokenUsage, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let (prompt_tokens, completion_tokens): (Option<u64>, Option<u64>) = redis::Cmd::hget(
+            self.keys.usage(month),
+            &[
+                self.usage_field("prompt_tokens"),
+                self.usage_field("completion_tokens"),
+            ],
+        )
+        .query_async(&mut conn)
+        .await?;
+
+        Ok(TokenUsage {
+            prompt_tokens: prompt_tokens.unwrap_or_default(),
+            completion_tokens: completion_tokens.unwrap_or_default(),
+        })
+    }
+
+    async fn usage_by_tenant(
+        &self,
+        month: &str,
+    ) -> Result<Vec<(Option<String>, TokenUsage)>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let fields: BTreeMap<String, u64> = redis::Cmd::hgetall(self.keys.usage(month))
+            .query_async(&mut conn)
+            .await?;
+
+        let mut usage: BTreeMap<String, TokenUsage> = BTreeMap::new();
+        for (field, tokens) in fields {
+            let Some((tenant, kind)) = field.rsplit_once(':') else {
+                continue;
+            };
+            let tenant_usage = usage.entry(tenant.to_string()).or_default();
+            match kind {
+                "prompt_tokens" => tenant_usage.prompt_tokens = tokens,
+                "completion_tokens" => tenant_usage.completion_tokens = tokens,
+                _ => {}
+            }
+        }
+
+        Ok(usage
+            .into_iter()
+            .map(|(tenant, usage)| ((!tenant.is_empty()).then_some(tenant), usage))
+            .collect())
+    }
+
+    async fn record_analytics(
+        &self,
+        day: &str,
+        counts: &AnalyticsCounts,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        for (field, count) in counts.fields() {
+            if count > 0 {
+                pipe.hincr(self.keys.analytics(day), self.usage_field(field), count)
+                    .ignore();
+            }
+        }
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn analytics_by_tenant(
+        &self,
+        day: &str,
+    ) -> Result<Vec<(Option<String>, AnalyticsCounts)>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let fields: BTreeMap<String, u64> = redis::Cmd::hgetall(self.keys.analytics(day))
+            .query_async(&mut conn)
+            .await?;
+
+        let mut analytics: BTreeMap<String, AnalyticsCounts> = BTreeMap::new();
+        for (field, count) in fields {
+            let Some((tenant, counter)) = field.rsplit_once(':') else {
+                continue;
+            };
+            analytics
+                .entry(tenant.to_string())
+                .or_default()
+                .add(counter, count);
+        }
+
+        Ok(analytics
+            .into_iter()
+            .map(|(tenant, counts)| ((!tenant.is_empty()).then_some(tenant), counts))
+            .collect())
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        session_id: &str,
+        key: &str,
+        ttl_seconds: u64,
+    ) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(self.keys.idempotency(session_id, key))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl_seconds)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(claimed.is_some())
+    }
+
+    async fn release_idempotency_key(
+        &self,
+        session_id: &str,
+        key: &str,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        redis::Cmd::del(self.keys.idempotency(session_id, key))
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Only the sessions in the unnamespaced session index are moved, so that keys of other
+    /// services sharing the instance are left alone.
+    async fn migrate_legacy_sessions(&self) -> Result<usize, MotorheadError> {
+        if self.keys.is_hash_tagged() {
+            return Err(MotorheadError::Unsupported(
+                "moving sessions across cluster slots",
+            ));
+        }
+        let Some(legacy) = self.keys.legacy() else {
+            return Ok(0);
+        };
+
+        let mut conn = self.conn().await?;
+        let mut moved = 0;
+        // The hashed ids with no original left to name their keys by, which stay indexed.
+        let mut stuck = 0;
+        loop {
+            let ids: Vec<String> = redis::Cmd::zrange(
+                legacy.sessions(),
+                stuck as isize,
+                (stuck + MIGRATION_PAGE_SIZE - 1) as isize,
+            )
+            .query_async(&mut conn)
+            .await?;
+            if ids.is_empty() {
+                return Ok(moved);
+            }
+
+            let session_ids: Vec<Option<String>> = if self.keys.is_hashed() {
+                redis::cmd("HMGET")
+                    .arg(legacy.session_ids())
+                    .arg(&ids)
+                    .query_async(&mut conn)
+                    .await?
+            } else {
+                ids.into_iter().map(Some).collect()
+            };
+            let mut pipe = redis::pipe();
+            for session_id in session_ids {
+                match session_id {
+                    Some(session_id) => {
+                        self.queue_migration(&mut pipe, &legacy, &session_id);
+                        moved += 1;
+                    }
+                    None => stuck += 1,
+                }
+            }
+            pipe.query_async::<_, ()>(&mut conn).await?;
+        }
+    }
+
+    async fn list_sessions(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(String, u64)>, MotorheadError> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.conn().await?;
+
+        let entries: Vec<(String, u64)> = redis::Cmd::zrevrange_withscores(
+            self.keys.sessions(),
+            offset as isize,
+            (offset + limit - 1) as isize,
+        )
+        .query_async(&mut conn)
+        .await?;
+
+        if !self.keys.is_hashed() || entries.is_empty() {
+            return Ok(entries);
+        }
+
+        let ids: Vec<&String> = entries.iter().map(|(id, _)| id).collect();
+        let originals: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(self.keys.session_ids())
+            .arg(ids)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(originals
+            .into_iter()
+            .zip(entries)
+            .map(|(original, (id, last_activity))| (original.unwrap_or(id), last_activity))
+            .collect())
+    }
+
+    async fn update_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> Result<bool, MotorheadError> {
+        if self.log == MessageLog::Stream {
+            return Err(MotorheadError::Unsupported(
+                "Editing messages kept in streams",
+            ));
+        }
+
+        let mut conn = self.conn().await?;
+
+        let Some((entry, mut message)) =
+            self.find_message(&mut conn, session_id, message_id).await?
+        else {
+            return Ok(false);
+        };
+        message.content = content.to_string();
+
+        let replaced: i64 = redis::cmd("EVAL")
+            .arg(REPLACE_ENTRY_SCRIPT)
+            .arg(1)
+            .arg(self.keys.messages(session_id))
+            .arg(entry)
+            .arg(self.encode_message(&message)?)
+            .query_async(&mut conn)
+            .await?;
+        if replaced == 0 {
+            return Ok(false);
+        }
+
+        let mut pipe = redis::pipe();
+        self.publish(
+            &mut pipe,
+            session_id,
+            &SessionEvent::MessageUpdated {
+                id: message_id,
+                content,
+            },
+        )?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(true)
+    }
+
+    async fn delete_message(
+        &self,
+        session_id: &str,
+        message_id: &str,
+    ) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let Some((entry, _)) = self.find_message(&mut conn, session_id, message_id).await? else {
+            return Ok(false);
+        };
+
+        // Removing by value rather than index, the list may have been compacted meanwhile.
+        let removed: i64 = match self.log {
+            MessageLog::List => redis::Cmd::lrem(self.keys.messages(session_id), 1, entry),
+            MessageLog::Stream => redis::cmd("EVAL")
+                .arg(REMOVE_STREAM_ENTRY_SCRIPT)
+                .arg(1)
+                .arg(self.keys.messages(session_id))
+                .arg(entry)
+                .to_owned(),
+        }
+        .query_async(&mut conn)
+        .await?;
+        if removed == 0 {
+            return Ok(false);
+        }
+
+        let mut pipe = redis::pipe();
+        self.publish(
+            &mut pipe,
+            session_id,
+            &SessionEvent::MessageDeleted { id: message_id },
+        )?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(true)
+    }
+
+    async fn delete_newest_messages(
+        &self,
+        session_id: &str,
+        ids: &[Option<String>],
+    ) -> Result<bool, MotorheadError> {
+        if ids.is_empty() {
+            return Ok(true);
+        }
+        let mut conn = self.conn().await?;
+
+        let mut script = redis::cmd("EVAL");
+        script
+            .arg(DELETE_NEWEST_SCRIPT)
+            .arg(3)
+            .arg(self.keys.messages(session_id))
+            .arg(self.keys.pinned(session_id))
+            .arg(self.keys.pinned_auto(session_id))
+            .arg(match self.log {
+                MessageLog::List => "list",
+                MessageLog::Stream => "stream",
+            });
+        for id in ids {
+            script.arg(id.as_deref().unwrap_or_default());
+        }
+        let deleted: i64 = script.query_async(&mut conn).await?;
+        if deleted == 0 {
+            return Ok(false);
+        }
+
+        // Messages without ids publish no event, but change the session all the same.
+        let mut pipe = redis::pipe();
+        self.queue_version(&mut pipe, session_id);
+        for id in ids.iter().flatten() {
+            self.publish(&mut pipe, session_id, &SessionEvent::MessageDeleted { id })?;
+        }
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(true)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let vector_keys: Vec<String> = redis::Cmd::smembers(self.keys.vectors(session_id))
+            .query_async(&mut conn)
+            .await?;
+
+        let mut pipe = redis::pipe();
+        self.queue_delete(&mut pipe, session_id, vector_keys)?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        let mut unindex = redis::pipe();
+        self.queue_unindex(&mut unindex, session_id);
+        unindex.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn apply_batch(&self, ops: &[BatchOp<'_>]) -> Result<Vec<Option<i64>>, MotorheadError> {
+        // A cluster can't pipeline keys of different slots together.
+        if self.keys.is_hash_tagged() || ops.is_empty() {
+            return apply_batch_sequentially(self, ops).await;
+        }
+
+        let mut conn = self.conn().await?;
+
+        // A first round trip fetches the vector keys of the sessions to delete.
+        let mut prepare = redis::pipe();
+        for op in ops {
+            if let BatchOp::Delete { session_id } = op {
+                prepare.smembers(self.keys.vectors(session_id));
+            }
+        }
+        let vector_keys: Vec<Vec<String>> = prepare.query_async(&mut conn).await?;
+        let mut vector_keys = vector_keys.into_iter();
+
+        let mut pipe = redis::pipe();
+        for op in ops {
+            match op {
+                BatchOp::Append {
+                    session_id,
+                    messages,
+                } => {
+                    self.queue_append(&mut pipe, session_id, messages)?;
+                    self.queue_index(&mut pipe, session_id);
+                }
+                BatchOp::Delete { session_id } => {
+                    let keys = vector_keys.next().unwrap_or_default();
+                    self.queue_delete(&mut pipe, session_id, keys)?;
+                    self.queue_unindex(&mut pipe, session_id);
+                }
+            }
+        }
+        let lengths: Vec<i64> = pipe.query_async(&mut conn).await?;
+        let mut lengths = lengths.into_iter();
+
+        Ok(ops
+            .iter()
+            .map(|op| match op {
+                BatchOp::Append { .. } => lengths.next(),
+                BatchOp::Delete { .. } => None,
+            })
+            .collect())
+    }
+
+    async fn track_expiry(&self, session_id: &str, ttl_seconds: u64) -> Result<(), MotorheadError> {
+        // Keyspace notifications only reach subscribers of the node the key lives on.
+        if self.keys.is_hash_tagged() {
+            return Err(MotorheadError::Unsupported(
+                "expiry notifications on Redis Cluster",
+            ));
+        }
+        let mut conn = self.conn().await?;
+
+        redis::Cmd::set_ex(self.keys.expiry(session_id), "", ttl_seconds)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Needs keyspace notifications for expired keys (`notify-keyspace-events` with `Ex`).
+    async fn expirations(
+        &self,
+    ) -> Result<BoxStream<'static, (Option<String>, String)>, MotorheadError> {
+        if self.keys.is_hash_tagged() {
+            return Err(MotorheadError::Unsupported(
+                "expiry notifications on Redis Cluster",
+            ));
+        }
+        let mut pubsub = self.pool.pubsub().await?;
+        pubsub.psubscribe("__keyevent@*__:expired").await?;
+
+        let keys = self.keys.clone();
+        Ok(pubsub
+            .into_on_message()
+            .filter_map(move |message| {
+                let expired = message
+                    .get_payload::<String>()
+                    .ok()
+                    .and_then(|key| keys.parse_expiry(&key));
+                async move { expired }
+            })
+            .boxed())
+    }
+
+    async fn subscribe(
+        &self,
+        session_id: &str,
+    ) -> Result<BoxStream<'static, String>, MotorheadError> {
+        let mut pubsub = self.pool.pubsub().await?;
+        pubsub.subscribe(self.keys.events(session_id)).await?;
+
+        Ok(pubsub
+            .into_on_message()
+            .filter_map(|message| async move { message.get_payload::<String>().ok() })
+            .boxed())
+    }
+
+    fn changes_channel(&self, session_id: &str) -> Option<String> {
+        Some(self.keys.events(session_id))
+    }
+
+    async fn session_changes(&self) -> Result<BoxStream<'static, String>, MotorheadError> {
+        let mut pubsub = self.pool.pubsub().await?;
+        pubsub.psubscribe(self.keys.events_pattern()).await?;
+
+        Ok(pubsub
+            .into_on_message()
+            .map(|message| message.get_channel_name().to_string())
+            .boxed())
+    }
+
+    async fn repair_entries(&self, session_id: &str, purge: bool) -> Result<usize, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        // Streams postdate JSON entries, only lists can hold old ones.
+        let mut keys = vec![self.keys.history(session_id)];
+        if self.log == MessageLog::List {
+            keys.push(self.keys.messages(session_id));
+        }
+
+        let mut repaired = 0;
+        for key in keys {
+            let entries: Vec<String> = redis::Cmd::lrange(&key, 0, -1)
+                .query_async(&mut conn)
+                .await?;
+            // By value rather than index, the list may be appended to or compacted meanwhile.
+            for entry in entries.into_iter().filter(|entry| is_malformed(entry)) {
+                let fixed: i64 = if purge {
+                    redis::Cmd::lrem(&key, 1, &entry)
+                        .query_async(&mut conn)
+                        .await?
+                } else {
+                    let message = MemoryMessage {
+                        id: Some(uuid::Uuid::new_v4().to_string()),
+                        ..legacy_message(UNKNOWN_ROLE.to_string(), entry.clone())
+                    };
+                    redis::cmd("EVAL")
+                        .arg(REPLACE_ENTRY_SCRIPT)
+                        .arg(1)
+                        .arg(&key)
+                        .arg(&entry)
+                        .arg(self.encode_message(&message)?)
+                        .query_async(&mut conn)
+                        .await?
+                };
+                repaired += fixed as usize;
+            }
+        }
+
+        if repaired > 0 {
+            let mut pipe = redis::pipe();
+            self.queue_version(&mut pipe, session_id);
+            pipe.query_async::<_, ()>(&mut conn).await?;
+        }
+        Ok(repaired)
+    }
+
+    async fn record_compaction_failure(
+        &self,
+        failure: &CompactionFailure,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let value = serde_json::to_string(failure)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+        redis::Cmd::hset(
+            self.keys.compaction_failures(),
+            compaction_failure_field(failure.tenant.as_deref(), &failure.session_id),
+            value,
+        )
+        .query_async::<_, ()>(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn clear_compaction_failure(
+        &self,
+        tenant: Option<&str>,
+        session_id: &str,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        redis::Cmd::hdel(
+            self.keys.compaction_failures(),
+            compaction_failure_field(tenant, session_id),
+        )
+        .query_async::<_, ()>(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn compaction_failures(&self) -> Result<Vec<CompactionFailure>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let values: Vec<String> = redis::Cmd::hvals(self.keys.compaction_failures())
+            .query_async(&mut conn)
+            .await?;
+
+        values
+            .iter()
+            .map(|value| serde_json::from_str(value))
+            .collect::<Result<_, _>>()
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    async fn set_recap(&self, recap: &Recap, oldest_date: &str) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+        let recaps = self.keys.recaps(&recap.user_id);
+        let value = serde_json::to_string(recap)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+        let value = self.seal(&value)?;
+
+        // Days are scored alike, so `YYYY-MM-DD` sorts them.
+        let expired: Vec<String> = redis::cmd("ZRANGEBYLEX")
+            .arg(&recaps)
+            .arg("-")
+            .arg(format!("({}", oldest_date))
+            .query_async(&mut conn)
+            .await?;
+
+        let mut pipe = redis::pipe();
+        pipe.set(self.keys.recap(&recap.user_id, &recap.date), value)
+            .ignore()
+            .zadd(&recaps, &recap.date, 0)
+            .ignore();
+        if !expired.is_empty() {
+            let keys: Vec<String> = expired
+                .iter()
+                .map(|date| self.keys.recap(&recap.user_id, date))
+                .collect();
+            pipe.del(keys).ignore().zrem(&recaps, expired).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn has_recap(&self, user_id: &str, date: &str) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        Ok(redis::Cmd::exists(self.keys.recap(user_id, date))
+            .query_async(&mut conn)
+            .await?)
+    }
+
+    async fn get_recaps(&self, user_id: &str, limit: usize) -> Result<Vec<Recap>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let dates: Vec<String> = redis::cmd("ZREVRANGEBYLEX")
+            .arg(self.keys.recaps(user_id))
+            .arg("+")
+            .arg("-")
+            .arg("LIMIT")
+            .arg(0)
+            .arg(limit)
+            .query_async(&mut conn)
+            .await?;
+        if dates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let keys: Vec<String> = dates
+            .iter()
+            .map(|date| self.keys.recap(user_id, date))
+            .collect();
+        let values: Vec<Option<String>> =
+            redis::cmd("MGET").arg(keys).query_async(&mut conn).await?;
+
+        values
+            .into_iter()
+            .flatten()
+            .map(|value| {
+                serde_json::from_str(&self.unseal(value)?)
+                    .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+            })
+            .collect()
+    }
+
+    async fn delete_recaps(&self, user_id: &str) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+        let recaps = self.keys.recaps(user_id);
+
+        let dates: Vec<String> = redis::Cmd::zrange(&recaps, 0, -1)
+            .query_async(&mut conn)
+            .await?;
+        let mut keys: Vec<String> = dates
+            .iter()
+            .map(|date| self.keys.recap(user_id, date))
+            .collect();
+        keys.push(recaps);
+        redis::Cmd::del(keys)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+        let value = serde_json::to_string(entry)
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))?;
+
+        redis::cmd("XADD")
+            .arg(self.keys.audit())
+            .arg("*")
+            .arg("entry")
+            .arg(value)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn audit_entries(
+        &self,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        // Stream ids start with the millisecond they were added at.
+        let entries: Vec<(String, Vec<String>)> = redis::cmd("XRANGE")
+            .arg(self.keys.audit())
+            .arg(since)
+            .arg("+")
+            .arg("COUNT")
+            .arg(limit)
+            .query_async(&mut conn)
+            .await?;
+
+        entries
+            .iter()
+            .filter_map(|(_, fields)| fields.get(1))
+            .map(|value| serde_json::from_str(value))
+            .collect::<Result<_, _>>()
+            .map_err(|e| MotorheadError::SerializationError(e.to_string()))
+    }
+
+    async fn take_rate_token(
+        &self,
+        bucket: &str,
+        capacity: u32,
+        refill_per_ms: f64,
+    ) -> Result<Option<u64>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let wait: u64 = redis::cmd("EVAL")
+            .arg(TAKE_TOKEN_SCRIPT)
+            .arg(1)
+            .arg(self.keys.rate_limit(bucket))
+            .arg(capacity)
+            .arg(refill_per_ms)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok((wait > 0).then_some(wait))
+    }
+
+    async fn init_vectors(&self, dimensions: usize) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let result = redis::cmd("FT.CREATE")
+            .arg(VECTOR_INDEX)
+            .arg("ON")
+            .arg("HASH")
+            .arg("PREFIX")
+            .arg(1)
+            .arg(VECTOR_PREFIX)
+            .arg("SCHEMA")
+            .arg("session")
+            .arg("TAG")
+            .arg("content")
+            .arg("TEXT")
+            .arg("role")
+            .arg("TEXT")
+            .arg("vector")
+            .arg("VECTOR")
+            .arg("HNSW")
+            .arg(6)
+            .arg("TYPE")
+            .arg("FLOAT32")
+            .arg("DIM")
+            .arg(dimensions)
+            .arg("DISTANCE_METRIC")
+            .arg("COSINE")
+            .query_async::<_, ()>(&mut conn)
+            .await;
+
+        match result {
+            Err(e) if e.to_string().contains("Index already exists") => {}
+            result => return Ok(result?),
+        }
+
+        // An index made for another model would silently find nothing with these vectors.
+        let info: redis::Value = redis::cmd("FT.INFO")
+            .arg(VECTOR_INDEX)
+            .query_async(&mut conn)
+            .await?;
+        match index_dimensions(&info) {
+            Some(existing) if existing != dimensions => {
+                Err(MotorheadError::EmbeddingError(format!(
+                "The {} index holds vectors of {} dimensions, not {}: drop it with FT.DROPINDEX \
+                 to recreate it, and embed the messages again",
+                VECTOR_INDEX, existing, dimensions
+            )))
+            }
+            Some(_) => Ok(()),
+            None => {
+                tracing::warn!("Could not read the dimensions of the existing vector index");
+                Ok(())
+            }
+        }
+    }
+
+    async fn add_vectors(
+        &self,
+        session_id: &str,
+        entries: Vec<(MemoryMessage, Vec<f32>)>,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = redis::pipe();
+        for (message, vector) in entries {
+            let key = self
+                .keys
+                .vector(session_id, &uuid::Uuid::new_v4().to_string());
+            pipe.hset_multiple(
+                &key,
+                &[
+                    ("session", self.keys.base(session_id).into_bytes()),
+                    ("role", String::from(message.role).into_bytes()),
+                    ("content", self.seal(&message.content)?.into_bytes()),
+                    ("vector", vector_bytes(&vector)),
+                ],
+            )
+            .ignore()
+            .sadd(self.keys.vectors(session_id), &key)
+            .ignore();
+            inherit_ttl(&mut pipe, &self.keys.messages(session_id), &key);
+        }
+        inherit_ttl(
+            &mut pipe,
+            &self.keys.messages(session_id),
+            &self.keys.vectors(session_id),
+        );
+
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn search_vectors(
+        &self,
+        session_id: &str,
+        vector: Vec<f32>,
+        limit: usize,
+    ) -> Result<Vec<RetrievalResult>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let query = format!(
+            "@session:{{{}}}=>[KNN {} @vector $V AS dist]",
+            escape_tag(&self.keys.base(session_id)),
+            limit
+        );
+
+        let reply: redis::Value = redis::cmd("FT.SEARCH")
+            .arg(VECTOR_INDEX)
+            .arg(query)
+            .arg("PARAMS")
+            .arg(2)
+            .arg("V")
+            .arg(vector_bytes(&vector))
+            .arg("RETURN")
+            .arg(3)
+            .arg("role")
+            .arg("content")
+            .arg("dist")
+            .arg("SORTBY")
+            .arg("dist")
+            // Otherwise only the first 10 are returned.
+            .arg("LIMIT")
+            .arg(0)
+            .arg(limit)
+            .arg("DIALECT")
+            .arg(2)
+            .query_async(&mut conn)
+            .await?;
+
+        parse_search_results(reply)?
+            .into_iter()
+            .map(|result| {
+                Ok(RetrievalResult {
+                    content: self.unseal(result.content)?,
+                    ..result
+                })
+            })
+            .collect()
+    }
+
+    async fn session_version(&self, session_id: &str) -> Result<Option<String>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        Ok(redis::Cmd::get(self.keys.version(session_id))
+            .query_async(&mut conn)
+            .await?)
+    }
+
+    async fn claim_version(&self, session_id: &str, version: &str) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let claimed: i64 = redis::cmd("EVAL")
+            .arg(CLAIM_VERSION_SCRIPT)
+            .arg(1)
+            .arg(self.keys.version(session_id))
+            .arg(version)
+            .arg(uuid::Uuid::new_v4().simple().to_string())
+            .query_async(&mut conn)
+            .await?;
+        Ok(claimed == 1)
+    }
+
+    async fn acquire_lock(
+        &self,
+        session_id: &str,
+        token: &str,
+        ttl_ms: u64,
+        now: u64,
+    ) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let acquired: i64 = redis::cmd("EVAL")
+            .arg(ACQUIRE_LOCK_SCRIPT)
+            .arg(1)
+            .arg(self.keys.lock(session_id))
+            .arg(token)
+            .arg(ttl_ms)
+            .arg(now + ttl_ms)
+            .query_async(&mut conn)
+            .await?;
+        Ok(acquired == 1)
+    }
+
+    async fn release_lock(&self, session_id: &str, token: &str) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let released: i64 = redis::cmd("EVAL")
+            .arg(RELEASE_LOCK_SCRIPT)
+            .arg(1)
+            .arg(self.keys.lock(session_id))
+            .arg(token)
+            .query_async(&mut conn)
+            .await?;
+        Ok(released == 1)
+    }
+
+    async fn set_delete_intent(
+        &self,
+        session_id: &str,
+        token: &str,
+        ttl_ms: u64,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        redis::Cmd::pset_ex(self.keys.delete_intent(session_id), token, ttl_ms)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn take_delete_intent(
+        &self,
+        session_id: &str,
+        token: &str,
+    ) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let taken: i64 = redis::cmd("EVAL")
+            .arg(TAKE_DELETE_INTENT_SCRIPT)
+            .arg(1)
+            .arg(self.keys.delete_intent(session_id))
+            .arg(token)
+            .query_async(&mut conn)
+            .await?;
+        Ok(taken == 1)
+    }
+
+    async fn lock_expiry(&self, session_id: &str) -> Result<Option<u64>, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let lock: Option<String> = redis::Cmd::get(self.keys.lock(session_id))
+            .query_async(&mut conn)
+            .await?;
+        Ok(lock.and_then(|lock| lock.rsplit_once(':')?.1.parse().ok()))
+    }
+
+    async fn claim_compaction(
+        &self,
+        session_id: &str,
+        token: &str,
+        ttl_ms: u64,
+    ) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(self.keys.compaction_claim(session_id))
+            .arg(token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_ms)
+            .query_async(&mut conn)
+            .await?;
+        Ok(claimed.is_some())
+    }
+
+    async fn renew_compaction(
+        &self,
+        session_id: &str,
+        token: &str,
+        ttl_ms: u64,
+    ) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        let renewed: i64 = redis::cmd("EVAL")
+            .arg(RENEW_COMPACTION_SCRIPT)
+            .arg(1)
+            .arg(self.keys.compaction_claim(session_id))
+            .arg(token)
+            .arg(ttl_ms)
+            .query_async(&mut conn)
+            .await?;
+        Ok(renewed == 1)
+    }
+
+    async fn release_compaction(
+        &self,
+        session_id: &str,
+        token: &str,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        redis::cmd("EVAL")
+            .arg(RELEASE_COMPACTION_SCRIPT)
+            .arg(1)
+            .arg(self.keys.compaction_claim(session_id))
+            .arg(token)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn compaction_claimed(&self, session_id: &str) -> Result<bool, MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        Ok(redis::Cmd::exists(self.keys.compaction_claim(session_id))
+            .query_async(&mut conn)
+            .await?)
+    }
+
+    async fn trash_session(
+        &self,
+        session_id: &str,
+        ttl_seconds: u64,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+        let trashed = self.keys.trashed();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        let mut script = redis::cmd("EVAL");
+        script.arg(TRASH_SESSION_SCRIPT).arg(2 * OWN_KEYS + 1);
+        for (key, trashed_key) in Self::own_keys(&self.keys, session_id)
+            .into_iter()
+            .zip(Self::own_keys(&trashed, session_id))
+        {
+            script.arg(key).arg(trashed_key);
+        }
+        script
+            .arg(trashed.deleted(session_id))
+            .arg(ttl_seconds)
+            .arg(self.keys.trashed_vector_prefix())
+            .arg(now);
+
+        let mut pipe = redis::pipe();
+        pipe.add_command(script).ignore();
+        self.publish(&mut pipe, session_id, &SessionEvent::SessionDeleted)?;
+        pipe.query_async::<_, ()>(&mut conn).await?;
+
+        let mut unindex = redis::pipe();
+        self.queue_unindex(&mut unindex, session_id);
+        unindex.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn restore_session(&self, session_id: &str) -> Result<Restore, MotorheadError> {
+        let mut conn = self.conn().await?;
+        let trashed = self.keys.trashed();
+
+        let mut script = redis::cmd("EVAL");
+        script.arg(RESTORE_SESSION_SCRIPT).arg(2 * OWN_KEYS + 1);
+        for (trashed_key, key) in Self::own_keys(&trashed, session_id)
+            .into_iter()
+            .zip(Self::own_keys(&self.keys, session_id))
+        {
+            script.arg(trashed_key).arg(key);
+        }
+        script
+            .arg(trashed.deleted(session_id))
+            .arg(self.keys.trashed_vector_prefix());
+        let restored: i64 = script.query_async(&mut conn).await?;
+
+        match restored {
+            0 => Ok(Restore::NotTrashed),
+            -1 => Ok(Restore::SessionExists),
+            _ => {
+                let mut index = redis::pipe();
+                self.queue_index(&mut index, session_id);
+                index.query_async::<_, ()>(&mut conn).await?;
+                Ok(Restore::Restored)
+            }
+        }
+    }
+
+    fn expires_sessions(&self) -> bool {
+        true
+    }
+
+    async fn expire_session(
+        &self,
+        session_id: &str,
+        ttl_seconds: u64,
+    ) -> Result<(), MotorheadError> {
+        let mut conn = self.conn().await?;
+
+        redis::cmd("EVAL")
+            .arg(EXPIRE_SESSION_SCRIPT)
+            .arg(OWN_KEYS)
+            .arg(&Self::own_keys(&self.keys, session_id))
+            .arg(ttl_seconds)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+}
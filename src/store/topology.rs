@@ -0,0 +1,335 @@
+use deadpool::managed::{Manager, Metrics, Object, Pool, PoolError, RecycleError, RecycleResult};
+use deadpool::Runtime;
+use redis::aio::{ConnectionLike, ConnectionManager, PubSub};
+use redis::cluster::{ClusterClient, ClusterClientBuilder};
+use redis::cluster_async::ClusterConnection;
+use redis::sentinel::{Sentinel, SentinelNodeConnectionInfo};
+use redis::{
+    Arg, Cmd, ConnectionAddr, ConnectionInfo, ErrorKind, IntoConnectionInfo, Pipeline, RedisError,
+    RedisFuture, RedisResult, TlsCertificates, TlsMode, Value,
+};
+use std::io;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::Instrument;
+
+use crate::telemetry;
+
+/// Credentials and certificates given apart from the URLs. Set credentials take precedence
+/// over the ones in the URLs.
+pub struct RedisAuth {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub certificates: Option<TlsCertificates>,
+}
+
+impl RedisAuth {
+    fn connection_info(&self, url: &str) -> RedisResult<ConnectionInfo> {
+        let mut info = url.into_connection_info()?;
+        if self.username.is_some() {
+            info.redis.username = self.username.clone();
+        }
+        if self.password.is_some() {
+            info.redis.password = self.password.clone();
+        }
+
+        if self.certificates.is_some() && !matches!(info.addr, ConnectionAddr::TcpTls { .. }) {
+            return Err((
+                ErrorKind::InvalidClientConfig,
+                "TLS certificates are set but the URL doesn't use rediss://",
+            )
+                .into());
+        }
+
+        Ok(info)
+    }
+
+    fn client(&self, info: ConnectionInfo) -> RedisResult<redis::Client> {
+        match &self.certificates {
+            Some(certificates) => redis::Client::build_with_tls(info, certificates.clone()),
+            None => redis::Client::open(info),
+        }
+    }
+}
+
+/// How the Redis store reaches its server(s), picked with `MOTORHEAD_REDIS_MODE`.
+pub enum RedisTopology {
+    Standalone(redis::Client),
+    /// Messages published on a cluster reach every node, so subscriptions are served by the
+    /// first node given.
+    Cluster {
+        client: ClusterClient,
+        pubsub: redis::Client,
+    },
+    /// The sentinels are asked for the current master on every connection, which follows
+    /// failovers.
+    Sentinel {
+        sentinel: Mutex<Sentinel>,
+        master: String,
+        node: SentinelNodeConnectionInfo,
+    },
+}
+
+/// A connection to any topology, kept in a `RedisPool`.
+pub enum PooledConnection {
+    Single(Box<ConnectionManager>),
+    Cluster(ClusterConnection),
+}
+
+/// A connection taken from a `RedisPool`, usable wherever redis-rs takes an async
+/// connection. It goes back to the pool when dropped.
+pub struct RedisConnection(Object<RedisManager>);
+
+/// Opens the connections of a `RedisPool`, and checks them before they're reused.
+pub struct RedisManager(RedisTopology);
+
+impl Manager for RedisManager {
+    type Type = PooledConnection;
+    type Error = RedisError;
+
+    async fn create(&self) -> RedisResult<PooledConnection> {
+        self.0.connect().await
+    }
+
+    /// Connections to a sentinel's master are dropped once it's no longer the master, so
+    /// failovers are followed.
+    async fn recycle(&self, conn: &mut PooledConnection, _: &Metrics) -> RecycleResult<RedisError> {
+        if let RedisTopology::Sentinel { .. } = self.0 {
+            let role: Vec<Value> = redis::cmd("ROLE").query_async(conn).await?;
+            if !matches!(role.first(), Some(Value::Data(role)) if role == b"master") {
+                return Err(RecycleError::message("No longer connected to the master"));
+            }
+        } else {
+            redis::cmd("PING").query_async::<_, ()>(conn).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The connections shared by every request, opened as needed up to `size` of them and
+/// checked with a `PING` before being reused.
+#[derive(Clone)]
+pub struct RedisPool(Pool<RedisManager>);
+
+impl RedisPool {
+    /// Waiting for a free connection and checking one each time out after `timeout`.
+    /// Opening one is left to redis-rs, which retries refused connections a few times and
+    /// then fails with the actual error.
+    pub fn new(topology: RedisTopology, size: usize, timeout: Duration) -> RedisResult<Self> {
+        Pool::builder(RedisManager(topology))
+            .max_size(size)
+            .wait_timeout(Some(timeout))
+            .recycle_timeout(Some(timeout))
+            .runtime(Runtime::Tokio1)
+            .build()
+            .map(RedisPool)
+            .map_err(|e| {
+                (
+                    ErrorKind::InvalidClientConfig,
+                    "Invalid connection pool",
+                    e.to_string(),
+                )
+                    .into()
+            })
+    }
+
+    pub async fn get(&self) -> RedisResult<RedisConnection> {
+        self.0
+            .get()
+            .await
+            .map(RedisConnection)
+            .map_err(|e| match e {
+                PoolError::Backend(e) => e,
+                PoolError::Timeout(_) => io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Timed out getting a Redis connection from the pool",
+                )
+                .into(),
+                e => io::Error::other(e.to_string()).into(),
+            })
+    }
+
+    /// Opens a dedicated connection for subscriptions, which can't share a multiplexed one.
+    pub async fn pubsub(&self) -> RedisResult<PubSub> {
+        self.0.manager().0.pubsub().await
+    }
+}
+
+impl RedisTopology {
+    pub fn standalone(url: &str, auth: &RedisAuth) -> RedisResult<Self> {
+        Ok(RedisTopology::Standalone(
+            auth.client(auth.connection_info(url)?)?,
+        ))
+    }
+
+    pub fn cluster(urls: &[&str], auth: &RedisAuth) -> RedisResult<Self> {
+        let nodes = urls
+            .iter()
+            .map(|url| auth.connection_info(url))
+            .collect::<RedisResult<Vec<_>>>()?;
+
+        let mut client = ClusterClientBuilder::new(nodes.clone());
+        if let Some(certificates) = &auth.certificates {
+            client = client.certs(certificates.clone());
+        }
+
+        Ok(RedisTopology::Cluster {
+            client: client.build()?,
+            pubsub: auth.client(nodes[0].clone())?,
+        })
+    }
+
+    /// The master is connected to with the TLS mode and credentials of the first sentinel.
+    pub fn sentinel(urls: &[&str], master: &str, auth: &RedisAuth) -> RedisResult<Self> {
+        if auth.certificates.is_some() {
+            return Err((
+                ErrorKind::InvalidClientConfig,
+                "TLS certificates aren't supported with sentinel",
+            )
+                .into());
+        }
+
+        let sentinels = urls
+            .iter()
+            .map(|url| auth.connection_info(url))
+            .collect::<RedisResult<Vec<_>>>()?;
+        let node = SentinelNodeConnectionInfo {
+            tls_mode: match sentinels[0].addr {
+                ConnectionAddr::TcpTls { insecure: true, .. } => Some(TlsMode::Insecure),
+                ConnectionAddr::TcpTls { .. } => Some(TlsMode::Secure),
+                _ => None,
+            },
+            redis_connection_info: Some(sentinels[0].redis.clone()),
+        };
+
+        Ok(RedisTopology::Sentinel {
+            sentinel: Mutex::new(Sentinel::build(sentinels)?),
+            master: master.to_string(),
+            node,
+        })
+    }
+
+    async fn master(
+        sentinel: &Mutex<Sentinel>,
+        master: &str,
+        node: &SentinelNodeConnectionInfo,
+    ) -> RedisResult<redis::Client> {
+        sentinel
+            .lock()
+            .await
+            .async_master_for(master, Some(node))
+            .await
+    }
+
+    async fn connect(&self) -> RedisResult<PooledConnection> {
+        match self {
+            RedisTopology::Standalone(client) => Ok(PooledConnection::Single(Box::new(
+                client.get_connection_manager().await?,
+            ))),
+            RedisTopology::Cluster { client, .. } => Ok(PooledConnection::Cluster(
+                client.get_async_connection().await?,
+            )),
+            RedisTopology::Sentinel {
+                sentinel,
+                master,
+                node,
+            } => {
+                let client = Self::master(sentinel, master, node).await?;
+                Ok(PooledConnection::Single(Box::new(
+                    client.get_connection_manager().await?,
+                )))
+            }
+        }
+    }
+
+    async fn pubsub(&self) -> RedisResult<PubSub> {
+        match self {
+            RedisTopology::Standalone(client) | RedisTopology::Cluster { pubsub: client, .. } => {
+                client.get_async_pubsub().await
+            }
+            RedisTopology::Sentinel {
+                sentinel,
+                master,
+                node,
+            } => {
+                Self::master(sentinel, master, node)
+                    .await?
+                    .get_async_pubsub()
+                    .await
+            }
+        }
+    }
+}
+
+/// Every round trip gets a span, so time spent in Redis shows up in traces.
+fn command_span(cmd: &Cmd) -> tracing::Span {
+    let name = match cmd.args_iter().next() {
+        Some(Arg::Simple(name)) => String::from_utf8_lossy(name).to_uppercase(),
+        _ => "UNKNOWN".to_string(),
+    };
+    tracing::info_span!(
+        "redis",
+        otel.name = %name,
+        otel.kind = "client",
+        db.system.name = "redis",
+        db.operation.name = %name,
+    )
+}
+
+impl ConnectionLike for PooledConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            PooledConnection::Single(conn) => conn.req_packed_command(cmd),
+            PooledConnection::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            PooledConnection::Single(conn) => conn.req_packed_commands(cmd, offset, count),
+            PooledConnection::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            PooledConnection::Single(conn) => conn.get_db(),
+            PooledConnection::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let span = command_span(cmd);
+        let request = self.0.req_packed_command(cmd);
+        Box::pin(telemetry::time_redis(request.instrument(span)))
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let span = tracing::info_span!(
+            "redis",
+            otel.name = "PIPELINE",
+            otel.kind = "client",
+            db.system.name = "redis",
+            db.operation.name = "PIPELINE",
+            db.operation.batch.size = count,
+        );
+        let request = self.0.req_packed_commands(cmd, offset, count);
+        Box::pin(telemetry::time_redis(request.instrument(span)))
+    }
+
+    fn get_db(&self) -> i64 {
+        self.0.get_db()
+    }
+}
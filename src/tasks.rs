@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Counts the background tasks (compaction, ...) still running for each session so that
+/// callers can wait for a session to settle.
+#[derive(Default)]
+pub struct TaskTracker {
+    pending: Mutex<HashMap<String, usize>>,
+    settled: Notify,
+}
+
+pub struct TaskGuard {
+    tracker: Arc<TaskTracker>,
+    session_id: String,
+}
+
+impl TaskTracker {
+    pub fn track(tracker: &Arc<TaskTracker>, session_id: &str) -> TaskGuard {
+        let mut pending = tracker.pending.lock().unwrap();
+        *pending.entry(session_id.to_string()).or_insert(0) += 1;
+
+        TaskGuard {
+            tracker: Arc::clone(tracker),
+            session_id: session_id.to_string(),
+        }
+    }
+
+    pub fn pending(&self, session_id: &str) -> usize {
+        let pending = self.pending.lock().unwrap();
+        pending.get(session_id).copied().unwrap_or(0)
+    }
+
+    /// Counts the tasks pending across all sessions.
+    pub fn total_pending(&self) -> usize {
+        self.pending.lock().unwrap().values().sum()
+    }
+
+    /// Waits until no tasks are pending for `session_id`. Returns false if `timeout` elapses
+    /// first.
+    pub async fn wait_settled(&self, session_id: &str, timeout: Duration) -> bool {
+        self.wait_until(|| self.pending(session_id) == 0, timeout)
+            .await
+    }
+
+    /// Waits until no tasks are pending for any session. Returns false if `timeout` elapses
+    /// first.
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        self.wait_until(|| self.total_pending() == 0, timeout).await
+    }
+
+    async fn wait_until(&self, done: impl Fn() -> bool, timeout: Duration) -> bool {
+        let wait = async {
+            loop {
+                let notified = self.settled.notified();
+                if done() {
+                    return;
+                }
+                notified.await;
+            }
+        };
+
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        let mut pending = self.tracker.pending.lock().unwrap();
+        if let Some(count) = pending.get_mut(&self.session_id) {
+            *count -= 1;
+            if *count == 0 {
+                pending.remove(&self.session_id);
+            }
+        }
+        drop(pending);
+
+        self.tracker.settled.notify_waiters();
+    }
+}
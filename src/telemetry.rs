@@ -0,0 +1,348 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::middleware::Next;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::{global, trace::TracerProvider};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use serde_json::{Map, Value};
+use std::cell::Cell;
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Record};
+use tracing::{field, Event, Id, Instrument, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::models::MotorheadError;
+
+/// Set by `MOTORHEAD_LOG_REDACT_CONTENT`, see `error_message`.
+static REDACT_CONTENT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Sets up logging (filtered with `RUST_LOG`, `info` by default, as text or JSON lines
+/// depending on `MOTORHEAD_LOG_FORMAT`) and, when an OTLP endpoint is configured, the export
+/// of spans to it. The returned provider flushes the spans still buffered when shut down.
+pub fn init() -> Option<SdkTracerProvider> {
+    let format = match env::var("MOTORHEAD_LOG_FORMAT").as_deref() {
+        Ok("text") | Err(_) => LogFormat::Text,
+        Ok("json") => LogFormat::Json,
+        Ok(other) => panic!("Unknown $MOTORHEAD_LOG_FORMAT: {}", other),
+    };
+    let redact_content = env::var("MOTORHEAD_LOG_REDACT_CONTENT")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+    REDACT_CONTENT.store(redact_content, Ordering::Relaxed);
+
+    let mut filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    if redact_content {
+        // Its debug logs have the parameters of every query, message contents included.
+        filter = filter.add_directive("tokio_postgres=info".parse().unwrap());
+    }
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let provider = otlp_configured().then(|| {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .build()
+            .unwrap_or_else(|e| panic!("Could not set up the OTLP exporter: {}", e));
+        let service_name =
+            env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "motorhead".to_string());
+
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name).build())
+            .build()
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with((format == LogFormat::Text).then(|| {
+            tracing_subscriber::fmt::layer()
+                .with_writer(io::stderr)
+                .with_ansi(io::stderr().is_terminal())
+        }))
+        .with((format == LogFormat::Json).then_some(JsonLayer))
+        .with(provider.as_ref().map(|provider| {
+            tracing_opentelemetry::layer().with_tracer(provider.tracer("motorhead"))
+        }))
+        .init();
+
+    provider
+}
+
+/// Flushes the traces still buffered, before exiting.
+pub fn shutdown(provider: Option<SdkTracerProvider>) {
+    if let Some(provider) = provider {
+        if let Err(e) = provider.shutdown() {
+            tracing::error!(error = %e, "Problem flushing traces");
+        }
+    }
+}
+
+fn otlp_configured() -> bool {
+    [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|name| env::var(name).is_ok_and(|value| !value.is_empty()))
+}
+
+/// The message of `error` for logs. The messages of upstream errors can quote what was sent,
+/// message contents included, so with `MOTORHEAD_LOG_REDACT_CONTENT` it's only the error's
+/// code instead.
+pub fn error_message(error: &MotorheadError) -> String {
+    if REDACT_CONTENT.load(Ordering::Relaxed) {
+        return error.code().as_str().to_string();
+    }
+    error.to_string()
+}
+
+/// `message` for logs, or a placeholder with `MOTORHEAD_LOG_REDACT_CONTENT`, for messages
+/// that may have come from an error of an upstream service.
+pub fn redact(message: &str) -> &str {
+    if REDACT_CONTENT.load(Ordering::Relaxed) {
+        return "[redacted]";
+    }
+    message
+}
+
+/// The fields of a span, as the JSON log lines of events in it include them.
+struct JsonFields(Map<String, Value>);
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// Writes every event to stderr as a line of JSON with its `timestamp`, `level`, `target`,
+/// `message` and fields, after those of the spans it's in, innermost last. Span fields for
+/// OpenTelemetry (`otel.*`) and those of records from the `log` crate (`log.*`) are left out.
+struct JsonLayer;
+
+impl<S> Layer<S> for JsonLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        span.extensions_mut().insert(JsonFields(fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(JsonFields(fields)) = extensions.get_mut::<JsonFields>() {
+            values.record(&mut JsonVisitor(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut timestamp = String::new();
+        let _ = SystemTime.format_time(&mut Writer::new(&mut timestamp));
+
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), timestamp.into());
+        line.insert("level".to_string(), metadata.level().as_str().into());
+        line.insert("target".to_string(), metadata.target().into());
+
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(JsonFields(fields)) = span.extensions().get::<JsonFields>() {
+                    line.extend(fields.clone());
+                }
+            }
+        }
+        event.record(&mut JsonVisitor(&mut line));
+        line.retain(|name, _| !name.starts_with("otel.") && !name.starts_with("log."));
+
+        let mut stderr = io::stderr().lock();
+        let _ = writeln!(stderr, "{}", Value::Object(line));
+    }
+}
+
+tokio::task_local! {
+    /// Time the current request spent waiting on Redis, for its access log.
+    static REDIS_TIME: Cell<Duration>;
+}
+
+/// Adds the time `future` takes to the current request's Redis time. Work outside of a
+/// request, like compactions, isn't counted.
+pub async fn time_redis<T>(future: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let output = future.await;
+    let _ = REDIS_TIME.try_with(|time| time.set(time.get() + start.elapsed()));
+    output
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Runs every request in a span, continuing the trace of an incoming `traceparent` header,
+/// and logs it once answered. Work spawned from the request, like compactions, is traced as
+/// part of it. The log has the matched route rather than the path, so session ids and search
+/// terms from query strings stay out of it, and the `session_id` as a field.
+pub async fn trace_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    // Named after the matched route once it's known, so session ids stay out of span names.
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %method,
+        otel.kind = "server",
+        http.request.method = %method,
+        http.route = field::Empty,
+        http.response.status_code = field::Empty,
+    );
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    let _ = span.set_parent(parent);
+
+    let start = Instant::now();
+    let (result, redis_time) = REDIS_TIME
+        .scope(Cell::new(Duration::ZERO), async {
+            let result = next.call(req).instrument(span.clone()).await;
+            (result, REDIS_TIME.with(Cell::get))
+        })
+        .await;
+
+    let mut route = None;
+    let mut session_id = None;
+    let status = match &result {
+        Ok(response) => {
+            route = response.request().match_pattern();
+            if let Some(route) = &route {
+                span.record("otel.name", format!("{} {}", method, route));
+                span.record("http.route", route);
+            }
+            session_id = response
+                .request()
+                .match_info()
+                .get("session_id")
+                .map(str::to_string);
+            response.status()
+        }
+        Err(e) => e.as_response_error().status_code(),
+    };
+    span.record("http.response.status_code", status.as_u16());
+
+    tracing::info!(
+        method = %method,
+        route = route.as_deref().unwrap_or("unmatched"),
+        session_id,
+        status = status.as_u16(),
+        latency_ms = start.elapsed().as_secs_f64() * 1000.0,
+        redis_ms = redis_time.as_secs_f64() * 1000.0,
+        "Request answered"
+    );
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_logs_leave_content_out() {
+        let content = "my card is 4111 1111 1111 1111";
+        let errors = [
+            MotorheadError::IncrementalSummarizationError(content.to_string()),
+            MotorheadError::SerializationError(content.to_string()),
+            MotorheadError::PostgresError(content.to_string()),
+            MotorheadError::PostgresUnavailable(content.to_string()),
+            MotorheadError::EmbeddingError(content.to_string()),
+            MotorheadError::LlmError(content.to_string()),
+            MotorheadError::LlmUnavailable(content.to_string()),
+            MotorheadError::ModerationError(content.to_string()),
+            MotorheadError::HookError(content.to_string()),
+            MotorheadError::AuditUnavailable(content.to_string()),
+            MotorheadError::ColdStorageUnavailable(content.to_string()),
+            MotorheadError::EncryptionError(content.to_string()),
+            MotorheadError::RedisError(redis::RedisError::from((
+                redis::ErrorKind::ResponseError,
+                "Redis refused",
+                content.to_string(),
+            ))),
+        ];
+
+        REDACT_CONTENT.store(true, Ordering::Relaxed);
+        for error in &errors {
+            assert!(!error_message(error).contains(content));
+        }
+        assert!(!redact(content).contains(content));
+        REDACT_CONTENT.store(false, Ordering::Relaxed);
+
+        // Which they would log otherwise.
+        for error in &errors {
+            assert!(error_message(error).contains(content));
+        }
+        assert_eq!(redact(content), content);
+    }
+}
@@ -0,0 +1,151 @@
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use std::collections::BTreeSet;
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::AppState;
+use crate::store::MemoryStore;
+
+pub const TENANT_HEADER: &str = "X-Tenant-Id";
+
+/// Tenant bound to the API key a request authenticated with. Set by the auth middleware.
+#[derive(Clone)]
+pub struct KeyTenant(pub String);
+
+/// The tenant a request acts on: the one bound to its API key, or else the `X-Tenant-Id`
+/// header. Requests with neither use the default namespace.
+#[derive(Clone)]
+pub struct Tenant(Option<String>);
+
+pub fn is_valid_tenant(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant.len() <= 64
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The default namespace and the tenants of the API keys. Sessions of tenants only picked
+/// with `X-Tenant-Id` can't be listed together, so background jobs like the idle reaper leave
+/// them out.
+pub fn listed_tenants(state: &AppState) -> Vec<Tenant> {
+    let tenants: BTreeSet<&str> = state
+        .api_keys
+        .iter()
+        .filter_map(|key| key.tenant.as_deref())
+        .collect();
+    std::iter::once(Tenant::new(None))
+        .chain(
+            tenants
+                .into_iter()
+                .map(|tenant| Tenant::new(Some(tenant.to_string()))),
+        )
+        .collect()
+}
+
+impl Tenant {
+    pub fn new(id: Option<String>) -> Self {
+        Tenant(id)
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    pub fn store(&self, state: &AppState) -> Arc<dyn MemoryStore> {
+        match &self.0 {
+            Some(tenant) => state.store.for_tenant(tenant),
+            None => Arc::clone(&state.store),
+        }
+    }
+
+    /// The store for reads that can be a little stale: the read replica while it's within
+    /// `MOTORHEAD_REDIS_REPLICA_MAX_STALENESS_MS` of the primary, the store otherwise.
+    pub fn read_store(&self, state: &AppState) -> Arc<dyn MemoryStore> {
+        let store = match &state.replica {
+            Some(replica) if replica.is_fresh() => &replica.store,
+            _ => &state.store,
+        };
+        match &self.0 {
+            Some(tenant) => store.for_tenant(tenant),
+            None => Arc::clone(store),
+        }
+    }
+
+    /// Session key for in-process bookkeeping (pending tasks, running compactions).
+    pub fn scope(&self, session_id: &str) -> String {
+        match &self.0 {
+            Some(tenant) => format!("{}:{}", tenant, session_id),
+            None => session_id.to_string(),
+        }
+    }
+
+    /// The tenant of a request that authenticated with a key bound to `key_tenant`, if any,
+    /// and sent `header` as its `X-Tenant-Id`.
+    pub fn resolve(key_tenant: Option<String>, header: Option<&[u8]>) -> Result<Self, TenantError> {
+        let header = header
+            .map(|value| {
+                std::str::from_utf8(value)
+                    .ok()
+                    .filter(|tenant| is_valid_tenant(tenant))
+                    .map(str::to_string)
+                    .ok_or(TenantError::InvalidHeader)
+            })
+            .transpose()?;
+
+        match (key_tenant, header) {
+            (Some(key_tenant), Some(header)) if key_tenant != header => {
+                Err(TenantError::KeyMismatch)
+            }
+            (Some(key_tenant), _) => Ok(Tenant(Some(key_tenant))),
+            (None, header) => Ok(Tenant(header)),
+        }
+    }
+
+    fn from_request(req: &HttpRequest) -> Result<Self, actix_web::Error> {
+        let key_tenant = req
+            .extensions()
+            .get::<KeyTenant>()
+            .map(|KeyTenant(tenant)| tenant.clone());
+        let header = req
+            .headers()
+            .get(TENANT_HEADER)
+            .map(|value| value.as_bytes());
+
+        Tenant::resolve(key_tenant, header).map_err(|e| {
+            let code = match e {
+                TenantError::InvalidHeader => ErrorCode::InvalidRequest,
+                TenantError::KeyMismatch => ErrorCode::Forbidden,
+            };
+            ApiError::new(code, e).into()
+        })
+    }
+}
+
+pub enum TenantError {
+    InvalidHeader,
+    /// The header names another tenant than the API key's.
+    KeyMismatch,
+}
+
+impl std::fmt::Display for TenantError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TenantError::InvalidHeader => f.write_str("Invalid X-Tenant-Id header"),
+            TenantError::KeyMismatch => {
+                f.write_str("X-Tenant-Id does not match the API key's tenant")
+            }
+        }
+    }
+}
+
+impl FromRequest for Tenant {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Tenant::from_request(req))
+    }
+}
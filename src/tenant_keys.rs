@@ -0,0 +1,106 @@
+use actix_web::{delete, put, web, HttpResponse, Responder};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::encryption::{Cipher, TenantKeys};
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{AckResponse, AppState, MotorheadError, TenantKeyRequest};
+use crate::telemetry;
+use crate::tenant::Tenant;
+
+/// How often the keys are read again, for the ones added or deleted by other instances.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+fn enabled(data: &AppState) -> actix_web::Result<&TenantKeys> {
+    data.tenant_keys.as_ref().ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::FeatureDisabled,
+            "Tenant encryption keys are not enabled",
+        )
+        .into()
+    })
+}
+
+fn tenant_id(tenant: &Tenant) -> actix_web::Result<&str> {
+    tenant.id().ok_or_else(|| {
+        ApiError::invalid_request("Encryption keys belong to a tenant, none was given").into()
+    })
+}
+
+/// Loads the stored keys into `keys`.
+pub async fn load(state: &AppState, keys: &TenantKeys) -> Result<(), MotorheadError> {
+    keys.replace(state.store.get_tenant_keys().await?);
+    Ok(())
+}
+
+/// Registers the tenant's own key, which its messages and summaries are encrypted with from
+/// then on. Keys can't be replaced, as what the old one encrypted would be lost.
+#[put("/tenant/encryption-key")]
+pub async fn put_tenant_key(
+    web::Json(request): web::Json<TenantKeyRequest>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let keys = enabled(&data)?;
+    let tenant_id = tenant_id(&tenant)?;
+    let cipher = Cipher::from_base64(&request.key).map_err(ApiError::invalid_request)?;
+
+    if !data
+        .store
+        .add_tenant_key(tenant_id, request.key.trim())
+        .await?
+    {
+        return Err(ApiError::new(
+            ErrorCode::KeyExists,
+            "The tenant already has an encryption key",
+        )
+        .into());
+    }
+    keys.insert(tenant_id, cipher.for_tenant());
+    tracing::info!(tenant = tenant_id, "Tenant encryption key added");
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+/// Deletes the tenant's key, making what it encrypted unreadable: reading it fails with
+/// `KEY_DELETED` from then on. Backups of the store still hold the key.
+#[delete("/tenant/encryption-key")]
+pub async fn delete_tenant_key(
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let keys = enabled(&data)?;
+    let tenant_id = tenant_id(&tenant)?;
+
+    if !data.store.delete_tenant_key(tenant_id).await? {
+        return Err(ApiError::not_found("The tenant has no encryption key").into());
+    }
+    keys.remove(tenant_id);
+    tracing::info!(tenant = tenant_id, "Tenant encryption key deleted");
+
+    let response = AckResponse { status: "Ok" };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+/// Reads the keys again every few seconds, for as long as the server runs.
+pub async fn run_tenant_key_refresh(state: Arc<AppState>) {
+    let Some(keys) = &state.tenant_keys else {
+        return;
+    };
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = load(&state, keys).await {
+            tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem refreshing the tenant encryption keys"
+            );
+        }
+    }
+}
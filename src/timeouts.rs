@@ -0,0 +1,110 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::web;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::AppState;
+
+/// An entry of `MOTORHEAD_ROUTE_TIMEOUTS`: a method, a route or both, and how long their
+/// requests can take.
+pub struct RouteTimeout {
+    method: Option<Method>,
+    /// As registered, e.g. `/sessions/{session_id}/summarize`.
+    route: Option<String>,
+    timeout: Duration,
+}
+
+impl RouteTimeout {
+    /// `METHOD /route=ms`, `METHOD=ms` or `/route=ms`.
+    pub fn parse(entry: &str) -> Result<Self, String> {
+        let invalid = || format!("expected [METHOD] [/route]=ms, got {}", entry);
+        let (target, timeout_ms) = entry.trim().rsplit_once('=').ok_or_else(invalid)?;
+        let timeout_ms = timeout_ms
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|ms| *ms > 0)
+            .ok_or_else(invalid)?;
+
+        let mut method = None;
+        let mut route = None;
+        for part in target.split_whitespace() {
+            if part.starts_with('/') && route.is_none() {
+                route = Some(part.to_string());
+            } else if method.is_none() && route.is_none() {
+                method = Some(
+                    Method::from_bytes(part.to_ascii_uppercase().as_bytes())
+                        .map_err(|_| invalid())?,
+                );
+            } else {
+                return Err(invalid());
+            }
+        }
+        if method.is_none() && route.is_none() {
+            return Err(invalid());
+        }
+
+        Ok(RouteTimeout {
+            method,
+            route,
+            timeout: Duration::from_millis(timeout_ms),
+        })
+    }
+}
+
+/// How long requests can take before they're answered with a `504`, from
+/// `MOTORHEAD_REQUEST_TIMEOUT_MS` and `MOTORHEAD_ROUTE_TIMEOUTS`.
+pub struct RequestTimeouts {
+    pub default: Option<Duration>,
+    pub routes: Vec<RouteTimeout>,
+}
+
+impl RequestTimeouts {
+    /// The timeout of the most specific entry matching the request, a method and route over
+    /// a route over a method, or the default.
+    fn timeout(&self, method: &Method, route: Option<&str>) -> Option<Duration> {
+        self.routes
+            .iter()
+            .filter(|entry| entry.method.as_ref().is_none_or(|m| m == method))
+            .filter(|entry| {
+                entry
+                    .route
+                    .as_deref()
+                    .is_none_or(|entry_route| Some(entry_route) == route)
+            })
+            .max_by_key(|entry| (entry.route.is_some(), entry.method.is_some()))
+            .map(|entry| entry.timeout)
+            .or(self.default)
+    }
+}
+
+/// Answers with a `504` once the request's timeout runs out. The handler is left to finish
+/// in the background rather than dropped midway, so a compaction or an append isn't cut
+/// short; only its response is lost. Streamed bodies aren't timed, only getting to them.
+pub async fn limit_duration(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let timeout = req
+        .app_data::<web::Data<Arc<AppState>>>()
+        .and_then(|state| state.request_timeouts.as_ref())
+        .and_then(|timeouts| timeouts.timeout(req.method(), req.match_pattern().as_deref()));
+    let Some(timeout) = timeout else {
+        return next.call(req).await;
+    };
+
+    let handler = actix_web::rt::spawn(next.call(req));
+    match tokio::time::timeout(timeout, handler).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(_)) => Err(ApiError::internal("The request handler panicked").into()),
+        Err(_) => Err(ApiError::new(
+            ErrorCode::Timeout,
+            format!("The request took longer than {}ms", timeout.as_millis()),
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+use tracing::Instrument;
+
+use crate::llm::CompletionRequest;
+use crate::models::{AppState, MotorheadError};
+use crate::store::MemoryStore;
+use crate::tasks::TaskTracker;
+use crate::telemetry;
+use crate::tenant::Tenant;
+use crate::usage::{check_budget, record_usage};
+
+pub const TITLE_PROMPT: &str = r#"
+        Write a short title, of at most six words, for the conversation below, like the ones chat apps list conversations under. Reply with the title only, without quotes or punctuation at the end. If the lines are meaningless just return NONE
+
+        Conversation:
+        {messages}
+        Title:
+        "#;
+
+/// Longest title kept, in characters.
+const MAX_TITLE_CHARS: usize = 100;
+
+/// Whether the append that took the session's window from `before` to `len` messages reached
+/// `MOTORHEAD_SESSION_TITLE_AFTER_MESSAGES`.
+pub fn wants_title(state: &AppState, before: i64, len: i64) -> bool {
+    state
+        .session_title_after_messages
+        .is_some_and(|after| before < after as i64 && len >= after as i64)
+}
+
+/// The first line of the completion, without the quotes and final period models add anyway.
+fn clean_title(completion: &str) -> Option<String> {
+    let title = completion
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?
+        .trim_start_matches("Title:")
+        .trim()
+        .trim_matches(|c| matches!(c, '"' | '\'' | '*' | '.'))
+        .trim();
+    if title.is_empty() || title == "NONE" {
+        return None;
+    }
+    Some(title.chars().take(MAX_TITLE_CHARS).collect())
+}
+
+/// Asks the LLM for a title of the session's first messages and stores it as the session's
+/// `metadata.title`, unless it already has one.
+#[tracing::instrument(skip_all, fields(session_id = %session_id))]
+async fn generate_title(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    count: usize,
+) -> Result<(), MotorheadError> {
+    let has_title = |metadata: &Option<serde_json::Value>| {
+        metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.get("title").is_some())
+    };
+    if has_title(&store.get_metadata(session_id).await?) {
+        return Ok(());
+    }
+    check_budget(state, store).await?;
+
+    let messages = store.get_messages(session_id, 0, -1).await?;
+    let lines: Vec<String> = messages
+        .iter()
+        .rev()
+        .take(count)
+        .map(|message| message.transcript_line(state.summarize_attachments))
+        .collect();
+    let prompt = TITLE_PROMPT.replace("{messages}", &lines.join("\n"));
+
+    let options = state.runtime().summary_options;
+    let completion = state
+        .llm
+        .complete(CompletionRequest {
+            system: "You are a helpful AI assistant.",
+            prompt: &prompt,
+            max_tokens: 32,
+            model: options.model.as_deref(),
+            temperature: options.temperature,
+        })
+        .await?;
+    record_usage(state, store, session_id, completion.usage).await;
+    let Some(title) = clean_title(&completion.content) else {
+        return Ok(());
+    };
+
+    // Read again, as it may have been replaced during the call.
+    let metadata = store.get_metadata(session_id).await?;
+    if has_title(&metadata) {
+        return Ok(());
+    }
+    let mut metadata = match metadata {
+        Some(serde_json::Value::Object(metadata)) => metadata,
+        _ => serde_json::Map::new(),
+    };
+    metadata.insert("title".to_string(), title.into());
+    store
+        .set_metadata(session_id, &serde_json::Value::Object(metadata))
+        .await
+}
+
+/// Titles the session in the background.
+pub fn spawn_title(state: &Arc<AppState>, tenant: &Tenant, session_id: &str) {
+    let Some(count) = state.session_title_after_messages else {
+        return;
+    };
+    let state = Arc::clone(state);
+    let store = tenant.store(&state);
+    let session_id = session_id.to_string();
+    let task_guard = TaskTracker::track(&state.tasks, &tenant.scope(&session_id));
+
+    tokio::spawn(
+        async move {
+            let _task_guard = task_guard;
+            if let Err(e) = generate_title(&state, store.as_ref(), &session_id, count).await {
+                tracing::error!(
+                    error = telemetry::error_message(&e),
+                    "Problem generating the session title"
+                );
+            }
+        }
+        .in_current_span(),
+    );
+}
@@ -0,0 +1,31 @@
+use rustls::crypto::ring;
+use rustls::ServerConfig;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+
+/// The TLS settings of the HTTP server, from the PEM certificate chain at `cert_path` and the
+/// private key at `key_path` (`MOTORHEAD_TLS_CERT` and `MOTORHEAD_TLS_KEY`).
+pub fn server_config(cert_path: &str, key_path: &str) -> io::Result<ServerConfig> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No certificate in {}", cert_path),
+        ));
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?.ok_or_else(
+        || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No private key in {}", key_path),
+            )
+        },
+    )?;
+
+    ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
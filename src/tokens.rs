@@ -0,0 +1,35 @@
+use tiktoken_rs::cl100k_base_singleton;
+
+use crate::models::MemoryMessage;
+
+/// Counts tokens the way the OpenAI chat models do (cl100k_base).
+pub fn count_tokens(text: &str) -> usize {
+    cl100k_base_singleton()
+        .encode_with_special_tokens(text)
+        .len()
+}
+
+pub fn count_message_tokens(message: &MemoryMessage) -> usize {
+    let tool_calls: usize = message
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|call| count_tokens(&call.function.name) + count_tokens(&call.function.arguments))
+        .sum();
+
+    count_tokens(message.role.as_str()) + count_tokens(&message.content) + tool_calls
+}
+
+/// Number of messages, newest first, that fit within `budget` tokens. Always at least one
+/// when `messages` isn't empty, so a single oversized message is still returned.
+pub fn fit_within_tokens(messages: &[MemoryMessage], budget: usize) -> usize {
+    let mut used = 0;
+    for (i, message) in messages.iter().enumerate() {
+        used += count_message_tokens(message);
+        if used > budget {
+            return i.max(1);
+        }
+    }
+
+    messages.len()
+}
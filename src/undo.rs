@@ -0,0 +1,269 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::errors::{ApiError, ErrorCode};
+use crate::models::{AppState, ContextSegment, MemoryMessage, MotorheadError, UndoResponse};
+use crate::reducer::claim_compaction;
+use crate::store::MemoryStore;
+use crate::telemetry;
+use crate::tenant::Tenant;
+
+/// The part of a session an undo puts back: its window and summaries.
+pub struct Snapshot {
+    /// Newest first.
+    messages: Vec<MemoryMessage>,
+    context: Option<String>,
+    long_term_context: Option<String>,
+    context_segments: Vec<ContextSegment>,
+}
+
+#[derive(Default)]
+struct History {
+    /// Oldest first.
+    undo: VecDeque<Snapshot>,
+    redo: Vec<Snapshot>,
+    updated_at: Option<Instant>,
+}
+
+/// Up to `MOTORHEAD_UNDO_DEPTH` snapshots of each session, taken before its mutations, for
+/// `POST /sessions/:id/undo`. Kept by this instance for `MOTORHEAD_UNDO_TTL_SECONDS` after the
+/// session's last mutation.
+pub struct UndoLog {
+    depth: usize,
+    ttl: Duration,
+    sessions: Mutex<HashMap<String, History>>,
+}
+
+impl UndoLog {
+    pub fn new(depth: usize, ttl: Duration) -> Self {
+        UndoLog {
+            depth,
+            ttl,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn push(&self, key: String, snapshot: Snapshot) {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, history| {
+            history
+                .updated_at
+                .is_some_and(|at| now.duration_since(at) < self.ttl)
+        });
+
+        let history = sessions.entry(key).or_default();
+        history.undo.push_back(snapshot);
+        if history.undo.len() > self.depth {
+            history.undo.pop_front();
+        }
+        // A new mutation branches off what was undone.
+        history.redo.clear();
+        history.updated_at = Some(now);
+    }
+
+    /// Forgets the session, e.g. once it's deleted.
+    pub fn forget(&self, key: &str) {
+        self.sessions.lock().unwrap().remove(key);
+    }
+}
+
+async fn read_snapshot(
+    store: &dyn MemoryStore,
+    session_id: &str,
+) -> Result<Snapshot, MotorheadError> {
+    let messages = store.get_messages(session_id, 0, -1).await?;
+    let context = store.get_context(session_id).await?;
+    let long_term_context = store.get_long_term_context(session_id).await?;
+    let context_segments = store.get_context_segments(session_id).await?;
+    Ok(Snapshot {
+        messages,
+        context,
+        long_term_context,
+        context_segments,
+    })
+}
+
+/// The session as it is before a mutation, to `record` once the mutation went through.
+/// `None` without an undo log, or if the session couldn't be read, in which case that mutation
+/// can't be undone.
+pub async fn snapshot(state: &AppState, tenant: &Tenant, session_id: &str) -> Option<Snapshot> {
+    state.undo.as_ref()?;
+    match read_snapshot(tenant.store(state).as_ref(), session_id).await {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            tracing::warn!(
+                session_id,
+                error = telemetry::error_message(&e),
+                "Problem reading the session for its undo log"
+            );
+            None
+        }
+    }
+}
+
+pub fn record(state: &AppState, tenant: &Tenant, session_id: &str, snapshot: Option<Snapshot>) {
+    if let (Some(undo), Some(snapshot)) = (&state.undo, snapshot) {
+        undo.push(tenant.scope(session_id), snapshot);
+    }
+}
+
+/// Puts the window and summaries back as they were. Messages compactions archived since stay
+/// in the history too.
+async fn restore(
+    store: &dyn MemoryStore,
+    session_id: &str,
+    snapshot: &Snapshot,
+) -> Result<(), MotorheadError> {
+    // An empty range, as in `LTRIM`, keeps nothing.
+    store.trim_messages(session_id, 1, 0, false).await?;
+    if !snapshot.messages.is_empty() {
+        let mut messages = snapshot.messages.clone();
+        messages.reverse();
+        store.append_messages(session_id, messages).await?;
+    }
+    store
+        .replace_contexts(
+            session_id,
+            snapshot.context.as_deref(),
+            snapshot.long_term_context.as_deref(),
+        )
+        .await?;
+    store
+        .set_context_segments(session_id, &snapshot.context_segments)
+        .await
+}
+
+/// Whether `before` holds part of a turn that `after` holds more of, as when the turn's
+/// messages were appended over several requests.
+fn splits_turn(before: &[MemoryMessage], after: &[MemoryMessage]) -> bool {
+    let count = |messages: &[MemoryMessage], turn_id: &str| {
+        messages
+            .iter()
+            .filter(|message| message.turn_id.as_deref() == Some(turn_id))
+            .count()
+    };
+    before
+        .iter()
+        .filter_map(|message| message.turn_id.as_deref())
+        .any(|turn_id| count(before, turn_id) < count(after, turn_id))
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Undo,
+    Redo,
+}
+
+/// Swaps the session with its last snapshot in one direction, pushing how it was onto the
+/// other. Compactions are held off meanwhile, and one running makes it fail with `409`: the
+/// compaction an append started is undone with it. Undos go back past the snapshots that would
+/// leave part of a turn, which a single redo then reapplies.
+async fn step(
+    state: &Arc<AppState>,
+    tenant: &Tenant,
+    session_id: &str,
+    direction: Direction,
+) -> actix_web::Result<UndoResponse> {
+    let Some(undo) = &state.undo else {
+        return Err(ApiError::new(ErrorCode::FeatureDisabled, "Undo is not enabled").into());
+    };
+    let claim = claim_compaction(state, tenant, session_id)
+        .await
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::CompactionInProgress,
+                "A compaction is already running for this session",
+            )
+        })?;
+
+    let key = tenant.scope(session_id);
+    let result = async {
+        let store = tenant.store(state);
+        let current = read_snapshot(store.as_ref(), session_id).await?;
+        // Newest first.
+        let mut skipped = Vec::new();
+        let target = {
+            let mut sessions = undo.sessions.lock().unwrap();
+            let history = sessions.get_mut(&key);
+            match direction {
+                Direction::Undo => history.and_then(|history| {
+                    let mut target = history.undo.pop_back()?;
+                    while splits_turn(&target.messages, &current.messages) {
+                        let Some(earlier) = history.undo.pop_back() else {
+                            break;
+                        };
+                        skipped.push(std::mem::replace(&mut target, earlier));
+                    }
+                    Some(target)
+                }),
+                Direction::Redo => history.and_then(|history| history.redo.pop()),
+            }
+        };
+        let Some(target) = target else {
+            return Err(ApiError::not_found(match direction {
+                Direction::Undo => "Nothing to undo",
+                Direction::Redo => "Nothing to redo",
+            })
+            .into());
+        };
+
+        let restored = restore(store.as_ref(), session_id, &target).await;
+
+        let mut sessions = undo.sessions.lock().unwrap();
+        let history = sessions.entry(key.clone()).or_default();
+        history.updated_at = Some(Instant::now());
+        match (direction, restored) {
+            (Direction::Undo, Ok(())) => history.redo.push(current),
+            (Direction::Redo, Ok(())) => history.undo.push_back(current),
+            (Direction::Undo, Err(e)) => {
+                history.undo.push_back(target);
+                history.undo.extend(skipped.into_iter().rev());
+                return Err(e.into());
+            }
+            (Direction::Redo, Err(e)) => {
+                history.redo.push(target);
+                return Err(e.into());
+            }
+        }
+        Ok(UndoResponse {
+            status: "Ok",
+            undo: history.undo.len(),
+            redo: history.redo.len(),
+        })
+    }
+    .await;
+
+    claim.release().await;
+    result
+}
+
+/// Puts the session back as it was before its last mutation (an append, with the compaction
+/// it started, an edit or a deletion), or before the turn it finished, up to
+/// `MOTORHEAD_UNDO_DEPTH` times.
+#[post("/sessions/{session_id}/undo")]
+pub async fn undo_session(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let response = step(&data, &tenant, &session_id, Direction::Undo).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
+
+/// Reapplies what the last undo rolled back, until the session is mutated again.
+#[post("/sessions/{session_id}/redo")]
+pub async fn redo_session(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let response = step(&data, &tenant, &session_id, Direction::Redo).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(response))
+}
@@ -0,0 +1,146 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::analytics;
+use crate::config::check_admin;
+use crate::errors::ApiError;
+use crate::models::{
+    AdminUsageResponse, AnalyticsCounts, AppState, MotorheadError, TenantUsage, TokenUsage,
+    UsageQuery, UsageResponse,
+};
+use crate::response::read_response;
+use crate::store::MemoryStore;
+use crate::telemetry;
+use crate::tenant::Tenant;
+
+/// The current month in UTC, as `YYYY-MM`.
+pub fn current_month() -> String {
+    let days = (SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / 86_400) as i64;
+
+    // Converts days since the epoch to a civil date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}", year, month)
+}
+
+fn is_valid_month(month: &str) -> bool {
+    let Some((year, month)) = month.split_once('-') else {
+        return false;
+    };
+    year.len() == 4
+        && month.len() == 2
+        && (year.bytes().chain(month.bytes())).all(|byte| byte.is_ascii_digit())
+        && month
+            .parse::<u8>()
+            .is_ok_and(|month| (1..=12).contains(&month))
+}
+
+/// Fails once the store's tenant has used up `MOTORHEAD_MONTHLY_TOKEN_BUDGET` this month.
+pub async fn check_budget(state: &AppState, store: &dyn MemoryStore) -> Result<(), MotorheadError> {
+    let Some(budget) = state.monthly_token_budget else {
+        return Ok(());
+    };
+
+    let used = store.get_tenant_usage(&current_month()).await?;
+    if used.total() >= budget {
+        return Err(MotorheadError::TokenBudgetExhausted(budget));
+    }
+    Ok(())
+}
+
+/// Adds the tokens a compaction used to the session's and its tenant's usage, and to its
+/// tenant's analytics. Failing to doesn't fail the compaction.
+pub async fn record_usage(
+    state: &AppState,
+    store: &dyn MemoryStore,
+    session_id: &str,
+    usage: TokenUsage,
+) {
+    if usage.total() == 0 {
+        return;
+    }
+    analytics::record(
+        state,
+        store,
+        AnalyticsCounts {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            ..Default::default()
+        },
+    )
+    .await;
+    if let Err(e) = store
+        .record_usage(session_id, &current_month(), usage)
+        .await
+    {
+        tracing::error!(
+            session_id,
+            error = telemetry::error_message(&e),
+            "Problem recording the token usage"
+        );
+    }
+}
+
+/// The tokens compactions of the session have used.
+#[get("/sessions/{session_id}/usage")]
+pub async fn get_session_usage(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    let usage = tenant.store(&data).get_session_usage(&session_id).await?;
+
+    Ok(read_response(
+        &data,
+        Some(&session_id),
+        UsageResponse::from(usage),
+    ))
+}
+
+/// The tokens compactions have used in a month, the current one by default, per tenant.
+#[get("/admin/usage")]
+pub async fn get_admin_usage(
+    req: HttpRequest,
+    web::Query(query): web::Query<UsageQuery>,
+    data: web::Data<Arc<AppState>>,
+) -> actix_web::Result<impl Responder> {
+    check_admin(&req)?;
+
+    let month = query.month.unwrap_or_else(current_month);
+    if !is_valid_month(&month) {
+        return Err(ApiError::invalid_request("month must be formatted as YYYY-MM").into());
+    }
+
+    let tenants = data
+        .store
+        .usage_by_tenant(&month)
+        .await?
+        .into_iter()
+        .map(|(tenant, usage)| TenantUsage {
+            tenant,
+            usage: UsageResponse::from(usage),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(AdminUsageResponse {
+            month,
+            monthly_token_budget: data.monthly_token_budget,
+            tenants,
+        }))
+}
@@ -0,0 +1,276 @@
+use futures_util::StreamExt;
+use hmac::{Hmac, KeyInit, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::models::{AppState, MotorheadError};
+use crate::reducer::retry_delay;
+use crate::telemetry;
+
+const EVENT_HEADER: &str = "X-Motorhead-Event";
+const DELIVERY_HEADER: &str = "X-Motorhead-Delivery";
+const TIMESTAMP_HEADER: &str = "X-Motorhead-Timestamp";
+const SIGNATURE_HEADER: &str = "X-Motorhead-Signature";
+
+/// How long a receiver has to answer a delivery.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause before listening for expired sessions again after the subscription dropped.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    SessionCreated,
+    CompactionCompleted,
+    SessionDeleted,
+    SessionExpired,
+    /// See `MOTORHEAD_ALERT_MESSAGES_PER_MINUTE` and the other alert thresholds.
+    SessionAnomaly,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 5] = [
+        WebhookEvent::SessionCreated,
+        WebhookEvent::CompactionCompleted,
+        WebhookEvent::SessionDeleted,
+        WebhookEvent::SessionExpired,
+        WebhookEvent::SessionAnomaly,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::SessionCreated => "session_created",
+            WebhookEvent::CompactionCompleted => "compaction_completed",
+            WebhookEvent::SessionDeleted => "session_deleted",
+            WebhookEvent::SessionExpired => "session_expired",
+            WebhookEvent::SessionAnomaly => "session_anomaly",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        WebhookEvent::ALL
+            .into_iter()
+            .find(|event| event.as_str() == name)
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    id: &'a str,
+    #[serde(rename = "type")]
+    event: WebhookEvent,
+    /// Milliseconds since the Unix epoch.
+    created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<&'a str>,
+    session_id: &'a str,
+    data: serde_json::Value,
+}
+
+/// Where session events are posted: the webhook URLs, and the callback URLs of the sessions.
+/// Deliveries are signed with the secret, if set, and retried with backoff until the
+/// receiver answers with a 2xx.
+pub struct Webhooks {
+    http: reqwest::Client,
+    urls: Vec<String>,
+    secret: Option<String>,
+    events: Vec<WebhookEvent>,
+    max_attempts: u32,
+    retry_base_delay_ms: u64,
+}
+
+impl Webhooks {
+    pub fn new(
+        urls: Vec<String>,
+        secret: Option<String>,
+        events: Vec<WebhookEvent>,
+        max_attempts: u32,
+        retry_base_delay_ms: u64,
+    ) -> Self {
+        Webhooks {
+            http: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .expect("Could not build the webhook client"),
+            urls,
+            secret,
+            events,
+            max_attempts: max_attempts.max(1),
+            retry_base_delay_ms,
+        }
+    }
+
+    /// Whether the webhook URLs get `event`. Always false without URLs.
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        !self.urls.is_empty() && self.events.contains(&event)
+    }
+
+    /// `HMAC-SHA256(secret, "{timestamp}.{body}")`, hex encoded. Including the timestamp
+    /// lets receivers reject replayed deliveries.
+    fn signature(&self, timestamp: u64, body: &str) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+        mac.update(format!("{}.{}", timestamp, body).as_bytes());
+
+        let signature: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Some(format!("sha256={}", signature))
+    }
+
+    async fn deliver(&self, url: &str, event: WebhookEvent, delivery_id: &str, body: &str) {
+        let mut attempt = 1;
+        loop {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let mut request = self
+                .http
+                .post(url)
+                .header("Content-Type", "application/json")
+                .header(EVENT_HEADER, event.as_str())
+                .header(DELIVERY_HEADER, delivery_id)
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .body(body.to_string());
+            if let Some(signature) = self.signature(timestamp, body) {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => format!("{} answered {}", url, response.status()),
+                Err(e) => e.to_string(),
+            };
+
+            if attempt >= self.max_attempts {
+                tracing::error!(
+                    event = event.as_str(),
+                    delivery_id,
+                    attempt,
+                    error,
+                    "Giving up on webhook delivery"
+                );
+                return;
+            }
+            let delay = retry_delay(self.retry_base_delay_ms, attempt);
+            tracing::warn!(
+                event = event.as_str(),
+                delivery_id,
+                attempt,
+                retry_in_ms = delay.as_millis() as u64,
+                error,
+                "Webhook delivery attempt failed"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// The JSON body of a delivery, with a new delivery id.
+fn payload(
+    tenant: Option<&str>,
+    session_id: &str,
+    event: WebhookEvent,
+    data: serde_json::Value,
+) -> Option<(String, Arc<String>)> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let payload = Payload {
+        id: &id,
+        event,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64,
+        tenant,
+        session_id,
+        data,
+    };
+    match serde_json::to_string(&payload) {
+        Ok(body) => Some((id, Arc::new(body))),
+        Err(e) => {
+            tracing::error!(session_id, error = %e, "Problem serializing webhook payload");
+            None
+        }
+    }
+}
+
+/// Posts `event` to every webhook URL in the background, if webhooks are configured for it.
+pub fn notify(
+    state: &AppState,
+    tenant: Option<&str>,
+    session_id: &str,
+    event: WebhookEvent,
+    data: serde_json::Value,
+) {
+    let webhooks = &state.webhooks;
+    if !webhooks.wants(event) {
+        return;
+    }
+    let Some((id, body)) = payload(tenant, session_id, event, data) else {
+        return;
+    };
+
+    for url in &webhooks.urls {
+        let webhooks = Arc::clone(webhooks);
+        let url = url.clone();
+        let id = id.clone();
+        let body = Arc::clone(&body);
+        tokio::spawn(async move { webhooks.deliver(&url, event, &id, &body).await });
+    }
+}
+
+/// Posts `event` to a callback URL of the session in the background, like webhooks are,
+/// whatever `MOTORHEAD_WEBHOOK_URLS` and `MOTORHEAD_WEBHOOK_EVENTS` are.
+pub fn call_back(
+    state: &AppState,
+    url: String,
+    tenant: Option<&str>,
+    session_id: &str,
+    event: WebhookEvent,
+    data: serde_json::Value,
+) {
+    let Some((id, body)) = payload(tenant, session_id, event, data) else {
+        return;
+    };
+    let webhooks = Arc::clone(&state.webhooks);
+    tokio::spawn(async move { webhooks.deliver(&url, event, &id, &body).await });
+}
+
+/// Sends `session_expired` webhooks for the sessions whose TTL runs out, for as long as the
+/// server runs. Stops right away on storage backends that can't report expirations.
+pub async fn run_expiry_listener(state: Arc<AppState>) {
+    loop {
+        match state.store.expirations().await {
+            Ok(mut expired) => {
+                while let Some((tenant, session_id)) = expired.next().await {
+                    notify(
+                        &state,
+                        tenant.as_deref(),
+                        &session_id,
+                        WebhookEvent::SessionExpired,
+                        serde_json::json!({}),
+                    );
+                }
+                tracing::warn!("Lost the subscription to expired sessions, resubscribing");
+            }
+            Err(e @ MotorheadError::Unsupported(_)) => {
+                tracing::warn!("{}, no session_expired webhooks will be sent", e);
+                return;
+            }
+            Err(e) => tracing::error!(
+                error = telemetry::error_message(&e),
+                "Problem listening for expired sessions"
+            ),
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::errors::ErrorCode;
+use crate::memory::after_append;
+use crate::metrics;
+use crate::models::{AppState, MemoryMessage, MotorheadError, SummaryOptions};
+use crate::telemetry;
+use crate::tenant::Tenant;
+
+/// What happens to an append once the buffer is full.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// The append fails like it would without a buffer.
+    Reject,
+    /// The oldest buffered append is dropped to make room.
+    DropOldest,
+}
+
+#[derive(Clone)]
+struct PendingAppend {
+    tenant: Tenant,
+    session_id: String,
+    messages: Vec<MemoryMessage>,
+    ttl_seconds: Option<u64>,
+    summary: SummaryOptions,
+}
+
+/// Appends that came in while the store was unreachable, oldest first, stored by
+/// `run_write_buffer_flusher` once it's back. Set with `MOTORHEAD_WRITE_BUFFER_CAPACITY`.
+pub struct WriteBuffer {
+    capacity: usize,
+    overflow: OverflowPolicy,
+    flush_interval: Duration,
+    pending: Mutex<VecDeque<PendingAppend>>,
+}
+
+/// Whether `error` comes from the store being briefly unreachable, rather than from the
+/// append itself.
+pub fn is_outage(error: &MotorheadError) -> bool {
+    matches!(
+        error.code(),
+        ErrorCode::RedisUnavailable | ErrorCode::PostgresUnavailable
+    )
+}
+
+impl WriteBuffer {
+    pub fn new(capacity: usize, overflow: OverflowPolicy, flush_interval: Duration) -> Self {
+        WriteBuffer {
+            capacity,
+            overflow,
+            flush_interval,
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Whether the session has buffered appends, which later ones have to wait behind to be
+    /// stored in order.
+    pub fn has_pending(&self, tenant: &Tenant, session_id: &str) -> bool {
+        self.pending
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|append| append.tenant.id() == tenant.id() && append.session_id == session_id)
+    }
+
+    /// Buffers an append, unless the buffer is full and `OverflowPolicy::Reject` applies.
+    pub fn push(
+        &self,
+        tenant: &Tenant,
+        session_id: &str,
+        messages: Vec<MemoryMessage>,
+        ttl_seconds: Option<u64>,
+        summary: SummaryOptions,
+    ) -> Result<(), MotorheadError> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() >= self.capacity {
+            match self.overflow {
+                OverflowPolicy::Reject => {
+                    metrics::WRITE_BUFFER_REJECTED.inc();
+                    return Err(MotorheadError::WriteBufferFull);
+                }
+                OverflowPolicy::DropOldest => {
+                    if let Some(dropped) = pending.pop_front() {
+                        metrics::WRITE_BUFFER_DROPPED.inc();
+                        tracing::error!(
+                            session_id = %dropped.session_id,
+                            messages = dropped.messages.len(),
+                            "Write buffer full, dropped its oldest append"
+                        );
+                    }
+                }
+            }
+        }
+
+        pending.push_back(PendingAppend {
+            tenant: tenant.clone(),
+            session_id: session_id.to_string(),
+            messages,
+            ttl_seconds,
+            summary,
+        });
+        metrics::WRITE_BUFFER_DEPTH.set(pending.len() as i64);
+        Ok(())
+    }
+
+    fn front(&self) -> Option<PendingAppend> {
+        self.pending.lock().unwrap().front().cloned()
+    }
+
+    fn pop_front(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.pop_front();
+        metrics::WRITE_BUFFER_DEPTH.set(pending.len() as i64);
+    }
+}
+
+/// Stores the buffered appends oldest first, then does the work that follows them
+/// (compaction, indexing, webhooks), for as long as the server runs. Stops at the first
+/// that fails with an outage and tries again after `MOTORHEAD_WRITE_BUFFER_FLUSH_INTERVAL_MS`.
+pub async fn run_write_buffer_flusher(state: Arc<AppState>) {
+    let Some(buffer) = &state.write_buffer else {
+        return;
+    };
+    let mut interval = tokio::time::interval(buffer.flush_interval);
+
+    loop {
+        interval.tick().await;
+
+        // The append stays first until stored, so appends to its session keep queueing
+        // behind it meanwhile.
+        while let Some(append) = buffer.front() {
+            let store = append.tenant.store(&state);
+            let len = match store
+                .append_messages(&append.session_id, append.messages.clone())
+                .await
+            {
+                Ok(len) => len,
+                Err(e) if is_outage(&e) => break,
+                Err(e) => {
+                    buffer.pop_front();
+                    metrics::WRITE_BUFFER_DROPPED.inc();
+                    tracing::error!(
+                        session_id = %append.session_id,
+                        error = telemetry::error_message(&e),
+                        "Problem storing a buffered append, dropped it"
+                    );
+                    continue;
+                }
+            };
+            buffer.pop_front();
+
+            if let Err(e) = after_append(
+                &state,
+                &append.tenant,
+                &append.session_id,
+                append.messages,
+                len,
+                append.ttl_seconds,
+                append.summary,
+            )
+            .await
+            {
+                tracing::error!(
+                    session_id = %append.session_id,
+                    error = telemetry::error_message(&e),
+                    "Problem after storing a buffered append"
+                );
+            }
+        }
+    }
+}
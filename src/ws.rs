@@ -0,0 +1,156 @@
+use actix_web::{get, web, HttpMessage, HttpRequest, Responder};
+use actix_ws::{Message, Session};
+use futures_util::stream::{self, StreamExt};
+use std::sync::Arc;
+
+use crate::audit;
+use crate::auth::Actor;
+use crate::delete_intent::confirm_delete;
+use crate::memory::{append_memory, check_roles, delete_session, read_memory};
+use crate::models::{AppState, MemoryFields, MotorheadError, WsRequest, WsResponse};
+use crate::tenant::Tenant;
+
+/// Same operations as the HTTP memory endpoints over one connection, with the session's
+/// change events pushed as they happen.
+#[get("/ws/sessions/{session_id}")]
+pub async fn memory_ws(
+    session_id: web::Path<String>,
+    tenant: Tenant,
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    body: web::Payload,
+) -> actix_web::Result<impl Responder> {
+    let session_id = session_id.into_inner();
+    let state = data.get_ref().clone();
+
+    let mut events = match tenant.store(&state).subscribe(&session_id).await {
+        Ok(events) => events,
+        Err(MotorheadError::Unsupported(_)) => stream::pending().boxed(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let Actor(actor) = req
+        .extensions()
+        .get::<Actor>()
+        .cloned()
+        .unwrap_or_else(|| Actor::of(None));
+    let (response, mut session, mut frames) = actix_ws::handle(&req, body)?;
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                frame = frames.next() => match frame {
+                    Some(Ok(Message::Text(text))) => {
+                        let reply = handle_request(&state, &actor, &tenant, &session_id, &text).await;
+                        if send(&mut session, &reply).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(reason))) => {
+                        let _ = session.close(reason).await;
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => break,
+                },
+                Some(event) = events.next() => {
+                    if session.text(event).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+async fn handle_request(
+    state: &Arc<AppState>,
+    actor: &str,
+    tenant: &Tenant,
+    session_id: &str,
+    text: &str,
+) -> WsResponse {
+    let request = match serde_json::from_str::<WsRequest>(text) {
+        Ok(request) => request,
+        Err(e) => {
+            return WsResponse::Error {
+                error: e.to_string(),
+            }
+        }
+    };
+
+    if let WsRequest::Append { messages, .. } = &request {
+        if let Err(error) = check_roles(state, messages) {
+            return WsResponse::Error { error };
+        }
+    }
+    // The connection is recorded when it opens, the changes it makes one by one.
+    let action = match &request {
+        WsRequest::Append { .. } => Some("ws append"),
+        WsRequest::Get => None,
+        WsRequest::Delete { .. } => Some("ws delete"),
+    };
+    if let Some(action) = action {
+        if let Err(e) = audit::record(state, actor, tenant, action, Some(session_id), None).await {
+            return WsResponse::Error {
+                error: e.to_string(),
+            };
+        }
+    }
+
+    let result = match request {
+        WsRequest::Append {
+            messages,
+            ttl_seconds,
+        } => append_memory(
+            state,
+            tenant,
+            session_id,
+            messages,
+            ttl_seconds,
+            state.runtime().summary_options,
+        )
+        .await
+        .map(|_| WsResponse::Ack),
+        WsRequest::Get => read_memory(
+            state,
+            tenant,
+            tenant.read_store(state).as_ref(),
+            session_id,
+            None,
+            MemoryFields::ALL,
+        )
+        .await
+        .map(WsResponse::Memory)
+        .map_err(actix_web::Error::from),
+        WsRequest::Delete { confirm } => {
+            async {
+                let store = tenant.store(state);
+                confirm_delete(state, store.as_ref(), session_id, confirm.as_deref()).await?;
+                delete_session(state, tenant, session_id).await?;
+                Ok(WsResponse::Ack)
+            }
+            .await
+        }
+    };
+
+    result.unwrap_or_else(|e| WsResponse::Error {
+        error: e.to_string(),
+    })
+}
+
+async fn send(session: &mut Session, response: &WsResponse) -> Result<(), actix_ws::Closed> {
+    // Serializing these types can't fail.
+    session
+        .text(serde_json::to_string(response).unwrap_or_default())
+        .await
+}